@@ -0,0 +1,80 @@
+// Compares the radix tree keyspace with a HashMap holding the same keys
+//
+// Run with `cargo bench -p rustdss_core --bench keyspace`. The prefix scans are what KEYS and
+// SCAN MATCH do with a pattern like `user:123:*` - a HashMap has to look at every key, the radix
+// tree only at the keys under the prefix.
+
+use rustdss_core::keyspace::Keyspace;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const USERS: usize = 100_000;
+const FIELDS: [&str; 5] = ["name", "email", "age", "city", "visits"];
+
+fn key_names() -> Vec<String> {
+    (0..USERS)
+        .flat_map(|user| {
+            FIELDS
+                .iter()
+                .map(move |field| format!("user:{}:{}", user, field))
+        })
+        .collect()
+}
+
+/// Runs `f` `runs` times and prints the average time each run took
+fn time<T>(name: &str, runs: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        black_box(f());
+    }
+    let each = start.elapsed() / runs;
+    println!("{:<40} {:>12.3?}", name, each);
+    each
+}
+
+fn main() {
+    let keys = key_names();
+    println!("{} keys", keys.len());
+
+    let mut hash_map = HashMap::new();
+    time("insert - hashmap", 1, || {
+        for key in &keys {
+            hash_map.insert(key.clone(), ());
+        }
+    });
+    let mut keyspace = Keyspace::default();
+    time("insert - radix tree", 1, || {
+        for key in &keys {
+            keyspace.insert(key.clone(), ());
+        }
+    });
+
+    time("get every key - hashmap", 1, || {
+        keys.iter()
+            .filter(|key| hash_map.contains_key(*key))
+            .count()
+    });
+    time("get every key - radix tree", 1, || {
+        keys.iter().filter(|key| keyspace.contains_key(key)).count()
+    });
+
+    // From a handful of matches up to a tenth of the keys
+    for prefix in ["user:12345:", "user:1234", "user:123", "user:1"] {
+        let matches = keyspace.scan(prefix, None).count();
+        let hashed = time(&format!("scan {:<12} - hashmap", prefix), 10, || {
+            hash_map
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .count()
+        });
+        let radix = time(&format!("scan {:<12} - radix tree", prefix), 10, || {
+            keyspace.scan(prefix, None).count()
+        });
+        println!(
+            "  {} matches, radix tree is {:.1}x faster",
+            matches,
+            hashed.as_secs_f64() / radix.as_secs_f64()
+        );
+    }
+}
@@ -1,30 +1,144 @@
 use super::CoreState;
-use rustdss_data::{Command, RespData};
+use crate::db_logic::streams::BlockedRead;
+use crate::memory;
+use crate::replication;
+use rustdss_data::{Command, Key, RespData};
 
 use crate::db_logic::admin;
+use crate::db_logic::expiry;
+use crate::db_logic::geo;
+use crate::db_logic::hyperloglog;
 use crate::db_logic::key_val;
 use crate::db_logic::lists;
 use crate::db_logic::number;
+use crate::db_logic::sets;
+use crate::db_logic::streams;
 
 // Maybe move this mapping function into the module root?
 pub fn core_logic(state: &mut CoreState, cmd: Command) -> RespData {
     match cmd {
-        Command::Set(key, value) => key_val::set(state, key, value),
+        Command::Set(key, value, options) => key_val::set(state, key, value, &options),
         Command::Get(key) => key_val::get(state, key),
         Command::Incr(key, maybe_by) => number::incr(state, key, maybe_by),
         Command::Decr(key, maybe_by) => number::decr(state, key, maybe_by),
-        Command::Lpop(key) => lists::lpop(state, &key),
-        Command::Rpop(key) => lists::rpop(state, &key),
-        Command::Lpush(key, value) => lists::lpush(state, &key, value),
-        Command::Rpush(key, value) => lists::rpush(state, &key, value),
+        Command::Lpop(key, count) => lists::lpop(state, &key, count),
+        Command::Rpop(key, count) => lists::rpop(state, &key, count),
+        Command::Lpush(key, values) => lists::lpush(state, &key, values),
+        Command::Rpush(key, values) => lists::rpush(state, &key, values),
         Command::Llen(key) => lists::llen(state, &key),
-        Command::Keys => admin::keys(state),
+        Command::Keys(pattern) => admin::keys(state, &pattern),
+        Command::Scan(args) => admin::scan(state, &args),
         Command::FlushAll => admin::flushall(state),
         Command::Dump(key) => admin::dump(state, &key),
+        Command::Exists(keys) => admin::exists(state, &keys),
+        Command::Restore(key, args) => admin::restore(state, &key, &args),
         Command::Lrange(key, start, end) => lists::lrange(state, &key, start, end),
-        _ => RespData::Error("Unknown core cmd".into()),
+        Command::Pfadd(key, elements) => hyperloglog::pfadd(state, &key, &elements),
+        Command::Pfcount(keys) => hyperloglog::pfcount(state, &keys),
+        Command::Pfmerge(dest, sources) => hyperloglog::pfmerge(state, &dest, &sources),
+        Command::Xadd(key, args) => streams::xadd(state, &key, &args),
+        Command::Xlen(key) => streams::xlen(state, &key),
+        Command::Xrange(key, args) => streams::xrange(state, &key, &args),
+        Command::Xrevrange(key, args) => streams::xrevrange(state, &key, &args),
+        Command::Xdel(key, ids) => streams::xdel(state, &key, &ids),
+        Command::Xtrim(key, args) => streams::xtrim(state, &key, &args),
+        // Without anywhere to park a blocked read, it times out straight away
+        Command::Xread(args) => streams::xread(state, &args).unwrap_or_else(|_| RespData::nil()),
+        Command::Xreadgroup(args) => {
+            streams::xreadgroup(state, &args).unwrap_or_else(|_| RespData::nil())
+        }
+        Command::Xgroup(args) => streams::xgroup(state, &args),
+        Command::Xack(key, group, ids) => streams::xack(state, &key, &group, &ids),
+        Command::Xpending(key, group, args) => streams::xpending(state, &key, &group, &args),
+        Command::Xclaim(key, args) => streams::xclaim(state, &key, &args),
+        Command::Xautoclaim(key, args) => streams::xautoclaim(state, &key, &args),
+        Command::Xinfo(args) => streams::xinfo(state, &args),
+        Command::Geoadd(key, args) => geo::geoadd(state, &key, &args),
+        Command::Geopos(key, members) => geo::geopos(state, &key, &members),
+        Command::Geodist(key, args) => geo::geodist(state, &key, &args),
+        Command::Geohash(key, members) => geo::geohash(state, &key, &members),
+        Command::Geosearch(key, args) => geo::geosearch(state, &key, &args),
+        Command::Geosearchstore(dest, source, args) => {
+            geo::geosearchstore(state, &dest, &source, &args)
+        }
+        Command::Del(keys) => expiry::del(state, &keys),
+        Command::Expire(key, seconds, options) => expiry::expire(state, &key, &seconds, &options),
+        Command::Pexpire(key, millis, options) => expiry::pexpire(state, &key, &millis, &options),
+        Command::Expireat(key, seconds, options) => {
+            expiry::expireat(state, &key, &seconds, &options)
+        }
+        Command::Pexpireat(key, millis, options) => {
+            expiry::pexpireat(state, &key, &millis, &options)
+        }
+        Command::Ttl(key) => expiry::ttl(state, &key),
+        Command::Pttl(key) => expiry::pttl(state, &key),
+        Command::Persist(key) => expiry::persist(state, &key),
+        Command::Object(subcommand, key) => memory::object(state, &subcommand, &key),
+        Command::Mget(keys) => key_val::mget(state, &keys),
+        Command::Rename(source, dest) => admin::rename(state, &source, &dest),
+        Command::Sadd(key, members) => sets::sadd(state, &key, members),
+        Command::Srem(key, members) => sets::srem(state, &key, &members),
+        Command::Smembers(key) => sets::smembers(state, &key),
+        Command::Sismember(key, member) => sets::sismember(state, &key, &member),
+        Command::Scard(key) => sets::scard(state, &key),
+        Command::Sinter(keys) => sets::sinter(state, &keys),
+        Command::Sinterstore(dest, keys) => sets::sinterstore(state, &dest, &keys),
+        // These only reach the core inside a transaction
+        Command::Ping(None) => RespData::SimpleStr("PONG".into()),
+        Command::Ping(Some(data)) | Command::Echo(data) => data,
+        other => RespData::Error(format!(
+            "ERR '{}' can't be run by a database",
+            other.name().to_lowercase()
+        )),
     }
 }
+
+/// Like `core_logic`, but commands that can block hand back what they're waiting for instead of
+/// replying straight away.
+pub fn blocking_logic(state: &mut CoreState, cmd: Command) -> Result<RespData, BlockedRead> {
+    match cmd {
+        Command::Xread(args) => streams::xread(state, &args),
+        Command::Xreadgroup(args) => streams::xreadgroup(state, &args),
+        cmd => Ok(core_logic(state, cmd)),
+    }
+}
+
+/// Runs a command with the expiry and memory bookkeeping that goes around every command
+pub fn execute(state: &mut CoreState, cmd: Command) -> Result<RespData, BlockedRead> {
+    if let Command::Exec(commands) = cmd {
+        return Ok(RespData::List(
+            commands
+                .into_iter()
+                // Blocking commands don't block inside a transaction
+                .map(|cmd| execute(state, cmd).unwrap_or(RespData::nil()))
+                .collect(),
+        ));
+    }
+
+    let keys: Vec<Key> = cmd.key_args().into_iter().cloned().collect();
+    if let Command::Keys(_) | Command::Scan(_) = cmd {
+        expiry::expire_all(state);
+    } else {
+        expiry::expire_keys(state, &keys);
+    }
+
+    if cmd.denied_when_out_of_memory() && !state.make_room() {
+        return Ok(memory::out_of_memory());
+    }
+    state.count_lookups(&cmd);
+
+    // OBJECT looks at a key without counting as a use of it
+    let touch = !matches!(cmd, Command::Object(..));
+    let replicated = replication::args_to_replicate(&cmd);
+    let response = blocking_logic(state, cmd);
+    state.track_keys(&keys, touch);
+    if let (Some(args), Ok(response)) = (replicated, &response) {
+        if let Some(args) = replication::finish_replicated_args(args, response) {
+            state.replicate(&args);
+        }
+    }
+    response
+}
 #[cfg(test)]
 mod should {
     use super::*;
@@ -32,13 +146,11 @@ mod should {
 
     #[test]
     fn set_adds_a_new_key() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         let response = core_logic(
             &mut state,
-            Command::Set("a".into(), RespData::SimpleStr("hello".into())),
+            Command::Set("a".into(), RespData::SimpleStr("hello".into()), vec![]),
         );
 
         assert_eq!(response, RespData::ok());
@@ -54,9 +166,7 @@ mod should {
         let mut inner_keyval = HashMap::new();
         inner_keyval.insert("a".into(), RespData::SimpleStr("hello".into()));
 
-        let mut state = CoreState {
-            keyval: inner_keyval,
-        };
+        let mut state = CoreState::from(inner_keyval);
 
         let response = core_logic(&mut state, Command::Get("a".into()));
 
@@ -70,9 +180,7 @@ mod should {
 
     #[test]
     fn get_returns_nil_when_key_is_not_found() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         let response = core_logic(&mut state, Command::Get("a".into()));
 
@@ -83,20 +191,18 @@ mod should {
 
     #[test]
     fn set_overwrites_existing_value() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         let key: String = "key-a".into();
 
         let response_a = core_logic(
             &mut state,
-            Command::Set(key.clone(), RespData::SimpleStr("hello".into())),
+            Command::Set(key.clone(), RespData::SimpleStr("hello".into()), vec![]),
         );
 
         let response_b = core_logic(
             &mut state,
-            Command::Set(key.clone(), RespData::SimpleStr("goodbye".into())),
+            Command::Set(key.clone(), RespData::SimpleStr("goodbye".into()), vec![]),
         );
 
         assert_eq!(response_a, RespData::ok());
@@ -110,17 +216,15 @@ mod should {
 
     #[test]
     fn flushall_deletes_everything() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         core_logic(
             &mut state,
-            Command::Set("a".into(), RespData::SimpleStr("hello".into())),
+            Command::Set("a".into(), RespData::SimpleStr("hello".into()), vec![]),
         );
         core_logic(
             &mut state,
-            Command::Set("b".into(), RespData::SimpleStr("goodbye".into())),
+            Command::Set("b".into(), RespData::SimpleStr("goodbye".into()), vec![]),
         );
 
         assert_eq!(state.keyval.len(), 2);
@@ -142,9 +246,7 @@ mod should {
 
     #[test]
     fn incr() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         // It creates a key when there isn't one
         let response = core_logic(&mut state, Command::Incr("a".into(), None));
@@ -164,9 +266,7 @@ mod should {
 
     #[test]
     fn decr() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         // It creates a key when there isn't one
         let response = core_logic(&mut state, Command::Decr("a".into(), None));
@@ -1,26 +1,505 @@
+use crate::base_logic;
+use crate::db_logic::expiry;
+use crate::keyspace::{glob_match, literal_prefix, Keyspace};
+use crate::replication;
 use crate::CoreState;
-use rustdss_data::RespData;
+use rustdss_data::{Command, Key, RespData};
+use rustdss_transport::deserialise::DeserialiseRespData;
 use rustdss_transport::serialise::SerialiseRespData;
+use std::iter::Peekable;
 
 pub fn flushall(state: &mut CoreState) -> RespData {
     state.keyval.clear();
+    state.streams.clear();
+    state.sorted_sets.clear();
+    state.sets.clear();
+    state.forget_all_keys();
     RespData::ok()
 }
 
-pub fn keys(state: &CoreState) -> RespData {
+/// Every key starting with `prefix` and sorting after `after`, in order, whatever type of value
+/// it holds
+fn keys_under<'a>(
+    state: &'a CoreState,
+    prefix: &str,
+    after: Option<&str>,
+) -> impl Iterator<Item = &'a Key> {
+    fn keys_of<'a, V>(
+        keyspace: &'a Keyspace<V>,
+        prefix: &str,
+        after: Option<&str>,
+    ) -> Peekable<Box<dyn Iterator<Item = &'a Key> + 'a>> {
+        let keys: Box<dyn Iterator<Item = &'a Key>> =
+            Box::new(keyspace.scan(prefix, after).map(|(key, _)| key));
+        keys.peekable()
+    }
+
+    let mut keyspaces = [
+        keys_of(&state.keyval, prefix, after),
+        keys_of(&state.streams, prefix, after),
+        keys_of(&state.sorted_sets, prefix, after),
+        keys_of(&state.sets, prefix, after),
+    ];
+    // A key only lives in one keyspace, so merging them never sees the same key twice
+    std::iter::from_fn(move || {
+        let next = keyspaces
+            .iter_mut()
+            .filter_map(|keys| keys.peek().copied().map(|key| (key, keys)))
+            .min_by(|(a, _), (b, _)| a.cmp(b))?;
+        next.1.next()
+    })
+}
+
+pub fn keys(state: &CoreState, pattern: &str) -> RespData {
+    let prefix = literal_prefix(pattern);
+    let pattern: Vec<char> = pattern.chars().collect();
     RespData::List(
-        state
-            .keyval
-            .keys()
+        keys_under(state, prefix, None)
+            .filter(|key| glob_match(&pattern, &key.chars().collect::<Vec<_>>()))
             .map(|key| RespData::SimpleStr(key.into()))
             .collect(),
     )
 }
 
-pub fn dump(state: &CoreState, key: &String) -> RespData {
-    state
-        .keyval
-        .get(key)
-        .map(|value| RespData::BulkStr(value.as_string()))
-        .unwrap_or(RespData::nil())
+// How many keys SCAN looks at when it isn't given a COUNT
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+///
+/// Keys are visited in order and a cursor remembers the last key it looked at, so keys that are
+/// there for the whole scan are always returned, and only once unless the cursor was forgotten
+/// part way through. Only the keys under the pattern's literal prefix are looked at.
+pub fn scan(state: &mut CoreState, args: &[String]) -> RespData {
+    let cursor: u64 = match args.first().and_then(|cursor| cursor.parse().ok()) {
+        Some(cursor) => cursor,
+        None => return RespData::Error("ERR invalid cursor".into()),
+    };
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    let mut value_type = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = match options.next() {
+            Some(value) => value,
+            None => return RespData::Error("ERR syntax error".into()),
+        };
+        match option.to_lowercase().as_str() {
+            "match" => pattern = Some(value),
+            "count" => match value.parse() {
+                Ok(0) => return RespData::Error("ERR syntax error".into()),
+                Ok(n) => count = n,
+                Err(_) => {
+                    return RespData::Error("ERR value is not an integer or out of range".into())
+                }
+            },
+            "type" => value_type = Some(value.to_lowercase()),
+            _ => return RespData::Error("ERR syntax error".into()),
+        }
+    }
+
+    let after = match cursor {
+        0 => None,
+        cursor => state.scan_cursors.resume_after(cursor),
+    };
+    let prefix = pattern.map(|pattern| literal_prefix(pattern)).unwrap_or("");
+    let pattern: Option<Vec<char>> = pattern.map(|pattern| pattern.chars().collect());
+
+    let mut keys = keys_under(state, prefix, after.as_deref());
+    let mut found = vec![];
+    let mut last = None;
+    for key in keys.by_ref().take(count) {
+        last = Some(key);
+        let matches_pattern = match &pattern {
+            Some(pattern) => glob_match(pattern, &key.chars().collect::<Vec<_>>()),
+            None => true,
+        };
+        let matches_type = match &value_type {
+            Some(value_type) => state.value_type(key) == Some(value_type.as_str()),
+            None => true,
+        };
+        if matches_pattern && matches_type {
+            found.push(key.clone());
+        }
+    }
+    let last = match (last, keys.next()) {
+        (Some(last), Some(_)) => Some(last.clone()),
+        _ => None,
+    };
+    drop(keys);
+
+    let next_cursor = match last {
+        Some(last) => state.scan_cursors.create(last),
+        None => 0,
+    };
+    scan_reply(next_cursor, found)
+}
+
+fn scan_reply(cursor: u64, keys: Vec<Key>) -> RespData {
+    RespData::List(
+        vec![
+            RespData::BulkStr(cursor.to_string()),
+            RespData::List(keys.into_iter().map(RespData::BulkStr).collect()),
+        ]
+        .into(),
+    )
+}
+
+/// Counts the keys that exist, a key named twice counts twice
+pub fn exists(state: &CoreState, keys: &[Key]) -> RespData {
+    RespData::Number(
+        keys.iter()
+            .filter(|key| state.value_type(key).is_some())
+            .count() as i64,
+    )
+}
+
+/// The value at a key in a form RESTORE can recreate it from: the commands that rebuild it
+pub fn dump(state: &CoreState, key: &str) -> RespData {
+    let commands = replication::rebuild_key(state, key);
+    if commands.is_empty() {
+        return RespData::nil();
+    }
+    let payload = RespData::List(
+        commands
+            .into_iter()
+            .map(|args| RespData::List(args.into_iter().map(RespData::BulkStr).collect()))
+            .collect(),
+    );
+    RespData::BulkStr(payload.as_string())
+}
+
+/// The commands in a DUMP payload, rebuilding `key`
+fn parse_payload(key: &str, payload: &str) -> Option<Vec<Command>> {
+    let commands = match RespData::from_char_stream(&mut payload.chars())? {
+        RespData::List(commands) if !commands.is_empty() => commands,
+        _ => return None,
+    };
+    commands
+        .into_iter()
+        .map(|command| match command {
+            RespData::List(args) => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| match arg {
+                        RespData::BulkStr(arg) => Some(arg),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<String>>>()?;
+                replication::rebuild_command(key, args)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// RESTORE key ttl payload [REPLACE], the TTL is in milliseconds and 0 means none
+pub fn restore(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    let (ttl, payload, options) = match args {
+        [ttl, payload, options @ ..] => (ttl, payload, options),
+        _ => return RespData::Error("ERR syntax error".into()),
+    };
+    let mut replace = false;
+    for option in options {
+        if option.eq_ignore_ascii_case("replace") {
+            replace = true;
+        } else {
+            return RespData::Error("ERR syntax error".into());
+        }
+    }
+    let ttl: i64 = match ttl.parse() {
+        Ok(ttl) if ttl >= 0 => ttl,
+        Ok(_) => return RespData::Error("ERR Invalid TTL value, must be >= 0".into()),
+        Err(_) => return RespData::Error("ERR value is not an integer or out of range".into()),
+    };
+    let bad_payload = || RespData::Error("ERR DUMP payload version or checksum are wrong".into());
+    let commands = match parse_payload(key, payload) {
+        Some(commands) => commands,
+        None => return bad_payload(),
+    };
+
+    if state.value_type(key).is_some() {
+        if !replace {
+            return RespData::Error("BUSYKEY Target key name already exists.".into());
+        }
+        state.remove_key(key);
+    }
+    for command in commands {
+        if let RespData::Error(_) = base_logic::core_logic(state, command) {
+            state.remove_key(key);
+            return bad_payload();
+        }
+    }
+    if ttl > 0 {
+        expiry::pexpire(state, key, &ttl.to_string(), &[]);
+    }
+    RespData::ok()
+}
+
+pub fn rename(state: &mut CoreState, source: &Key, dest: &Key) -> RespData {
+    match state.take_key(source) {
+        Some(entry) => {
+            state.put_key(dest.clone(), entry);
+            RespData::ok()
+        }
+        None => RespData::Error("ERR no such key".into()),
+    }
+}
+
+#[cfg(test)]
+mod scan_should {
+    use super::*;
+    use crate::db_logic::sets;
+    use crate::test_helpers::args;
+
+    fn state_with(keys: &[&str]) -> CoreState {
+        let mut state = CoreState::default();
+        for key in keys {
+            state
+                .keyval
+                .insert(key.to_string(), RespData::BulkStr("v".into()));
+        }
+        state
+    }
+
+    fn bulk_str(data: &RespData) -> String {
+        match data {
+            RespData::BulkStr(string) => string.clone(),
+            other => panic!("expected a bulk string, got {:?}", other),
+        }
+    }
+
+    fn scan_all(state: &mut CoreState, options: &[&str]) -> (usize, Vec<String>) {
+        let mut cursor = "0".to_string();
+        let mut calls = 0;
+        let mut found = vec![];
+        loop {
+            let mut call = args(&[&cursor]);
+            call.extend(args(options));
+            calls += 1;
+            match scan(state, &call) {
+                RespData::List(reply) => {
+                    cursor = bulk_str(&reply[0]);
+                    if let RespData::List(keys) = &reply[1] {
+                        found.extend(keys.iter().map(bulk_str));
+                    }
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+            if cursor == "0" {
+                return (calls, found);
+            }
+        }
+    }
+
+    #[test]
+    fn list_keys_matching_a_pattern() {
+        let state = state_with(&["user:1:name", "user:1:email", "user:2:name", "other"]);
+
+        assert_eq!(
+            keys(&state, "user:*:name"),
+            RespData::List(
+                vec![
+                    RespData::SimpleStr("user:1:name".into()),
+                    RespData::SimpleStr("user:2:name".into())
+                ]
+                .into()
+            )
+        );
+        assert_eq!(
+            keys(&state, "*"),
+            RespData::List(
+                ["other", "user:1:email", "user:1:name", "user:2:name"]
+                    .iter()
+                    .map(|key| RespData::SimpleStr(key.to_string()))
+                    .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn visit_every_key_once_across_calls() {
+        let mut state = state_with(&["a", "b", "c", "d", "e"]);
+        sets::sadd(&mut state, &"ab".into(), args(&["x"]));
+
+        let (calls, found) = scan_all(&mut state, &["COUNT", "2"]);
+
+        assert_eq!(found, args(&["a", "ab", "b", "c", "d", "e"]));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn only_look_under_the_pattern_prefix() {
+        let mut state = state_with(&["a:1", "a:2", "b:1", "b:2", "b:3", "c:1"]);
+
+        // Only the three keys under b: are looked at, so one call covers them all
+        let (calls, found) = scan_all(&mut state, &["MATCH", "b:*", "COUNT", "3"]);
+
+        assert_eq!(found, args(&["b:1", "b:2", "b:3"]));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn filter_by_type() {
+        let mut state = state_with(&["a", "c"]);
+        sets::sadd(&mut state, &"b".into(), args(&["x"]));
+
+        let (_, found) = scan_all(&mut state, &["TYPE", "set"]);
+
+        assert_eq!(found, args(&["b"]));
+    }
+
+    #[test]
+    fn keep_going_when_keys_change_during_the_scan() {
+        let mut state = state_with(&["a", "b", "c", "d"]);
+
+        let first = scan(&mut state, &args(&["0", "COUNT", "2"]));
+        let cursor = match &first {
+            RespData::List(reply) => bulk_str(&reply[0]),
+            _ => panic!("unexpected reply"),
+        };
+        // The key the cursor points at goes away, the scan carries on from where it was
+        state.remove_key("b");
+        state
+            .keyval
+            .insert("bb".into(), RespData::BulkStr("v".into()));
+
+        assert_eq!(
+            scan(&mut state, &args(&[&cursor, "COUNT", "10"])),
+            scan_reply(0, args(&["bb", "c", "d"]))
+        );
+    }
+
+    #[test]
+    fn reject_bad_arguments() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            scan(&mut state, &args(&["x"])),
+            RespData::Error("ERR invalid cursor".into())
+        );
+        assert_eq!(
+            scan(&mut state, &args(&["0", "COUNT"])),
+            RespData::Error("ERR syntax error".into())
+        );
+        assert_eq!(
+            scan(&mut state, &args(&["0", "COUNT", "0"])),
+            RespData::Error("ERR syntax error".into())
+        );
+        assert_eq!(
+            scan(&mut state, &args(&["0", "COUNT", "many"])),
+            RespData::Error("ERR value is not an integer or out of range".into())
+        );
+    }
+
+    #[test]
+    fn carry_on_from_a_forgotten_cursor_without_missing_keys() {
+        let mut state = state_with(&["a1", "b1", "c1", "d1"]);
+
+        let first = scan(&mut state, &args(&["0", "COUNT", "2"]));
+        let cursor = match &first {
+            RespData::List(reply) => bulk_str(&reply[0]),
+            _ => panic!("unexpected reply"),
+        };
+        state.scan_cursors = Default::default();
+
+        // It starts again from the first key beginning with "b", the one it stopped at
+        assert_eq!(
+            scan(&mut state, &args(&[&cursor, "COUNT", "10"])),
+            scan_reply(0, args(&["b1", "c1", "d1"]))
+        );
+        // Cursors that were never handed out start from the beginning
+        assert_eq!(
+            scan(&mut state, &args(&["42", "COUNT", "10"])),
+            scan_reply(0, args(&["a1", "b1", "c1", "d1"]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod dump_should {
+    use super::*;
+    use crate::db_logic::{sets, streams};
+    use crate::test_helpers::args;
+
+    fn payload(state: &CoreState, key: &str) -> String {
+        match dump(state, key) {
+            RespData::BulkStr(payload) => payload,
+            other => panic!("expected a payload, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn restore_what_it_dumps_under_another_key() {
+        let mut state = CoreState::default();
+        let list = RespData::List(vec![RespData::BulkStr("a".into())].into());
+        state.keyval.insert("list".into(), list.clone());
+        sets::sadd(&mut state, &"set".into(), args(&["x", "y"]));
+        base_logic::core_logic(
+            &mut state,
+            Command::Xadd("stream".into(), args(&["1-1", "f", "v"])),
+        );
+
+        for key in ["list", "set", "stream"] {
+            let payload = payload(&state, key);
+            let copy = format!("{}-copy", key);
+            assert_eq!(
+                restore(&mut state, &copy, &args(&["0", &payload])),
+                RespData::ok()
+            );
+        }
+        assert_eq!(state.keyval.get("list-copy"), Some(&list));
+        assert_eq!(state.sets.get("set-copy"), state.sets.get("set"));
+        assert_eq!(
+            streams::xrange(&state, &"stream-copy".into(), &args(&["-", "+"])),
+            streams::xrange(&state, &"stream".into(), &args(&["-", "+"]))
+        );
+        assert_eq!(
+            exists(&state, &args(&["set-copy", "nope", "set"])),
+            RespData::Number(2)
+        );
+    }
+
+    #[test]
+    fn refuse_to_overwrite_without_replace() {
+        let mut state = CoreState::default();
+        key_val_set(&mut state, "a", "1");
+        key_val_set(&mut state, "b", "2");
+        let payload = payload(&state, "a");
+
+        assert_eq!(
+            restore(&mut state, &"b".into(), &args(&["0", &payload])),
+            RespData::Error("BUSYKEY Target key name already exists.".into())
+        );
+        assert_eq!(
+            restore(
+                &mut state,
+                &"b".into(),
+                &args(&["5000", &payload, "REPLACE"])
+            ),
+            RespData::ok()
+        );
+        assert_eq!(state.keyval.get("b"), Some(&RespData::BulkStr("1".into())));
+        assert!(state.expires.get("b").is_some());
+    }
+
+    #[test]
+    fn reject_bad_payloads() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            restore(&mut state, &"a".into(), &args(&["0", "garbage"])),
+            RespData::Error("ERR DUMP payload version or checksum are wrong".into())
+        );
+        assert_eq!(
+            restore(&mut state, &"a".into(), &args(&["-1", "garbage"])),
+            RespData::Error("ERR Invalid TTL value, must be >= 0".into())
+        );
+        assert_eq!(dump(&state, "a"), RespData::nil());
+    }
+
+    fn key_val_set(state: &mut CoreState, key: &str, value: &str) {
+        state
+            .keyval
+            .insert(key.to_string(), RespData::BulkStr(value.into()));
+    }
 }
@@ -0,0 +1,402 @@
+// Key expiry - DEL, EXPIRE, PEXPIRE, EXPIREAT, PEXPIREAT, TTL, PTTL and PERSIST
+//
+// Expired keys are removed lazily when a command touches them, and a few at a time by the
+// database thread in between commands.
+
+use crate::CoreState;
+use rustdss_data::{Key, RespData};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How many keys with an expiry get checked on each pass of the active expiry cycle
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
+fn is_expired(state: &CoreState, key: &str, now: Instant) -> bool {
+    state
+        .expires
+        .get(key)
+        .map(|expires_at| *expires_at <= now)
+        .unwrap_or(false)
+}
+
+fn expire_key(state: &mut CoreState, key: &str) {
+    state.remove_key(key);
+    state.forget_key(key);
+    state.memory.count_expired();
+    // Replicas find out about expired keys from the master
+    state.replicate(&["DEL".into(), key.into()]);
+}
+
+/// Removes any of the keys that have expired, before a command gets to see them
+pub fn expire_keys(state: &mut CoreState, keys: &[Key]) {
+    let now = Instant::now();
+    for key in keys {
+        if is_expired(state, key, now) {
+            expire_key(state, key);
+        }
+    }
+}
+
+/// Removes every expired key, for commands that look at the whole keyspace
+pub fn expire_all(state: &mut CoreState) {
+    let now = Instant::now();
+    let expired: Vec<Key> = state
+        .expires
+        .keys()
+        .filter(|key| is_expired(state, key, now))
+        .cloned()
+        .collect();
+    for key in expired {
+        expire_key(state, &key);
+    }
+}
+
+/// Samples keys with an expiry and removes the expired ones, carrying on while more than a
+/// quarter of each sample had expired
+pub fn active_expire(state: &mut CoreState) {
+    loop {
+        let now = Instant::now();
+        let sampled: Vec<Key> = state
+            .expires
+            .sample_keys(ACTIVE_EXPIRE_SAMPLES, &mut state.rng)
+            .into_iter()
+            .cloned()
+            .collect();
+        let expired: Vec<Key> = sampled
+            .iter()
+            .filter(|key| is_expired(state, key, now))
+            .cloned()
+            .collect();
+        for key in &expired {
+            expire_key(state, key);
+        }
+        if expired.is_empty() || expired.len() * 4 <= sampled.len() {
+            break;
+        }
+    }
+}
+
+fn exists(state: &CoreState, key: &str) -> bool {
+    state.value_type(key).is_some()
+}
+
+fn unix_time_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The instant a unix time in milliseconds falls on, now for times that have already passed
+pub(crate) fn instant_at_unix_millis(millis: u64) -> Option<Instant> {
+    Instant::now().checked_add(Duration::from_millis(millis).saturating_sub(unix_time_now()))
+}
+
+/// The unix time in milliseconds an instant falls on
+pub(crate) fn unix_millis_at(instant: Instant) -> u128 {
+    let now = Instant::now();
+    let unix_now = unix_time_now();
+    match instant.checked_duration_since(now) {
+        Some(ahead) => (unix_now + ahead).as_millis(),
+        None => unix_now.saturating_sub(now - instant).as_millis(),
+    }
+}
+
+/// The unix time in milliseconds, `millis` from now
+pub(crate) fn unix_millis_after(millis: u64) -> u128 {
+    unix_time_now().as_millis() + millis as u128
+}
+
+pub fn del(state: &mut CoreState, keys: &[Key]) -> RespData {
+    RespData::Number(keys.iter().filter(|key| state.remove_key(key)).count() as i64)
+}
+
+/// Which of EXPIRE's NX, XX, GT and LT options were given
+#[derive(Default)]
+struct ExpireOptions {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireOptions {
+    fn parse(options: &[String]) -> Result<Self, RespData> {
+        let mut parsed = Self::default();
+        for option in options {
+            match option.to_uppercase().as_str() {
+                "NX" => parsed.nx = true,
+                "XX" => parsed.xx = true,
+                "GT" => parsed.gt = true,
+                "LT" => parsed.lt = true,
+                _ => {
+                    return Err(RespData::Error(format!(
+                        "ERR Unsupported option {}",
+                        option
+                    )))
+                }
+            }
+        }
+        if parsed.nx && (parsed.xx || parsed.gt || parsed.lt) {
+            return Err(RespData::Error(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".into(),
+            ));
+        }
+        if parsed.gt && parsed.lt {
+            return Err(RespData::Error(
+                "ERR GT and LT options at the same time are not compatible".into(),
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Whether a key expiring at `current` can be changed to expire at `new`. No expiry counts
+    /// as never expiring, and `new` being `None` means it's already in the past.
+    fn allow(&self, current: Option<Instant>, new: Option<Instant>) -> bool {
+        if (self.nx && current.is_some()) || (self.xx && current.is_none()) {
+            return false;
+        }
+        match (current, new) {
+            (None, _) | (Some(_), None) => !self.gt,
+            (Some(current), Some(new)) => {
+                (!self.gt || new > current) && (!self.lt || new < current)
+            }
+        }
+    }
+}
+
+fn set_expiry(
+    state: &mut CoreState,
+    key: &Key,
+    amount: &str,
+    options: &[String],
+    to_instant: fn(u64) -> Option<Instant>,
+    command: &str,
+) -> RespData {
+    let amount = match amount.parse::<i64>() {
+        Ok(amount) => amount,
+        Err(_) => return RespData::Error("ERR value is not an integer or out of range".into()),
+    };
+    let options = match ExpireOptions::parse(options) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+    // None when the time has already passed
+    let expires_at = match amount {
+        amount if amount <= 0 => None,
+        amount => match to_instant(amount as u64) {
+            Some(expires_at) => Some(expires_at).filter(|at| *at > Instant::now()),
+            None => {
+                return RespData::Error(format!("ERR invalid expire time in '{}' command", command))
+            }
+        },
+    };
+    if !exists(state, key) || !options.allow(state.expires.get(key).copied(), expires_at) {
+        return RespData::Number(0);
+    }
+
+    match expires_at {
+        Some(expires_at) => {
+            state.expires.insert(key.clone(), expires_at);
+        }
+        None => {
+            state.remove_key(key);
+        }
+    }
+    RespData::Number(1)
+}
+
+pub fn expire(state: &mut CoreState, key: &Key, seconds: &str, options: &[String]) -> RespData {
+    let to_instant = |seconds| Instant::now().checked_add(Duration::from_secs(seconds));
+    set_expiry(state, key, seconds, options, to_instant, "expire")
+}
+
+pub fn pexpire(state: &mut CoreState, key: &Key, millis: &str, options: &[String]) -> RespData {
+    let to_instant = |millis| Instant::now().checked_add(Duration::from_millis(millis));
+    set_expiry(state, key, millis, options, to_instant, "pexpire")
+}
+
+pub fn expireat(state: &mut CoreState, key: &Key, seconds: &str, options: &[String]) -> RespData {
+    let to_instant = |seconds: u64| seconds.checked_mul(1000).and_then(instant_at_unix_millis);
+    set_expiry(state, key, seconds, options, to_instant, "expireat")
+}
+
+pub fn pexpireat(state: &mut CoreState, key: &Key, millis: &str, options: &[String]) -> RespData {
+    set_expiry(
+        state,
+        key,
+        millis,
+        options,
+        instant_at_unix_millis,
+        "pexpireat",
+    )
+}
+
+fn remaining_millis(state: &CoreState, key: &str) -> i64 {
+    if !exists(state, key) {
+        return -2;
+    }
+    match state.expires.get(key) {
+        Some(expires_at) => expires_at
+            .saturating_duration_since(Instant::now())
+            .as_millis() as i64,
+        None => -1,
+    }
+}
+
+pub fn ttl(state: &CoreState, key: &Key) -> RespData {
+    match remaining_millis(state, key) {
+        millis if millis < 0 => RespData::Number(millis),
+        // Rounded to the nearest second, like Redis
+        millis => RespData::Number((millis + 500) / 1000),
+    }
+}
+
+pub fn pttl(state: &CoreState, key: &Key) -> RespData {
+    RespData::Number(remaining_millis(state, key))
+}
+
+pub fn persist(state: &mut CoreState, key: &Key) -> RespData {
+    let removed = exists(state, key) && state.expires.remove(key).is_some();
+    RespData::Number(removed as i64)
+}
+
+#[cfg(test)]
+mod expire_should {
+    use super::*;
+
+    fn state_with(key: &str) -> CoreState {
+        let mut state = CoreState::default();
+        state.keyval.insert(key.into(), RespData::Number(1));
+        state
+    }
+
+    #[test]
+    fn set_and_report_a_ttl() {
+        let mut state = state_with("a");
+
+        assert_eq!(
+            expire(&mut state, &"a".into(), "100", &[]),
+            RespData::Number(1)
+        );
+
+        assert_eq!(ttl(&state, &"a".into()), RespData::Number(100));
+        assert_eq!(ttl(&state, &"missing".into()), RespData::Number(-2));
+        assert_eq!(
+            expire(&mut state, &"missing".into(), "100", &[]),
+            RespData::Number(0)
+        );
+    }
+
+    #[test]
+    fn only_change_the_ttl_when_the_options_allow_it() {
+        let mut state = state_with("a");
+        let options = |options: &[&str]| -> Vec<String> {
+            options.iter().map(|option| option.to_string()).collect()
+        };
+
+        assert_eq!(
+            expire(&mut state, &"a".into(), "100", &options(&["XX"])),
+            RespData::Number(0)
+        );
+        assert_eq!(
+            expire(&mut state, &"a".into(), "100", &options(&["NX"])),
+            RespData::Number(1)
+        );
+        assert_eq!(
+            expire(&mut state, &"a".into(), "50", &options(&["GT"])),
+            RespData::Number(0)
+        );
+        assert_eq!(
+            expire(&mut state, &"a".into(), "50", &options(&["XX", "LT"])),
+            RespData::Number(1)
+        );
+        assert_eq!(ttl(&state, &"a".into()), RespData::Number(50));
+        assert_eq!(
+            expire(&mut state, &"a".into(), "50", &options(&["NX", "GT"])),
+            RespData::Error(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".into()
+            )
+        );
+    }
+
+    #[test]
+    fn expire_at_a_unix_time() {
+        let mut state = state_with("a");
+        let in_a_minute = (unix_millis_after(60_000) / 1000).to_string();
+
+        assert_eq!(
+            expireat(&mut state, &"a".into(), &in_a_minute, &[]),
+            RespData::Number(1)
+        );
+        assert!(matches!(
+            ttl(&state, &"a".into()),
+            RespData::Number(59..=60)
+        ));
+
+        assert_eq!(
+            pexpireat(&mut state, &"a".into(), "1000", &[]),
+            RespData::Number(1)
+        );
+        assert!(state.keyval.is_empty());
+    }
+
+    #[test]
+    fn persist_a_key() {
+        let mut state = state_with("a");
+        expire(&mut state, &"a".into(), "100", &[]);
+
+        assert_eq!(persist(&mut state, &"a".into()), RespData::Number(1));
+
+        assert_eq!(ttl(&state, &"a".into()), RespData::Number(-1));
+        assert_eq!(persist(&mut state, &"a".into()), RespData::Number(0));
+    }
+
+    #[test]
+    fn delete_the_key_when_the_ttl_is_not_positive() {
+        let mut state = state_with("a");
+
+        assert_eq!(
+            expire(&mut state, &"a".into(), "-1", &[]),
+            RespData::Number(1)
+        );
+
+        assert!(state.keyval.is_empty());
+    }
+
+    #[test]
+    fn remove_expired_keys_when_they_are_used() {
+        let mut state = state_with("a");
+        pexpire(&mut state, &"a".into(), "1", &[]);
+        std::thread::sleep(Duration::from_millis(5));
+
+        expire_keys(&mut state, &["a".into()]);
+
+        assert!(state.keyval.is_empty());
+        assert!(state.expires.is_empty());
+        assert_eq!(state.memory.expired_keys(), 1);
+    }
+
+    #[test]
+    fn remove_expired_keys_in_the_background() {
+        let mut state = CoreState::default();
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            state.keyval.insert(key.clone(), RespData::Number(i));
+            pexpire(&mut state, &key, "1", &[]);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+
+        active_expire(&mut state);
+
+        assert!(state.keyval.is_empty());
+    }
+
+    #[test]
+    fn refuse_expiry_times_that_are_not_numbers() {
+        let mut state = state_with("a");
+
+        assert_eq!(
+            expire(&mut state, &"a".into(), "soon", &[]),
+            RespData::Error("ERR value is not an integer or out of range".into())
+        );
+    }
+}
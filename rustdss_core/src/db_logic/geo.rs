@@ -0,0 +1,952 @@
+// Geospatial support - GEOADD, GEOPOS, GEODIST, GEOHASH, GEOSEARCH and GEOSEARCHSTORE
+//
+// Like Redis, a geo index is just a sorted set where each member's score is the 52 bit geohash of
+// its position, so a geohash cell is a contiguous range of scores. Searches look up the handful
+// of cells that cover the area being searched and then check the exact distance of each member
+// found in them.
+
+use super::sorted_sets::SortedSet;
+use crate::CoreState;
+use rustdss_data::{Key, RespData};
+
+const GEO_STEP_MAX: u32 = 26; // 26 * 2 = 52 bits
+const GEO_LAT_MIN: f64 = -85.051_128_78;
+const GEO_LAT_MAX: f64 = 85.051_128_78;
+const GEO_LONG_MIN: f64 = -180.0;
+const GEO_LONG_MAX: f64 = 180.0;
+const EARTH_RADIUS_IN_METERS: f64 = 6_372_797.560_856;
+const MERCATOR_MAX: f64 = 20_037_726.37;
+// Searches never look at more cells than this, the step is reduced until they fit
+const MAX_SEARCH_CELLS: u64 = 64;
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+fn error(message: &str) -> RespData {
+    RespData::Error(message.into())
+}
+
+fn syntax_error() -> RespData {
+    error("ERR syntax error")
+}
+
+fn not_a_float() -> RespData {
+    error("ERR value is not a valid float")
+}
+
+/// Spreads the bits of `x` out into the even bits and `y` into the odd bits
+fn interleave(x: u32, y: u32) -> u64 {
+    (0..32).fold(0, |bits, i| {
+        bits | (((x as u64 >> i) & 1) << (2 * i)) | (((y as u64 >> i) & 1) << (2 * i + 1))
+    })
+}
+
+fn deinterleave(bits: u64) -> (u32, u32) {
+    (0..32).fold((0, 0), |(x, y), i| {
+        (
+            x | (((bits >> (2 * i)) & 1) as u32) << i,
+            y | (((bits >> (2 * i + 1)) & 1) as u32) << i,
+        )
+    })
+}
+
+fn encode_with_range(lon: f64, lat: f64, lat_min: f64, lat_max: f64, step: u32) -> u64 {
+    let cells = (1u64 << step) as f64;
+    let lat_offset = ((lat - lat_min) / (lat_max - lat_min) * cells) as u32;
+    let lon_offset = ((lon - GEO_LONG_MIN) / (GEO_LONG_MAX - GEO_LONG_MIN) * cells) as u32;
+    interleave(lat_offset, lon_offset)
+}
+
+fn encode(lon: f64, lat: f64) -> u64 {
+    encode_with_range(lon, lat, GEO_LAT_MIN, GEO_LAT_MAX, GEO_STEP_MAX)
+}
+
+/// Returns the centre of the cell a full precision geohash refers to
+fn decode(bits: u64) -> (f64, f64) {
+    let (ilat, ilon) = deinterleave(bits);
+    let cells = (1u64 << GEO_STEP_MAX) as f64;
+    let lat_scale = GEO_LAT_MAX - GEO_LAT_MIN;
+    let lon_scale = GEO_LONG_MAX - GEO_LONG_MIN;
+
+    let lat_min = GEO_LAT_MIN + (ilat as f64 / cells) * lat_scale;
+    let lat_max = GEO_LAT_MIN + ((ilat as f64 + 1.0) / cells) * lat_scale;
+    let lon_min = GEO_LONG_MIN + (ilon as f64 / cells) * lon_scale;
+    let lon_max = GEO_LONG_MIN + ((ilon as f64 + 1.0) / cells) * lon_scale;
+
+    (
+        ((lon_min + lon_max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX),
+        ((lat_min + lat_max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX),
+    )
+}
+
+/// The standard 11 character base32 geohash, which uses the full -90..90 latitude range
+fn geohash_string(bits: u64) -> String {
+    let (lon, lat) = decode(bits);
+    let hash = encode_with_range(lon, lat, -90.0, 90.0, GEO_STEP_MAX);
+    (0..11)
+        .map(|i| {
+            // There are only 52 bits, so the last character is always a zero
+            let index = if i == 10 {
+                0
+            } else {
+                (hash >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEOHASH_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/// Great circle distance between two points using the haversine formula
+fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1r, lon1r) = (lat1.to_radians(), lon1.to_radians());
+    let (lat2r, lon2r) = (lat2.to_radians(), lon2.to_radians());
+    let u = ((lat2r - lat1r) / 2.0).sin();
+    let v = ((lon2r - lon1r) / 2.0).sin();
+    let a = u * u + lat1r.cos() * lat2r.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+fn format_distance(meters: f64, to_meters: f64) -> RespData {
+    RespData::BulkStr(format!("{:.4}", meters / to_meters))
+}
+
+fn format_coordinate(value: f64) -> RespData {
+    let formatted = format!("{:.17}", value);
+    RespData::BulkStr(
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+    )
+}
+
+fn parse_float(arg: &str) -> Result<f64, RespData> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|value| !value.is_nan())
+        .ok_or_else(not_a_float)
+}
+
+fn parse_unit(arg: &str) -> Result<f64, RespData> {
+    match arg.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(error(
+            "ERR unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    }
+}
+
+fn parse_lon_lat(lon: &str, lat: &str) -> Result<(f64, f64), RespData> {
+    let (lon, lat) = (parse_float(lon)?, parse_float(lat)?);
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&lon) || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&lat)
+    {
+        return Err(RespData::Error(format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            lon, lat
+        )));
+    }
+    Ok((lon, lat))
+}
+
+fn get_set<'a>(state: &'a CoreState, key: &str) -> Result<Option<&'a SortedSet>, RespData> {
+    match state.value_type(key) {
+        Some("zset") | None => Ok(state.sorted_sets.get(key)),
+        Some(_) => Err(RespData::wrong_type()),
+    }
+}
+
+/// The GEOADD that recreates a set, the decoded coordinates encode back to the same scores
+pub fn rebuild_commands(key: &str, set: &SortedSet) -> Vec<Vec<String>> {
+    let mut args = vec!["GEOADD".to_string(), key.into()];
+    for (member, score) in set.range_by_score(f64::NEG_INFINITY, f64::INFINITY) {
+        let (lon, lat) = decode(score as u64);
+        args.extend([lon.to_string(), lat.to_string(), member.into()]);
+    }
+    vec![args]
+}
+
+/// GEOADD key [NX|XX] [CH] longitude latitude member ...
+pub fn geoadd(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    let (mut nx, mut xx, mut ch) = (false, false, false);
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        match arg.to_lowercase().as_str() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "ch" => ch = true,
+            _ => break,
+        }
+        index += 1;
+    }
+    if nx && xx {
+        return error("ERR XX and NX options at the same time are not compatible");
+    }
+
+    let triplets = &args[index..];
+    if triplets.is_empty() || !triplets.len().is_multiple_of(3) {
+        return error("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ");
+    }
+
+    // Check every position before touching the set
+    let mut members = Vec::with_capacity(triplets.len() / 3);
+    for triplet in triplets.chunks(3) {
+        match parse_lon_lat(&triplet[0], &triplet[1]) {
+            Ok((lon, lat)) => members.push((triplet[2].clone(), encode(lon, lat) as f64)),
+            Err(e) => return e,
+        }
+    }
+
+    if let Err(e) = get_set(state, key) {
+        return e;
+    }
+    let set = state.sorted_sets.get_or_insert_default(key);
+
+    let mut changed = 0;
+    for (member, score) in members {
+        match set.score(&member) {
+            Some(_) if nx => continue,
+            None if xx => continue,
+            Some(previous) if previous == score => continue,
+            Some(_) => {
+                if ch {
+                    changed += 1;
+                }
+            }
+            None => changed += 1,
+        }
+        set.insert(member, score);
+    }
+
+    if set.is_empty() {
+        state.sorted_sets.remove(key);
+    }
+    RespData::Number(changed)
+}
+
+pub fn geopos(state: &CoreState, key: &Key, members: &[String]) -> RespData {
+    let set = match get_set(state, key) {
+        Ok(set) => set,
+        Err(e) => return e,
+    };
+    RespData::List(
+        members
+            .iter()
+            .map(|member| match set.and_then(|set| set.score(member)) {
+                Some(score) => {
+                    let (lon, lat) = decode(score as u64);
+                    RespData::List(vec![format_coordinate(lon), format_coordinate(lat)].into())
+                }
+                None => RespData::nil(),
+            })
+            .collect(),
+    )
+}
+
+/// GEODIST key member1 member2 [M|KM|FT|MI]
+pub fn geodist(state: &CoreState, key: &Key, args: &[String]) -> RespData {
+    if args.len() != 2 && args.len() != 3 {
+        return syntax_error();
+    }
+    let to_meters = match args.get(2).map(|unit| parse_unit(unit)) {
+        Some(Ok(to_meters)) => to_meters,
+        Some(Err(e)) => return e,
+        None => 1.0,
+    };
+    let set = match get_set(state, key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return RespData::nil(),
+        Err(e) => return e,
+    };
+
+    match (set.score(&args[0]), set.score(&args[1])) {
+        (Some(a), Some(b)) => {
+            let (lon1, lat1) = decode(a as u64);
+            let (lon2, lat2) = decode(b as u64);
+            format_distance(distance(lon1, lat1, lon2, lat2), to_meters)
+        }
+        _ => RespData::nil(),
+    }
+}
+
+pub fn geohash(state: &CoreState, key: &Key, members: &[String]) -> RespData {
+    let set = match get_set(state, key) {
+        Ok(set) => set,
+        Err(e) => return e,
+    };
+    RespData::List(
+        members
+            .iter()
+            .map(|member| match set.and_then(|set| set.score(member)) {
+                Some(score) => RespData::BulkStr(geohash_string(score as u64)),
+                None => RespData::nil(),
+            })
+            .collect(),
+    )
+}
+
+enum Origin {
+    Member(String),
+    LonLat(f64, f64),
+}
+
+enum Shape {
+    Radius(f64),
+    // Width and height
+    Box(f64, f64),
+}
+
+struct Search {
+    origin: Origin,
+    shape: Shape,
+    to_meters: f64,
+    descending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+struct Found {
+    member: String,
+    distance: f64,
+    score: f64,
+    lon: f64,
+    lat: f64,
+}
+
+fn parse_search(args: &[String], store: bool) -> Result<Search, RespData> {
+    let mut origin = None;
+    let mut shape = None;
+    let mut search = Search {
+        origin: Origin::LonLat(0.0, 0.0),
+        shape: Shape::Radius(0.0),
+        to_meters: 1.0,
+        descending: None,
+        count: None,
+        any: false,
+        with_coord: false,
+        with_dist: false,
+        with_hash: false,
+        store_dist: false,
+    };
+
+    let mut index = 0;
+    while index < args.len() {
+        let arg = |offset: usize| args.get(index + offset).ok_or_else(syntax_error);
+        let from_or_by_twice = |what: &str| {
+            RespData::Error(format!(
+                "ERR exactly one of {} can be specified for geosearch",
+                what
+            ))
+        };
+        match args[index].to_lowercase().as_str() {
+            "frommember" => {
+                if origin.is_some() {
+                    return Err(from_or_by_twice("FROMMEMBER or FROMLONLAT"));
+                }
+                origin = Some(Origin::Member(arg(1)?.clone()));
+                index += 2;
+            }
+            "fromlonlat" => {
+                if origin.is_some() {
+                    return Err(from_or_by_twice("FROMMEMBER or FROMLONLAT"));
+                }
+                let (lon, lat) = parse_lon_lat(arg(1)?, arg(2)?)?;
+                origin = Some(Origin::LonLat(lon, lat));
+                index += 3;
+            }
+            "byradius" => {
+                if shape.is_some() {
+                    return Err(from_or_by_twice("BYRADIUS and BYBOX"));
+                }
+                let radius = parse_float(arg(1)?)?;
+                if radius < 0.0 {
+                    return Err(error("ERR radius cannot be negative"));
+                }
+                search.to_meters = parse_unit(arg(2)?)?;
+                shape = Some(Shape::Radius(radius * search.to_meters));
+                index += 3;
+            }
+            "bybox" => {
+                if shape.is_some() {
+                    return Err(from_or_by_twice("BYRADIUS and BYBOX"));
+                }
+                let (width, height) = (parse_float(arg(1)?)?, parse_float(arg(2)?)?);
+                if width < 0.0 || height < 0.0 {
+                    return Err(error("ERR height or width cannot be negative"));
+                }
+                search.to_meters = parse_unit(arg(3)?)?;
+                shape = Some(Shape::Box(
+                    width * search.to_meters,
+                    height * search.to_meters,
+                ));
+                index += 4;
+            }
+            "asc" => {
+                search.descending = Some(false);
+                index += 1;
+            }
+            "desc" => {
+                search.descending = Some(true);
+                index += 1;
+            }
+            "count" => {
+                match arg(1)?.parse::<i64>() {
+                    Ok(count) if count > 0 => search.count = Some(count as usize),
+                    Ok(_) => return Err(error("ERR COUNT must be > 0")),
+                    Err(_) => return Err(error("ERR value is not an integer or out of range")),
+                }
+                index += 2;
+                if args
+                    .get(index)
+                    .map(|arg| arg.eq_ignore_ascii_case("any"))
+                    .unwrap_or(false)
+                {
+                    search.any = true;
+                    index += 1;
+                }
+            }
+            "withcoord" if !store => {
+                search.with_coord = true;
+                index += 1;
+            }
+            "withdist" if !store => {
+                search.with_dist = true;
+                index += 1;
+            }
+            "withhash" if !store => {
+                search.with_hash = true;
+                index += 1;
+            }
+            "storedist" if store => {
+                search.store_dist = true;
+                index += 1;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    search.origin = origin.ok_or_else(|| {
+        error("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch")
+    })?;
+    search.shape = shape.ok_or_else(|| {
+        error("ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch")
+    })?;
+    if search.any && search.count.is_none() {
+        return Err(error("ERR the ANY argument requires COUNT argument"));
+    }
+    Ok(search)
+}
+
+/// Picks the geohash precision so that a 3x3 block of cells covers the search radius
+fn estimate_step(mut range_meters: f64, lat: f64) -> u32 {
+    if range_meters == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while range_meters < MERCATOR_MAX {
+        range_meters *= 2.0;
+        step += 1;
+    }
+    // Make sure the range is included in most of the base cases
+    step -= 2;
+    // Cells get narrower towards the poles
+    if !(-66.0..=66.0).contains(&lat) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&lat) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u32
+}
+
+/// Returns the score ranges of every cell that overlaps the bounding box of the search
+fn covering_ranges(lon: f64, lat: f64, shape: &Shape) -> Vec<(f64, f64)> {
+    let (half_width, half_height) = match shape {
+        Shape::Radius(radius) => (*radius, *radius),
+        Shape::Box(width, height) => (width / 2.0, height / 2.0),
+    };
+
+    let lat_delta = (half_height / EARTH_RADIUS_IN_METERS).to_degrees();
+    let widest_lat = (lat.abs() + lat_delta).min(90.0);
+    let lon_delta = if widest_lat >= 90.0 {
+        360.0
+    } else {
+        (half_width / EARTH_RADIUS_IN_METERS / widest_lat.to_radians().cos()).to_degrees()
+    };
+
+    let min_lat = (lat - lat_delta).max(GEO_LAT_MIN);
+    let max_lat = (lat + lat_delta).min(GEO_LAT_MAX);
+
+    let radius = (half_width * half_width + half_height * half_height).sqrt();
+    let mut step = estimate_step(radius, lat);
+    loop {
+        let cells = 1i64 << step;
+        let lat_cell = (GEO_LAT_MAX - GEO_LAT_MIN) / cells as f64;
+        let lon_cell = (GEO_LONG_MAX - GEO_LONG_MIN) / cells as f64;
+
+        let lat_index =
+            |lat: f64| (((lat - GEO_LAT_MIN) / lat_cell).floor() as i64).clamp(0, cells - 1);
+        let (lat_from, lat_to) = (lat_index(min_lat), lat_index(max_lat));
+
+        let (lon_from, lon_to) = if lon_delta >= 180.0 {
+            (0, cells - 1)
+        } else {
+            let from = ((lon - lon_delta - GEO_LONG_MIN) / lon_cell).floor() as i64;
+            let to = ((lon + lon_delta - GEO_LONG_MIN) / lon_cell).floor() as i64;
+            if to - from >= cells {
+                (0, cells - 1)
+            } else {
+                (from, to)
+            }
+        };
+
+        let total = ((lat_to - lat_from + 1) * (lon_to - lon_from + 1)) as u64;
+        if total > MAX_SEARCH_CELLS && step > 1 {
+            step -= 1;
+            continue;
+        }
+
+        let shift = 2 * (GEO_STEP_MAX - step);
+        let mut ranges = vec![];
+        for ilat in lat_from..=lat_to {
+            for ilon in lon_from..=lon_to {
+                // Searches that cross the antimeridian wrap around
+                let ilon = ilon.rem_euclid(cells);
+                let bits = interleave(ilat as u32, ilon as u32);
+                ranges.push(((bits << shift) as f64, ((bits + 1) << shift) as f64));
+            }
+        }
+        ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ranges.dedup();
+        return ranges;
+    }
+}
+
+fn search(set: &SortedSet, lon: f64, lat: f64, search: &Search) -> Vec<Found> {
+    let mut found = vec![];
+    for (min, max) in covering_ranges(lon, lat, &search.shape) {
+        for (member, score) in set.range_by_score(min, max) {
+            let (point_lon, point_lat) = decode(score as u64);
+            let dist = distance(lon, lat, point_lon, point_lat);
+            let inside = match search.shape {
+                Shape::Radius(radius) => dist <= radius,
+                Shape::Box(width, height) => {
+                    let lat_distance =
+                        EARTH_RADIUS_IN_METERS * (point_lat.to_radians() - lat.to_radians()).abs();
+                    lat_distance <= height / 2.0
+                        && distance(lon, point_lat, point_lon, point_lat) <= width / 2.0
+                }
+            };
+            if inside {
+                found.push(Found {
+                    member: member.into(),
+                    distance: dist,
+                    score,
+                    lon: point_lon,
+                    lat: point_lat,
+                });
+                if search.any && Some(found.len()) == search.count {
+                    return found;
+                }
+            }
+        }
+    }
+    found
+}
+
+fn run_search(state: &CoreState, key: &Key, query: &Search) -> Result<Vec<Found>, RespData> {
+    let set = match get_set(state, key)? {
+        Some(set) => set,
+        None => return Ok(vec![]),
+    };
+
+    let (lon, lat) = match &query.origin {
+        Origin::LonLat(lon, lat) => (*lon, *lat),
+        Origin::Member(member) => match set.score(member) {
+            Some(score) => decode(score as u64),
+            None => return Err(error("ERR could not decode requested zset member")),
+        },
+    };
+
+    let mut found = search(set, lon, lat, query);
+    // COUNT on its own means the closest ones
+    let descending = match (query.descending, query.count, query.any) {
+        (None, Some(_), false) => Some(false),
+        (descending, _, _) => descending,
+    };
+    if let Some(descending) = descending {
+        found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if descending {
+            found.reverse();
+        }
+    }
+    if let Some(count) = query.count {
+        found.truncate(count);
+    }
+    Ok(found)
+}
+
+/// GEOSEARCH key FROMMEMBER member|FROMLONLAT lon lat BYRADIUS radius unit|BYBOX width height
+/// unit [ASC|DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+pub fn geosearch(state: &CoreState, key: &Key, args: &[String]) -> RespData {
+    let query = match parse_search(args, false) {
+        Ok(query) => query,
+        Err(e) => return e,
+    };
+    let found = match run_search(state, key, &query) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+
+    let plain = !(query.with_coord || query.with_dist || query.with_hash);
+    RespData::List(
+        found
+            .into_iter()
+            .map(|item| {
+                if plain {
+                    return RespData::BulkStr(item.member);
+                }
+                let mut reply = vec![RespData::BulkStr(item.member)];
+                if query.with_dist {
+                    reply.push(format_distance(item.distance, query.to_meters));
+                }
+                if query.with_hash {
+                    reply.push(RespData::Number(item.score as i64));
+                }
+                if query.with_coord {
+                    reply.push(RespData::List(
+                        vec![format_coordinate(item.lon), format_coordinate(item.lat)].into(),
+                    ));
+                }
+                RespData::List(reply.into())
+            })
+            .collect(),
+    )
+}
+
+/// GEOSEARCHSTORE destination source ... [STOREDIST]
+pub fn geosearchstore(
+    state: &mut CoreState,
+    dest: &Key,
+    source: &Key,
+    args: &[String],
+) -> RespData {
+    let query = match parse_search(args, true) {
+        Ok(query) => query,
+        Err(e) => return e,
+    };
+    let found = match run_search(state, source, &query) {
+        Ok(found) => found,
+        Err(e) => return e,
+    };
+
+    state.remove_key(dest);
+    if found.is_empty() {
+        return RespData::Number(0);
+    }
+
+    let mut set = SortedSet::default();
+    for item in found {
+        let score = if query.store_dist {
+            item.distance / query.to_meters
+        } else {
+            item.score
+        };
+        set.insert(item.member, score);
+    }
+    let stored = set.len();
+    state.sorted_sets.insert(dest.clone(), set);
+    RespData::Number(stored as i64)
+}
+
+#[cfg(test)]
+fn sicily() -> CoreState {
+    let mut state = CoreState::default();
+    geoadd(
+        &mut state,
+        &"Sicily".into(),
+        &crate::test_helpers::args(&[
+            "13.361389",
+            "38.115556",
+            "Palermo",
+            "15.087269",
+            "37.502669",
+            "Catania",
+        ]),
+    );
+    state
+}
+
+#[cfg(test)]
+mod geoadd_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn store_members_with_their_geohash_as_the_score() {
+        let state = sicily();
+
+        // These are the scores Redis gives the same members
+        let set = state.sorted_sets.get("Sicily").unwrap();
+        assert_eq!(set.score("Palermo"), Some(3_479_099_956_230_698.0));
+        assert_eq!(set.score("Catania"), Some(3_479_447_370_796_909.0));
+    }
+
+    #[test]
+    fn honour_nx_xx_and_ch() {
+        let mut state = sicily();
+
+        assert_eq!(
+            geoadd(
+                &mut state,
+                &"Sicily".into(),
+                &args(&["XX", "1", "1", "Rome"])
+            ),
+            RespData::Number(0)
+        );
+        assert_eq!(
+            geoadd(
+                &mut state,
+                &"Sicily".into(),
+                &args(&["NX", "1", "1", "Palermo"])
+            ),
+            RespData::Number(0)
+        );
+        assert_eq!(
+            geoadd(
+                &mut state,
+                &"Sicily".into(),
+                &args(&["CH", "1", "1", "Palermo"])
+            ),
+            RespData::Number(1)
+        );
+        assert_eq!(
+            geoadd(
+                &mut state,
+                &"Sicily".into(),
+                &args(&["NX", "XX", "1", "1", "a"])
+            ),
+            error("ERR XX and NX options at the same time are not compatible")
+        );
+    }
+
+    #[test]
+    fn refuse_invalid_positions() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            geoadd(&mut state, &"g".into(), &args(&["181", "0", "a"])),
+            error("ERR invalid longitude,latitude pair 181.000000,0.000000")
+        );
+        assert!(state.sorted_sets.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod geo_queries_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn measure_the_distance_between_members() {
+        let state = sicily();
+
+        assert_eq!(
+            geodist(&state, &"Sicily".into(), &args(&["Palermo", "Catania"])),
+            RespData::BulkStr("166274.1516".into())
+        );
+        assert_eq!(
+            geodist(
+                &state,
+                &"Sicily".into(),
+                &args(&["Palermo", "Catania", "km"])
+            ),
+            RespData::BulkStr("166.2742".into())
+        );
+        assert_eq!(
+            geodist(&state, &"Sicily".into(), &args(&["Palermo", "Nowhere"])),
+            RespData::nil()
+        );
+    }
+
+    #[test]
+    fn return_geohash_strings() {
+        let state = sicily();
+
+        assert_eq!(
+            geohash(&state, &"Sicily".into(), &args(&["Palermo", "Catania"])),
+            RespData::List(
+                vec![
+                    RespData::BulkStr("sqc8b49rny0".into()),
+                    RespData::BulkStr("sqdtr74hyu0".into())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn return_positions_close_to_the_originals() {
+        let state = sicily();
+
+        let response = geopos(&state, &"Sicily".into(), &args(&["Palermo", "Nowhere"]));
+
+        if let RespData::List(positions) = response {
+            if let RespData::List(position) = &positions[0] {
+                if let (RespData::BulkStr(lon), RespData::BulkStr(lat)) =
+                    (&position[0], &position[1])
+                {
+                    assert!((lon.parse::<f64>().unwrap() - 13.361389).abs() < 0.00001);
+                    assert!((lat.parse::<f64>().unwrap() - 38.115556).abs() < 0.00001);
+                    assert_eq!(positions[1], RespData::nil());
+                    return;
+                }
+            }
+        }
+        panic!("unexpected GEOPOS reply");
+    }
+}
+
+#[cfg(test)]
+mod geosearch_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn find_members_within_a_radius() {
+        let state = sicily();
+
+        assert_eq!(
+            geosearch(
+                &state,
+                &"Sicily".into(),
+                &args(&["FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "ASC"])
+            ),
+            RespData::List(
+                vec![
+                    RespData::BulkStr("Catania".into()),
+                    RespData::BulkStr("Palermo".into())
+                ]
+                .into()
+            )
+        );
+        assert_eq!(
+            geosearch(
+                &state,
+                &"Sicily".into(),
+                &args(&["FROMLONLAT", "15", "37", "BYRADIUS", "100", "km"])
+            ),
+            RespData::List(vec![RespData::BulkStr("Catania".into())].into())
+        );
+    }
+
+    #[test]
+    fn find_members_within_a_box() {
+        let state = sicily();
+
+        assert_eq!(
+            geosearch(
+                &state,
+                &"Sicily".into(),
+                &args(&[
+                    "FROMMEMBER",
+                    "Palermo",
+                    "BYBOX",
+                    "400",
+                    "400",
+                    "km",
+                    "DESC",
+                    "WITHDIST"
+                ])
+            ),
+            RespData::List(
+                vec![
+                    RespData::List(
+                        vec![
+                            RespData::BulkStr("Catania".into()),
+                            RespData::BulkStr("166.2742".into())
+                        ]
+                        .into()
+                    ),
+                    RespData::List(
+                        vec![
+                            RespData::BulkStr("Palermo".into()),
+                            RespData::BulkStr("0.0000".into())
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn search_across_the_antimeridian() {
+        let mut state = CoreState::default();
+        geoadd(
+            &mut state,
+            &"g".into(),
+            &args(&["179.99", "0", "east", "-179.99", "0", "west"]),
+        );
+
+        let response = geosearch(
+            &state,
+            &"g".into(),
+            &args(&["FROMLONLAT", "179.999", "0", "BYRADIUS", "10", "km", "ASC"]),
+        );
+
+        assert_eq!(
+            response,
+            RespData::List(
+                vec![
+                    RespData::BulkStr("east".into()),
+                    RespData::BulkStr("west".into())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn store_the_results() {
+        let mut state = sicily();
+
+        let response = geosearchstore(
+            &mut state,
+            &"dest".into(),
+            &"Sicily".into(),
+            &args(&[
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "100",
+                "km",
+                "STOREDIST",
+            ]),
+        );
+
+        assert_eq!(response, RespData::Number(1));
+        let score = state.sorted_sets["dest"].score("Catania").unwrap();
+        assert!((score - 56.4413).abs() < 0.001);
+    }
+
+    #[test]
+    fn need_an_origin_and_a_shape() {
+        let state = sicily();
+
+        assert_eq!(
+            geosearch(&state, &"Sicily".into(), &args(&["BYRADIUS", "1", "km"])),
+            error("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch")
+        );
+        assert_eq!(
+            geosearch(&state, &"Sicily".into(), &args(&["FROMMEMBER", "Palermo"])),
+            error("ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch")
+        );
+    }
+}
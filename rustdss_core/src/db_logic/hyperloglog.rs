@@ -0,0 +1,618 @@
+// HyperLogLog support - PFADD, PFCOUNT, PFMERGE
+//
+// These use exactly the same representation as Redis (the "HYLL" header followed by either the
+// sparse or the dense register encoding) and store it as a plain string value, so a value can be
+// moved between Redis and rustdss with GET/SET and still be counted.
+//
+// Strings coming off the wire hold one char per byte, so the HLL bytes are stored the same way.
+
+use crate::CoreState;
+use rustdss_data::{Key, RespData};
+
+const HLL_P: u32 = 14; // Bits of the hash used to pick a register
+const HLL_Q: usize = 64 - HLL_P as usize; // Bits of the hash used to count leading zeros
+const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_P_MASK: u64 = HLL_REGISTERS as u64 - 1;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const HLL_SPARSE_MAX_BYTES: usize = 3000;
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+const HLL_HASH_SEED: u64 = 0xadc8_3b19;
+
+fn invalid_hll() -> RespData {
+    RespData::Error("WRONGTYPE Key is not a valid HyperLogLog string value.".into())
+}
+
+fn corrupted_hll() -> RespData {
+    RespData::Error("INVALIDOBJ Corrupted HLL object detected".into())
+}
+
+fn to_bytes(string: &str) -> Option<Vec<u8>> {
+    string
+        .chars()
+        .map(|c| {
+            if (c as u32) <= 0xff {
+                Some(c as u32 as u8)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+/// MurmurHash2, 64 bit version, as used by Redis to hash HLL elements
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(chunk);
+        let mut k = u64::from_le_bytes(buf);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Returns the register index and the run length of zeros (+1) for an element
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, HLL_HASH_SEED);
+    let index = (hash & HLL_P_MASK) as usize;
+    // Make sure the loop terminates and the count fits in a register
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// An HLL decoded into one byte per register
+struct Hll {
+    registers: Vec<u8>,
+    dense: bool,
+}
+
+impl Hll {
+    fn empty() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS],
+            dense: false,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, RespData> {
+        if bytes.len() < HLL_HDR_SIZE || &bytes[0..4] != b"HYLL" {
+            return Err(invalid_hll());
+        }
+
+        match bytes[4] {
+            HLL_DENSE if bytes.len() == HLL_DENSE_SIZE => Ok(Self {
+                registers: decode_dense(&bytes[HLL_HDR_SIZE..]),
+                dense: true,
+            }),
+            HLL_SPARSE => decode_sparse(&bytes[HLL_HDR_SIZE..])
+                .map(|registers| Self {
+                    registers,
+                    dense: false,
+                })
+                .ok_or_else(corrupted_hll),
+            _ => Err(invalid_hll()),
+        }
+    }
+
+    fn encode(&mut self) -> Vec<u8> {
+        let mut output = vec![0; HLL_HDR_SIZE];
+        output[0..4].copy_from_slice(b"HYLL");
+
+        if !self.dense {
+            let sparse = encode_sparse(&self.registers);
+            match sparse {
+                Some(body) if HLL_HDR_SIZE + body.len() <= HLL_SPARSE_MAX_BYTES => {
+                    output[4] = HLL_SPARSE;
+                    output.extend_from_slice(&body);
+                    return output;
+                }
+                // Too big or too large a value for the sparse encoding - promote it
+                _ => self.dense = true,
+            }
+        }
+
+        output[4] = HLL_DENSE;
+        output.extend_from_slice(&encode_dense(&self.registers));
+        output
+    }
+
+    /// Returns true when the register was changed
+    fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern_len(element);
+        if self.registers[index] < count {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn merge(&mut self, other: &Hll) {
+        for (mine, theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *mine = (*mine).max(*theirs);
+        }
+        self.dense = self.dense || other.dense;
+    }
+
+    /// The improved estimator from Otmar Ertl's "New cardinality estimation algorithms for
+    /// HyperLogLog sketches", same as Redis.
+    fn count(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let mut histogram = [0usize; 64];
+        for register in &self.registers {
+            histogram[*register as usize] += 1;
+        }
+
+        let mut z = m * tau((m - histogram[HLL_Q + 1] as f64) / m);
+        for j in (1..=HLL_Q).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        (HLL_ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn decode_dense(body: &[u8]) -> Vec<u8> {
+    (0..HLL_REGISTERS)
+        .map(|regnum| {
+            let byte = regnum * HLL_BITS / 8;
+            let fb = (regnum * HLL_BITS) & 7;
+            let b0 = body[byte] as u16;
+            let b1 = *body.get(byte + 1).unwrap_or(&0) as u16;
+            (((b0 >> fb) | (b1 << (8 - fb))) & HLL_REGISTER_MAX as u16) as u8
+        })
+        .collect()
+}
+
+fn encode_dense(registers: &[u8]) -> Vec<u8> {
+    let mut body = vec![0u8; HLL_DENSE_SIZE - HLL_HDR_SIZE];
+    for (regnum, value) in registers.iter().enumerate() {
+        let byte = regnum * HLL_BITS / 8;
+        let fb = (regnum * HLL_BITS) & 7;
+        let value = *value as u16 & HLL_REGISTER_MAX as u16;
+        body[byte] |= (value << fb) as u8;
+        if let Some(next) = body.get_mut(byte + 1) {
+            *next |= (value >> (8 - fb)) as u8;
+        }
+    }
+    body
+}
+
+/// Sparse opcodes:
+/// - ZERO:  00xxxxxx          - a run of (xxxxxx + 1) empty registers
+/// - XZERO: 01xxxxxx yyyyyyyy - a run of (xxxxxxyyyyyyyy + 1) empty registers
+/// - VAL:   1vvvvvxx          - a run of (xx + 1) registers set to (vvvvv + 1)
+fn decode_sparse(body: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = Vec::with_capacity(HLL_REGISTERS);
+    let mut bytes = body.iter();
+
+    while let Some(opcode) = bytes.next() {
+        let (value, len) = if opcode & 0xc0 == 0 {
+            (0, (opcode & 0x3f) as usize + 1)
+        } else if opcode & 0xc0 == 0x40 {
+            let low = *bytes.next()? as usize;
+            (0, ((((opcode & 0x3f) as usize) << 8) | low) + 1)
+        } else {
+            (((opcode >> 2) & 0x1f) + 1, (opcode & 0x3) as usize + 1)
+        };
+
+        if registers.len() + len > HLL_REGISTERS {
+            return None;
+        }
+        registers.extend(std::iter::repeat_n(value, len));
+    }
+
+    if registers.len() == HLL_REGISTERS {
+        Some(registers)
+    } else {
+        None
+    }
+}
+
+/// Returns None when a register holds a value that can't be represented sparsely
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut index = 0;
+
+    while index < registers.len() {
+        let value = registers[index];
+        let run = registers[index..]
+            .iter()
+            .take_while(|item| **item == value)
+            .count();
+
+        if value == 0 {
+            let mut remaining = run;
+            while remaining > 0 {
+                if remaining > HLL_SPARSE_ZERO_MAX_LEN {
+                    let len = remaining.min(HLL_SPARSE_XZERO_MAX_LEN);
+                    body.push((((len - 1) >> 8) as u8) | 0x40);
+                    body.push(((len - 1) & 0xff) as u8);
+                    remaining -= len;
+                } else {
+                    body.push((remaining - 1) as u8);
+                    remaining = 0;
+                }
+            }
+        } else if value > HLL_SPARSE_VAL_MAX_VALUE {
+            return None;
+        } else {
+            let mut remaining = run;
+            while remaining > 0 {
+                let len = remaining.min(HLL_SPARSE_VAL_MAX_LEN);
+                body.push(0x80 | ((value - 1) << 2) | (len - 1) as u8);
+                remaining -= len;
+            }
+        }
+
+        index += run;
+    }
+
+    Some(body)
+}
+
+fn cached_cardinality(bytes: &[u8]) -> Option<u64> {
+    if bytes[15] & 0x80 == 0 {
+        let mut card = [0; 8];
+        card.copy_from_slice(&bytes[8..16]);
+        Some(u64::from_le_bytes(card))
+    } else {
+        None
+    }
+}
+
+fn invalidate_cache(bytes: &mut [u8]) {
+    bytes[15] |= 0x80;
+}
+
+/// Loads the raw HLL bytes at a key, if the key exists
+fn load(state: &CoreState, key: &Key) -> Result<Option<Vec<u8>>, RespData> {
+    if state.has_typed_value(key) {
+        return Err(RespData::wrong_type());
+    }
+    match state.keyval.get(key) {
+        Some(RespData::BulkStr(value)) | Some(RespData::SimpleStr(value)) => {
+            to_bytes(value).map(Some).ok_or_else(invalid_hll)
+        }
+        Some(RespData::List(_)) => Err(RespData::wrong_type()),
+        Some(_) => Err(invalid_hll()),
+        None => Ok(None),
+    }
+}
+
+fn store(state: &mut CoreState, key: &Key, bytes: &[u8]) {
+    state
+        .keyval
+        .insert(key.clone(), RespData::BulkStr(from_bytes(bytes)));
+}
+
+pub fn pfadd(state: &mut CoreState, key: &Key, elements: &[String]) -> RespData {
+    let (mut hll, mut changed) = match load(state, key) {
+        Ok(Some(bytes)) => match Hll::decode(&bytes) {
+            Ok(hll) => (hll, false),
+            Err(e) => return e,
+        },
+        Ok(None) => (Hll::empty(), true),
+        Err(e) => return e,
+    };
+
+    for element in elements {
+        let element = to_bytes(element).unwrap_or_else(|| element.as_bytes().to_vec());
+        changed |= hll.add(&element);
+    }
+
+    if changed {
+        let mut bytes = hll.encode();
+        invalidate_cache(&mut bytes);
+        store(state, key, &bytes);
+        RespData::Number(1)
+    } else {
+        RespData::Number(0)
+    }
+}
+
+pub fn pfcount(state: &mut CoreState, keys: &[Key]) -> RespData {
+    if let [key] = keys {
+        match load(state, key) {
+            Ok(Some(mut bytes)) => {
+                let hll = match Hll::decode(&bytes) {
+                    Ok(hll) => hll,
+                    Err(e) => return e,
+                };
+                if let Some(card) = cached_cardinality(&bytes) {
+                    return RespData::Number(card as i64);
+                }
+                let card = hll.count();
+                bytes[8..16].copy_from_slice(&card.to_le_bytes());
+                store(state, key, &bytes);
+                RespData::Number(card as i64)
+            }
+            Ok(None) => RespData::Number(0),
+            Err(e) => e,
+        }
+    } else {
+        // Counting several keys counts their union, without touching any of them
+        let mut union = Hll::empty();
+        for key in keys {
+            match load(state, key) {
+                Ok(Some(bytes)) => match Hll::decode(&bytes) {
+                    Ok(hll) => union.merge(&hll),
+                    Err(e) => return e,
+                },
+                Ok(None) => {}
+                Err(e) => return e,
+            }
+        }
+        RespData::Number(union.count() as i64)
+    }
+}
+
+pub fn pfmerge(state: &mut CoreState, dest: &Key, sources: &[Key]) -> RespData {
+    let mut merged = Hll::empty();
+    for key in std::iter::once(dest).chain(sources.iter()) {
+        match load(state, key) {
+            Ok(Some(bytes)) => match Hll::decode(&bytes) {
+                Ok(hll) => merged.merge(&hll),
+                Err(e) => return e,
+            },
+            Ok(None) => {}
+            Err(e) => return e,
+        }
+    }
+
+    let mut bytes = merged.encode();
+    invalidate_cache(&mut bytes);
+    store(state, dest, &bytes);
+    RespData::ok()
+}
+
+#[cfg(test)]
+mod pfadd_should {
+    use super::*;
+    use crate::CoreState;
+    use std::collections::HashMap;
+
+    fn elements(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("element:{}", i)).collect()
+    }
+
+    #[test]
+    fn create_a_sparse_hll_compatible_with_redis() {
+        let mut state = CoreState::default();
+
+        let response = pfadd(&mut state, &"hll".into(), &[]);
+
+        assert_eq!(response, RespData::Number(1));
+        // This is byte for byte what Redis creates for `PFADD hll`
+        let mut expected = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        expected.extend_from_slice(b"\x7f\xff");
+        assert_eq!(
+            state.keyval.get("hll"),
+            Some(&RespData::BulkStr(from_bytes(&expected)))
+        );
+    }
+
+    #[test]
+    fn only_report_changes_when_a_register_is_altered() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            pfadd(&mut state, &"hll".into(), &["a".into(), "b".into()]),
+            RespData::Number(1)
+        );
+        assert_eq!(
+            pfadd(&mut state, &"hll".into(), &["a".into(), "b".into()]),
+            RespData::Number(0)
+        );
+    }
+
+    #[test]
+    fn promote_to_dense_when_the_sparse_encoding_gets_too_big() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"hll".into(), &elements(0..10));
+        let sparse = load(&state, &"hll".into()).unwrap().unwrap();
+        assert_eq!(sparse[4], HLL_SPARSE);
+
+        pfadd(&mut state, &"hll".into(), &elements(10..5000));
+        let dense = load(&state, &"hll".into()).unwrap().unwrap();
+        assert_eq!(dense[4], HLL_DENSE);
+        assert_eq!(dense.len(), HLL_DENSE_SIZE);
+    }
+
+    #[test]
+    fn refuse_values_that_are_not_hlls() {
+        let mut keyval = HashMap::new();
+        keyval.insert("str".into(), RespData::BulkStr("hello".into()));
+        keyval.insert("list".into(), RespData::List(vec![].into()));
+        let mut state = CoreState::from(keyval);
+
+        assert_eq!(
+            pfadd(&mut state, &"str".into(), &["a".into()]),
+            invalid_hll()
+        );
+        assert_eq!(
+            pfadd(&mut state, &"list".into(), &["a".into()]),
+            RespData::wrong_type()
+        );
+    }
+}
+
+#[cfg(test)]
+mod pfcount_should {
+    use super::*;
+    use crate::CoreState;
+    use std::collections::HashMap;
+
+    fn elements(prefix: &str, count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("{}:{}", prefix, i)).collect()
+    }
+
+    fn assert_close(response: RespData, expected: f64) {
+        if let RespData::Number(count) = response {
+            let error = (count as f64 - expected).abs() / expected;
+            // The standard error is 0.81%, allow a bit of slack
+            assert!(error < 0.03, "{} is too far from {}", count, expected);
+        } else {
+            panic!("expected a number, got {:?}", response);
+        }
+    }
+
+    #[test]
+    fn count_small_sets_exactly() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"hll".into(), &elements("a", 10));
+
+        assert_eq!(pfcount(&mut state, &["hll".into()]), RespData::Number(10));
+        assert_eq!(pfcount(&mut state, &["nope".into()]), RespData::Number(0));
+    }
+
+    #[test]
+    fn estimate_large_sets_within_the_error_bounds() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"hll".into(), &elements("a", 100_000));
+
+        assert_close(pfcount(&mut state, &["hll".into()]), 100_000.0);
+    }
+
+    #[test]
+    fn cache_the_cardinality_in_the_header() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"hll".into(), &elements("a", 10));
+        let before = load(&state, &"hll".into()).unwrap().unwrap();
+        assert_eq!(cached_cardinality(&before), None);
+
+        pfcount(&mut state, &["hll".into()]);
+        let after = load(&state, &"hll".into()).unwrap().unwrap();
+        assert_eq!(cached_cardinality(&after), Some(10));
+    }
+
+    #[test]
+    fn count_the_union_of_several_keys() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"a".into(), &elements("x", 3000));
+        pfadd(&mut state, &"b".into(), &elements("x", 6000));
+
+        assert_close(pfcount(&mut state, &["a".into(), "b".into()]), 6000.0);
+    }
+
+    #[test]
+    fn report_corrupted_hlls() {
+        let mut keyval = HashMap::new();
+        let mut bytes = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        bytes.push(0x00);
+        keyval.insert("hll".into(), RespData::BulkStr(from_bytes(&bytes)));
+        let mut state = CoreState::from(keyval);
+
+        assert_eq!(pfcount(&mut state, &["hll".into()]), corrupted_hll());
+    }
+}
+
+#[cfg(test)]
+mod pfmerge_should {
+    use super::*;
+    use crate::CoreState;
+
+    #[test]
+    fn merge_the_registers_of_every_source() {
+        let mut state = CoreState::default();
+
+        pfadd(&mut state, &"a".into(), &["1".into(), "2".into()]);
+        pfadd(&mut state, &"b".into(), &["2".into(), "3".into()]);
+
+        assert_eq!(
+            pfmerge(&mut state, &"dest".into(), &["a".into(), "b".into()]),
+            RespData::ok()
+        );
+        assert_eq!(pfcount(&mut state, &["dest".into()]), RespData::Number(3));
+    }
+
+    #[test]
+    fn keep_dense_sources_dense() {
+        let mut state = CoreState::default();
+
+        let elements: Vec<String> = (0..5000).map(|i| i.to_string()).collect();
+        pfadd(&mut state, &"big".into(), &elements);
+        pfadd(&mut state, &"small".into(), &["a".into()]);
+
+        pfmerge(&mut state, &"dest".into(), &["small".into(), "big".into()]);
+
+        let dest = load(&state, &"dest".into()).unwrap().unwrap();
+        assert_eq!(dest[4], HLL_DENSE);
+    }
+}
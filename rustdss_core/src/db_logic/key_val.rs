@@ -1,11 +1,186 @@
+use crate::db_logic::expiry;
 use crate::CoreState;
-use rustdss_data::RespData;
+use rustdss_data::{Key, RespData};
+use std::time::{Duration, Instant};
 
-pub fn set(state: &mut CoreState, key: String, value: RespData) -> RespData {
+fn syntax_error() -> RespData {
+    RespData::Error("ERR syntax error".into())
+}
+
+/// The SET options that were given: NX or XX, GET, and EX, PX, EXAT, PXAT or KEEPTTL
+#[derive(Default)]
+struct SetOptions {
+    nx: bool,
+    xx: bool,
+    get: bool,
+    keep_ttl: bool,
+    expires_at: Option<Instant>,
+}
+
+impl SetOptions {
+    fn parse(options: &[String]) -> Result<Self, RespData> {
+        let mut parsed = Self::default();
+        let mut has_expiry = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "NX" if !parsed.xx => parsed.nx = true,
+                "XX" if !parsed.nx => parsed.xx = true,
+                "GET" => parsed.get = true,
+                "KEEPTTL" if !has_expiry => {
+                    parsed.keep_ttl = true;
+                    has_expiry = true;
+                }
+                unit @ ("EX" | "PX" | "EXAT" | "PXAT") if !has_expiry => {
+                    let amount = options.next().ok_or_else(syntax_error)?;
+                    parsed.expires_at = Some(Self::expires_at(unit, amount)?);
+                    has_expiry = true;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn expires_at(unit: &str, amount: &str) -> Result<Instant, RespData> {
+        let invalid = || RespData::Error("ERR invalid expire time in 'set' command".into());
+        let amount = match amount.parse::<i64>() {
+            Ok(amount) if amount <= 0 => return Err(invalid()),
+            Ok(amount) => amount as u64,
+            Err(_) => {
+                return Err(RespData::Error(
+                    "ERR value is not an integer or out of range".into(),
+                ))
+            }
+        };
+        match unit {
+            "EX" => Instant::now().checked_add(Duration::from_secs(amount)),
+            "PX" => Instant::now().checked_add(Duration::from_millis(amount)),
+            "EXAT" => amount
+                .checked_mul(1000)
+                .and_then(expiry::instant_at_unix_millis),
+            _ => expiry::instant_at_unix_millis(amount),
+        }
+        .ok_or_else(invalid)
+    }
+}
+
+pub fn set(state: &mut CoreState, key: String, value: RespData, options: &[String]) -> RespData {
+    let options = match SetOptions::parse(options) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+    let old = if options.get {
+        match get(state, key.clone()) {
+            error @ RespData::Error(_) => return error,
+            old => old,
+        }
+    } else {
+        RespData::ok()
+    };
+    let exists = state.value_type(&key).is_some();
+    if (options.nx && exists) || (options.xx && !exists) {
+        return if options.get { old } else { RespData::nil() };
+    }
+
+    let kept_ttl = match options.keep_ttl {
+        true => state.expires.get(&key).copied(),
+        false => None,
+    };
+    // SET overwrites whatever type of value was there before
+    if state.has_typed_value(&key) {
+        state.remove_key(&key);
+    }
+    // SET replaces the TTL along with the value, unless it's told to keep it
+    state.expires.remove(&key);
+    if let Some(expires_at) = options.expires_at.or(kept_ttl) {
+        state.expires.insert(key.clone(), expires_at);
+    }
     state.keyval.insert(key, value);
-    RespData::ok()
+    old
 }
 
 pub fn get(state: &CoreState, key: String) -> RespData {
-    state.keyval.get(&key).unwrap_or(&RespData::nil()).clone()
+    if state.has_typed_value(&key) {
+        return RespData::wrong_type();
+    }
+    match state.keyval.get(&key) {
+        Some(RespData::List(_)) => RespData::wrong_type(),
+        value => value.cloned().unwrap_or(RespData::nil()),
+    }
+}
+
+/// Like GET for each key, except that keys holding other types are nil rather than an error
+pub fn mget(state: &CoreState, keys: &[Key]) -> RespData {
+    RespData::List(
+        keys.iter()
+            .map(|key| match state.keyval.get(key) {
+                Some(RespData::List(_)) | None => RespData::nil(),
+                Some(value) => value.clone(),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod set_should {
+    use super::*;
+
+    fn set_with(state: &mut CoreState, value: &str, options: &[&str]) -> RespData {
+        let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+        set(state, "a".into(), RespData::BulkStr(value.into()), &options)
+    }
+
+    #[test]
+    fn only_set_when_nx_or_xx_allow_it() {
+        let mut state = CoreState::default();
+
+        assert_eq!(set_with(&mut state, "1", &["XX"]), RespData::nil());
+        assert_eq!(set_with(&mut state, "1", &["NX"]), RespData::ok());
+        assert_eq!(set_with(&mut state, "2", &["NX"]), RespData::nil());
+        assert_eq!(
+            set_with(&mut state, "3", &["XX", "GET"]),
+            RespData::BulkStr("1".into())
+        );
+        assert_eq!(get(&state, "a".into()), RespData::BulkStr("3".into()));
+    }
+
+    #[test]
+    fn set_or_keep_a_ttl() {
+        let mut state = CoreState::default();
+
+        set_with(&mut state, "1", &["EX", "100"]);
+        assert!(state.expires.get("a").is_some());
+        set_with(&mut state, "2", &["KEEPTTL"]);
+        assert!(state.expires.get("a").is_some());
+        set_with(&mut state, "3", &[]);
+        assert!(state.expires.get("a").is_none());
+
+        set_with(&mut state, "4", &["PXAT", "1"]);
+        assert!(state
+            .expires
+            .get("a")
+            .is_some_and(|at| *at <= Instant::now()));
+    }
+
+    #[test]
+    fn refuse_options_it_does_not_know() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            set_with(&mut state, "1", &["NX", "garbage", "more"]),
+            syntax_error()
+        );
+        assert_eq!(set_with(&mut state, "1", &["NX", "XX"]), syntax_error());
+        assert_eq!(
+            set_with(&mut state, "1", &["EX", "10", "PX", "10"]),
+            syntax_error()
+        );
+        assert_eq!(set_with(&mut state, "1", &["EX"]), syntax_error());
+        assert_eq!(
+            set_with(&mut state, "1", &["EX", "0"]),
+            RespData::Error("ERR invalid expire time in 'set' command".into())
+        );
+        assert!(state.keyval.is_empty());
+    }
 }
@@ -5,57 +5,78 @@ use crate::CoreState;
 use rustdss_data::{Key, RespData};
 use std::collections::VecDeque;
 
-pub fn lpush(state: &mut CoreState, key: &Key, data: RespData) -> RespData {
-    match state.keyval.get_mut(key) {
-        Some(RespData::List(list)) => {
-            list.push_front(data);
-            RespData::Number(list.len() as i64)
-        }
-        Some(_) => RespData::wrong_type(),
-        None => {
-            // Create a new list with the one thing in it
-            state
-                .keyval
-                .insert(key.clone(), RespData::List(vec![data].into()));
-            RespData::Number(1)
+/// Pushes each value on to the front or back of the list in turn, making the list if it isn't
+/// there
+fn push(state: &mut CoreState, key: &Key, values: Vec<RespData>, front: bool) -> RespData {
+    fn add(list: &mut VecDeque<RespData>, values: Vec<RespData>, front: bool) -> RespData {
+        for value in values {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
         }
+        RespData::Number(list.len() as i64)
     }
-}
 
-pub fn lpop(state: &mut CoreState, key: &Key) -> RespData {
-    match state.keyval.get_mut(key) {
-        Some(RespData::List(list)) => list.pop_front().unwrap_or(RespData::nil()),
-        Some(_) => RespData::wrong_type(),
-        _ => RespData::nil(),
+    if state.has_typed_value(key) {
+        return RespData::wrong_type();
     }
-}
-
-pub fn rpush(state: &mut CoreState, key: &Key, data: RespData) -> RespData {
     match state.keyval.get_mut(key) {
-        Some(RespData::List(list)) => {
-            list.push_back(data);
-            RespData::Number(list.len() as i64)
-        }
+        Some(RespData::List(list)) => add(list, values, front),
         Some(_) => RespData::wrong_type(),
         None => {
-            // Create a new list with the one thing in it
-            state
-                .keyval
-                .insert(key.clone(), RespData::List(vec![data].into()));
-            RespData::Number(1)
+            let mut list = VecDeque::new();
+            let response = add(&mut list, values, front);
+            state.keyval.insert(key.clone(), RespData::List(list));
+            response
         }
     }
 }
 
-pub fn rpop(state: &mut CoreState, key: &Key) -> RespData {
-    match state.keyval.get_mut(key) {
-        Some(RespData::List(list)) => list.pop_back().unwrap_or(RespData::nil()),
-        Some(_) => RespData::wrong_type(),
-        _ => RespData::nil(),
+/// Pops one value, or a list of up to `count` of them when a count is given
+fn pop(state: &mut CoreState, key: &Key, count: Option<i64>, front: bool) -> RespData {
+    if state.has_typed_value(key) {
+        return RespData::wrong_type();
+    }
+    let list = match state.keyval.get_mut(key) {
+        Some(RespData::List(list)) => list,
+        Some(_) => return RespData::wrong_type(),
+        None => return RespData::nil(),
+    };
+    let mut pop_one = || {
+        if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        }
+    };
+    match count {
+        None => pop_one().unwrap_or(RespData::nil()),
+        Some(count) => RespData::List((0..count).map_while(|_| pop_one()).collect()),
     }
 }
 
+pub fn lpush(state: &mut CoreState, key: &Key, values: Vec<RespData>) -> RespData {
+    push(state, key, values, true)
+}
+
+pub fn lpop(state: &mut CoreState, key: &Key, count: Option<i64>) -> RespData {
+    pop(state, key, count, true)
+}
+
+pub fn rpush(state: &mut CoreState, key: &Key, values: Vec<RespData>) -> RespData {
+    push(state, key, values, false)
+}
+
+pub fn rpop(state: &mut CoreState, key: &Key, count: Option<i64>) -> RespData {
+    pop(state, key, count, false)
+}
+
 pub fn llen(state: &CoreState, key: &Key) -> RespData {
+    if state.has_typed_value(key) {
+        return RespData::wrong_type();
+    }
     state
         .keyval
         .get(key)
@@ -78,6 +99,9 @@ pub fn llen(state: &CoreState, key: &Key) -> RespData {
 */
 
 pub fn lrange(state: &CoreState, key: &Key, start: i64, end: i64) -> RespData {
+    if state.has_typed_value(key) {
+        return RespData::wrong_type();
+    }
     fn start_front_or_back(total: usize, start: i64) -> i64 {
         if start >= 0 {
             start
@@ -90,11 +114,9 @@ pub fn lrange(state: &CoreState, key: &Key, start: i64, end: i64) -> RespData {
         // We want the offset from the start
         let start_offset = start_front_or_back(total, start);
         if end >= 0 {
-            (end - start_offset as i64) as usize + 1
+            (end - start_offset) as usize + 1
         } else {
-            println!("debug: {}", end);
             let end_abs = start_front_or_back(total, end);
-            println!("debug_abs: {}", end_abs);
             ((end_abs - start_offset) + 1) as usize
         }
     }
@@ -110,7 +132,7 @@ pub fn lrange(state: &CoreState, key: &Key, start: i64, end: i64) -> RespData {
                     .iter()
                     .skip(start_front_or_back(total, start) as usize)
                     .take(end_front_or_back(total, start, end))
-                    .map(|item| item.clone()) // ew gross - could be v expensive!
+                    .cloned() // ew gross - could be v expensive!
                     .collect();
 
                 //
@@ -130,11 +152,9 @@ mod lpush_should {
     #[test]
     fn create_a_new_list() {
         let key: String = "key".into();
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
-        let response = lpush(&mut state, &key, RespData::SimpleStr("value".into()));
+        let response = lpush(&mut state, &key, vec![RespData::SimpleStr("value".into())]);
 
         assert_eq!(
             state.keyval.get(&key),
@@ -160,12 +180,12 @@ mod lpush_should {
                 .into(),
             ),
         );
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response = lpush(
             &mut state,
             &key,
-            RespData::SimpleStr("should_be_first".into()),
+            vec![RespData::SimpleStr("should_be_first".into())],
         );
 
         assert_eq!(
@@ -188,12 +208,12 @@ mod lpush_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::SimpleStr("not_a_list".into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response = lpush(
             &mut state,
             &key,
-            RespData::SimpleStr("some_new_data".into()),
+            vec![RespData::SimpleStr("some_new_data".into())],
         );
 
         // Assert that the original item was not mutated
@@ -203,6 +223,34 @@ mod lpush_should {
         );
         assert_eq!(response, RespData::wrong_type());
     }
+    #[test]
+    fn push_several_items_one_after_another() {
+        let key: String = "key".into();
+        let mut state = CoreState::default();
+
+        let response = lpush(
+            &mut state,
+            &key,
+            vec![
+                RespData::Number(1),
+                RespData::Number(2),
+                RespData::Number(3),
+            ],
+        );
+
+        assert_eq!(
+            state.keyval.get(&key),
+            Some(&RespData::List(
+                vec![
+                    RespData::Number(3),
+                    RespData::Number(2),
+                    RespData::Number(1)
+                ]
+                .into()
+            ))
+        );
+        assert_eq!(response, RespData::Number(3));
+    }
 }
 
 #[cfg(test)]
@@ -214,11 +262,9 @@ mod rpush_should {
     #[test]
     fn create_a_new_list() {
         let key: String = "key".into();
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
-        let response = rpush(&mut state, &key, RespData::SimpleStr("value".into()));
+        let response = rpush(&mut state, &key, vec![RespData::SimpleStr("value".into())]);
 
         assert_eq!(
             state.keyval.get(&key),
@@ -244,12 +290,12 @@ mod rpush_should {
                 .into(),
             ),
         );
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response = rpush(
             &mut state,
             &key,
-            RespData::SimpleStr("should_be_last".into()),
+            vec![RespData::SimpleStr("should_be_last".into())],
         );
 
         assert_eq!(
@@ -272,12 +318,12 @@ mod rpush_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::SimpleStr("not_a_list".into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response = rpush(
             &mut state,
             &key,
-            RespData::SimpleStr("some_new_data".into()),
+            vec![RespData::SimpleStr("some_new_data".into())],
         );
 
         // Assert that the original item was not mutated
@@ -298,11 +344,9 @@ mod rpop_should {
     #[test]
     fn return_nil_when_the_list_doesnt_exist() {
         let key: String = "key".into();
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
-        let response = rpop(&mut state, &key);
+        let response = rpop(&mut state, &key, None);
 
         assert_eq!(state.keyval.get(&key), None);
         assert_eq!(response, RespData::nil());
@@ -324,9 +368,9 @@ mod rpop_should {
                 .into(),
             ),
         );
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = rpop(&mut state, &key);
+        let response = rpop(&mut state, &key, None);
 
         assert_eq!(
             state.keyval.get(&key),
@@ -347,9 +391,9 @@ mod rpop_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::List(vec![].into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = rpop(&mut state, &key);
+        let response = rpop(&mut state, &key, None);
 
         assert_eq!(state.keyval.get(&key), Some(&RespData::List(vec![].into())));
         assert_eq!(response, RespData::nil());
@@ -361,9 +405,9 @@ mod rpop_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::SimpleStr("not_a_list".into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = rpop(&mut state, &key);
+        let response = rpop(&mut state, &key, None);
 
         // Assert that the original item was not mutated
         assert_eq!(
@@ -383,11 +427,9 @@ mod lpop_should {
     #[test]
     fn return_nil_when_the_list_doesnt_exist() {
         let key: String = "key".into();
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
-        let response = lpop(&mut state, &key);
+        let response = lpop(&mut state, &key, None);
 
         assert_eq!(state.keyval.get(&key), None);
         assert_eq!(response, RespData::nil());
@@ -409,9 +451,9 @@ mod lpop_should {
                 .into(),
             ),
         );
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = lpop(&mut state, &key);
+        let response = lpop(&mut state, &key, None);
 
         assert_eq!(
             state.keyval.get(&key),
@@ -432,9 +474,9 @@ mod lpop_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::List(vec![].into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = lpop(&mut state, &key);
+        let response = lpop(&mut state, &key, None);
 
         assert_eq!(state.keyval.get(&key), Some(&RespData::List(vec![].into())));
         assert_eq!(response, RespData::nil());
@@ -446,9 +488,9 @@ mod lpop_should {
 
         let mut keyval = HashMap::new();
         keyval.insert(key.clone(), RespData::SimpleStr("not_a_list".into()));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
-        let response = lpop(&mut state, &key);
+        let response = lpop(&mut state, &key, None);
 
         // Assert that the original item was not mutated
         assert_eq!(
@@ -457,6 +499,33 @@ mod lpop_should {
         );
         assert_eq!(response, RespData::wrong_type());
     }
+    #[test]
+    fn pop_up_to_a_count_of_items() {
+        let key: String = "key".into();
+        let mut state = CoreState::default();
+        rpush(
+            &mut state,
+            &key,
+            vec![
+                RespData::Number(1),
+                RespData::Number(2),
+                RespData::Number(3),
+            ],
+        );
+
+        assert_eq!(
+            lpop(&mut state, &key, Some(2)),
+            RespData::List(vec![RespData::Number(1), RespData::Number(2)].into())
+        );
+        assert_eq!(
+            lpop(&mut state, &key, Some(5)),
+            RespData::List(vec![RespData::Number(3)].into())
+        );
+        assert_eq!(
+            lpop(&mut state, &"missing".into(), Some(2)),
+            RespData::nil()
+        );
+    }
 }
 
 #[cfg(test)]
@@ -482,7 +551,7 @@ mod llen_should {
             ),
         );
 
-        let state = CoreState { keyval };
+        let state = CoreState::from(keyval);
 
         let response = llen(&state, &"key".into());
 
@@ -491,9 +560,7 @@ mod llen_should {
 
     #[test]
     fn it_returns_nil_when_the_list_isnt_there() {
-        let state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let state = CoreState::default();
 
         let response = llen(&state, &"key".into());
         assert_eq!(response, RespData::nil());
@@ -511,7 +578,7 @@ mod lrange_should {
         let mut keyval = HashMap::new();
         keyval.insert("key".into(), RespData::List(vec![].into()));
 
-        let state = CoreState { keyval };
+        let state = CoreState::from(keyval);
 
         let response1 = lrange(&state, &"key".into(), 0, -1);
         let response2 = lrange(&state, &"key".into(), -1, 0);
@@ -527,7 +594,7 @@ mod lrange_should {
         let source = RespData::List((0..10).map(RespData::Number).collect());
         let mut keyval = HashMap::new();
         keyval.insert("key".into(), source.clone());
-        let state = CoreState { keyval };
+        let state = CoreState::from(keyval);
 
         let response = lrange(&state, &"key".into(), 0, -1);
         assert_eq!(response, source);
@@ -538,7 +605,7 @@ mod lrange_should {
         let source = RespData::List((0..10).map(RespData::Number).collect());
         let mut keyval = HashMap::new();
         keyval.insert("key".into(), source.clone());
-        let state = CoreState { keyval };
+        let state = CoreState::from(keyval);
 
         let response = lrange(&state, &"key".into(), 3, 7);
         assert_eq!(
@@ -1,4 +1,10 @@
 pub mod admin;
+pub mod expiry;
+pub mod geo;
+pub mod hyperloglog;
 pub mod key_val;
 pub mod lists;
 pub mod number;
+pub mod sets;
+pub mod sorted_sets;
+pub mod streams;
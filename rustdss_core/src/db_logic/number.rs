@@ -10,41 +10,44 @@ fn can_be_number(data: &RespData) -> Option<i64> {
     }
 }
 
-pub fn incr(state: &mut CoreState, key: String, maybe_by: Option<i64>) -> RespData {
-    let prev = state.keyval.get(&key);
-
-    let op = match prev {
-        Some(RespData::Number(val)) => Ok(RespData::Number((*val) + maybe_by.unwrap_or(1))),
-        Some(val) => can_be_number(val)
-            .map(|v| RespData::Number(v + maybe_by.unwrap_or(1)))
-            .ok_or(RespData::Error("NaN".into())),
-        None => Ok(RespData::Number(1)),
-    };
+fn not_an_integer() -> RespData {
+    RespData::Error("ERR value is not an integer or out of range".into())
+}
 
-    if let Ok(new_val) = op {
-        state.keyval.insert(key, new_val.clone());
-        new_val
-    } else {
-        op.err().unwrap()
-    }
+fn overflow() -> RespData {
+    RespData::Error("ERR increment or decrement would overflow".into())
 }
 
-pub fn decr(state: &mut CoreState, key: String, maybe_by: Option<i64>) -> RespData {
-    let prev = state.keyval.get(&key);
-
-    let op = match prev {
-        Some(RespData::Number(val)) => Ok(RespData::Number((*val) - maybe_by.unwrap_or(1))),
-        Some(val) => can_be_number(val)
-            .map(|v| RespData::Number(v - maybe_by.unwrap_or(1)))
-            .ok_or(RespData::Error("NaN".into())),
-        None => Ok(RespData::Number(-1)),
+/// Adds to a key's number, starting from 0 when it's missing
+fn add(state: &mut CoreState, key: String, by: i64) -> RespData {
+    if state.has_typed_value(&key) {
+        return RespData::wrong_type();
+    }
+    let prev = match state.keyval.get(&key) {
+        Some(RespData::List(_)) => return RespData::wrong_type(),
+        Some(val) => match can_be_number(val) {
+            Some(prev) => prev,
+            None => return not_an_integer(),
+        },
+        None => 0,
     };
+    match prev.checked_add(by) {
+        Some(new_val) => {
+            state.keyval.insert(key, RespData::Number(new_val));
+            RespData::Number(new_val)
+        }
+        None => overflow(),
+    }
+}
 
-    if let Ok(new_val) = op {
-        state.keyval.insert(key, new_val.clone());
-        new_val
-    } else {
-        op.err().unwrap()
+pub fn incr(state: &mut CoreState, key: String, maybe_by: Option<i64>) -> RespData {
+    add(state, key, maybe_by.unwrap_or(1))
+}
+
+pub fn decr(state: &mut CoreState, key: String, maybe_by: Option<i64>) -> RespData {
+    match maybe_by.unwrap_or(1).checked_neg() {
+        Some(by) => add(state, key, by),
+        None => overflow(),
     }
 }
 
@@ -58,15 +61,26 @@ mod incr_should {
     fn increase_values_that_are_already_numbers() {
         let mut keyval = HashMap::new();
         keyval.insert("key".into(), RespData::Number(5));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response1 = incr(&mut state, "key".into(), None);
         let response2 = incr(&mut state, "key".into(), Some(2));
 
-        assert_eq!(state.keyval.get("key".into()), Some(&RespData::Number(8)));
+        assert_eq!(state.keyval.get("key"), Some(&RespData::Number(8)));
         assert_eq!(response1, RespData::Number(6));
         assert_eq!(response2, RespData::Number(8));
     }
+
+    #[test]
+    fn start_missing_keys_from_zero() {
+        let mut state = CoreState::default();
+
+        assert_eq!(incr(&mut state, "up".into(), Some(5)), RespData::Number(5));
+        assert_eq!(
+            decr(&mut state, "down".into(), Some(3)),
+            RespData::Number(-3)
+        );
+    }
     // try_to_convert_strings_into_numbers
 
     #[test]
@@ -75,7 +89,7 @@ mod incr_should {
         keyval.insert("key1".into(), RespData::SimpleStr("27".into()));
         keyval.insert("key2".into(), RespData::SimpleStr("not_a_number".into()));
 
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response1 = incr(&mut state, "key1".into(), None);
         let response2 = incr(&mut state, "key1".into(), Some(2));
@@ -85,28 +99,49 @@ mod incr_should {
 
         assert_eq!(response1, RespData::Number(28));
         assert_eq!(response2, RespData::Number(30));
-        assert_eq!(state.keyval.get("key1".into()), Some(&RespData::Number(30)));
+        assert_eq!(state.keyval.get("key1"), Some(&RespData::Number(30)));
 
-        assert_eq!(response3, RespData::Error("NaN".into()));
-        assert_eq!(response4, RespData::Error("NaN".into()));
+        assert_eq!(response3, not_an_integer());
+        assert_eq!(response4, not_an_integer());
         assert_eq!(
-            state.keyval.get("key2".into()),
+            state.keyval.get("key2"),
             Some(&RespData::SimpleStr("not_a_number".into()))
         );
     }
 
+    #[test]
+    fn refuse_to_overflow() {
+        let mut keyval = HashMap::new();
+        keyval.insert("key".into(), RespData::Number(i64::MAX));
+        let mut state = CoreState::from(keyval);
+
+        assert_eq!(incr(&mut state, "key".into(), None), overflow());
+        assert_eq!(decr(&mut state, "new".into(), Some(i64::MIN)), overflow());
+        assert_eq!(state.keyval.get("key"), Some(&RespData::Number(i64::MAX)));
+    }
+
+    #[test]
+    fn refuse_lists() {
+        let mut keyval = HashMap::new();
+        keyval.insert("list".into(), RespData::List(vec![].into()));
+        let mut state = CoreState::from(keyval);
+
+        assert_eq!(
+            incr(&mut state, "list".into(), None),
+            RespData::wrong_type()
+        );
+    }
+
     #[test]
     fn create_new_keys() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         let response1 = incr(&mut state, "key".into(), None);
         let response2 = incr(&mut state, "key".into(), Some(4));
 
         assert_eq!(response1, RespData::Number(1));
         assert_eq!(response2, RespData::Number(5));
-        assert_eq!(state.keyval.get("key".into()), Some(&RespData::Number(5)));
+        assert_eq!(state.keyval.get("key"), Some(&RespData::Number(5)));
     }
 }
 
@@ -119,12 +154,12 @@ mod decr_should {
     fn decrease_values_that_are_already_numbers() {
         let mut keyval = HashMap::new();
         keyval.insert("key".into(), RespData::Number(5));
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response1 = decr(&mut state, "key".into(), None);
         let response2 = decr(&mut state, "key".into(), Some(2));
 
-        assert_eq!(state.keyval.get("key".into()), Some(&RespData::Number(2)));
+        assert_eq!(state.keyval.get("key"), Some(&RespData::Number(2)));
         assert_eq!(response1, RespData::Number(4));
         assert_eq!(response2, RespData::Number(2));
     }
@@ -136,7 +171,7 @@ mod decr_should {
         keyval.insert("key1".into(), RespData::SimpleStr("27".into()));
         keyval.insert("key2".into(), RespData::SimpleStr("not_a_number".into()));
 
-        let mut state = CoreState { keyval };
+        let mut state = CoreState::from(keyval);
 
         let response1 = decr(&mut state, "key1".into(), None);
         let response2 = decr(&mut state, "key1".into(), Some(2));
@@ -146,27 +181,25 @@ mod decr_should {
 
         assert_eq!(response1, RespData::Number(26));
         assert_eq!(response2, RespData::Number(24));
-        assert_eq!(state.keyval.get("key1".into()), Some(&RespData::Number(24)));
+        assert_eq!(state.keyval.get("key1"), Some(&RespData::Number(24)));
 
-        assert_eq!(response3, RespData::Error("NaN".into()));
-        assert_eq!(response4, RespData::Error("NaN".into()));
+        assert_eq!(response3, not_an_integer());
+        assert_eq!(response4, not_an_integer());
         assert_eq!(
-            state.keyval.get("key2".into()),
+            state.keyval.get("key2"),
             Some(&RespData::SimpleStr("not_a_number".into()))
         );
     }
 
     #[test]
     fn create_new_keys() {
-        let mut state = CoreState {
-            keyval: HashMap::new(),
-        };
+        let mut state = CoreState::default();
 
         let response1 = decr(&mut state, "key".into(), None);
         let response2 = decr(&mut state, "key".into(), Some(4));
 
         assert_eq!(response1, RespData::Number(-1));
         assert_eq!(response2, RespData::Number(-5));
-        assert_eq!(state.keyval.get("key".into()), Some(&RespData::Number(-5)));
+        assert_eq!(state.keyval.get("key"), Some(&RespData::Number(-5)));
     }
 }
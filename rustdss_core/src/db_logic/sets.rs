@@ -0,0 +1,178 @@
+// The set value type - SADD, SREM, SMEMBERS, SISMEMBER, SCARD, SINTER and SINTERSTORE
+
+use crate::CoreState;
+use rustdss_data::{Key, RespData};
+use std::collections::HashSet;
+
+fn get_set<'a>(state: &'a CoreState, key: &str) -> Result<Option<&'a HashSet<String>>, RespData> {
+    match state.value_type(key) {
+        Some("set") | None => Ok(state.sets.get(key)),
+        Some(_) => Err(RespData::wrong_type()),
+    }
+}
+
+fn members_to_resp<'a>(members: impl Iterator<Item = &'a String>) -> RespData {
+    RespData::List(members.cloned().map(RespData::BulkStr).collect())
+}
+
+pub fn sadd(state: &mut CoreState, key: &Key, members: Vec<String>) -> RespData {
+    if let Err(e) = get_set(state, key) {
+        return e;
+    }
+    let set = state.sets.get_or_insert_default(key);
+    let added = members
+        .into_iter()
+        .filter(|member| set.insert(member.clone()))
+        .count();
+    RespData::Number(added as i64)
+}
+
+pub fn srem(state: &mut CoreState, key: &Key, members: &[String]) -> RespData {
+    if let Err(e) = get_set(state, key) {
+        return e;
+    }
+    let set = match state.sets.get_mut(key) {
+        Some(set) => set,
+        None => return RespData::Number(0),
+    };
+    let removed = members.iter().filter(|member| set.remove(*member)).count();
+    // Empty sets don't exist
+    if set.is_empty() {
+        state.sets.remove(key);
+    }
+    RespData::Number(removed as i64)
+}
+
+pub fn smembers(state: &CoreState, key: &Key) -> RespData {
+    match get_set(state, key) {
+        Ok(Some(set)) => members_to_resp(set.iter()),
+        Ok(None) => RespData::List(Default::default()),
+        Err(e) => e,
+    }
+}
+
+pub fn sismember(state: &CoreState, key: &Key, member: &str) -> RespData {
+    match get_set(state, key) {
+        Ok(set) => RespData::Number(set.map(|set| set.contains(member)).unwrap_or(false) as i64),
+        Err(e) => e,
+    }
+}
+
+pub fn scard(state: &CoreState, key: &Key) -> RespData {
+    match get_set(state, key) {
+        Ok(set) => RespData::Number(set.map(|set| set.len()).unwrap_or(0) as i64),
+        Err(e) => e,
+    }
+}
+
+fn intersection(state: &CoreState, keys: &[Key]) -> Result<HashSet<String>, RespData> {
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        match get_set(state, key)? {
+            Some(set) => sets.push(set),
+            // A missing key is an empty set, so the intersection is empty too
+            None => return Ok(HashSet::new()),
+        }
+    }
+    // Start from the smallest set, there's nothing to gain from checking more members
+    sets.sort_by_key(|set| set.len());
+    let (smallest, rest) = match sets.split_first() {
+        Some(split) => split,
+        None => return Ok(HashSet::new()),
+    };
+    Ok(smallest
+        .iter()
+        .filter(|member| rest.iter().all(|set| set.contains(*member)))
+        .cloned()
+        .collect())
+}
+
+pub fn sinter(state: &CoreState, keys: &[Key]) -> RespData {
+    match intersection(state, keys) {
+        Ok(members) => members_to_resp(members.iter()),
+        Err(e) => e,
+    }
+}
+
+pub fn sinterstore(state: &mut CoreState, dest: &Key, keys: &[Key]) -> RespData {
+    let members = match intersection(state, keys) {
+        Ok(members) => members,
+        Err(e) => return e,
+    };
+    state.remove_key(dest);
+    let stored = members.len();
+    if stored > 0 {
+        state.sets.insert(dest.clone(), members);
+    }
+    RespData::Number(stored as i64)
+}
+
+#[cfg(test)]
+mod sets_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn only_count_new_members() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            sadd(&mut state, &"s".into(), args(&["a", "b", "a"])),
+            RespData::Number(2)
+        );
+        assert_eq!(
+            sadd(&mut state, &"s".into(), args(&["b", "c"])),
+            RespData::Number(1)
+        );
+        assert_eq!(scard(&state, &"s".into()), RespData::Number(3));
+        assert_eq!(sismember(&state, &"s".into(), "c"), RespData::Number(1));
+    }
+
+    #[test]
+    fn remove_the_key_with_its_last_member() {
+        let mut state = CoreState::default();
+        sadd(&mut state, &"s".into(), args(&["a"]));
+
+        assert_eq!(
+            srem(&mut state, &"s".into(), &args(&["a", "z"])),
+            RespData::Number(1)
+        );
+
+        assert_eq!(state.value_type("s"), None);
+    }
+
+    #[test]
+    fn intersect_sets() {
+        let mut state = CoreState::default();
+        sadd(&mut state, &"a".into(), args(&["1", "2", "3"]));
+        sadd(&mut state, &"b".into(), args(&["2", "3", "4"]));
+        sadd(&mut state, &"c".into(), args(&["3", "2"]));
+
+        assert_eq!(
+            sinterstore(&mut state, &"d".into(), &args(&["a", "b", "c"])),
+            RespData::Number(2)
+        );
+        let mut stored: Vec<_> = state.sets["d"].iter().cloned().collect();
+        stored.sort();
+        assert_eq!(stored, args(&["2", "3"]));
+
+        assert_eq!(
+            sinter(&state, &args(&["a", "missing"])),
+            RespData::List(Default::default())
+        );
+    }
+
+    #[test]
+    fn refuse_other_types() {
+        let mut state = CoreState::default();
+        state
+            .keyval
+            .insert("str".into(), RespData::BulkStr("x".into()));
+
+        assert_eq!(
+            sadd(&mut state, &"str".into(), args(&["a"])),
+            RespData::wrong_type()
+        );
+        assert_eq!(sinter(&state, &args(&["str"])), RespData::wrong_type());
+    }
+}
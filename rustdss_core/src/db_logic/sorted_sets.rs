@@ -0,0 +1,115 @@
+// The sorted set value type - members are unique and ordered by their score, then by name.
+//
+// There aren't any Z* commands yet, but the geo commands store their index in one of these.
+
+use crate::memory::sampled_size;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds or updates a member, returning its previous score
+    pub fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous
+    }
+
+    /// Members with `min <= score < max`, in score order
+    pub fn range_by_score(&self, min: f64, max: f64) -> impl Iterator<Item = (&str, f64)> {
+        let range: Box<dyn Iterator<Item = &(Score, String)>> = if min < max {
+            Box::new(self.ordered.range((
+                Bound::Included((Score(min), String::new())),
+                Bound::Excluded((Score(max), String::new())),
+            )))
+        } else {
+            Box::new(std::iter::empty())
+        };
+        range.map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// Estimated bytes used, each member is stored in both the hash map and the ordered set
+    pub fn memory_usage(&self) -> usize {
+        64 + sampled_size(
+            self.scores.len(),
+            self.scores.keys().map(|member| 64 + 2 * member.len()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod sorted_set_should {
+    use super::*;
+
+    #[test]
+    fn keep_members_ordered_by_score() {
+        let mut set = SortedSet::default();
+        set.insert("c".into(), 3.0);
+        set.insert("a".into(), 1.0);
+        set.insert("b".into(), 2.0);
+
+        let members: Vec<_> = set.range_by_score(0.0, 10.0).map(|(m, _)| m).collect();
+        assert_eq!(members, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn move_members_when_their_score_changes() {
+        let mut set = SortedSet::default();
+        set.insert("a".into(), 1.0);
+        set.insert("b".into(), 2.0);
+
+        assert_eq!(set.insert("a".into(), 5.0), Some(1.0));
+
+        let members: Vec<_> = set.range_by_score(0.0, 10.0).collect();
+        assert_eq!(members, vec![("b", 2.0), ("a", 5.0)]);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn exclude_the_upper_bound_of_a_range() {
+        let mut set = SortedSet::default();
+        set.insert("a".into(), 1.0);
+        set.insert("b".into(), 2.0);
+
+        let members: Vec<_> = set.range_by_score(1.0, 2.0).map(|(m, _)| m).collect();
+        assert_eq!(members, vec!["a"]);
+    }
+}
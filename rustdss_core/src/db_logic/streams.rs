@@ -0,0 +1,2217 @@
+// Stream support - XADD, XRANGE, XREAD, consumer groups and friends
+//
+// Entries are kept in a BTreeMap keyed by their ID, which gives us the same ordered seeks and
+// range scans as the radix tree Redis uses. Most of these commands have a lot of optional
+// arguments, so they take the raw argument list and parse it here.
+
+use crate::memory::sampled_size;
+use crate::CoreState;
+use rustdss_data::{Command, Key, RespData};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId {
+    const MIN: Self = Self { ms: 0, seq: 0 };
+    const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    fn next(self) -> Option<Self> {
+        if self.seq < u64::MAX {
+            Some(Self {
+                ms: self.ms,
+                seq: self.seq + 1,
+            })
+        } else if self.ms < u64::MAX {
+            Some(Self {
+                ms: self.ms + 1,
+                seq: 0,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn prev(self) -> Option<Self> {
+        if self.seq > 0 {
+            Some(Self {
+                ms: self.ms,
+                seq: self.seq - 1,
+            })
+        } else if self.ms > 0 {
+            Some(Self {
+                ms: self.ms - 1,
+                seq: u64::MAX,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn to_resp(self) -> RespData {
+        RespData::BulkStr(self.to_string())
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+type Fields = Vec<(String, String)>;
+
+struct PendingEntry {
+    consumer: String,
+    delivery_time: u64,
+    delivery_count: u64,
+}
+
+struct Consumer {
+    seen_time: u64,
+    active_time: Option<u64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: u64) -> Self {
+        Self {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+struct ConsumerGroup {
+    last_delivered: StreamId,
+    entries_read: Option<u64>,
+    pel: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+#[derive(Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    entries_added: u64,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn first_id(&self) -> StreamId {
+        self.entries.keys().next().copied().unwrap_or_default()
+    }
+
+    fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> Box<dyn DoubleEndedIterator<Item = (&StreamId, &Fields)> + '_> {
+        if start <= end {
+            Box::new(self.entries.range(start..=end))
+        } else {
+            Box::new(std::iter::empty())
+        }
+    }
+
+    fn delete(&mut self, id: &StreamId) -> bool {
+        if self.entries.remove(id).is_some() {
+            self.max_deleted_id = self.max_deleted_id.max(*id);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn trim(&mut self, trim: &Trim) -> u64 {
+        let limit = trim.limit.filter(|limit| *limit > 0).unwrap_or(u64::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let first = match self.entries.keys().next() {
+                Some(first) => *first,
+                None => break,
+            };
+            let remove = match trim.strategy {
+                TrimStrategy::MaxLen(max) => self.entries.len() as u64 > max,
+                TrimStrategy::MinId(min) => first < min,
+            };
+            if !remove {
+                break;
+            }
+            self.entries.remove(&first);
+            removed += 1;
+        }
+        removed
+    }
+
+    /// How many entries were ever added up to and including `id`, when that can be worked out
+    fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.entries.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if id == self.last_id {
+            return Some(self.entries_added);
+        } else if id > self.last_id {
+            return None;
+        }
+
+        let first = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first {
+            if id < first {
+                return Some(self.entries_added - self.entries.len() as u64);
+            } else if id == first {
+                return Some(self.entries_added - self.entries.len() as u64 + 1);
+            }
+        }
+        None
+    }
+
+    fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        group
+            .entries_read
+            .or_else(|| self.estimate_entries_read(group.last_delivered))
+            .map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// Estimated bytes used by the entries and consumer groups
+    pub fn memory_usage(&self) -> usize {
+        let entries = sampled_size(
+            self.entries.len(),
+            self.entries.values().map(|fields| {
+                16 + fields
+                    .iter()
+                    .map(|(field, value)| 32 + field.len() + value.len())
+                    .sum::<usize>()
+            }),
+        );
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                64 + name.len() + group.pel.len() * 48 + group.consumers.len() * 64
+            })
+            .sum();
+        64 + entries + groups
+    }
+
+    /// The commands that recreate the stream, its consumer groups and their pending entries
+    pub fn rebuild_commands(&self, key: &str) -> Vec<Vec<String>> {
+        let mut commands: Vec<Vec<String>> = self
+            .entries
+            .iter()
+            .map(|(id, fields)| {
+                let mut args = vec!["XADD".into(), key.into(), id.to_string()];
+                for (field, value) in fields {
+                    args.extend([field.clone(), value.clone()]);
+                }
+                args
+            })
+            .collect();
+        for (name, group) in &self.groups {
+            let entries_read = group
+                .entries_read
+                .map(|read| read as i64)
+                .unwrap_or(-1)
+                .to_string();
+            commands.push(
+                [
+                    "XGROUP",
+                    "CREATE",
+                    key,
+                    name,
+                    &group.last_delivered.to_string(),
+                    "MKSTREAM",
+                    "ENTRIESREAD",
+                    &entries_read,
+                ]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+            );
+            for consumer in group.consumers.keys() {
+                commands.push(
+                    ["XGROUP", "CREATECONSUMER", key, name, consumer]
+                        .iter()
+                        .map(|arg| arg.to_string())
+                        .collect(),
+                );
+            }
+            for (id, pending) in &group.pel {
+                commands.push(
+                    [
+                        "XCLAIM",
+                        key,
+                        name,
+                        &pending.consumer,
+                        "0",
+                        &id.to_string(),
+                        "TIME",
+                        &pending.delivery_time.to_string(),
+                        "RETRYCOUNT",
+                        &pending.delivery_count.to_string(),
+                        "FORCE",
+                        "JUSTID",
+                    ]
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect(),
+                );
+            }
+        }
+        commands
+    }
+}
+
+enum TrimStrategy {
+    MaxLen(u64),
+    MinId(StreamId),
+}
+
+struct Trim {
+    strategy: TrimStrategy,
+    limit: Option<u64>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn error(message: &str) -> RespData {
+    RespData::Error(message.into())
+}
+
+fn syntax_error() -> RespData {
+    error("ERR syntax error")
+}
+
+fn invalid_id() -> RespData {
+    error("ERR Invalid stream ID specified as stream command argument")
+}
+
+fn not_an_integer() -> RespData {
+    error("ERR value is not an integer or out of range")
+}
+
+fn wrong_args(command: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+fn no_such_group(key: &str, group: &str) -> RespData {
+    RespData::Error(format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        group, key
+    ))
+}
+
+fn no_such_key_or_group(key: &str, group: &str) -> RespData {
+    RespData::Error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        key, group
+    ))
+}
+
+fn parse_u64(arg: &str) -> Result<u64, RespData> {
+    arg.parse().map_err(|_| not_an_integer())
+}
+
+fn parse_count(arg: &str) -> Result<usize, RespData> {
+    arg.parse::<i64>()
+        .map(|count| count.max(0) as usize)
+        .map_err(|_| not_an_integer())
+}
+
+/// Parses `ms-seq` or `ms`, filling in the sequence number with `missing_seq`
+fn parse_id(arg: &str, missing_seq: u64) -> Result<StreamId, RespData> {
+    match arg {
+        "-" => return Ok(StreamId::MIN),
+        "+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let mut parts = arg.splitn(2, '-');
+    let ms = parts
+        .next()
+        .and_then(|ms| ms.parse().ok())
+        .ok_or_else(invalid_id)?;
+    let seq = match parts.next() {
+        Some(seq) => seq.parse().map_err(|_| invalid_id())?,
+        None => missing_seq,
+    };
+    Ok(StreamId { ms, seq })
+}
+
+/// Parses a range bound as used by XRANGE, which may be exclusive when prefixed with `(`
+fn parse_range_id(arg: &str, missing_seq: u64, is_start: bool) -> Result<StreamId, RespData> {
+    if let Some(exclusive) = arg.strip_prefix('(') {
+        let id = parse_id(exclusive, missing_seq)?;
+        let bound = if is_start { id.next() } else { id.prev() };
+        bound.ok_or_else(|| {
+            if is_start {
+                error("ERR invalid start ID for the interval")
+            } else {
+                error("ERR invalid end ID for the interval")
+            }
+        })
+    } else {
+        parse_id(arg, missing_seq)
+    }
+}
+
+fn entry_to_resp(id: &StreamId, fields: &[(String, String)]) -> RespData {
+    RespData::List(
+        vec![
+            id.to_resp(),
+            RespData::List(
+                fields
+                    .iter()
+                    .flat_map(|(field, value)| {
+                        vec![
+                            RespData::BulkStr(field.clone()),
+                            RespData::BulkStr(value.clone()),
+                        ]
+                    })
+                    .collect(),
+            ),
+        ]
+        .into(),
+    )
+}
+
+fn bulk(string: &str) -> RespData {
+    RespData::BulkStr(string.into())
+}
+
+fn get_stream<'a>(state: &'a CoreState, key: &str) -> Result<Option<&'a Stream>, RespData> {
+    if matches!(state.value_type(key), Some(value_type) if value_type != "stream") {
+        Err(RespData::wrong_type())
+    } else {
+        Ok(state.streams.get(key))
+    }
+}
+
+fn get_stream_mut<'a>(
+    state: &'a mut CoreState,
+    key: &str,
+) -> Result<Option<&'a mut Stream>, RespData> {
+    if matches!(state.value_type(key), Some(value_type) if value_type != "stream") {
+        Err(RespData::wrong_type())
+    } else {
+        Ok(state.streams.get_mut(key))
+    }
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]`, starting at `args[*index]`
+fn parse_trim(args: &[String], index: &mut usize) -> Result<Trim, RespData> {
+    let is_maxlen = args[*index].eq_ignore_ascii_case("maxlen");
+    *index += 1;
+
+    let mut approx = false;
+    match args.get(*index).map(|arg| arg.as_str()) {
+        Some("~") => {
+            approx = true;
+            *index += 1;
+        }
+        Some("=") => *index += 1,
+        _ => {}
+    }
+
+    let threshold = args.get(*index).ok_or_else(syntax_error)?;
+    *index += 1;
+    let strategy = if is_maxlen {
+        match threshold.parse::<i64>() {
+            Ok(max) if max >= 0 => TrimStrategy::MaxLen(max as u64),
+            Ok(_) => return Err(error("ERR The MAXLEN argument must be >= 0.")),
+            Err(_) => return Err(not_an_integer()),
+        }
+    } else {
+        TrimStrategy::MinId(parse_id(threshold, 0)?)
+    };
+
+    let mut limit = None;
+    if args
+        .get(*index)
+        .map(|arg| arg.eq_ignore_ascii_case("limit"))
+        .unwrap_or(false)
+    {
+        if !approx {
+            return Err(error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option",
+            ));
+        }
+        let count = args.get(*index + 1).ok_or_else(syntax_error)?;
+        limit = Some(parse_u64(count)?);
+        *index += 2;
+    }
+
+    Ok(Trim { strategy, limit })
+}
+
+/// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value ...
+pub fn xadd(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    let mut index = 0;
+    let mut make_stream = true;
+    let mut trim = None;
+    while let Some(arg) = args.get(index) {
+        if arg.eq_ignore_ascii_case("nomkstream") {
+            make_stream = false;
+            index += 1;
+        } else if arg.eq_ignore_ascii_case("maxlen") || arg.eq_ignore_ascii_case("minid") {
+            trim = match parse_trim(args, &mut index) {
+                Ok(trim) => Some(trim),
+                Err(e) => return e,
+            };
+        } else {
+            break;
+        }
+    }
+
+    let id_arg = match args.get(index) {
+        Some(id_arg) => id_arg,
+        None => return wrong_args("xadd"),
+    };
+    let field_values = &args[index + 1..];
+    if field_values.is_empty() || !field_values.len().is_multiple_of(2) {
+        return wrong_args("xadd");
+    }
+
+    // Work out what the ID will look like before touching anything
+    let explicit = if id_arg == "*" {
+        None
+    } else if let Some(ms) = id_arg.strip_suffix("-*") {
+        match ms.parse::<u64>() {
+            Ok(ms) => Some((ms, None)),
+            Err(_) => return invalid_id(),
+        }
+    } else {
+        match parse_id(id_arg, 0) {
+            Ok(id) if id == StreamId::MIN => {
+                return error("ERR The ID specified in XADD must be greater than 0-0")
+            }
+            Ok(id) => Some((id.ms, Some(id.seq))),
+            Err(e) => return e,
+        }
+    };
+
+    let stream = match get_stream_mut(state, key) {
+        Ok(Some(_)) => state.streams.get_mut(key).unwrap(),
+        Ok(None) if make_stream => state.streams.get_or_insert_default(key),
+        Ok(None) => return RespData::nil(),
+        Err(e) => return e,
+    };
+
+    let last = stream.last_id;
+    let too_small = || {
+        error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+    };
+    let id = match explicit {
+        None => {
+            let ms = now_ms();
+            if ms > last.ms {
+                StreamId { ms, seq: 0 }
+            } else {
+                match last.next() {
+                    Some(id) => id,
+                    None => return too_small(),
+                }
+            }
+        }
+        Some((ms, None)) => {
+            if ms > last.ms {
+                StreamId { ms, seq: 0 }
+            } else if ms == last.ms && last.seq < u64::MAX {
+                StreamId {
+                    ms,
+                    seq: last.seq + 1,
+                }
+            } else {
+                return too_small();
+            }
+        }
+        Some((ms, Some(seq))) => {
+            let id = StreamId { ms, seq };
+            if id <= last {
+                return too_small();
+            }
+            id
+        }
+    };
+
+    let fields = field_values
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    stream.entries.insert(id, fields);
+    stream.last_id = id;
+    stream.entries_added += 1;
+
+    if let Some(trim) = trim {
+        stream.trim(&trim);
+    }
+
+    id.to_resp()
+}
+
+pub fn xlen(state: &CoreState, key: &Key) -> RespData {
+    match get_stream(state, key) {
+        Ok(stream) => RespData::Number(stream.map(|s| s.len() as i64).unwrap_or(0)),
+        Err(e) => e,
+    }
+}
+
+fn range(state: &CoreState, key: &Key, args: &[String], reverse: bool) -> RespData {
+    let command = if reverse { "xrevrange" } else { "xrange" };
+    if args.len() != 2 && args.len() != 4 {
+        return wrong_args(command);
+    }
+    let (start_arg, end_arg) = if reverse {
+        (&args[1], &args[0])
+    } else {
+        (&args[0], &args[1])
+    };
+
+    let start = match parse_range_id(start_arg, 0, true) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    let end = match parse_range_id(end_arg, u64::MAX, false) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+
+    let count = if args.len() == 4 {
+        if !args[2].eq_ignore_ascii_case("count") {
+            return syntax_error();
+        }
+        match parse_count(&args[3]) {
+            Ok(count) => count,
+            Err(e) => return e,
+        }
+    } else {
+        usize::MAX
+    };
+
+    match get_stream(state, key) {
+        Ok(Some(stream)) => {
+            let entries = stream.range(start, end);
+            let entries: Box<dyn Iterator<Item = _>> = if reverse {
+                Box::new(entries.rev())
+            } else {
+                entries
+            };
+            RespData::List(
+                entries
+                    .take(count)
+                    .map(|(id, fields)| entry_to_resp(id, fields))
+                    .collect(),
+            )
+        }
+        Ok(None) => RespData::List(vec![].into()),
+        Err(e) => e,
+    }
+}
+
+/// XRANGE key start end [COUNT count]
+pub fn xrange(state: &CoreState, key: &Key, args: &[String]) -> RespData {
+    range(state, key, args, false)
+}
+
+/// XREVRANGE key end start [COUNT count]
+pub fn xrevrange(state: &CoreState, key: &Key, args: &[String]) -> RespData {
+    range(state, key, args, true)
+}
+
+pub fn xdel(state: &mut CoreState, key: &Key, ids: &[String]) -> RespData {
+    let ids = match ids
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(e) => return e,
+    };
+
+    match get_stream_mut(state, key) {
+        Ok(Some(stream)) => {
+            RespData::Number(ids.iter().filter(|id| stream.delete(id)).count() as i64)
+        }
+        Ok(None) => RespData::Number(0),
+        Err(e) => e,
+    }
+}
+
+/// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+pub fn xtrim(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    match args.first() {
+        Some(arg) if arg.eq_ignore_ascii_case("maxlen") || arg.eq_ignore_ascii_case("minid") => {}
+        Some(_) => return syntax_error(),
+        None => return wrong_args("xtrim"),
+    }
+    let mut index = 0;
+    let trim = match parse_trim(args, &mut index) {
+        Ok(trim) => trim,
+        Err(e) => return e,
+    };
+    if index != args.len() {
+        return syntax_error();
+    }
+
+    match get_stream_mut(state, key) {
+        Ok(Some(stream)) => RespData::Number(stream.trim(&trim) as i64),
+        Ok(None) => RespData::Number(0),
+        Err(e) => e,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ReadFrom {
+    After(StreamId),
+    // `>` - entries never delivered to the group
+    Undelivered,
+}
+
+pub struct ReadRequest {
+    group: Option<(String, String)>,
+    count: usize,
+    block: Option<u64>,
+    noack: bool,
+    streams: Vec<(Key, ReadFrom)>,
+}
+
+/// A read that had nothing to return yet, and is waiting for something to be added
+pub struct BlockedRead {
+    request: ReadRequest,
+    deadline: Option<Instant>,
+}
+
+impl BlockedRead {
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// True when the read is waiting for something to happen to `key`
+    pub fn waits_on(&self, key: &str) -> bool {
+        self.request.streams.iter().any(|(stream, _)| stream == key)
+    }
+
+    /// True when running `cmd` might give this read something to return
+    pub fn woken_by(&self, cmd: &Command) -> bool {
+        cmd.uses_whole_keyspace() || cmd.key_args().iter().any(|key| self.waits_on(key))
+    }
+}
+
+fn parse_read(
+    state: &CoreState,
+    args: &[String],
+    with_group: bool,
+) -> Result<ReadRequest, RespData> {
+    let command = if with_group { "xreadgroup" } else { "xread" };
+    let mut request = ReadRequest {
+        group: None,
+        count: usize::MAX,
+        block: None,
+        noack: false,
+        streams: vec![],
+    };
+
+    let mut index = 0;
+    let streams_at = loop {
+        let arg = args.get(index).ok_or_else(|| wrong_args(command))?;
+        let next = |offset: usize| args.get(index + offset).ok_or_else(syntax_error);
+        match arg.to_lowercase().as_str() {
+            "count" => {
+                request.count = parse_count(next(1)?)?;
+                index += 2;
+            }
+            "block" => {
+                let timeout = next(1)?
+                    .parse::<i64>()
+                    .map_err(|_| error("ERR timeout is not an integer or out of range"))?;
+                if timeout < 0 {
+                    return Err(error("ERR timeout is negative"));
+                }
+                request.block = Some(timeout as u64);
+                index += 2;
+            }
+            "group" if with_group => {
+                request.group = Some((next(1)?.clone(), next(2)?.clone()));
+                index += 3;
+            }
+            "noack" if with_group => {
+                request.noack = true;
+                index += 1;
+            }
+            "streams" => break index + 1,
+            _ => return Err(syntax_error()),
+        }
+    };
+
+    if with_group && request.group.is_none() {
+        return Err(error("ERR Missing GROUP option for XREADGROUP"));
+    }
+
+    let rest = &args[streams_at..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(RespData::Error(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            command
+        )));
+    }
+
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    for (key, id) in keys.iter().zip(ids.iter()) {
+        let from = match (id.as_str(), with_group) {
+            (">", true) => ReadFrom::Undelivered,
+            (">", false) => {
+                return Err(error("ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."));
+            }
+            ("$", true) => {
+                return Err(error("ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."));
+            }
+            ("$", false) => ReadFrom::After(
+                get_stream(state, key)?
+                    .map(|stream| stream.last_id)
+                    .unwrap_or_default(),
+            ),
+            (id, _) => ReadFrom::After(parse_id(id, 0)?),
+        };
+        request.streams.push((key.clone(), from));
+    }
+
+    Ok(request)
+}
+
+/// Runs a read, returning None when there's nothing to reply with yet
+fn read(state: &mut CoreState, request: &ReadRequest) -> Result<Option<RespData>, RespData> {
+    let mut results = vec![];
+    let now = now_ms();
+
+    for (key, from) in &request.streams {
+        let entries = match &request.group {
+            None => {
+                let after = match from {
+                    ReadFrom::After(after) => *after,
+                    ReadFrom::Undelivered => continue,
+                };
+                match (get_stream(state, key)?, after.next()) {
+                    (Some(stream), Some(start)) => stream
+                        .range(start, StreamId::MAX)
+                        .take(request.count)
+                        .map(|(id, fields)| entry_to_resp(id, fields))
+                        .collect(),
+                    _ => vec![],
+                }
+            }
+            Some((group_name, consumer_name)) => {
+                let stream = get_stream_mut(state, key)?
+                    .filter(|stream| stream.groups.contains_key(group_name))
+                    .ok_or_else(|| {
+                        RespData::Error(format!(
+                            "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                            key, group_name
+                        ))
+                    })?;
+                let entries = read_group(stream, group_name, consumer_name, *from, request, now);
+                if let ReadFrom::After(_) = from {
+                    // History reads always reply, even with nothing in them
+                    results.push(RespData::List(
+                        vec![bulk(key), RespData::List(entries.into())].into(),
+                    ));
+                    continue;
+                }
+                entries
+            }
+        };
+
+        if !entries.is_empty() {
+            results.push(RespData::List(
+                vec![bulk(key), RespData::List(entries.into())].into(),
+            ));
+        }
+    }
+
+    if results.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(RespData::List(results.into())))
+    }
+}
+
+fn read_group(
+    stream: &mut Stream,
+    group_name: &str,
+    consumer_name: &str,
+    from: ReadFrom,
+    request: &ReadRequest,
+    now: u64,
+) -> Vec<RespData> {
+    let Stream {
+        entries,
+        groups,
+        max_deleted_id,
+        ..
+    } = stream;
+    let group = groups.get_mut(group_name).unwrap();
+    group
+        .consumers
+        .entry(consumer_name.into())
+        .or_insert_with(|| Consumer::new(now))
+        .seen_time = now;
+
+    match from {
+        ReadFrom::After(after) => {
+            // Read this consumer's history from the PEL, deleted entries come back as nil
+            let start = match after.next() {
+                Some(start) => start,
+                None => return vec![],
+            };
+            group.consumers[consumer_name]
+                .pending
+                .range(start..)
+                .take(request.count)
+                .map(|id| match entries.get(id) {
+                    Some(fields) => entry_to_resp(id, fields),
+                    None => RespData::List(vec![id.to_resp(), RespData::nil()].into()),
+                })
+                .collect()
+        }
+        ReadFrom::Undelivered => {
+            let start = match group.last_delivered.next() {
+                Some(start) => start,
+                None => return vec![],
+            };
+            let delivered: Vec<_> = entries
+                .range(start..)
+                .take(request.count)
+                .map(|(id, fields)| (*id, entry_to_resp(id, fields)))
+                .collect();
+
+            for (id, _) in &delivered {
+                group.last_delivered = *id;
+                group.entries_read = match group.entries_read {
+                    Some(read) if *max_deleted_id < *id => Some(read + 1),
+                    _ => None,
+                };
+                if !request.noack {
+                    if let Some(previous) = group.pel.insert(
+                        *id,
+                        PendingEntry {
+                            consumer: consumer_name.into(),
+                            delivery_time: now,
+                            delivery_count: 1,
+                        },
+                    ) {
+                        // Only possible after SETID moved the group backwards
+                        if previous.consumer != consumer_name {
+                            if let Some(owner) = group.consumers.get_mut(&previous.consumer) {
+                                owner.pending.remove(id);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let consumer = group.consumers.get_mut(consumer_name).unwrap();
+            if !delivered.is_empty() {
+                consumer.active_time = Some(now);
+            }
+            if !request.noack {
+                consumer.pending.extend(delivered.iter().map(|(id, _)| *id));
+            }
+            delivered.into_iter().map(|(_, entry)| entry).collect()
+        }
+    }
+}
+
+fn blocking_read(state: &mut CoreState, request: ReadRequest) -> Result<RespData, BlockedRead> {
+    match read(state, &request) {
+        Ok(Some(response)) => Ok(response),
+        Ok(None) => match request.block {
+            Some(timeout) => {
+                // History reads never get this far, so only new entries can wake this up
+                let deadline = if timeout == 0 {
+                    None
+                } else {
+                    Some(Instant::now() + Duration::from_millis(timeout))
+                };
+                Err(BlockedRead { request, deadline })
+            }
+            None => Ok(RespData::nil()),
+        },
+        Err(e) => Ok(e),
+    }
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key ... id ...
+///
+/// Returns Err with the read to park when it needs to block.
+pub fn xread(state: &mut CoreState, args: &[String]) -> Result<RespData, BlockedRead> {
+    match parse_read(state, args, false) {
+        Ok(request) => blocking_read(state, request),
+        Err(e) => Ok(e),
+    }
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key ... id ...
+pub fn xreadgroup(state: &mut CoreState, args: &[String]) -> Result<RespData, BlockedRead> {
+    match parse_read(state, args, true) {
+        Ok(request) => blocking_read(state, request),
+        Err(e) => Ok(e),
+    }
+}
+
+/// Re-runs a blocked read, returning the reply once there's something to send
+pub fn retry(state: &mut CoreState, blocked: &BlockedRead) -> Option<RespData> {
+    match read(state, &blocked.request) {
+        Ok(response) => response,
+        Err(e) => Some(e),
+    }
+}
+
+/// XGROUP CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER key group ...
+pub fn xgroup(state: &mut CoreState, args: &[String]) -> RespData {
+    let subcommand = match args.first() {
+        Some(subcommand) => subcommand.to_lowercase(),
+        None => return wrong_args("xgroup"),
+    };
+    let arity = match subcommand.as_str() {
+        "create" => 4..=7,
+        "setid" => 4..=6,
+        "destroy" => 3..=3,
+        "createconsumer" | "delconsumer" => 4..=4,
+        _ => {
+            return RespData::Error(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                args[0]
+            ))
+        }
+    };
+    if !arity.contains(&args.len()) {
+        return RespData::Error(format!(
+            "ERR wrong number of arguments for 'xgroup|{}' command",
+            subcommand
+        ));
+    }
+
+    let key = &args[1];
+    let group_name = &args[2];
+    let now = now_ms();
+
+    // Options for CREATE and SETID
+    let mut make_stream = false;
+    let mut entries_read = None;
+    let mut index = 4;
+    while index < args.len() {
+        match args[index].to_lowercase().as_str() {
+            "mkstream" if subcommand == "create" => {
+                make_stream = true;
+                index += 1;
+            }
+            "entriesread" => {
+                match args.get(index + 1).map(|arg| arg.parse::<i64>()) {
+                    Some(Ok(read)) if read >= -1 => {
+                        entries_read = if read == -1 { None } else { Some(read as u64) };
+                    }
+                    Some(_) => {
+                        return error("ERR value for ENTRIESREAD must be positive or -1");
+                    }
+                    None => return syntax_error(),
+                }
+                index += 2;
+            }
+            _ => return syntax_error(),
+        }
+    }
+    let entries_read_given = args
+        .iter()
+        .any(|arg| arg.eq_ignore_ascii_case("entriesread"));
+
+    let stream = match get_stream_mut(state, key) {
+        Ok(Some(_)) => state.streams.get_mut(key).unwrap(),
+        Ok(None) if make_stream => state.streams.get_or_insert_default(key),
+        Ok(None) => {
+            return error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+        }
+        Err(e) => return e,
+    };
+
+    let resolve_id = |stream: &Stream, arg: &str| -> Result<(StreamId, Option<u64>), RespData> {
+        if arg == "$" {
+            Ok((stream.last_id, Some(stream.entries_added)))
+        } else {
+            let id = parse_id(arg, 0)?;
+            Ok((id, stream.estimate_entries_read(id)))
+        }
+    };
+
+    match subcommand.as_str() {
+        "create" => {
+            if stream.groups.contains_key(group_name) {
+                return error("BUSYGROUP Consumer Group name already exists");
+            }
+            let (last_delivered, estimate) = match resolve_id(stream, &args[3]) {
+                Ok(resolved) => resolved,
+                Err(e) => return e,
+            };
+            stream.groups.insert(
+                group_name.clone(),
+                ConsumerGroup {
+                    last_delivered,
+                    entries_read: if entries_read_given {
+                        entries_read
+                    } else {
+                        estimate
+                    },
+                    pel: BTreeMap::new(),
+                    consumers: BTreeMap::new(),
+                },
+            );
+            RespData::ok()
+        }
+        "setid" => {
+            let (last_delivered, estimate) = match resolve_id(stream, &args[3]) {
+                Ok(resolved) => resolved,
+                Err(e) => return e,
+            };
+            match stream.groups.get_mut(group_name) {
+                Some(group) => {
+                    group.last_delivered = last_delivered;
+                    group.entries_read = if entries_read_given {
+                        entries_read
+                    } else {
+                        estimate
+                    };
+                    RespData::ok()
+                }
+                None => no_such_group(key, group_name),
+            }
+        }
+        "destroy" => RespData::Number(stream.groups.remove(group_name).is_some() as i64),
+        "createconsumer" => match stream.groups.get_mut(group_name) {
+            Some(group) => {
+                if group.consumers.contains_key(&args[3]) {
+                    RespData::Number(0)
+                } else {
+                    group.consumers.insert(args[3].clone(), Consumer::new(now));
+                    RespData::Number(1)
+                }
+            }
+            None => no_such_group(key, group_name),
+        },
+        "delconsumer" => match stream.groups.get_mut(group_name) {
+            Some(group) => match group.consumers.remove(&args[3]) {
+                Some(consumer) => {
+                    for id in &consumer.pending {
+                        group.pel.remove(id);
+                    }
+                    RespData::Number(consumer.pending.len() as i64)
+                }
+                None => RespData::Number(0),
+            },
+            None => no_such_group(key, group_name),
+        },
+        _ => unreachable!(),
+    }
+}
+
+pub fn xack(state: &mut CoreState, key: &Key, group_name: &str, ids: &[String]) -> RespData {
+    let ids = match ids
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(e) => return e,
+    };
+
+    let group = match get_stream_mut(state, key) {
+        Ok(stream) => stream.and_then(|stream| stream.groups.get_mut(group_name)),
+        Err(e) => return e,
+    };
+
+    match group {
+        Some(group) => {
+            let mut acked = 0;
+            for id in ids {
+                if let Some(pending) = group.pel.remove(&id) {
+                    if let Some(consumer) = group.consumers.get_mut(&pending.consumer) {
+                        consumer.pending.remove(&id);
+                    }
+                    acked += 1;
+                }
+            }
+            RespData::Number(acked)
+        }
+        // No key or group means there's nothing to acknowledge
+        None => RespData::Number(0),
+    }
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+pub fn xpending(state: &CoreState, key: &Key, group_name: &str, args: &[String]) -> RespData {
+    let group = match get_stream(state, key) {
+        Ok(stream) => stream.and_then(|stream| stream.groups.get(group_name)),
+        Err(e) => return e,
+    };
+    let group = match group {
+        Some(group) => group,
+        None => return no_such_key_or_group(key, group_name),
+    };
+    let now = now_ms();
+
+    if args.is_empty() {
+        // The summary form
+        if group.pel.is_empty() {
+            return RespData::List(
+                vec![
+                    RespData::Number(0),
+                    RespData::nil(),
+                    RespData::nil(),
+                    RespData::nil(),
+                ]
+                .into(),
+            );
+        }
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| {
+                RespData::List(
+                    vec![
+                        bulk(name),
+                        RespData::BulkStr(consumer.pending.len().to_string()),
+                    ]
+                    .into(),
+                )
+            })
+            .collect();
+        return RespData::List(
+            vec![
+                RespData::Number(group.pel.len() as i64),
+                group.pel.keys().next().unwrap().to_resp(),
+                group.pel.keys().next_back().unwrap().to_resp(),
+                RespData::List(consumers),
+            ]
+            .into(),
+        );
+    }
+
+    let mut args = args;
+    let mut min_idle = 0;
+    if args[0].eq_ignore_ascii_case("idle") {
+        min_idle = match args.get(1).map(|arg| parse_u64(arg)) {
+            Some(Ok(idle)) => idle,
+            Some(Err(e)) => return e,
+            None => return syntax_error(),
+        };
+        args = &args[2..];
+    }
+    if args.len() != 3 && args.len() != 4 {
+        return syntax_error();
+    }
+    let start = match parse_range_id(&args[0], 0, true) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    let end = match parse_range_id(&args[1], u64::MAX, false) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    let count = match parse_count(&args[2]) {
+        Ok(count) => count,
+        Err(e) => return e,
+    };
+    let consumer = args.get(3);
+
+    if start > end {
+        return RespData::List(vec![].into());
+    }
+    RespData::List(
+        group
+            .pel
+            .range(start..=end)
+            .filter(|(_, pending)| consumer.map(|c| *c == pending.consumer).unwrap_or(true))
+            .filter(|(_, pending)| now.saturating_sub(pending.delivery_time) >= min_idle)
+            .take(count)
+            .map(|(id, pending)| {
+                RespData::List(
+                    vec![
+                        id.to_resp(),
+                        bulk(&pending.consumer),
+                        RespData::Number(now.saturating_sub(pending.delivery_time) as i64),
+                        RespData::Number(pending.delivery_count as i64),
+                    ]
+                    .into(),
+                )
+            })
+            .collect(),
+    )
+}
+
+struct ClaimOptions {
+    idle: Option<u64>,
+    time: Option<u64>,
+    retry_count: Option<u64>,
+    force: bool,
+    just_id: bool,
+    last_id: Option<StreamId>,
+}
+
+/// Moves a pending entry to `consumer_name`, returning false if the entry no longer exists
+fn claim(
+    stream: &mut Stream,
+    group_name: &str,
+    consumer_name: &str,
+    id: StreamId,
+    options: &ClaimOptions,
+    now: u64,
+) -> bool {
+    let Stream {
+        entries, groups, ..
+    } = stream;
+    let group = groups.get_mut(group_name).unwrap();
+
+    if !entries.contains_key(&id) {
+        // Deleted entries are dropped from the PEL instead of being claimed
+        if let Some(pending) = group.pel.remove(&id) {
+            if let Some(owner) = group.consumers.get_mut(&pending.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        return false;
+    }
+
+    let pending = group.pel.entry(id).or_insert_with(|| PendingEntry {
+        consumer: consumer_name.into(),
+        delivery_time: now,
+        delivery_count: 0,
+    });
+    if pending.consumer != consumer_name {
+        if let Some(owner) = group.consumers.get_mut(&pending.consumer) {
+            owner.pending.remove(&id);
+        }
+        pending.consumer = consumer_name.into();
+    }
+    pending.delivery_time = match (options.time, options.idle) {
+        (Some(time), _) => time,
+        (None, Some(idle)) => now.saturating_sub(idle),
+        (None, None) => now,
+    };
+    if let Some(retry_count) = options.retry_count {
+        pending.delivery_count = retry_count;
+    } else if !options.just_id {
+        pending.delivery_count += 1;
+    }
+
+    let consumer = group
+        .consumers
+        .entry(consumer_name.into())
+        .or_insert_with(|| Consumer::new(now));
+    consumer.pending.insert(id);
+    consumer.seen_time = now;
+    consumer.active_time = Some(now);
+    true
+}
+
+/// XCLAIM key group consumer min-idle-time id ... [IDLE ms] [TIME ms] [RETRYCOUNT count]
+/// [FORCE] [JUSTID] [LASTID id]
+pub fn xclaim(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    if args.len() < 4 {
+        return wrong_args("xclaim");
+    }
+    let (group_name, consumer_name) = (&args[0], &args[1]);
+    let min_idle = match args[2].parse::<i64>() {
+        Ok(idle) => idle.max(0) as u64,
+        Err(_) => return error("ERR Invalid min-idle-time argument for XCLAIM"),
+    };
+
+    let mut ids = vec![];
+    let mut index = 3;
+    while let Some(Ok(id)) = args.get(index).map(|arg| parse_id(arg, 0)) {
+        ids.push(id);
+        index += 1;
+    }
+    if ids.is_empty() {
+        return invalid_id();
+    }
+
+    let mut options = ClaimOptions {
+        idle: None,
+        time: None,
+        retry_count: None,
+        force: false,
+        just_id: false,
+        last_id: None,
+    };
+    while index < args.len() {
+        let value = args.get(index + 1);
+        let consumed = match args[index].to_lowercase().as_str() {
+            "force" => {
+                options.force = true;
+                1
+            }
+            "justid" => {
+                options.just_id = true;
+                1
+            }
+            "idle" | "time" | "retrycount" | "lastid" if value.is_none() => return syntax_error(),
+            "idle" => match parse_u64(value.unwrap()) {
+                Ok(idle) => {
+                    options.idle = Some(idle);
+                    2
+                }
+                Err(_) => return error("ERR Invalid IDLE option argument for XCLAIM"),
+            },
+            "time" => match parse_u64(value.unwrap()) {
+                Ok(time) => {
+                    options.time = Some(time);
+                    2
+                }
+                Err(_) => return error("ERR Invalid TIME option argument for XCLAIM"),
+            },
+            "retrycount" => match parse_u64(value.unwrap()) {
+                Ok(count) => {
+                    options.retry_count = Some(count);
+                    2
+                }
+                Err(_) => return error("ERR Invalid RETRYCOUNT option argument for XCLAIM"),
+            },
+            "lastid" => match parse_id(value.unwrap(), 0) {
+                Ok(id) => {
+                    options.last_id = Some(id);
+                    2
+                }
+                Err(e) => return e,
+            },
+            _ => {
+                return RespData::Error(format!("ERR Unrecognized XCLAIM option '{}'", args[index]))
+            }
+        };
+        index += consumed;
+    }
+
+    let stream = match get_stream_mut(state, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(group_name) => stream,
+        Ok(_) => return no_such_key_or_group(key, group_name),
+        Err(e) => return e,
+    };
+    let now = now_ms();
+
+    if let Some(last_id) = options.last_id {
+        let group = stream.groups.get_mut(group_name).unwrap();
+        if last_id > group.last_delivered {
+            group.last_delivered = last_id;
+        }
+    }
+
+    let mut claimed = vec![];
+    for id in ids {
+        let group = &stream.groups[group_name];
+        match group.pel.get(&id) {
+            Some(pending) if now.saturating_sub(pending.delivery_time) < min_idle => continue,
+            None if !options.force || !stream.entries.contains_key(&id) => continue,
+            _ => {}
+        }
+        if claim(stream, group_name, consumer_name, id, &options, now) {
+            claimed.push(if options.just_id {
+                id.to_resp()
+            } else {
+                entry_to_resp(&id, &stream.entries[&id])
+            });
+        }
+    }
+    RespData::List(claimed.into())
+}
+
+/// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+pub fn xautoclaim(state: &mut CoreState, key: &Key, args: &[String]) -> RespData {
+    if args.len() < 4 {
+        return wrong_args("xautoclaim");
+    }
+    let (group_name, consumer_name) = (&args[0], &args[1]);
+    let min_idle = match args[2].parse::<i64>() {
+        Ok(idle) => idle.max(0) as u64,
+        Err(_) => return error("ERR Invalid min-idle-time argument for XAUTOCLAIM"),
+    };
+    let start = match parse_range_id(&args[3], 0, true) {
+        Ok(start) => start,
+        Err(e) => return e,
+    };
+
+    let mut count = 100;
+    let mut just_id = false;
+    let mut index = 4;
+    while index < args.len() {
+        match args[index].to_lowercase().as_str() {
+            "count" => {
+                count = match args.get(index + 1).map(|arg| arg.parse::<usize>()) {
+                    Some(Ok(count)) if count > 0 => count,
+                    Some(_) => return error("ERR COUNT must be > 0"),
+                    None => return syntax_error(),
+                };
+                index += 2;
+            }
+            "justid" => {
+                just_id = true;
+                index += 1;
+            }
+            _ => return syntax_error(),
+        }
+    }
+
+    let stream = match get_stream_mut(state, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(group_name) => stream,
+        Ok(_) => return no_such_key_or_group(key, group_name),
+        Err(e) => return e,
+    };
+    let now = now_ms();
+    let options = ClaimOptions {
+        idle: None,
+        time: None,
+        retry_count: None,
+        force: false,
+        just_id,
+        last_id: None,
+    };
+
+    // Like Redis, look at no more than ten times as many entries as we're asked to claim
+    let mut attempts = count.saturating_mul(10);
+    let candidates: Vec<(StreamId, u64)> = stream.groups[group_name]
+        .pel
+        .range(start..)
+        .map(|(id, pending)| (*id, pending.delivery_time))
+        .collect();
+
+    let mut claimed = vec![];
+    let mut deleted = vec![];
+    let mut next = StreamId::MIN;
+    let mut candidates = candidates.into_iter();
+    for (id, delivery_time) in &mut candidates {
+        if attempts == 0 || claimed.len() >= count {
+            next = id;
+            break;
+        }
+        attempts -= 1;
+        if now.saturating_sub(delivery_time) < min_idle {
+            continue;
+        }
+        if claim(stream, group_name, consumer_name, id, &options, now) {
+            claimed.push(if just_id {
+                id.to_resp()
+            } else {
+                entry_to_resp(&id, &stream.entries[&id])
+            });
+        } else {
+            deleted.push(id.to_resp());
+        }
+    }
+
+    RespData::List(
+        vec![
+            next.to_resp(),
+            RespData::List(claimed.into()),
+            RespData::List(deleted.into()),
+        ]
+        .into(),
+    )
+}
+
+fn optional_number(number: Option<u64>) -> RespData {
+    number
+        .map(|number| RespData::Number(number as i64))
+        .unwrap_or_else(RespData::nil)
+}
+
+/// XINFO STREAM key [FULL [COUNT count]] | XINFO GROUPS key | XINFO CONSUMERS key group
+pub fn xinfo(state: &CoreState, args: &[String]) -> RespData {
+    let subcommand = match args.first() {
+        Some(subcommand) => subcommand.to_lowercase(),
+        None => return wrong_args("xinfo"),
+    };
+    let arity = match subcommand.as_str() {
+        "stream" => 2..=5,
+        "groups" => 2..=2,
+        "consumers" => 3..=3,
+        _ => {
+            return RespData::Error(format!(
+                "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                args[0]
+            ))
+        }
+    };
+    if !arity.contains(&args.len()) {
+        return RespData::Error(format!(
+            "ERR wrong number of arguments for 'xinfo|{}' command",
+            subcommand
+        ));
+    }
+
+    let key = &args[1];
+    let stream = match get_stream(state, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return error("ERR no such key"),
+        Err(e) => return e,
+    };
+    let now = now_ms();
+
+    match subcommand.as_str() {
+        "stream" => {
+            let full = args
+                .get(2)
+                .map(|arg| arg.eq_ignore_ascii_case("full"))
+                .unwrap_or(false);
+            if args.len() > 2 && !full {
+                return syntax_error();
+            }
+            let count = match args.get(3) {
+                Some(arg) if arg.eq_ignore_ascii_case("count") => match args.get(4) {
+                    Some(count) => match parse_count(count) {
+                        Ok(0) => usize::MAX,
+                        Ok(count) => count,
+                        Err(e) => return e,
+                    },
+                    None => return syntax_error(),
+                },
+                Some(_) => return syntax_error(),
+                None => 10,
+            };
+            stream_info(stream, full, count)
+        }
+        "groups" => RespData::List(
+            stream
+                .groups
+                .iter()
+                .map(|(name, group)| {
+                    RespData::List(
+                        vec![
+                            bulk("name"),
+                            bulk(name),
+                            bulk("consumers"),
+                            RespData::Number(group.consumers.len() as i64),
+                            bulk("pending"),
+                            RespData::Number(group.pel.len() as i64),
+                            bulk("last-delivered-id"),
+                            group.last_delivered.to_resp(),
+                            bulk("entries-read"),
+                            optional_number(group.entries_read),
+                            bulk("lag"),
+                            optional_number(stream.lag(group)),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect(),
+        ),
+        "consumers" => match stream.groups.get(&args[2]) {
+            Some(group) => RespData::List(
+                group
+                    .consumers
+                    .iter()
+                    .map(|(name, consumer)| {
+                        RespData::List(
+                            vec![
+                                bulk("name"),
+                                bulk(name),
+                                bulk("pending"),
+                                RespData::Number(consumer.pending.len() as i64),
+                                bulk("idle"),
+                                RespData::Number(now.saturating_sub(consumer.seen_time) as i64),
+                                bulk("inactive"),
+                                RespData::Number(
+                                    consumer
+                                        .active_time
+                                        .map(|active| now.saturating_sub(active) as i64)
+                                        .unwrap_or(-1),
+                                ),
+                            ]
+                            .into(),
+                        )
+                    })
+                    .collect(),
+            ),
+            None => no_such_group(key, &args[2]),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn stream_info(stream: &Stream, full: bool, count: usize) -> RespData {
+    let mut info = vec![
+        bulk("length"),
+        RespData::Number(stream.len() as i64),
+        bulk("last-generated-id"),
+        stream.last_id.to_resp(),
+        bulk("max-deleted-entry-id"),
+        stream.max_deleted_id.to_resp(),
+        bulk("entries-added"),
+        RespData::Number(stream.entries_added as i64),
+        bulk("recorded-first-entry-id"),
+        stream.first_id().to_resp(),
+    ];
+
+    if !full {
+        let entry = |entry: Option<(&StreamId, &Fields)>| {
+            entry
+                .map(|(id, fields)| entry_to_resp(id, fields))
+                .unwrap_or_else(RespData::nil)
+        };
+        info.extend(vec![
+            bulk("groups"),
+            RespData::Number(stream.groups.len() as i64),
+            bulk("first-entry"),
+            entry(stream.entries.iter().next()),
+            bulk("last-entry"),
+            entry(stream.entries.iter().next_back()),
+        ]);
+        return RespData::List(info.into());
+    }
+
+    let groups = stream
+        .groups
+        .iter()
+        .map(|(name, group)| {
+            let pel = group
+                .pel
+                .iter()
+                .take(count)
+                .map(|(id, pending)| {
+                    RespData::List(
+                        vec![
+                            id.to_resp(),
+                            bulk(&pending.consumer),
+                            RespData::Number(pending.delivery_time as i64),
+                            RespData::Number(pending.delivery_count as i64),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect();
+            let consumers = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let pending = consumer
+                        .pending
+                        .iter()
+                        .take(count)
+                        .filter_map(|id| group.pel.get(id).map(|pending| (id, pending)))
+                        .map(|(id, pending)| {
+                            RespData::List(
+                                vec![
+                                    id.to_resp(),
+                                    RespData::Number(pending.delivery_time as i64),
+                                    RespData::Number(pending.delivery_count as i64),
+                                ]
+                                .into(),
+                            )
+                        })
+                        .collect();
+                    RespData::List(
+                        vec![
+                            bulk("name"),
+                            bulk(name),
+                            bulk("seen-time"),
+                            RespData::Number(consumer.seen_time as i64),
+                            bulk("active-time"),
+                            RespData::Number(consumer.active_time.map(|t| t as i64).unwrap_or(-1)),
+                            bulk("pel-count"),
+                            RespData::Number(consumer.pending.len() as i64),
+                            bulk("pending"),
+                            RespData::List(pending),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect();
+            RespData::List(
+                vec![
+                    bulk("name"),
+                    bulk(name),
+                    bulk("last-delivered-id"),
+                    group.last_delivered.to_resp(),
+                    bulk("entries-read"),
+                    optional_number(group.entries_read),
+                    bulk("lag"),
+                    optional_number(stream.lag(group)),
+                    bulk("pel-count"),
+                    RespData::Number(group.pel.len() as i64),
+                    bulk("pending"),
+                    RespData::List(pel),
+                    bulk("consumers"),
+                    RespData::List(consumers),
+                ]
+                .into(),
+            )
+        })
+        .collect();
+
+    info.extend(vec![
+        bulk("entries"),
+        RespData::List(
+            stream
+                .entries
+                .iter()
+                .take(count)
+                .map(|(id, fields)| entry_to_resp(id, fields))
+                .collect(),
+        ),
+        bulk("groups"),
+        RespData::List(groups),
+    ]);
+    RespData::List(info.into())
+}
+
+#[cfg(test)]
+mod xadd_should {
+    use super::*;
+    use crate::test_helpers::args;
+    use crate::CoreState;
+
+    #[test]
+    fn create_a_stream_with_explicit_ids() {
+        let mut state = CoreState::default();
+
+        let response = xadd(&mut state, &"s".into(), &args(&["1-1", "a", "1"]));
+
+        assert_eq!(response, bulk("1-1"));
+        assert_eq!(xlen(&state, &"s".into()), RespData::Number(1));
+    }
+
+    #[test]
+    fn generate_increasing_ids() {
+        let mut state = CoreState::default();
+
+        xadd(&mut state, &"s".into(), &args(&["5-*", "a", "1"]));
+        let second = xadd(&mut state, &"s".into(), &args(&["5-*", "a", "2"]));
+        let third = xadd(&mut state, &"s".into(), &args(&["*", "a", "3"]));
+
+        assert_eq!(second, bulk("5-1"));
+        if let RespData::BulkStr(id) = third {
+            assert!(parse_id(&id, 0).unwrap() > StreamId { ms: 5, seq: 1 });
+        } else {
+            panic!("expected an id, got {:?}", third);
+        }
+    }
+
+    #[test]
+    fn refuse_ids_that_are_not_increasing() {
+        let mut state = CoreState::default();
+
+        xadd(&mut state, &"s".into(), &args(&["5-5", "a", "1"]));
+
+        assert_eq!(
+            xadd(&mut state, &"s".into(), &args(&["5-5", "a", "1"])),
+            error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            )
+        );
+        assert_eq!(
+            xadd(&mut state, &"t".into(), &args(&["0-0", "a", "1"])),
+            error("ERR The ID specified in XADD must be greater than 0-0")
+        );
+    }
+
+    #[test]
+    fn trim_while_adding() {
+        let mut state = CoreState::default();
+
+        for i in 1..=5 {
+            let id = format!("{}-0", i);
+            xadd(
+                &mut state,
+                &"s".into(),
+                &args(&["MAXLEN", "3", &id, "a", "1"]),
+            );
+        }
+
+        assert_eq!(xlen(&state, &"s".into()), RespData::Number(3));
+        assert_eq!(
+            xadd(
+                &mut state,
+                &"s".into(),
+                &args(&["MINID", "5", "6-0", "a", "1"])
+            ),
+            bulk("6-0")
+        );
+        assert_eq!(xlen(&state, &"s".into()), RespData::Number(2));
+    }
+
+    #[test]
+    fn not_create_a_stream_with_nomkstream() {
+        let mut state = CoreState::default();
+
+        let response = xadd(
+            &mut state,
+            &"s".into(),
+            &args(&["NOMKSTREAM", "*", "a", "1"]),
+        );
+
+        assert_eq!(response, RespData::nil());
+        assert_eq!(xlen(&state, &"s".into()), RespData::Number(0));
+    }
+}
+
+#[cfg(test)]
+mod xrange_should {
+    use super::*;
+    use crate::test_helpers::args;
+    use crate::CoreState;
+
+    fn stream() -> CoreState {
+        let mut state = CoreState::default();
+        for i in 1..=5 {
+            let id = format!("{}-0", i);
+            xadd(&mut state, &"s".into(), &args(&[&id, "n", &i.to_string()]));
+        }
+        state
+    }
+
+    fn ids(response: RespData) -> Vec<RespData> {
+        match response {
+            RespData::List(entries) => entries
+                .into_iter()
+                .map(|entry| match entry {
+                    RespData::List(mut entry) => entry.pop_front().unwrap(),
+                    other => panic!("expected an entry, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn return_entries_in_order() {
+        let state = stream();
+
+        assert_eq!(
+            ids(xrange(&state, &"s".into(), &args(&["2", "(4-0"]))),
+            vec![bulk("2-0"), bulk("3-0")]
+        );
+        assert_eq!(
+            ids(xrange(
+                &state,
+                &"s".into(),
+                &args(&["-", "+", "COUNT", "2"])
+            )),
+            vec![bulk("1-0"), bulk("2-0")]
+        );
+    }
+
+    #[test]
+    fn return_entries_in_reverse_order() {
+        let state = stream();
+
+        assert_eq!(
+            ids(xrevrange(
+                &state,
+                &"s".into(),
+                &args(&["+", "-", "COUNT", "2"])
+            )),
+            vec![bulk("5-0"), bulk("4-0")]
+        );
+    }
+
+    #[test]
+    fn include_the_fields_of_each_entry() {
+        let state = stream();
+
+        assert_eq!(
+            xrange(&state, &"s".into(), &args(&["1", "1"])),
+            RespData::List(
+                vec![RespData::List(
+                    vec![
+                        bulk("1-0"),
+                        RespData::List(vec![bulk("n"), bulk("1")].into())
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn skip_deleted_entries() {
+        let mut state = stream();
+
+        assert_eq!(
+            xdel(&mut state, &"s".into(), &args(&["2-0", "3-0", "9-0"])),
+            RespData::Number(2)
+        );
+        assert_eq!(
+            ids(xrange(&state, &"s".into(), &args(&["-", "+"]))),
+            vec![bulk("1-0"), bulk("4-0"), bulk("5-0")]
+        );
+    }
+}
+
+#[cfg(test)]
+mod xread_should {
+    use super::*;
+    use crate::test_helpers::args;
+    use crate::CoreState;
+
+    #[test]
+    fn read_entries_after_the_given_id() {
+        let mut state = CoreState::default();
+        xadd(&mut state, &"s".into(), &args(&["1-0", "a", "1"]));
+        xadd(&mut state, &"s".into(), &args(&["2-0", "a", "2"]));
+
+        let response = xread(&mut state, &args(&["STREAMS", "s", "1-0"]));
+
+        assert_eq!(
+            response.ok(),
+            Some(RespData::List(
+                vec![RespData::List(
+                    vec![
+                        bulk("s"),
+                        RespData::List(
+                            vec![RespData::List(
+                                vec![
+                                    bulk("2-0"),
+                                    RespData::List(vec![bulk("a"), bulk("2")].into())
+                                ]
+                                .into()
+                            )]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    fn block_until_something_is_added() {
+        let mut state = CoreState::default();
+        xadd(&mut state, &"s".into(), &args(&["1-0", "a", "1"]));
+
+        let blocked = match xread(&mut state, &args(&["BLOCK", "0", "STREAMS", "s", "$"])) {
+            Err(blocked) => blocked,
+            Ok(response) => panic!("expected to block, got {:?}", response),
+        };
+        assert_eq!(blocked.deadline(), None);
+        assert_eq!(retry(&mut state, &blocked), None);
+
+        let cmd = Command::Xadd("s".into(), args(&["2-0", "a", "2"]));
+        assert!(blocked.woken_by(&cmd));
+        xadd(&mut state, &"s".into(), &args(&["2-0", "a", "2"]));
+
+        assert!(retry(&mut state, &blocked).is_some());
+    }
+
+    #[test]
+    fn return_nil_without_block() {
+        let mut state = CoreState::default();
+
+        let response = xread(&mut state, &args(&["STREAMS", "s", "0"]));
+
+        assert_eq!(response.ok(), Some(RespData::nil()));
+    }
+}
+
+#[cfg(test)]
+mod consumer_groups_should {
+    use super::*;
+    use crate::test_helpers::args;
+    use crate::CoreState;
+
+    fn stream_with_group() -> CoreState {
+        let mut state = CoreState::default();
+        xgroup(&mut state, &args(&["CREATE", "s", "g", "$", "MKSTREAM"]));
+        for i in 1..=3 {
+            let id = format!("{}-0", i);
+            xadd(&mut state, &"s".into(), &args(&[&id, "n", &i.to_string()]));
+        }
+        state
+    }
+
+    fn read_group(state: &mut CoreState, consumer: &str, count: &str) -> RespData {
+        xreadgroup(
+            state,
+            &args(&["GROUP", "g", consumer, "COUNT", count, "STREAMS", "s", ">"]),
+        )
+        .ok()
+        .unwrap()
+    }
+
+    #[test]
+    fn refuse_to_create_a_group_twice() {
+        let mut state = stream_with_group();
+
+        assert_eq!(
+            xgroup(&mut state, &args(&["CREATE", "s", "g", "$"])),
+            error("BUSYGROUP Consumer Group name already exists")
+        );
+    }
+
+    #[test]
+    fn deliver_each_entry_to_one_consumer() {
+        let mut state = stream_with_group();
+
+        read_group(&mut state, "alice", "2");
+        read_group(&mut state, "bob", "2");
+
+        let pending = xpending(&state, &"s".into(), "g", &args(&["-", "+", "10"]));
+        let owners: Vec<_> = match pending {
+            RespData::List(entries) => entries
+                .into_iter()
+                .map(|entry| match entry {
+                    RespData::List(entry) => entry[1].clone(),
+                    other => panic!("unexpected {:?}", other),
+                })
+                .collect(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(owners, vec![bulk("alice"), bulk("alice"), bulk("bob")]);
+    }
+
+    #[test]
+    fn acknowledge_pending_entries() {
+        let mut state = stream_with_group();
+        read_group(&mut state, "alice", "10");
+
+        assert_eq!(
+            xack(&mut state, &"s".into(), "g", &args(&["1-0", "2-0", "2-0"])),
+            RespData::Number(2)
+        );
+        assert_eq!(
+            xpending(&state, &"s".into(), "g", &[]),
+            RespData::List(
+                vec![
+                    RespData::Number(1),
+                    bulk("3-0"),
+                    bulk("3-0"),
+                    RespData::List(
+                        vec![RespData::List(vec![bulk("alice"), bulk("1")].into())].into()
+                    ),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn reread_history_from_the_pel() {
+        let mut state = stream_with_group();
+        read_group(&mut state, "alice", "2");
+        xdel(&mut state, &"s".into(), &args(&["1-0"]));
+
+        let response = xreadgroup(
+            &mut state,
+            &args(&["GROUP", "g", "alice", "STREAMS", "s", "0"]),
+        );
+
+        if let Ok(RespData::List(streams)) = response {
+            assert_eq!(
+                streams[0],
+                RespData::List(
+                    vec![
+                        bulk("s"),
+                        RespData::List(
+                            vec![
+                                RespData::List(vec![bulk("1-0"), RespData::nil()].into()),
+                                RespData::List(
+                                    vec![
+                                        bulk("2-0"),
+                                        RespData::List(vec![bulk("n"), bulk("2")].into())
+                                    ]
+                                    .into()
+                                ),
+                            ]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )
+            );
+        } else {
+            panic!("unexpected reply");
+        }
+    }
+
+    #[test]
+    fn claim_entries_from_other_consumers() {
+        let mut state = stream_with_group();
+        read_group(&mut state, "alice", "10");
+
+        let claimed = xclaim(
+            &mut state,
+            &"s".into(),
+            &args(&["g", "bob", "0", "1-0", "2-0", "JUSTID"]),
+        );
+        assert_eq!(
+            claimed,
+            RespData::List(vec![bulk("1-0"), bulk("2-0")].into())
+        );
+
+        let autoclaimed = xautoclaim(
+            &mut state,
+            &"s".into(),
+            &args(&["g", "carol", "0", "0", "COUNT", "1", "JUSTID"]),
+        );
+        assert_eq!(
+            autoclaimed,
+            RespData::List(
+                vec![
+                    bulk("2-0"),
+                    RespData::List(vec![bulk("1-0")].into()),
+                    RespData::List(vec![].into()),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn report_lag_in_xinfo_groups() {
+        let mut state = stream_with_group();
+        read_group(&mut state, "alice", "1");
+
+        let info = xinfo(&state, &args(&["GROUPS", "s"]));
+
+        if let RespData::List(groups) = info {
+            if let RespData::List(group) = &groups[0] {
+                assert_eq!(group[1], bulk("g"));
+                assert_eq!(group[9], RespData::Number(1)); // entries-read
+                assert_eq!(group[11], RespData::Number(2)); // lag
+                return;
+            }
+        }
+        panic!("unexpected XINFO reply");
+    }
+
+    #[test]
+    fn need_the_key_or_mkstream() {
+        let mut state = CoreState::default();
+
+        assert_eq!(
+            xgroup(&mut state, &args(&["CREATE", "nope", "g", "$"])),
+            error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+        );
+    }
+}
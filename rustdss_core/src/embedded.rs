@@ -0,0 +1,547 @@
+// A typed client for using the core in-process, without going through the server
+//
+// `Core::db` gives a `Db` that blocks until each command has run, `Core::async_db` gives an
+// `AsyncDb` whose commands are futures. Both get their methods from the `Commands` trait:
+//
+//     use rustdss_core::embedded::Commands;
+//
+//     let core = rustdss_core::Core::start();
+//     let db = core.db("0");
+//     db.set("greeting", "hello")?;
+//     assert_eq!(db.get("greeting")?, Some("hello".into()));
+
+use crate::{DatabaseId, Message};
+use rustdss_data::{Command, RespData};
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoreError {
+    /// The core has stopped
+    Disconnected,
+    /// The command failed, with the error a Redis client would have been sent
+    Command(String),
+    /// The reply wasn't what the command should reply with
+    UnexpectedReply(RespData),
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreError::Disconnected => write!(f, "the core has stopped"),
+            CoreError::Command(message) => write!(f, "{}", message),
+            CoreError::UnexpectedReply(reply) => write!(f, "unexpected reply {:?}", reply),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+/// A command along with how to turn its reply into a Rust value
+pub struct Call<T> {
+    command: Command,
+    convert: fn(RespData) -> Result<T, CoreError>,
+}
+
+impl<T> Call<T> {
+    pub fn new(command: Command, convert: fn(RespData) -> Result<T, CoreError>) -> Self {
+        Self { command, convert }
+    }
+
+    fn finish(
+        convert: fn(RespData) -> Result<T, CoreError>,
+        reply: RespData,
+    ) -> Result<T, CoreError> {
+        match reply {
+            RespData::Error(message) => Err(CoreError::Command(message)),
+            reply => convert(reply),
+        }
+    }
+}
+
+fn unexpected<T>(reply: RespData) -> Result<T, CoreError> {
+    Err(CoreError::UnexpectedReply(reply))
+}
+
+fn raw(reply: RespData) -> Result<RespData, CoreError> {
+    Ok(reply)
+}
+
+fn ok(reply: RespData) -> Result<(), CoreError> {
+    match reply {
+        RespData::SimpleStr(ref status) if status == "OK" => Ok(()),
+        reply => unexpected(reply),
+    }
+}
+
+fn integer(reply: RespData) -> Result<i64, CoreError> {
+    match reply {
+        RespData::Number(n) => Ok(n),
+        reply => unexpected(reply),
+    }
+}
+
+// Some commands reply with nil rather than 0 for a missing key
+fn length(reply: RespData) -> Result<i64, CoreError> {
+    match reply {
+        RespData::NullString => Ok(0),
+        reply => integer(reply),
+    }
+}
+
+fn boolean(reply: RespData) -> Result<bool, CoreError> {
+    integer(reply).map(|n| n != 0)
+}
+
+fn optional_string(reply: RespData) -> Result<Option<String>, CoreError> {
+    match reply {
+        RespData::NullString => Ok(None),
+        RespData::BulkStr(string) | RespData::SimpleStr(string) => Ok(Some(string)),
+        RespData::Number(n) => Ok(Some(n.to_string())),
+        reply => unexpected(reply),
+    }
+}
+
+fn strings(reply: RespData) -> Result<Vec<String>, CoreError> {
+    match reply {
+        RespData::List(items) => items
+            .into_iter()
+            .map(|item| optional_string(item)?.ok_or(CoreError::UnexpectedReply(RespData::nil())))
+            .collect(),
+        reply => unexpected(reply),
+    }
+}
+
+fn time_to_live(reply: RespData) -> Result<Option<Duration>, CoreError> {
+    // -2 for a missing key and -1 for a key without a TTL
+    integer(reply).map(|ms| u64::try_from(ms).ok().map(Duration::from_millis))
+}
+
+fn owned(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|key| key.to_string()).collect()
+}
+
+/// The commands the embedded clients support. `Reply<T>` is a `Result` for `Db` and a future of
+/// one for `AsyncDb`.
+pub trait Commands {
+    type Reply<T: 'static>;
+
+    /// Runs a command, converting its reply with the `Call`
+    fn call<T: 'static>(&self, call: Call<T>) -> Self::Reply<T>;
+
+    /// Runs any command, giving back the reply as it is
+    fn command(&self, command: Command) -> Self::Reply<RespData> {
+        self.call(Call::new(command, raw))
+    }
+
+    fn get(&self, key: &str) -> Self::Reply<Option<String>> {
+        self.call(Call::new(Command::Get(key.into()), optional_string))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Self::Reply<()> {
+        self.call(Call::new(
+            Command::Set(key.into(), RespData::BulkStr(value.into()), vec![]),
+            ok,
+        ))
+    }
+
+    /// Removes keys, giving how many there were
+    fn del(&self, keys: &[&str]) -> Self::Reply<i64> {
+        self.call(Call::new(Command::Del(owned(keys)), integer))
+    }
+
+    fn incr(&self, key: &str) -> Self::Reply<i64> {
+        self.incr_by(key, 1)
+    }
+
+    fn incr_by(&self, key: &str, by: i64) -> Self::Reply<i64> {
+        self.call(Call::new(Command::Incr(key.into(), Some(by)), integer))
+    }
+
+    fn decr(&self, key: &str) -> Self::Reply<i64> {
+        self.decr_by(key, 1)
+    }
+
+    fn decr_by(&self, key: &str, by: i64) -> Self::Reply<i64> {
+        self.call(Call::new(Command::Decr(key.into(), Some(by)), integer))
+    }
+
+    /// Adds to the front of a list, giving its new length
+    fn lpush(&self, key: &str, value: &str) -> Self::Reply<i64> {
+        self.call(Call::new(
+            Command::Lpush(key.into(), vec![RespData::BulkStr(value.into())]),
+            integer,
+        ))
+    }
+
+    /// Adds to the back of a list, giving its new length
+    fn rpush(&self, key: &str, value: &str) -> Self::Reply<i64> {
+        self.call(Call::new(
+            Command::Rpush(key.into(), vec![RespData::BulkStr(value.into())]),
+            integer,
+        ))
+    }
+
+    fn lpop(&self, key: &str) -> Self::Reply<Option<String>> {
+        self.call(Call::new(Command::Lpop(key.into(), None), optional_string))
+    }
+
+    fn rpop(&self, key: &str) -> Self::Reply<Option<String>> {
+        self.call(Call::new(Command::Rpop(key.into(), None), optional_string))
+    }
+
+    fn llen(&self, key: &str) -> Self::Reply<i64> {
+        self.call(Call::new(Command::Llen(key.into()), length))
+    }
+
+    /// The items from `start` to `stop` inclusive, negative indexes count from the end
+    fn lrange(&self, key: &str, start: i64, stop: i64) -> Self::Reply<Vec<String>> {
+        self.call(Call::new(Command::Lrange(key.into(), start, stop), strings))
+    }
+
+    /// Adds members to a set, giving how many weren't already there
+    fn sadd(&self, key: &str, members: &[&str]) -> Self::Reply<i64> {
+        self.call(Call::new(
+            Command::Sadd(key.into(), owned(members)),
+            integer,
+        ))
+    }
+
+    /// Removes members from a set, giving how many were there
+    fn srem(&self, key: &str, members: &[&str]) -> Self::Reply<i64> {
+        self.call(Call::new(
+            Command::Srem(key.into(), owned(members)),
+            integer,
+        ))
+    }
+
+    fn smembers(&self, key: &str) -> Self::Reply<Vec<String>> {
+        self.call(Call::new(Command::Smembers(key.into()), strings))
+    }
+
+    fn sismember(&self, key: &str, member: &str) -> Self::Reply<bool> {
+        self.call(Call::new(
+            Command::Sismember(key.into(), member.into()),
+            boolean,
+        ))
+    }
+
+    fn scard(&self, key: &str) -> Self::Reply<i64> {
+        self.call(Call::new(Command::Scard(key.into()), integer))
+    }
+
+    /// Gives a key a time to live, false if there's no such key
+    fn expire(&self, key: &str, ttl: Duration) -> Self::Reply<bool> {
+        self.call(Call::new(
+            Command::Pexpire(key.into(), ttl.as_millis().to_string(), vec![]),
+            boolean,
+        ))
+    }
+
+    /// How long until a key expires, `None` if it doesn't exist or won't expire
+    fn ttl(&self, key: &str) -> Self::Reply<Option<Duration>> {
+        self.call(Call::new(Command::Pttl(key.into()), time_to_live))
+    }
+
+    /// Removes a key's time to live, false if it didn't have one
+    fn persist(&self, key: &str) -> Self::Reply<bool> {
+        self.call(Call::new(Command::Persist(key.into()), boolean))
+    }
+
+    /// The keys matching a glob-style pattern
+    fn keys(&self, pattern: &str) -> Self::Reply<Vec<String>> {
+        self.call(Call::new(Command::Keys(pattern.into()), strings))
+    }
+
+    fn flushall(&self) -> Self::Reply<()> {
+        self.call(Call::new(Command::FlushAll, ok))
+    }
+}
+
+/// Sends a command to a database, giving back where the reply will turn up
+fn send(
+    core: &Sender<Message>,
+    database_id: &DatabaseId,
+    command: Command,
+) -> Result<Receiver<RespData>, CoreError> {
+    let (reply_sender, reply) = channel();
+    core.send((database_id.clone(), command, reply_sender, None))
+        .map_err(|_| CoreError::Disconnected)?;
+    Ok(reply)
+}
+
+/// A database that runs each command before returning
+#[derive(Clone)]
+pub struct Db {
+    core: Sender<Message>,
+    database_id: DatabaseId,
+}
+
+impl Db {
+    pub fn new(core: Sender<Message>, database_id: DatabaseId) -> Self {
+        Self { core, database_id }
+    }
+}
+
+impl Commands for Db {
+    type Reply<T: 'static> = Result<T, CoreError>;
+
+    fn call<T: 'static>(&self, call: Call<T>) -> Result<T, CoreError> {
+        let reply = send(&self.core, &self.database_id, call.command)?
+            .recv()
+            .map_err(|_| CoreError::Disconnected)?;
+        Call::finish(call.convert, reply)
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    reply: Option<Result<RespData, CoreError>>,
+    waker: Option<Waker>,
+}
+
+/// The reply to a command sent with `AsyncDb`
+pub struct ReplyFuture<T> {
+    pending: Arc<Mutex<Pending>>,
+    convert: fn(RespData) -> Result<T, CoreError>,
+}
+
+impl<T> Future for ReplyFuture<T> {
+    type Output = Result<T, CoreError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match pending.reply.take() {
+            Some(reply) => Poll::Ready(reply.and_then(|reply| Call::finish(self.convert, reply))),
+            None => {
+                pending.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Pending {
+    /// Waits for the reply and wakes whoever is waiting on the future
+    fn complete(pending: &Mutex<Pending>, reply: Receiver<RespData>) {
+        let reply = reply.recv().map_err(|_| CoreError::Disconnected);
+        let mut pending = pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.reply = Some(reply);
+        if let Some(waker) = pending.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A database whose commands are futures, for use from async code. Replies are waited for on a
+/// thread of its own, so no particular async runtime is needed.
+#[derive(Clone)]
+pub struct AsyncDb {
+    core: Sender<Message>,
+    database_id: DatabaseId,
+    waiter: Sender<(Receiver<RespData>, Arc<Mutex<Pending>>)>,
+}
+
+impl AsyncDb {
+    pub fn new(core: Sender<Message>, database_id: DatabaseId) -> Self {
+        let (waiter, waiting) = channel::<(Receiver<RespData>, Arc<Mutex<Pending>>)>();
+        // Ends once every clone of the AsyncDb has gone
+        thread::spawn(move || {
+            for (reply, pending) in waiting {
+                Pending::complete(&pending, reply);
+            }
+        });
+        Self {
+            core,
+            database_id,
+            waiter,
+        }
+    }
+}
+
+// Replies to these can take as long as the BLOCK asks for, so they get a waiter of their own
+// rather than holding up the replies queued behind them
+fn can_block(command: &Command) -> bool {
+    matches!(command, Command::Xread(_) | Command::Xreadgroup(_))
+}
+
+impl Commands for AsyncDb {
+    type Reply<T: 'static> = ReplyFuture<T>;
+
+    fn call<T: 'static>(&self, call: Call<T>) -> ReplyFuture<T> {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let blocks = can_block(&call.command);
+        let sent = send(&self.core, &self.database_id, call.command).and_then(|reply| {
+            if blocks {
+                let pending = pending.clone();
+                thread::spawn(move || Pending::complete(&pending, reply));
+                return Ok(());
+            }
+            self.waiter
+                .send((reply, pending.clone()))
+                .map_err(|_| CoreError::Disconnected)
+        });
+        if let Err(e) = sent {
+            pending
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .reply = Some(Err(e));
+        }
+        ReplyFuture {
+            pending,
+            convert: call.convert,
+        }
+    }
+}
+
+#[cfg(test)]
+mod embedded_should {
+    use super::*;
+    use crate::{Core, CoreOptions};
+    use std::task::Wake;
+
+    fn core() -> Core {
+        Core::start_with(CoreOptions {
+            shards: 2,
+            ..CoreOptions::default()
+        })
+    }
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn run_commands_and_give_back_rust_values() {
+        let db = core().db("embedded");
+
+        assert_eq!(db.get("missing"), Ok(None));
+        assert_eq!(db.set("greeting", "hello"), Ok(()));
+        assert_eq!(db.get("greeting"), Ok(Some("hello".into())));
+        assert_eq!(db.incr_by("counter", 5), Ok(5));
+        assert_eq!(db.decr("counter"), Ok(4));
+        assert_eq!(db.get("counter"), Ok(Some("4".into())));
+
+        assert_eq!(db.rpush("list", "a"), Ok(1));
+        assert_eq!(db.rpush("list", "b"), Ok(2));
+        assert_eq!(db.lpush("list", "z"), Ok(3));
+        assert_eq!(
+            db.lrange("list", 0, -1),
+            Ok(vec!["z".into(), "a".into(), "b".into()])
+        );
+        assert_eq!(db.lpop("list"), Ok(Some("z".into())));
+        assert_eq!(db.llen("list"), Ok(2));
+        assert_eq!(db.llen("missing"), Ok(0));
+
+        assert_eq!(db.sadd("set", &["x", "y", "x"]), Ok(2));
+        assert_eq!(db.sismember("set", "y"), Ok(true));
+        assert_eq!(db.scard("set"), Ok(2));
+
+        assert_eq!(db.del(&["greeting", "missing"]), Ok(1));
+    }
+
+    #[test]
+    fn handle_expiry() {
+        let db = core().db("embedded");
+        db.set("key", "value").unwrap();
+
+        assert_eq!(db.ttl("key"), Ok(None));
+        assert_eq!(db.expire("key", Duration::from_secs(100)), Ok(true));
+        assert!(matches!(db.ttl("key"), Ok(Some(ttl)) if ttl > Duration::from_secs(99)));
+        assert_eq!(db.persist("key"), Ok(true));
+        assert_eq!(db.expire("missing", Duration::from_secs(1)), Ok(false));
+    }
+
+    #[test]
+    fn give_back_command_errors() {
+        let db = core().db("embedded");
+        db.sadd("set", &["x"]).unwrap();
+
+        assert_eq!(
+            db.get("set"),
+            Err(CoreError::Command(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn keep_databases_apart() {
+        let core = core();
+        core.db("one").set("key", "1").unwrap();
+
+        assert_eq!(core.db("two").get("key"), Ok(None));
+        assert_eq!(core.db("one").keys("*"), Ok(vec!["key".into()]));
+    }
+
+    #[test]
+    fn run_commands_as_futures() {
+        let db = core().async_db("embedded");
+
+        let set = db.set("greeting", "hello");
+        let added = db.sadd("set", &["x"]);
+        assert_eq!(block_on(set), Ok(()));
+        assert_eq!(block_on(added), Ok(1));
+        assert_eq!(block_on(db.get("greeting")), Ok(Some("hello".into())));
+        assert_eq!(
+            block_on(db.incr("set")),
+            Err(CoreError::Command(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn run_pipelined_commands_in_order() {
+        let db = core().async_db("embedded");
+
+        // On different shards, so the DEL is run by the coordinator rather than queued on a shard
+        for _ in 0..100 {
+            let set = db.set("foo", "1");
+            let deleted = db.del(&["foo", "bar"]);
+            block_on(set).unwrap();
+            assert_eq!(block_on(deleted), Ok(1));
+            assert_eq!(block_on(db.get("foo")), Ok(None));
+        }
+    }
+
+    #[test]
+    fn not_hold_up_replies_behind_a_blocked_read() {
+        let db = core().async_db("embedded");
+
+        let read = db.command(Command::Xread(owned(&["BLOCK", "0", "STREAMS", "s", "$"])));
+        assert_eq!(block_on(db.set("key", "value")), Ok(()));
+        assert_eq!(block_on(db.get("key")), Ok(Some("value".into())));
+
+        block_on(db.command(Command::Xadd("s".into(), owned(&["*", "field", "1"])))).unwrap();
+        assert!(matches!(block_on(read), Ok(RespData::List(_))));
+    }
+}
@@ -0,0 +1,573 @@
+// The keyspace - a radix tree keyed by key name
+//
+// Lookups cost the length of the key rather than a hash of it, and keys come out in sorted order,
+// so everything under a prefix like `user:123:` sits in one subtree. That makes prefix-anchored
+// KEYS and SCAN MATCH patterns cost the number of keys that match instead of the number of keys
+// in the database. `cargo bench -p rustdss_core --bench keyspace` compares it with a HashMap.
+
+use rustdss_data::Key;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Index;
+
+struct Node<V> {
+    // The bytes on the edge leading to this node
+    label: Vec<u8>,
+    entry: Option<(Key, V)>,
+    // Sorted by the first byte of their label, which is different for every child
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(label: Vec<u8>, entry: Option<(Key, V)>) -> Self {
+        Self {
+            label,
+            entry,
+            children: Vec::new(),
+        }
+    }
+
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&byte, |child| child.label[0])
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+pub struct Keyspace<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> Default for Keyspace<V> {
+    fn default() -> Self {
+        Self {
+            root: Node::new(Vec::new(), None),
+            len: 0,
+        }
+    }
+}
+
+impl<V> Keyspace<V> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        let mut node = &self.root;
+        let mut rest = key.as_bytes();
+        loop {
+            if rest.is_empty() {
+                return node.entry.as_ref().map(|(_, value)| value);
+            }
+            let child = &node.children[node.child_index(rest[0]).ok()?];
+            rest = rest.strip_prefix(child.label.as_slice())?;
+            node = child;
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        let mut rest = key.as_bytes();
+        loop {
+            if rest.is_empty() {
+                return node.entry.as_mut().map(|(_, value)| value);
+            }
+            let i = node.child_index(rest[0]).ok()?;
+            rest = rest.strip_prefix(node.children[i].label.as_slice())?;
+            node = &mut node.children[i];
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Adds or replaces a key, returning the value it replaced
+    pub fn insert(&mut self, key: Key, value: V) -> Option<V> {
+        let mut node = &mut self.root;
+        let mut pos = 0;
+        loop {
+            let rest = &key.as_bytes()[pos..];
+            if rest.is_empty() {
+                let previous = node.entry.replace((key, value)).map(|(_, value)| value);
+                if previous.is_none() {
+                    self.len += 1;
+                }
+                return previous;
+            }
+
+            let i = match node.child_index(rest[0]) {
+                Ok(i) => i,
+                Err(i) => {
+                    let leaf = Node::new(rest.to_vec(), None);
+                    node.children.insert(i, leaf);
+                    node.children[i].entry = Some((key, value));
+                    self.len += 1;
+                    return None;
+                }
+            };
+
+            let child = &mut node.children[i];
+            let common = common_prefix(&child.label, rest);
+            if common < child.label.len() {
+                // The key leaves this edge part way along, so split it in two
+                let mut old = std::mem::replace(child, Node::new(Vec::new(), None));
+                let tail = old.label.split_off(common);
+                child.label = std::mem::replace(&mut old.label, tail);
+                child.children.push(old);
+            }
+            pos += common;
+            node = child;
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let removed = Self::remove_from(&mut self.root, key.as_bytes());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    fn remove_from(node: &mut Node<V>, rest: &[u8]) -> Option<V> {
+        if rest.is_empty() {
+            return node.entry.take().map(|(_, value)| value);
+        }
+        let i = node.child_index(rest[0]).ok()?;
+        let child = &mut node.children[i];
+        let rest = rest.strip_prefix(child.label.as_slice())?;
+        let removed = Self::remove_from(child, rest)?;
+
+        // Keep the tree compressed - no empty leaves, and no nodes with one child and no entry
+        if child.entry.is_none() {
+            match child.children.len() {
+                0 => {
+                    node.children.remove(i);
+                }
+                1 => {
+                    let mut only = child.children.pop().expect("one child");
+                    let mut label = std::mem::take(&mut child.label);
+                    label.extend_from_slice(&only.label);
+                    only.label = label;
+                    *child = only;
+                }
+                _ => {}
+            }
+        }
+        Some(removed)
+    }
+
+    /// The value for a key, adding the default value first if there isn't one
+    pub fn get_or_insert_default(&mut self, key: &str) -> &mut V
+    where
+        V: Default,
+    {
+        if !self.contains_key(key) {
+            self.insert(key.into(), V::default());
+        }
+        self.get_mut(key).expect("just inserted")
+    }
+
+    /// Every key in sorted order
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.scan("", None).map(|(key, _)| key)
+    }
+
+    /// Every key and value in sorted order
+    pub fn iter(&self) -> Scan<'_, V> {
+        self.scan("", None)
+    }
+
+    /// The keys starting with `prefix`, in sorted order, skipping everything up to and including
+    /// `after`. Only the part of the tree holding those keys is visited.
+    pub fn scan(&self, prefix: &str, after: Option<&str>) -> Scan<'_, V> {
+        let mut node = &self.root;
+        let mut path = Vec::new();
+        let mut rest = prefix.as_bytes();
+        let mut scan = Scan {
+            stack: Vec::new(),
+            after: after.map(|after| after.as_bytes().to_vec()),
+        };
+
+        while !rest.is_empty() {
+            let child = match node.child_index(rest[0]) {
+                Ok(i) => &node.children[i],
+                Err(_) => return scan,
+            };
+            let common = common_prefix(&child.label, rest);
+            path.extend_from_slice(&child.label);
+            if common == rest.len() {
+                // The prefix ends on or part way along this edge, so all of it matches
+                node = child;
+                break;
+            }
+            if common < child.label.len() {
+                return scan;
+            }
+            rest = &rest[common..];
+            node = child;
+        }
+
+        scan.stack.push((node, path));
+        scan
+    }
+}
+
+/// A depth first walk of part of the tree, see `Keyspace::scan`
+pub struct Scan<'a, V> {
+    stack: Vec<(&'a Node<V>, Vec<u8>)>,
+    after: Option<Vec<u8>>,
+}
+
+impl<'a, V> Iterator for Scan<'a, V> {
+    type Item = (&'a Key, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, path)) = self.stack.pop() {
+            for child in node.children.iter().rev() {
+                let mut child_path = path.clone();
+                child_path.extend_from_slice(&child.label);
+                if let Some(after) = &self.after {
+                    // Subtrees entirely before the cursor can be skipped
+                    if child_path < *after && !after.starts_with(&child_path) {
+                        continue;
+                    }
+                }
+                self.stack.push((child, child_path));
+            }
+
+            if let Some((key, value)) = &node.entry {
+                let is_after = match &self.after {
+                    Some(after) => key.as_bytes() > after.as_slice(),
+                    None => true,
+                };
+                if is_after {
+                    return Some((key, value));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<V> Index<&str> for Keyspace<V> {
+    type Output = V;
+
+    fn index(&self, key: &str) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<V> From<HashMap<Key, V>> for Keyspace<V> {
+    fn from(map: HashMap<Key, V>) -> Self {
+        let mut keyspace = Self::default();
+        for (key, value) in map {
+            keyspace.insert(key, value);
+        }
+        keyspace
+    }
+}
+
+/// Glob-style matching like Redis - `*`, `?`, `[abc]`, `[^a-z]` and `\` to escape
+pub fn glob_match(pattern: &[char], string: &[char]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            '*' => {
+                while pattern.get(p + 1) == Some(&'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len())
+                    .any(|start| glob_match(&pattern[p + 1..], &string[start..]));
+            }
+            '?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            '[' => {
+                let c = match string.get(s) {
+                    Some(c) => *c,
+                    None => return false,
+                };
+                p += 1;
+                let negate = pattern.get(p) == Some(&'^');
+                if negate {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        // An unterminated class ends with the pattern
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(']') => break,
+                        Some('\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            matched |= pattern[p] == c;
+                        }
+                        Some(start) if p + 2 < pattern.len() && pattern[p + 1] == '-' => {
+                            let (low, high) = if *start <= pattern[p + 2] {
+                                (*start, pattern[p + 2])
+                            } else {
+                                (pattern[p + 2], *start)
+                            };
+                            matched |= (low..=high).contains(&c);
+                            p += 2;
+                        }
+                        Some(other) => matched |= *other == c,
+                    }
+                    p += 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            '\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if string.get(s) != Some(&pattern[p]) {
+                    return false;
+                }
+                s += 1;
+            }
+            literal => {
+                if string.get(s) != Some(&literal) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+    s == string.len()
+}
+
+/// The part of a pattern before its first special character, every match starts with it
+pub fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+// How many SCAN cursors each database remembers before forgetting the oldest
+const MAX_SCAN_CURSORS: usize = 1024;
+// A cursor holds the first few bytes of the key it resumes after and a number telling it apart
+// from other cursors starting with the same bytes. It has to stay small enough to fit once
+// sharding has multiplied it by the number of shards.
+const CURSOR_PREFIX_BYTES: usize = 4;
+const CURSOR_ID_BITS: u32 = 22;
+
+/// SCAN cursors are numbers, but the scan resumes after the last key it looked at, so remember
+/// which key each cursor refers to. The start of that key is in the cursor as well, so a scan
+/// whose cursor has been forgotten can still carry on from a little before where it was.
+#[derive(Default)]
+pub struct ScanCursors {
+    positions: HashMap<u64, Key>,
+    order: VecDeque<u64>,
+    last_id: u64,
+}
+
+impl ScanCursors {
+    /// The key a scan carries on after, None to start from the beginning. A forgotten cursor
+    /// carries on from a key that sorts before the one it was made for, so some keys may come
+    /// back twice but none are missed.
+    pub fn resume_after(&self, cursor: u64) -> Option<Key> {
+        if let Some(after) = self.positions.get(&cursor) {
+            return Some(after.clone());
+        }
+        let bytes = (cursor >> CURSOR_ID_BITS).to_be_bytes();
+        let prefix = &bytes[bytes.len() - CURSOR_PREFIX_BYTES..];
+        let end = prefix
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |last| last + 1);
+        let prefix = match std::str::from_utf8(&prefix[..end]) {
+            Ok(prefix) => prefix,
+            Err(error) => std::str::from_utf8(&prefix[..error.valid_up_to()]).unwrap_or_default(),
+        };
+        let mut before = prefix.to_string();
+        before.pop()?;
+        Some(before)
+    }
+
+    pub fn create(&mut self, after: Key) -> u64 {
+        // Never zero, that's the cursor that ends a scan
+        self.last_id = self.last_id % ((1 << CURSOR_ID_BITS) - 1) + 1;
+        let mut prefix = [0; 8];
+        for (byte, from) in prefix[8 - CURSOR_PREFIX_BYTES..]
+            .iter_mut()
+            .zip(after.as_bytes())
+        {
+            *byte = *from;
+        }
+        let cursor = u64::from_be_bytes(prefix) << CURSOR_ID_BITS | self.last_id;
+
+        self.positions.insert(cursor, after);
+        self.order.push_back(cursor);
+        if self.order.len() > MAX_SCAN_CURSORS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.positions.remove(&oldest);
+            }
+        }
+        cursor
+    }
+}
+
+#[cfg(test)]
+mod keyspace_should {
+    use super::*;
+
+    fn keyspace(keys: &[&str]) -> Keyspace<usize> {
+        let mut keyspace = Keyspace::default();
+        for (i, key) in keys.iter().enumerate() {
+            keyspace.insert(key.to_string(), i);
+        }
+        keyspace
+    }
+
+    #[test]
+    fn store_and_find_keys_that_share_prefixes() {
+        let keyspace = keyspace(&["romane", "romanus", "romulus", "rubens", "ruber", "rom", ""]);
+
+        assert_eq!(keyspace.len(), 7);
+        assert_eq!(keyspace.get("romanus"), Some(&1));
+        assert_eq!(keyspace.get("rom"), Some(&5));
+        assert_eq!(keyspace.get(""), Some(&6));
+        assert_eq!(keyspace.get("roma"), None);
+        assert_eq!(keyspace.get("romanusx"), None);
+    }
+
+    #[test]
+    fn replace_existing_values() {
+        let mut keyspace = keyspace(&["a"]);
+
+        assert_eq!(keyspace.insert("a".into(), 10), Some(0));
+
+        assert_eq!(keyspace.len(), 1);
+        assert_eq!(keyspace["a"], 10);
+    }
+
+    #[test]
+    fn stay_consistent_when_removing() {
+        let mut keyspace = keyspace(&["test", "team", "toast", "te"]);
+
+        assert_eq!(keyspace.remove("te"), Some(3));
+        assert_eq!(keyspace.remove("te"), None);
+        assert_eq!(keyspace.remove("tea"), None);
+        assert_eq!(keyspace.remove("team"), Some(1));
+
+        assert_eq!(keyspace.len(), 2);
+        assert_eq!(keyspace.get("test"), Some(&0));
+        assert_eq!(keyspace.get("toast"), Some(&2));
+        assert_eq!(keyspace.keys().collect::<Vec<_>>(), vec!["test", "toast"]);
+        // The tree is merged back together once the branches are gone
+        assert_eq!(keyspace.root.children.len(), 1);
+        assert_eq!(keyspace.root.children[0].children.len(), 2);
+    }
+
+    #[test]
+    fn list_keys_in_order() {
+        let keyspace = keyspace(&["b", "abc", "a", "ab", "c"]);
+
+        assert_eq!(
+            keyspace.keys().collect::<Vec<_>>(),
+            vec!["a", "ab", "abc", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn scan_only_keys_under_a_prefix() {
+        let keyspace = keyspace(&[
+            "user:1:name",
+            "user:12:name",
+            "user:123:name",
+            "user:123:email",
+            "user:2:name",
+        ]);
+
+        let keys: Vec<_> = keyspace.scan("user:12", None).map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            vec!["user:123:email", "user:123:name", "user:12:name"]
+        );
+
+        let keys: Vec<_> = keyspace
+            .scan("user:123:", None)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["user:123:email", "user:123:name"]);
+
+        assert_eq!(keyspace.scan("users", None).count(), 0);
+    }
+
+    #[test]
+    fn resume_a_scan_after_a_key() {
+        let keyspace = keyspace(&["a", "ab", "abc", "b", "ba", "c"]);
+
+        let keys: Vec<_> = keyspace.scan("", Some("ab")).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["abc", "b", "ba", "c"]);
+
+        // The key the scan resumes after doesn't have to exist any more
+        let keys: Vec<_> = keyspace
+            .scan("b", Some("aaa"))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["b", "ba"]);
+        let keys: Vec<_> = keyspace.scan("", Some("bb")).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["c"]);
+    }
+}
+
+#[cfg(test)]
+mod glob_match_should {
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let string: Vec<char> = string.chars().collect();
+        glob_match(&pattern, &string)
+    }
+
+    #[test]
+    fn match_like_redis() {
+        assert!(matches("*", "anything"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(matches("h[b-a]llo", "hallo"));
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("user:*:name", "user:123:name"));
+        assert!(!matches("user:*:name", "user:123:email"));
+    }
+
+    #[test]
+    fn find_the_literal_prefix() {
+        assert_eq!(literal_prefix("user:123:*"), "user:123:");
+        assert_eq!(literal_prefix("*:name"), "");
+        assert_eq!(literal_prefix("exact"), "exact");
+        assert_eq!(literal_prefix("a\\*b"), "a");
+    }
+}
@@ -0,0 +1,207 @@
+// Latency monitoring, LATENCY LATEST / HISTORY / RESET
+//
+// Things that can hold up a database thread are timed as named events: commands, expire and
+// eviction cycles, single evictions and freeing values. While `latency-monitor-threshold` is set,
+// every event that takes at least that many milliseconds is kept, one sample a second per event
+// for the last 160 seconds that had one. With it at zero nothing is even timed.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How many samples each event keeps, like Redis
+const HISTORY_LEN: usize = 160;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySample {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// In milliseconds
+    pub latency: u64,
+}
+
+/// The history of one kind of event
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyEvent {
+    /// Oldest first
+    pub samples: VecDeque<LatencySample>,
+    /// The worst latency since the event was last reset, even if its sample has gone
+    pub max: u64,
+}
+
+impl LatencyEvent {
+    fn add(&mut self, sample: LatencySample) {
+        self.max = self.max.max(sample.latency);
+        // Events in the same second share a sample, which keeps the worst of them
+        if let Some(last) = self.samples.back_mut() {
+            if last.timestamp == sample.timestamp {
+                last.latency = last.latency.max(sample.latency);
+                return;
+            }
+        }
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn latest(&self) -> Option<LatencySample> {
+        self.samples.back().copied()
+    }
+}
+
+#[derive(Default)]
+pub struct LatencyMonitor {
+    // In milliseconds, zero turns the monitor off
+    threshold: AtomicU64,
+    events: Mutex<BTreeMap<String, LatencyEvent>>,
+}
+
+impl LatencyMonitor {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold: AtomicU64::new(threshold),
+            ..Self::default()
+        }
+    }
+
+    fn events(&self) -> MutexGuard<'_, BTreeMap<String, LatencyEvent>> {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_threshold(&self, millis: u64) {
+        self.threshold.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold() > 0
+    }
+
+    /// Runs something, keeping it as an event if it takes too long
+    pub fn time<T>(&self, event: &str, run: impl FnOnce() -> T) -> T {
+        if !self.is_enabled() {
+            return run();
+        }
+        let started = Instant::now();
+        let result = run();
+        self.add(event, started.elapsed());
+        result
+    }
+
+    /// Keeps an event if it took at least the threshold
+    pub fn add(&self, event: &str, took: Duration) {
+        let threshold = self.threshold();
+        let latency = took.as_millis() as u64;
+        if threshold == 0 || latency < threshold {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.add_sample(event, LatencySample { timestamp, latency });
+    }
+
+    fn add_sample(&self, event: &str, sample: LatencySample) {
+        self.events()
+            .entry(event.to_string())
+            .or_default()
+            .add(sample);
+    }
+
+    /// Every event that has been seen, by name
+    pub fn all(&self) -> BTreeMap<String, LatencyEvent> {
+        self.events().clone()
+    }
+
+    pub fn history(&self, event: &str) -> Vec<LatencySample> {
+        self.events()
+            .get(event)
+            .map(|event| event.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the events named, or all of them when none are, returning how many there were
+    pub fn reset(&self, events: &[String]) -> usize {
+        let mut all = self.events();
+        if events.is_empty() {
+            let count = all.len();
+            all.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| all.remove(event.as_str()).is_some())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod latency_should {
+    use super::*;
+
+    fn sample(timestamp: u64, latency: u64) -> LatencySample {
+        LatencySample { timestamp, latency }
+    }
+
+    #[test]
+    fn keep_events_over_the_threshold() {
+        let monitor = LatencyMonitor::new(10);
+        monitor.add("command", Duration::from_millis(9));
+        monitor.add("command", Duration::from_millis(10));
+        monitor.add("expire-cycle", Duration::from_millis(25));
+
+        let all = monitor.all();
+        assert_eq!(
+            all.keys().collect::<Vec<_>>(),
+            vec!["command", "expire-cycle"]
+        );
+        assert_eq!(all["command"].samples.len(), 1);
+        assert_eq!(all["command"].latest().map(|s| s.latency), Some(10));
+        assert_eq!(all["expire-cycle"].max, 25);
+    }
+
+    #[test]
+    fn keep_the_worst_of_each_second_for_the_last_160() {
+        let monitor = LatencyMonitor::new(1);
+        monitor.add_sample("command", sample(100, 5));
+        monitor.add_sample("command", sample(100, 30));
+        monitor.add_sample("command", sample(100, 7));
+        assert_eq!(monitor.history("command"), vec![sample(100, 30)]);
+
+        for second in 101..300 {
+            monitor.add_sample("command", sample(second, 2));
+        }
+        let history = monitor.history("command");
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history[0], sample(140, 2));
+        // The worst is remembered after its sample has gone
+        assert_eq!(monitor.all()["command"].max, 30);
+    }
+
+    #[test]
+    fn only_time_while_enabled() {
+        let monitor = LatencyMonitor::default();
+        monitor.add("command", Duration::from_secs(1));
+        assert_eq!(monitor.time("command", || 7), 7);
+        assert!(monitor.all().is_empty());
+    }
+
+    #[test]
+    fn reset_named_events_or_all_of_them() {
+        let monitor = LatencyMonitor::new(1);
+        for event in ["command", "expire-cycle", "eviction-del"] {
+            monitor.add(event, Duration::from_millis(5));
+        }
+        assert_eq!(monitor.reset(&["command".into(), "nonsense".into()]), 1);
+        assert_eq!(monitor.reset(&[]), 2);
+        assert!(monitor.all().is_empty());
+    }
+}
@@ -1,86 +1,332 @@
+use db_logic::sorted_sets::SortedSet;
+use db_logic::streams::Stream;
+use embedded::{AsyncDb, Db};
+use keyspace::{Keyspace, ScanCursors};
+use memory::{KeyMeta, Rng, SampledMap};
 use rustdss_data::{Command, Key, RespData};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 mod base_logic;
 mod db_logic;
+pub mod embedded;
+pub mod keyspace;
+pub mod latency;
+pub mod memory;
+pub mod replication;
+pub mod sharding;
+pub mod slowlog;
+pub mod stats;
+#[cfg(test)]
+mod test_helpers;
+
+pub use latency::LatencyMonitor;
+pub use memory::{EvictionPolicy, Memory};
+pub use replication::Replication;
+pub use slowlog::{Caller, SlowLog};
+pub use stats::Stats;
 
 pub type DatabaseId = String;
 
-pub type Message = (DatabaseId, Command, Sender<RespData>);
+/// A command for a database, where to send the reply, and who it's from if anyone
+pub type Message = (
+    DatabaseId,
+    Command,
+    Sender<RespData>,
+    Option<Arc<dyn Caller>>,
+);
 // This is the stateful part of the application
 pub struct Core {
     sender: Sender<Message>,
+    memory: Arc<Memory>,
+    replication: Arc<Replication>,
+    stats: Arc<Stats>,
+    slowlog: Arc<SlowLog>,
+    latency: Arc<LatencyMonitor>,
 }
 
+#[derive(Default)]
 pub struct CoreState {
-    keyval: HashMap<Key, RespData>,
+    keyval: Keyspace<RespData>,
+    // Values that can't be represented as RespData get their own keyspaces, a key only ever
+    // lives in one of these at a time.
+    streams: Keyspace<Stream>,
+    sorted_sets: Keyspace<SortedSet>,
+    sets: Keyspace<HashSet<String>>,
+    // When each key with a TTL expires
+    expires: SampledMap<Instant>,
+    // Size and access information for every key, see the memory module
+    meta: SampledMap<KeyMeta>,
+    memory: Arc<Memory>,
+    rng: Rng,
+    scan_cursors: ScanCursors,
+    // Where writes are sent on to replicas, and the database they're for
+    replication: Arc<Replication>,
+    database_id: DatabaseId,
+    // Counters shared by every database, for INFO
+    stats: Arc<Stats>,
+    // Where slow expire cycles, evictions and frees are noted
+    latency: Arc<LatencyMonitor>,
 }
 
-impl Core {
-    fn create_database(db_id: String) -> Sender<(Command, Sender<RespData>)> {
-        let (db_sender, db_reciever) = channel::<(Command, Sender<RespData>)>();
+impl CoreState {
+    fn for_database(
+        database_id: DatabaseId,
+        memory: Arc<Memory>,
+        replication: Arc<Replication>,
+        stats: Arc<Stats>,
+        latency: Arc<LatencyMonitor>,
+    ) -> Self {
+        Self {
+            memory,
+            replication,
+            database_id,
+            stats,
+            latency,
+            ..Self::default()
+        }
+    }
 
-        thread::spawn(move || {
-            let mut db_state = CoreState {
-                keyval: HashMap::new(),
-            };
-            loop {
-                if let Ok(msg) = db_reciever.recv() {
-                    let (cmd, responder) = msg;
-                    let response = base_logic::core_logic(&mut db_state, cmd);
-                    responder
-                        .send(response)
-                        .expect(format!("[core::{}] can't reply to messages", db_id).as_str());
-                } else {
-                    println!("[core::{}] db_core died/dropped?", db_id);
-                    break;
-                }
+    /// Sends a write on to replicas
+    fn replicate(&self, args: &[String]) {
+        self.replication.propagate(&self.database_id, args);
+    }
+
+    /// True when the key holds a value that isn't stored in `keyval`
+    fn has_typed_value(&self, key: &str) -> bool {
+        self.streams.contains_key(key)
+            || self.sorted_sets.contains_key(key)
+            || self.sets.contains_key(key)
+    }
+
+    /// The name TYPE would give the value held at a key
+    fn value_type(&self, key: &str) -> Option<&'static str> {
+        if self.streams.contains_key(key) {
+            Some("stream")
+        } else if self.sorted_sets.contains_key(key) {
+            Some("zset")
+        } else if self.sets.contains_key(key) {
+            Some("set")
+        } else {
+            match self.keyval.get(key)? {
+                RespData::List(_) => Some("list"),
+                _ => Some("string"),
             }
-        });
+        }
+    }
+
+    /// Removes a key whatever type of value it holds, returning true if it existed
+    fn remove_key(&mut self, key: &str) -> bool {
+        self.expires.remove(key);
+        // Dropping a big value can take a while, so that's an event of its own
+        let latency = self.latency.clone();
+        latency.time("key-free", || {
+            self.keyval.remove(key).is_some()
+                | self.streams.remove(key).is_some()
+                | self.sorted_sets.remove(key).is_some()
+                | self.sets.remove(key).is_some()
+        })
+    }
+
+    /// Every key in the database, whatever type of value it holds
+    fn key_names(&self) -> Vec<Key> {
+        self.keyval
+            .keys()
+            .chain(self.streams.keys())
+            .chain(self.sorted_sets.keys())
+            .chain(self.sets.keys())
+            .cloned()
+            .collect()
+    }
+
+    /// Takes a key out of the database along with its TTL and accounting, so that it can be put
+    /// back somewhere else
+    fn take_key(&mut self, key: &str) -> Option<KeyEntry> {
+        let value = if let Some(value) = self.keyval.remove(key) {
+            Value::Plain(value)
+        } else if let Some(stream) = self.streams.remove(key) {
+            Value::Stream(stream)
+        } else if let Some(set) = self.sorted_sets.remove(key) {
+            Value::SortedSet(set)
+        } else {
+            Value::Set(self.sets.remove(key)?)
+        };
+        Some(KeyEntry {
+            value,
+            expires_at: self.expires.remove(key),
+            meta: self.meta.remove(key),
+        })
+    }
+
+    /// Stores a key taken by `take_key`, replacing whatever was there
+    fn put_key(&mut self, key: Key, entry: KeyEntry) {
+        self.remove_key(&key);
+        self.forget_key(&key);
+        if let Some(expires_at) = entry.expires_at {
+            self.expires.insert(key.clone(), expires_at);
+        }
+        if let Some(meta) = entry.meta {
+            self.meta.insert(key.clone(), meta);
+        }
+        match entry.value {
+            Value::Plain(value) => self.keyval.insert(key, value).map(|_| ()),
+            Value::Stream(stream) => self.streams.insert(key, stream).map(|_| ()),
+            Value::SortedSet(set) => self.sorted_sets.insert(key, set).map(|_| ()),
+            Value::Set(set) => self.sets.insert(key, set).map(|_| ()),
+        };
+    }
+}
+
+enum Value {
+    Plain(RespData),
+    Stream(Stream),
+    SortedSet(SortedSet),
+    Set(HashSet<String>),
+}
+
+/// A key's value and everything else the database knows about it
+struct KeyEntry {
+    value: Value,
+    expires_at: Option<Instant>,
+    meta: Option<KeyMeta>,
+}
+
+impl From<HashMap<Key, RespData>> for CoreState {
+    fn from(keyval: HashMap<Key, RespData>) -> Self {
+        Self {
+            keyval: keyval.into(),
+            ..Self::default()
+        }
+    }
+}
+
+/// How the core should be set up
+pub struct CoreOptions {
+    pub memory: Memory,
+    pub slowlog: SlowLog,
+    pub latency: LatencyMonitor,
+    // How many worker threads each database's keyspace is split across
+    pub shards: usize,
+}
 
-        db_sender
+impl Default for CoreOptions {
+    fn default() -> Self {
+        Self {
+            memory: Memory::default(),
+            slowlog: SlowLog::default(),
+            latency: LatencyMonitor::default(),
+            shards: thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1),
+        }
     }
+}
+
+impl Core {
     pub fn start() -> Self {
-        // Could do something interesting using a threadpool - key-hash sharding for example
-        println!("[core] starting core");
+        Self::start_with(CoreOptions::default())
+    }
+
+    pub fn start_with(options: CoreOptions) -> Self {
+        log::info!(
+            "Starting the core with {} shards per database",
+            options.shards
+        );
         let (sender, reciever) = channel::<Message>();
+        let memory = Arc::new(options.memory);
+        let core_memory = memory.clone();
+        let replication = Arc::new(Replication::default());
+        let core_replication = replication.clone();
+        let stats = Arc::new(Stats::default());
+        let core_stats = stats.clone();
+        let slowlog = Arc::new(options.slowlog);
+        let core_slowlog = slowlog.clone();
+        let latency = Arc::new(options.latency);
+        let core_latency = latency.clone();
+        let shards = options.shards.max(1);
 
-        // Each database get's it's own thread
+        // Each database gets its own set of threads
         thread::spawn(move || {
             // This thread needs to keep track of all the databases available
-            // each database needs it's own CoreState
-            let mut databases: HashMap<DatabaseId, Sender<(Command, Sender<RespData>)>> =
+            let mut databases: HashMap<DatabaseId, Sender<sharding::DatabaseMessage>> =
                 HashMap::new();
 
-            databases.insert("default".into(), Self::create_database("default".into()));
-
-            loop {
-                if let Ok(msg) = reciever.recv() {
-                    let (database_id, cmd, responder) = msg;
-
-                    if let Some(db_sender) = databases.get(&database_id) {
-                        db_sender
-                            .send((cmd, responder))
-                            .expect("[core::router] Can't send to database");
-                    } else {
-                        let newdb_sender = Self::create_database(database_id.clone());
-                        databases.insert(database_id, newdb_sender.clone());
-
-                        newdb_sender
-                            .send((cmd, responder))
-                            .expect("[core::router] Can't send to new database");
-                    }
-                } else {
-                    println!("[core] death");
-                }
+            databases.insert(
+                "0".into(),
+                sharding::start_database(
+                    "0".into(),
+                    core_memory.clone(),
+                    core_replication.clone(),
+                    core_stats.clone(),
+                    core_slowlog.clone(),
+                    core_latency.clone(),
+                    shards,
+                ),
+            );
+
+            // Ends once everything that could send commands has gone, taking the databases with it
+            for (database_id, cmd, responder, caller) in reciever {
+                let db_sender = databases.entry(database_id.clone()).or_insert_with(|| {
+                    sharding::start_database(
+                        database_id,
+                        core_memory.clone(),
+                        core_replication.clone(),
+                        core_stats.clone(),
+                        core_slowlog.clone(),
+                        core_latency.clone(),
+                        shards,
+                    )
+                });
+                db_sender
+                    .send((cmd, responder, caller))
+                    .expect("[core::router] Can't send to database");
             }
+            log::debug!("The core stopped, nothing can send it commands");
         });
-        Self { sender: sender }
+        Self {
+            sender,
+            memory,
+            replication,
+            stats,
+            slowlog,
+            latency,
+        }
     }
 
     pub fn get_sender(&self) -> Sender<Message> {
         self.sender.clone()
     }
+
+    /// A typed client for one of the databases, for using the core in-process
+    pub fn db(&self, database_id: &str) -> Db {
+        Db::new(self.get_sender(), database_id.into())
+    }
+
+    /// Like `db`, but each command gives back a future
+    pub fn async_db(&self, database_id: &str) -> AsyncDb {
+        AsyncDb::new(self.get_sender(), database_id.into())
+    }
+
+    pub fn memory(&self) -> Arc<Memory> {
+        self.memory.clone()
+    }
+
+    pub fn replication(&self) -> Arc<Replication> {
+        self.replication.clone()
+    }
+
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn slowlog(&self) -> Arc<SlowLog> {
+        self.slowlog.clone()
+    }
+
+    pub fn latency(&self) -> Arc<LatencyMonitor> {
+        self.latency.clone()
+    }
 }
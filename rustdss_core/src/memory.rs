@@ -0,0 +1,702 @@
+// Memory accounting and eviction
+//
+// Every database thread keeps an estimate of how much memory each of its keys uses, and adds it
+// to a total shared by all of them. When the total goes over `maxmemory`, the thread about to run
+// a command that needs more memory evicts keys from its own database until it's back under the
+// limit. Like Redis, eviction picks the best of a few randomly sampled keys rather than keeping
+// the keys in LRU/LFU order.
+
+use crate::CoreState;
+use rustdss_data::{Key, RespData};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// How many keys are looked at to pick each one that gets evicted
+const EVICTION_SAMPLES: usize = 5;
+// How many items of a collection are measured to estimate its size
+const SIZE_SAMPLES: usize = 16;
+// Roughly what the hash table entry and value header cost for every key
+const KEY_OVERHEAD: usize = 48;
+
+// See https://redis.io/docs/reference/eviction/#the-new-lfu-mode
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_SECS: u64 = 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EvictionPolicy {
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+const POLICIES: [EvictionPolicy; 8] = [
+    EvictionPolicy::NoEviction,
+    EvictionPolicy::AllKeysLru,
+    EvictionPolicy::VolatileLru,
+    EvictionPolicy::AllKeysLfu,
+    EvictionPolicy::VolatileLfu,
+    EvictionPolicy::AllKeysRandom,
+    EvictionPolicy::VolatileRandom,
+    EvictionPolicy::VolatileTtl,
+];
+
+impl EvictionPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    fn is_lfu(&self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu
+        )
+    }
+
+    /// Whether keys without an expiry can be evicted
+    fn all_keys(&self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::AllKeysLru | EvictionPolicy::AllKeysLfu | EvictionPolicy::AllKeysRandom
+        )
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        POLICIES
+            .iter()
+            .find(|policy| policy.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown maxmemory policy '{}'", name))
+    }
+}
+
+/// Parses a memory size like `100mb` or `1gb`. Like Redis, `k`/`m`/`g` are powers of 1000 and
+/// `kb`/`mb`/`gb` are powers of 1024.
+pub fn parse_memory(size: &str) -> Option<usize> {
+    let size = size.to_lowercase();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// The memory limit and usage, shared by every database thread
+#[derive(Default)]
+pub struct Memory {
+    // Zero means there's no limit
+    maxmemory: AtomicUsize,
+    policy: AtomicU8,
+    used: AtomicUsize,
+    evicted_keys: AtomicU64,
+    expired_keys: AtomicU64,
+}
+
+impl Memory {
+    pub fn new(maxmemory: usize, policy: EvictionPolicy) -> Self {
+        let memory = Self::default();
+        memory.set_maxmemory(maxmemory);
+        memory.set_policy(policy);
+        memory
+    }
+
+    pub fn maxmemory(&self) -> usize {
+        self.maxmemory.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maxmemory(&self, maxmemory: usize) {
+        self.maxmemory.store(maxmemory, Ordering::Relaxed)
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        POLICIES[self.policy.load(Ordering::Relaxed) as usize]
+    }
+
+    pub fn set_policy(&self, policy: EvictionPolicy) {
+        let index = POLICIES.iter().position(|p| *p == policy).unwrap_or(0);
+        self.policy.store(index as u8, Ordering::Relaxed)
+    }
+
+    /// Estimated bytes used by the values in every database
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn over_limit(&self) -> bool {
+        let maxmemory = self.maxmemory();
+        maxmemory > 0 && self.used() > maxmemory
+    }
+
+    fn grow(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Zeroes the eviction and expiry counts, for CONFIG RESETSTAT
+    pub fn reset_stats(&self) {
+        self.evicted_keys.store(0, Ordering::Relaxed);
+        self.expired_keys.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_expired(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn out_of_memory() -> RespData {
+    RespData::Error("OOM command not allowed when used memory > 'maxmemory'.".into())
+}
+
+/// A small xorshift generator, good enough for picking keys to sample
+pub struct Rng(u64);
+
+impl Default for Rng {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng(seed | 1)
+    }
+}
+
+impl Rng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A map that can also hand out random entries in constant time
+pub struct SampledMap<V> {
+    entries: Vec<(Key, V)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl<V> Default for SampledMap<V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<V> SampledMap<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.positions.get(key).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        match self.positions.get(key) {
+            Some(i) => Some(&mut self.entries[*i].1),
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: Key, value: V) -> Option<V> {
+        if let Some(existing) = self.get_mut(&key) {
+            return Some(std::mem::replace(existing, value));
+        }
+        self.positions.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let index = self.positions.remove(key)?;
+        let (_, value) = self.entries.swap_remove(index);
+        if let Some((moved, _)) = self.entries.get(index) {
+            self.positions.insert(moved.clone(), index);
+        }
+        Some(value)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> Option<(&Key, &V)> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let (key, value) = &self.entries[rng.below(self.entries.len())];
+        Some((key, value))
+    }
+
+    /// `count` random keys, or every key when there aren't more than that
+    pub fn sample_keys(&self, count: usize, rng: &mut Rng) -> Vec<&Key> {
+        if self.entries.len() <= count {
+            self.entries.iter().map(|(key, _)| key).collect()
+        } else {
+            (0..count)
+                .filter_map(|_| self.sample(rng).map(|(key, _)| key))
+                .collect()
+        }
+    }
+}
+
+/// What's known about each key, for accounting and eviction
+pub struct KeyMeta {
+    size: usize,
+    last_access: Instant,
+    lfu_counter: u8,
+    lfu_decremented: Instant,
+}
+
+impl KeyMeta {
+    fn new(now: Instant) -> Self {
+        Self {
+            size: 0,
+            last_access: now,
+            lfu_counter: LFU_INIT_VAL,
+            lfu_decremented: now,
+        }
+    }
+
+    pub fn idle_secs(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.last_access).as_secs()
+    }
+
+    /// The access frequency counter, after it has decayed for the time since it was last used
+    pub fn frequency(&self, now: Instant) -> u8 {
+        let periods = now
+            .saturating_duration_since(self.lfu_decremented)
+            .as_secs()
+            / LFU_DECAY_SECS;
+        self.lfu_counter
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    fn touch(&mut self, now: Instant, rng: &mut Rng) {
+        let counter = self.frequency(now);
+        // The counter is logarithmic, the higher it is the less likely it is to go up
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        let counter = if counter < u8::MAX && rng.unit() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            counter + 1
+        } else {
+            counter
+        };
+        self.lfu_counter = counter;
+        self.lfu_decremented = now;
+        self.last_access = now;
+    }
+}
+
+/// Estimates the size of a collection from its first few items
+pub(crate) fn sampled_size(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (measured, total) = sizes
+        .take(SIZE_SAMPLES)
+        .fold((0, 0), |(count, total), size| (count + 1, total + size));
+    (total * len).checked_div(measured).unwrap_or(0)
+}
+
+fn resp_size(value: &RespData) -> usize {
+    match value {
+        RespData::Error(string) | RespData::SimpleStr(string) | RespData::BulkStr(string) => {
+            16 + string.len()
+        }
+        RespData::Number(_) => 8,
+        RespData::List(items) => 32 + sampled_size(items.len(), items.iter().map(resp_size)),
+        RespData::NullString => 0,
+    }
+}
+
+impl CoreState {
+    /// Estimated bytes used by a key and its value, if it exists
+    fn key_size(&self, key: &str) -> Option<usize> {
+        let value = if let Some(stream) = self.streams.get(key) {
+            stream.memory_usage()
+        } else if let Some(set) = self.sorted_sets.get(key) {
+            set.memory_usage()
+        } else if let Some(set) = self.sets.get(key) {
+            32 + sampled_size(set.len(), set.iter().map(|member| 32 + member.len()))
+        } else {
+            resp_size(self.keyval.get(key)?)
+        };
+        Some(KEY_OVERHEAD + key.len() + value)
+    }
+
+    /// Updates the accounting for keys a command has used, and their access time when `touch`
+    pub(crate) fn track_keys(&mut self, keys: &[Key], touch: bool) {
+        let now = Instant::now();
+        for key in keys {
+            match self.key_size(key) {
+                Some(size) => {
+                    if self.meta.get(key).is_none() {
+                        self.meta.insert(key.clone(), KeyMeta::new(now));
+                    }
+                    let meta = self.meta.get_mut(key).expect("just inserted");
+                    if size > meta.size {
+                        self.memory.grow(size - meta.size);
+                    } else {
+                        self.memory.shrink(meta.size - size);
+                    }
+                    meta.size = size;
+                    if touch {
+                        meta.touch(now, &mut self.rng);
+                    }
+                }
+                None => self.forget_key(key),
+            }
+        }
+    }
+
+    /// Drops the accounting for a key that no longer exists
+    pub(crate) fn forget_key(&mut self, key: &str) {
+        if let Some(meta) = self.meta.remove(key) {
+            self.memory.shrink(meta.size);
+        }
+        self.expires.remove(key);
+    }
+
+    pub(crate) fn forget_all_keys(&mut self) {
+        let total = self.meta.entries.iter().map(|(_, meta)| meta.size).sum();
+        self.memory.shrink(total);
+        self.meta.clear();
+        self.expires.clear();
+    }
+
+    /// Evicts keys until memory use is under the limit, returning false if that isn't possible
+    pub(crate) fn make_room(&mut self) -> bool {
+        if !self.memory.over_limit() {
+            return true;
+        }
+        let latency = self.latency.clone();
+        latency.time("eviction-cycle", || {
+            while self.memory.over_limit() {
+                match self.pick_eviction() {
+                    Some((_, key)) => self.evict(&key),
+                    None => return false,
+                }
+            }
+            true
+        })
+    }
+
+    /// Removes a key to free memory, telling replicas it's gone
+    pub(crate) fn evict(&mut self, key: &Key) {
+        let latency = self.latency.clone();
+        latency.time("eviction-del", || {
+            let removed = self.remove_key(key);
+            // Forgotten either way, so a key that's already gone can't be picked again
+            self.forget_key(key);
+            if removed {
+                self.memory.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                self.replicate(&["DEL".into(), key.clone()]);
+            }
+        })
+    }
+
+    /// The best key to evict out of a sample, with how good a candidate it is, higher being
+    /// better. Ranks from different shards of a database can be compared.
+    pub(crate) fn pick_eviction(&mut self) -> Option<(u64, Key)> {
+        let policy = self.memory.policy();
+        if policy == EvictionPolicy::NoEviction {
+            return None;
+        }
+        let sampled: Vec<Key> = if policy.all_keys() {
+            self.meta.sample_keys(EVICTION_SAMPLES, &mut self.rng)
+        } else {
+            self.expires.sample_keys(EVICTION_SAMPLES, &mut self.rng)
+        }
+        .into_iter()
+        .cloned()
+        .collect();
+
+        let now = Instant::now();
+        let mut best: Option<(u64, Key)> = None;
+        for key in sampled {
+            // Higher is a better candidate for eviction
+            let rank = match policy {
+                EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+                    self.rng.next_u64()
+                }
+                EvictionPolicy::VolatileTtl => {
+                    let expires_at = self.expires.get(&key)?;
+                    u64::MAX - expires_at.saturating_duration_since(now).as_millis() as u64
+                }
+                _ => match self.meta.get(&key) {
+                    Some(meta) if policy.is_lfu() => 255 - meta.frequency(now) as u64,
+                    Some(meta) => {
+                        now.saturating_duration_since(meta.last_access).as_millis() as u64
+                    }
+                    // Keys that haven't been accounted for yet have never been used
+                    None => u64::MAX,
+                },
+            };
+            if best.as_ref().map(|(best, _)| rank > *best).unwrap_or(true) {
+                best = Some((rank, key));
+            }
+        }
+        best
+    }
+}
+
+/// OBJECT IDLETIME|FREQ key
+pub fn object(state: &CoreState, subcommand: &str, key: &Key) -> RespData {
+    let policy = state.memory.policy();
+    let now = Instant::now();
+    match subcommand.to_lowercase().as_str() {
+        "idletime" if policy.is_lfu() => RespData::Error(
+            "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when \
+             switching between policies at runtime LRU and LFU data will take some time to adjust."
+                .into(),
+        ),
+        "freq" if !policy.is_lfu() => RespData::Error(
+            "ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note \
+             that when switching between policies at runtime LRU and LFU data will take some time \
+             to adjust."
+                .into(),
+        ),
+        "idletime" | "freq" if state.value_type(key).is_none() => RespData::nil(),
+        "idletime" => RespData::Number(
+            state
+                .meta
+                .get(key)
+                .map(|meta| meta.idle_secs(now))
+                .unwrap_or(0) as i64,
+        ),
+        "freq" => RespData::Number(
+            state
+                .meta
+                .get(key)
+                .map(|meta| meta.frequency(now))
+                .unwrap_or(LFU_INIT_VAL) as i64,
+        ),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+            subcommand
+        )),
+    }
+}
+
+#[cfg(test)]
+mod parse_memory_should {
+    use super::*;
+
+    #[test]
+    fn understand_redis_units() {
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("1kb"), Some(1024));
+        assert_eq!(parse_memory("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Some(1_000_000_000));
+        assert_eq!(parse_memory("lots"), None);
+        assert_eq!(parse_memory("1tb"), None);
+    }
+}
+
+#[cfg(test)]
+mod sampled_map_should {
+    use super::*;
+
+    #[test]
+    fn keep_its_index_in_step_when_removing() {
+        let mut map = SampledMap::default();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        map.insert("c".to_string(), 3);
+
+        assert_eq!(map.remove("a"), Some(1));
+
+        assert_eq!(map.get("b"), Some(&2));
+        assert_eq!(map.get("c"), Some(&3));
+        assert_eq!(map.len(), 2);
+        let mut rng = Rng::default();
+        for _ in 0..10 {
+            assert_ne!(map.sample(&mut rng).map(|(key, _)| key.as_str()), Some("a"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod eviction_should {
+    use super::*;
+    use std::sync::Arc;
+
+    fn limited_state(maxmemory: usize, policy: EvictionPolicy) -> CoreState {
+        CoreState {
+            memory: Arc::new(Memory::new(maxmemory, policy)),
+            ..CoreState::default()
+        }
+    }
+
+    fn set(state: &mut CoreState, key: &str) {
+        state
+            .keyval
+            .insert(key.into(), RespData::BulkStr("x".repeat(100)));
+        state.track_keys(&[key.into()], true);
+    }
+
+    #[test]
+    fn account_for_keys_as_they_change() {
+        let mut state = limited_state(0, EvictionPolicy::NoEviction);
+
+        set(&mut state, "a");
+        let one_key = state.memory.used();
+        set(&mut state, "b");
+        assert_eq!(state.memory.used(), one_key * 2);
+
+        state.keyval.remove("a");
+        state.track_keys(&["a".into()], false);
+        assert_eq!(state.memory.used(), one_key);
+
+        state.forget_all_keys();
+        assert_eq!(state.memory.used(), 0);
+    }
+
+    #[test]
+    fn refuse_to_evict_under_noeviction() {
+        let mut state = limited_state(1, EvictionPolicy::NoEviction);
+        set(&mut state, "a");
+
+        assert!(!state.make_room());
+        assert!(state.keyval.contains_key("a"));
+    }
+
+    #[test]
+    fn evict_any_key_under_allkeys_policies() {
+        let mut state = limited_state(500, EvictionPolicy::AllKeysLru);
+        for key in ["a", "b", "c", "d", "e"] {
+            set(&mut state, key);
+        }
+
+        assert!(state.make_room());
+
+        assert!(state.memory.used() <= 500);
+        assert!(state.keyval.len() < 5);
+        assert_eq!(state.memory.evicted_keys(), 5 - state.keyval.len() as u64);
+    }
+
+    #[test]
+    fn only_evict_keys_with_an_expiry_under_volatile_policies() {
+        let mut state = limited_state(1, EvictionPolicy::VolatileTtl);
+        set(&mut state, "persistent");
+        set(&mut state, "soon");
+        set(&mut state, "later");
+        let now = Instant::now();
+        state
+            .expires
+            .insert("soon".into(), now + std::time::Duration::from_secs(10));
+        state
+            .expires
+            .insert("later".into(), now + std::time::Duration::from_secs(1000));
+
+        assert!(!state.make_room());
+
+        assert!(state.keyval.contains_key("persistent"));
+        assert_eq!(state.keyval.len(), 1);
+    }
+
+    #[test]
+    fn prefer_the_least_recently_used_key() {
+        let mut state = limited_state(0, EvictionPolicy::AllKeysLru);
+        set(&mut state, "old");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        set(&mut state, "new");
+        state.memory.set_maxmemory(state.memory.used() - 1);
+
+        assert!(state.make_room());
+
+        assert_eq!(state.keyval.keys().collect::<Vec<_>>(), vec!["new"]);
+    }
+}
+
+#[cfg(test)]
+mod object_should {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn report_frequency_only_under_lfu() {
+        let mut state = CoreState::default();
+        state.keyval.insert("a".into(), RespData::Number(1));
+        state.track_keys(&["a".into()], true);
+
+        assert_eq!(object(&state, "IDLETIME", &"a".into()), RespData::Number(0));
+        assert!(matches!(
+            object(&state, "FREQ", &"a".into()),
+            RespData::Error(_)
+        ));
+
+        state.memory = Arc::new(Memory::new(0, EvictionPolicy::AllKeysLfu));
+        assert!(matches!(
+            object(&state, "FREQ", &"a".into()),
+            RespData::Number(5..=6)
+        ));
+        assert_eq!(object(&state, "FREQ", &"missing".into()), RespData::nil());
+    }
+}
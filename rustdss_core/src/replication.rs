@@ -0,0 +1,869 @@
+// Master/replica replication
+//
+// Every write that changes the data is turned back into the command a client would have sent and
+// appended to the replication stream, with a SELECT in front whenever the database changes. The
+// stream feeds each connected replica and a fixed size backlog. A replica that lost its
+// connection asks to carry on from its offset (PSYNC replid offset) and is sent what it missed
+// from the backlog. Anything else gets a full resync: a snapshot of every database, as the
+// commands that rebuild it, followed by the stream from the offset the snapshot was taken at.
+//
+// Replicas don't write their own stream, they pass on the master's byte for byte, so that their
+// offsets match the master's and their own replicas can resync against either.
+
+use crate::db_logic::{admin, expiry, geo};
+use crate::{CoreState, DatabaseId};
+use rustdss_data::{Command, RespData};
+use rustdss_transport::serialise::SerialiseRespData;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// A new random id, 40 hex characters like Redis's replication and cluster node ids
+pub fn random_id() -> String {
+    (0..3)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect::<String>()[..40]
+        .to_string()
+}
+
+fn encode(args: &[String]) -> Vec<u8> {
+    RespData::List(args.iter().cloned().map(RespData::BulkStr).collect()).as_bytes()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkState {
+    /// Waiting to connect to the master
+    Connect,
+    /// Connected, handshaking with the master
+    Connecting,
+    /// Receiving the master's snapshot
+    Sync,
+    /// Following the master's stream
+    Connected,
+}
+
+impl LinkState {
+    fn name(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    Master,
+    Replica {
+        host: String,
+        port: u16,
+        link: LinkState,
+    },
+}
+
+struct ReplicaFeed {
+    id: u64,
+    sender: Sender<Vec<u8>>,
+    address: String,
+    port: u16,
+    ack_offset: u64,
+    last_ack: Instant,
+}
+
+/// How the connection to a replica gets the replication stream
+pub struct ReplicaStream {
+    pub id: u64,
+    /// Sent first, before anything from `feed`: the snapshot or the missed part of the backlog
+    pub initial: Vec<u8>,
+    pub feed: Receiver<Vec<u8>>,
+}
+
+struct ReplicationState {
+    role: Role,
+    replid: String,
+    // The id of the previous master, which replicas can still resync against up to
+    // `second_offset`
+    replid2: String,
+    second_offset: Option<u64>,
+    // How many bytes have ever been written to the stream
+    offset: u64,
+    backlog: VecDeque<u8>,
+    backlog_size: usize,
+    // The database the last command in the stream was for
+    selected: Option<DatabaseId>,
+    replicas: Vec<ReplicaFeed>,
+    last_replica_id: u64,
+}
+
+impl ReplicationState {
+    fn append(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        self.backlog.extend(bytes);
+        let excess = self.backlog.len().saturating_sub(self.backlog_size);
+        self.backlog.drain(..excess);
+        // Replicas that have gone away are dropped the next time there's something to send
+        self.replicas
+            .retain(|replica| replica.sender.send(bytes.to_vec()).is_ok());
+    }
+
+    fn add_replica(&mut self, address: &str, port: u16) -> (u64, Receiver<Vec<u8>>) {
+        let (sender, feed) = channel();
+        self.last_replica_id += 1;
+        self.replicas.push(ReplicaFeed {
+            id: self.last_replica_id,
+            sender,
+            address: address.into(),
+            port,
+            ack_offset: 0,
+            last_ack: Instant::now(),
+        });
+        (self.last_replica_id, feed)
+    }
+
+    fn can_continue_from(&self, replid: &str, offset: u64) -> bool {
+        let known_id = replid == self.replid
+            || (replid == self.replid2 && self.second_offset.is_some_and(|max| offset <= max));
+        let backlog_start = self.offset - self.backlog.len() as u64 + 1;
+        known_id && offset >= backlog_start && offset <= self.offset + 1
+    }
+
+    fn new_history(&mut self) {
+        self.replid2 = std::mem::replace(&mut self.replid, random_id());
+        self.second_offset = Some(self.offset + 1);
+    }
+}
+
+// A database's shards, held weakly
+pub(crate) type DatabaseShards = (DatabaseId, Vec<Weak<Mutex<CoreState>>>);
+
+pub struct Replication {
+    state: Mutex<ReplicationState>,
+    acked: Condvar,
+    // Every shard of every database, so a snapshot can be taken with all of them locked. The
+    // shards hold on to the Replication, so these mustn't hold on to the shards.
+    databases: Mutex<Vec<DatabaseShards>>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new(DEFAULT_BACKLOG_SIZE)
+    }
+}
+
+impl Replication {
+    pub fn new(backlog_size: usize) -> Self {
+        Self {
+            state: Mutex::new(ReplicationState {
+                role: Role::Master,
+                replid: random_id(),
+                replid2: "0".repeat(40),
+                second_offset: None,
+                offset: 0,
+                backlog: VecDeque::new(),
+                backlog_size,
+                selected: None,
+                replicas: vec![],
+                last_replica_id: 0,
+            }),
+            acked: Condvar::new(),
+            databases: Mutex::new(vec![]),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ReplicationState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn add_database(&self, id: DatabaseId, shards: &[Arc<Mutex<CoreState>>]) {
+        self.databases
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((id, shards.iter().map(Arc::downgrade).collect()));
+    }
+
+    /// Runs `f` with every shard of every database locked, so nothing can change underneath it
+    fn with_all_shards<T>(&self, f: impl FnOnce(Vec<(&DatabaseId, &mut CoreState)>) -> T) -> T {
+        let databases = self
+            .databases
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let shards: Vec<(&DatabaseId, Arc<Mutex<CoreState>>)> = databases
+            .iter()
+            .flat_map(|(id, shards)| {
+                shards
+                    .iter()
+                    .filter_map(move |shard| Some((id, shard.upgrade()?)))
+            })
+            .collect();
+        // Shards are always locked in the same order, see sharding
+        let mut guards: Vec<(&DatabaseId, MutexGuard<CoreState>)> = shards
+            .iter()
+            .map(|(id, shard)| (*id, crate::sharding::lock(shard)))
+            .collect();
+        f(guards
+            .iter_mut()
+            .map(|(id, guard)| (*id, &mut **guard))
+            .collect())
+    }
+
+    /// Adds a write to the stream. Replicas only pass on their master's stream, so this does
+    /// nothing on them.
+    pub fn propagate(&self, database_id: &str, args: &[String]) {
+        let mut state = self.lock();
+        if state.role != Role::Master {
+            return;
+        }
+        if state.selected.as_deref() != Some(database_id) {
+            state.selected = Some(database_id.into());
+            state.append(&encode(&["SELECT".into(), database_id.into()]));
+        }
+        state.append(&encode(args));
+    }
+
+    /// Adds bytes from the master's stream, on a replica
+    pub fn append_raw(&self, bytes: &[u8]) {
+        self.lock().append(bytes);
+    }
+
+    /// Starts streaming to a new replica with a snapshot of every database
+    pub fn full_sync(&self, address: &str, port: u16) -> (String, u64, ReplicaStream) {
+        self.with_all_shards(|shards| {
+            let mut snapshot = vec![];
+            let mut last_database = None;
+            for (database_id, state) in shards {
+                if last_database != Some(database_id) {
+                    snapshot.extend(encode(&["SELECT".into(), database_id.clone()]));
+                    last_database = Some(database_id);
+                }
+                for args in rebuild_commands(state) {
+                    snapshot.extend(encode(&args));
+                }
+            }
+
+            let mut state = self.lock();
+            let (id, feed) = state.add_replica(address, port);
+            // The replica's idea of the selected database is whatever the snapshot left it on
+            state.selected = None;
+            let mut initial = format!("${}\r\n", snapshot.len()).into_bytes();
+            initial.extend(snapshot);
+            (
+                state.replid.clone(),
+                state.offset,
+                ReplicaStream { id, initial, feed },
+            )
+        })
+    }
+
+    /// Starts streaming to a replica from where it got to, if the backlog still goes back that
+    /// far. `offset` is the first byte the replica hasn't seen.
+    pub fn partial_sync(
+        &self,
+        replid: &str,
+        offset: u64,
+        address: &str,
+        port: u16,
+    ) -> Option<(String, ReplicaStream)> {
+        let mut state = self.lock();
+        if !state.can_continue_from(replid, offset) {
+            return None;
+        }
+        let skip = (offset - (state.offset - state.backlog.len() as u64 + 1)) as usize;
+        let initial: Vec<u8> = state.backlog.iter().skip(skip).copied().collect();
+        let (id, feed) = state.add_replica(address, port);
+        Some((state.replid.clone(), ReplicaStream { id, initial, feed }))
+    }
+
+    pub fn remove_replica(&self, id: u64) {
+        self.lock().replicas.retain(|replica| replica.id != id);
+        self.acked.notify_all();
+    }
+
+    /// A replica saying how far through the stream it's got
+    pub fn ack(&self, id: u64, offset: u64) {
+        let mut state = self.lock();
+        if let Some(replica) = state.replicas.iter_mut().find(|replica| replica.id == id) {
+            replica.ack_offset = offset;
+            replica.last_ack = Instant::now();
+        }
+        self.acked.notify_all();
+    }
+
+    /// Waits until `replicas` replicas have everything written so far, or the timeout passes.
+    /// Gives how many replicas got that far.
+    pub fn wait(&self, replicas: usize, timeout: Option<Duration>) -> usize {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock();
+        let target = state.offset;
+        let caught_up = |state: &ReplicationState| {
+            state
+                .replicas
+                .iter()
+                .filter(|replica| replica.ack_offset >= target)
+                .count()
+        };
+        if caught_up(&state) < replicas {
+            // Ask for acks straight away rather than waiting for the next regular one
+            state.append(&encode(&["REPLCONF".into(), "GETACK".into(), "*".into()]));
+        }
+        while caught_up(&state) < replicas {
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    self.acked
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .acked
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+        caught_up(&state)
+    }
+
+    pub fn role(&self) -> Role {
+        self.lock().role.clone()
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.role() != Role::Master
+    }
+
+    /// Starts following another server
+    pub fn replicate_from(&self, host: &str, port: u16) {
+        let mut state = self.lock();
+        if state.role == Role::Master {
+            // Writes made as a master aren't part of the new master's history
+            state.new_history();
+        }
+        state.role = Role::Replica {
+            host: host.into(),
+            port,
+            link: LinkState::Connect,
+        };
+    }
+
+    /// Stops following the master and takes writes again. The master's history is kept under the
+    /// previous id, so other replicas of it can carry on from this server.
+    pub fn promote(&self) {
+        let mut state = self.lock();
+        if state.role != Role::Master {
+            state.role = Role::Master;
+            state.new_history();
+            state.selected = None;
+        }
+    }
+
+    pub fn set_link_state(&self, new_link: LinkState) {
+        if let Role::Replica { link, .. } = &mut self.lock().role {
+            *link = new_link;
+        }
+    }
+
+    /// The replication id and offset a replica asks its master to carry on from
+    pub fn resume_point(&self) -> (String, u64) {
+        let state = self.lock();
+        (state.replid.clone(), state.offset + 1)
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.lock().offset
+    }
+
+    /// A replica took on its master's history after a full resync
+    pub fn reset(&self, replid: &str, offset: u64) {
+        let mut state = self.lock();
+        state.replid = replid.into();
+        state.replid2 = "0".repeat(40);
+        state.second_offset = None;
+        state.offset = offset;
+        state.backlog.clear();
+        state.selected = None;
+    }
+
+    /// The master changed its replication id after a failover, but the history carries on
+    pub fn switch_id(&self, replid: &str) {
+        let mut state = self.lock();
+        if state.replid != replid {
+            state.replid2 = std::mem::replace(&mut state.replid, replid.into());
+            state.second_offset = Some(state.offset + 1);
+        }
+    }
+
+    /// Empties every database, before a replica loads its master's snapshot
+    pub fn flush_everything(&self) {
+        self.with_all_shards(|shards| {
+            for (_, state) in shards {
+                admin::flushall(state);
+            }
+        })
+    }
+
+    /// The reply to ROLE
+    pub fn role_reply(&self) -> RespData {
+        let state = self.lock();
+        let bulk = |string: &str| RespData::BulkStr(string.into());
+        match &state.role {
+            Role::Master => RespData::List(
+                vec![
+                    bulk("master"),
+                    RespData::Number(state.offset as i64),
+                    RespData::List(
+                        state
+                            .replicas
+                            .iter()
+                            .map(|replica| {
+                                RespData::List(
+                                    vec![
+                                        bulk(&replica.address),
+                                        bulk(&replica.port.to_string()),
+                                        bulk(&replica.ack_offset.to_string()),
+                                    ]
+                                    .into(),
+                                )
+                            })
+                            .collect(),
+                    ),
+                ]
+                .into(),
+            ),
+            Role::Replica { host, port, link } => RespData::List(
+                vec![
+                    bulk("slave"),
+                    bulk(host),
+                    RespData::Number(*port as i64),
+                    bulk(link.name()),
+                    RespData::Number(state.offset as i64),
+                ]
+                .into(),
+            ),
+        }
+    }
+
+    /// The replication section of INFO
+    pub fn info(&self) -> String {
+        let state = self.lock();
+        let mut lines = vec!["# Replication".to_string()];
+        match &state.role {
+            Role::Master => lines.push("role:master".into()),
+            Role::Replica { host, port, link } => {
+                lines.push("role:slave".into());
+                lines.push(format!("master_host:{}", host));
+                lines.push(format!("master_port:{}", port));
+                let up = *link == LinkState::Connected;
+                lines.push(format!(
+                    "master_link_status:{}",
+                    if up { "up" } else { "down" }
+                ));
+                lines.push(format!(
+                    "master_sync_in_progress:{}",
+                    (*link == LinkState::Sync) as u8
+                ));
+                lines.push(format!("slave_read_repl_offset:{}", state.offset));
+                lines.push(format!("slave_repl_offset:{}", state.offset));
+                lines.push("slave_read_only:1".into());
+            }
+        }
+        lines.push(format!("connected_slaves:{}", state.replicas.len()));
+        for (i, replica) in state.replicas.iter().enumerate() {
+            lines.push(format!(
+                "slave{}:ip={},port={},state=online,offset={},lag={}",
+                i,
+                replica.address,
+                replica.port,
+                replica.ack_offset,
+                replica.last_ack.elapsed().as_secs()
+            ));
+        }
+        lines.push(format!("master_replid:{}", state.replid));
+        lines.push(format!("master_replid2:{}", state.replid2));
+        lines.push(format!("master_repl_offset:{}", state.offset));
+        lines.push(format!(
+            "second_repl_offset:{}",
+            state
+                .second_offset
+                .map(|offset| offset as i64)
+                .unwrap_or(-1)
+        ));
+        lines.push("repl_backlog_active:1".into());
+        lines.push(format!("repl_backlog_size:{}", state.backlog_size));
+        lines.push(format!(
+            "repl_backlog_first_byte_offset:{}",
+            state.offset - state.backlog.len() as u64 + 1
+        ));
+        lines.push(format!("repl_backlog_histlen:{}", state.backlog.len()));
+        lines.join("\r\n") + "\r\n"
+    }
+}
+
+/// The unix time in milliseconds a relative or absolute expiry time ends at, None when it isn't a
+/// positive number
+fn unix_millis(amount: &str, unit_millis: u64, relative: bool) -> Option<String> {
+    let millis = amount
+        .parse::<u64>()
+        .ok()
+        .filter(|amount| *amount > 0)?
+        .checked_mul(unit_millis)?;
+    Some(match relative {
+        true => expiry::unix_millis_after(millis).to_string(),
+        false => millis.to_string(),
+    })
+}
+
+/// Expiry times are sent as unix times like Redis does, so a replica that runs the command later,
+/// or loads it from a snapshot, still expires the key at the same moment as the master
+fn with_absolute_expiry(cmd: &Command) -> Option<Command> {
+    let (key, at, options) = match cmd {
+        Command::Expire(key, seconds, options) => (key, unix_millis(seconds, 1000, true)?, options),
+        Command::Pexpire(key, millis, options) => (key, unix_millis(millis, 1, true)?, options),
+        Command::Expireat(key, seconds, options) => {
+            (key, unix_millis(seconds, 1000, false)?, options)
+        }
+        Command::Set(key, value, options) => {
+            let mut options = options.clone();
+            let position = options.iter().position(|option| {
+                ["EX", "PX", "EXAT"]
+                    .iter()
+                    .any(|unit| option.eq_ignore_ascii_case(unit))
+            })?;
+            let (unit_millis, relative) = match options[position].to_uppercase().as_str() {
+                "EX" => (1000, true),
+                "PX" => (1, true),
+                _ => (1000, false),
+            };
+            let at = unix_millis(options.get(position + 1)?, unit_millis, relative)?;
+            options[position] = "PXAT".into();
+            options[position + 1] = at;
+            return Some(Command::Set(key.clone(), value.clone(), options));
+        }
+        _ => return None,
+    };
+    Some(Command::Pexpireat(key.clone(), at, options.clone()))
+}
+
+/// The arguments a write will be sent to replicas with, worked out before it runs. None for
+/// commands that don't need sending.
+pub fn args_to_replicate(cmd: &Command) -> Option<Vec<String>> {
+    if !cmd.is_write() {
+        return None;
+    }
+    let mut args = match with_absolute_expiry(cmd) {
+        Some(absolute) => absolute.to_args(),
+        None => cmd.to_args(),
+    };
+    if let Command::Xreadgroup(_) = cmd {
+        // Replicas run it straight away, there's nothing for them to wait for
+        if let Some(block) = args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case("block"))
+        {
+            args.drain(block..(block + 2).min(args.len()));
+        }
+    }
+    Some(args)
+}
+
+/// Fixes up the arguments once the write has run, so that replicas end up with exactly the same
+/// data. None when the write failed or didn't change anything.
+pub fn finish_replicated_args(cmd_args: Vec<String>, response: &RespData) -> Option<Vec<String>> {
+    match (cmd_args[0].as_str(), response) {
+        (_, RespData::Error(_)) => None,
+        ("LPOP", RespData::NullString)
+        | ("RPOP", RespData::NullString)
+        | ("XREADGROUP", RespData::NullString)
+        | ("PEXPIREAT", RespData::Number(0)) => None,
+        // The master picks the id, so send the one it picked
+        ("XADD", RespData::BulkStr(id)) => {
+            let mut args = cmd_args;
+            if let Some(position) = args
+                .iter()
+                .skip(2)
+                .position(|arg| arg == "*" || arg.ends_with("-*"))
+            {
+                args[position + 2] = id.clone();
+            }
+            Some(args)
+        }
+        _ => Some(cmd_args),
+    }
+}
+
+/// The commands that recreate the value held at a key, without its TTL
+pub(crate) fn rebuild_key(state: &CoreState, key: &str) -> Vec<Vec<String>> {
+    if let Some(value) = state.keyval.get(key) {
+        match value {
+            RespData::List(items) => items
+                .iter()
+                .map(|item| Command::Rpush(key.into(), vec![item.clone()]).to_args())
+                .collect(),
+            value => vec![Command::Set(key.into(), value.clone(), vec![]).to_args()],
+        }
+    } else if let Some(members) = state.sets.get(key) {
+        vec![Command::Sadd(key.into(), members.iter().cloned().collect()).to_args()]
+    } else if let Some(set) = state.sorted_sets.get(key) {
+        geo::rebuild_commands(key, set)
+    } else if let Some(stream) = state.streams.get(key) {
+        stream.rebuild_commands(key)
+    } else {
+        vec![]
+    }
+}
+
+/// Turns a command made by `rebuild_key` back into a command, for `key` rather than whichever key
+/// it was made from
+pub(crate) fn rebuild_command(key: &str, args: Vec<String>) -> Option<Command> {
+    let mut args = args.into_iter();
+    let name = args.next()?.to_uppercase();
+    let subcommand = if name == "XGROUP" { args.next() } else { None };
+    args.next()?;
+    let mut rest: Vec<String> = args.collect();
+    let key = key.to_string();
+    match name.as_str() {
+        "SET" if rest.len() == 1 => {
+            Some(Command::Set(key, RespData::BulkStr(rest.remove(0)), vec![]))
+        }
+        "RPUSH" => Some(Command::Rpush(
+            key,
+            rest.into_iter().map(RespData::BulkStr).collect(),
+        )),
+        "SADD" => Some(Command::Sadd(key, rest)),
+        "GEOADD" => Some(Command::Geoadd(key, rest)),
+        "XADD" => Some(Command::Xadd(key, rest)),
+        "XCLAIM" => Some(Command::Xclaim(key, rest)),
+        "XGROUP" => Some(Command::Xgroup(
+            subcommand
+                .into_iter()
+                .chain(Some(key))
+                .chain(rest)
+                .collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// The commands that recreate everything in a shard, TTLs included
+fn rebuild_commands(state: &CoreState) -> Vec<Vec<String>> {
+    let mut commands: Vec<Vec<String>> = state
+        .key_names()
+        .iter()
+        .flat_map(|key| rebuild_key(state, key))
+        .collect();
+
+    for key in state.expires.keys() {
+        if let Some(expires_at) = state.expires.get(key) {
+            let at = expiry::unix_millis_at(*expires_at);
+            commands.push(Command::Pexpireat(key.clone(), at.to_string(), vec![]).to_args());
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod replication_should {
+    use super::*;
+    use crate::base_logic;
+    use crate::test_helpers::args;
+    use rustdss_transport::deserialise::DeserialiseRespData;
+
+    fn commands_in(bytes: &[u8]) -> Vec<Vec<String>> {
+        let mut chars = bytes.iter().map(|byte| *byte as char);
+        let mut commands = vec![];
+        while let Some(RespData::List(items)) = RespData::from_char_stream(&mut chars) {
+            commands.push(
+                items
+                    .into_iter()
+                    .map(|item| match item {
+                        RespData::BulkStr(string) => string,
+                        other => panic!("unexpected {:?}", other),
+                    })
+                    .collect(),
+            );
+        }
+        commands
+    }
+
+    fn received(feed: &Receiver<Vec<u8>>) -> Vec<Vec<String>> {
+        commands_in(&feed.try_iter().flatten().collect::<Vec<u8>>())
+    }
+
+    #[test]
+    fn send_writes_to_replicas_with_the_database_selected() {
+        let replication = Replication::default();
+        let (_, _, stream) = replication.full_sync("127.0.0.1", 6381);
+
+        replication.propagate("0", &args(&["SET", "a", "1"]));
+        replication.propagate("0", &args(&["DEL", "a"]));
+        replication.propagate("other", &args(&["SET", "b", "2"]));
+
+        assert_eq!(
+            received(&stream.feed),
+            vec![
+                args(&["SELECT", "0"]),
+                args(&["SET", "a", "1"]),
+                args(&["DEL", "a"]),
+                args(&["SELECT", "other"]),
+                args(&["SET", "b", "2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn carry_on_from_the_backlog() {
+        let replication = Replication::default();
+        replication.propagate("0", &args(&["SET", "a", "1"]));
+        let (replid, resume_at) = replication.resume_point();
+        replication.propagate("0", &args(&["SET", "b", "2"]));
+
+        let (_, stream) = replication
+            .partial_sync(&replid, resume_at, "127.0.0.1", 6381)
+            .expect("still in the backlog");
+
+        assert_eq!(commands_in(&stream.initial), vec![args(&["SET", "b", "2"])]);
+        assert!(replication
+            .partial_sync("another-id", resume_at, "127.0.0.1", 6381)
+            .is_none());
+    }
+
+    #[test]
+    fn need_a_full_resync_once_the_backlog_has_moved_on() {
+        let replication = Replication::new(64);
+        let (replid, resume_at) = replication.resume_point();
+        for i in 0..10 {
+            replication.propagate("0", &args(&["SET", "key", &i.to_string()]));
+        }
+
+        assert!(replication
+            .partial_sync(&replid, resume_at, "127.0.0.1", 6381)
+            .is_none());
+    }
+
+    #[test]
+    fn keep_the_old_history_after_a_promotion() {
+        let replication = Replication::default();
+        replication.replicate_from("127.0.0.1", 6380);
+        replication.reset("master-id", 100);
+        replication.promote();
+
+        assert_ne!(replication.resume_point().0, "master-id");
+        assert!(replication
+            .partial_sync("master-id", 101, "127.0.0.1", 6382)
+            .is_some());
+    }
+
+    #[test]
+    fn snapshot_every_type_of_value() {
+        let mut state = CoreState::default();
+        for cmd in [
+            Command::Set("s".into(), RespData::BulkStr("v".into()), vec![]),
+            Command::Rpush(
+                "l".into(),
+                vec![RespData::BulkStr("1".into()), RespData::BulkStr("2".into())],
+            ),
+            Command::Sadd("set".into(), args(&["m"])),
+            Command::Xadd("x".into(), args(&["1-1", "f", "v"])),
+            Command::Pexpire("s".into(), "100000".into(), vec![]),
+        ] {
+            assert!(base_logic::execute(&mut state, cmd).is_ok());
+        }
+
+        let commands = rebuild_commands(&state);
+
+        assert!(commands.contains(&args(&["SET", "s", "v"])));
+        assert!(commands.contains(&args(&["RPUSH", "l", "1"])));
+        assert!(commands.contains(&args(&["RPUSH", "l", "2"])));
+        assert!(commands.contains(&args(&["SADD", "set", "m"])));
+        assert!(commands.contains(&args(&["XADD", "x", "1-1", "f", "v"])));
+        assert!(commands
+            .iter()
+            .any(|command| command[0] == "PEXPIREAT" && command[1] == "s"));
+    }
+
+    #[test]
+    fn rebuild_geo_sets_with_the_same_scores() {
+        let mut state = CoreState::default();
+        geo::geoadd(
+            &mut state,
+            &"places".into(),
+            &args(&[
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ]),
+        );
+
+        let commands = rebuild_commands(&state);
+        let mut copy = CoreState::default();
+        geo::geoadd(&mut copy, &"places".into(), &commands[0][2..]);
+
+        for member in ["Palermo", "Catania"] {
+            assert_eq!(
+                copy.sorted_sets["places"].score(member),
+                state.sorted_sets["places"].score(member)
+            );
+        }
+    }
+
+    #[test]
+    fn send_expiry_times_as_unix_times() {
+        let replicated = |cmd: Command| args_to_replicate(&cmd).unwrap();
+        let in_a_minute = expiry::unix_millis_after(60_000);
+        let close_to = |args: &[String], position: usize| {
+            let at: u128 = args[position].parse().unwrap();
+            at.abs_diff(in_a_minute) < 1000
+        };
+
+        let expire = replicated(Command::Expire("k".into(), "60".into(), args(&["NX"])));
+        assert_eq!(expire[..2], args(&["PEXPIREAT", "k"]));
+        assert!(close_to(&expire, 2));
+        assert_eq!(expire[3], "NX");
+
+        let set = replicated(Command::Set(
+            "k".into(),
+            RespData::BulkStr("v".into()),
+            args(&["px", "60000", "GET"]),
+        ));
+        assert_eq!(set[..4], args(&["SET", "k", "v", "PXAT"]));
+        assert!(close_to(&set, 4));
+
+        assert_eq!(
+            replicated(Command::Expireat("k".into(), "100".into(), vec![])),
+            args(&["PEXPIREAT", "k", "100000"])
+        );
+        assert_eq!(
+            finish_replicated_args(args(&["PEXPIREAT", "k", "1"]), &RespData::Number(0)),
+            None
+        );
+    }
+
+    #[test]
+    fn send_the_id_the_master_picked() {
+        assert_eq!(
+            finish_replicated_args(
+                args(&["XADD", "x", "MAXLEN", "10", "*", "f", "v"]),
+                &RespData::BulkStr("5-0".into())
+            ),
+            Some(args(&["XADD", "x", "MAXLEN", "10", "5-0", "f", "v"]))
+        );
+        assert_eq!(
+            finish_replicated_args(args(&["LPOP", "l"]), &RespData::nil()),
+            None
+        );
+    }
+}
@@ -0,0 +1,813 @@
+// Key-hash sharding
+//
+// Each database's keyspace is split across a number of shards, each with its own worker thread
+// and CoreState. A coordinator thread per database sends commands whose keys all live on one
+// shard straight to that shard's worker. Commands with keys on several shards (MGET, RENAME,
+// SINTERSTORE, transactions...) are run by the coordinator itself: it locks every shard involved,
+// moves the keys into a scratch CoreState, runs the command there and moves the keys back, so
+// nothing else can see the keys half way through.
+//
+// Keys are hashed the same way as Redis Cluster, so `{user1}.name` and `{user1}.email` always end
+// up on the same shard.
+
+use crate::base_logic;
+use crate::db_logic::expiry;
+use crate::db_logic::streams::{self, BlockedRead};
+use crate::latency::LatencyMonitor;
+use crate::memory::{out_of_memory, Memory};
+use crate::replication::Replication;
+use crate::slowlog::{Caller, SlowLog};
+use crate::stats::Stats;
+use crate::CoreState;
+use rustdss_data::{wire_bytes, Command, Key, RespData};
+use std::collections::BTreeSet;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const HASH_SLOTS: u16 = 16384;
+
+// How often expired keys are looked for when a shard is otherwise idle
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// CRC16/XMODEM, the checksum Redis Cluster uses for key slots
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The hash slot of a key. When the key contains a non-empty `{tag}`, only the tag is hashed.
+pub fn key_hash_slot(key: &str) -> u16 {
+    let bytes = wire_bytes(key);
+    let hashed = match bytes.iter().position(|b| *b == b'{') {
+        Some(open) => match bytes[open + 1..].iter().position(|b| *b == b'}') {
+            Some(len) if len > 0 => &bytes[open + 1..open + 1 + len],
+            _ => &bytes[..],
+        },
+        None => &bytes[..],
+    };
+    crc16(hashed) % HASH_SLOTS
+}
+
+/// The database-wide SCAN cursor for a shard's cursor, which keeps the shard in its low digits.
+/// Once a shard is finished the scan carries on from the start of the next one.
+fn next_cursor(local: u64, shard: u64, shards: u64) -> Option<u64> {
+    match local {
+        0 if shard + 1 < shards => Some(shard + 1),
+        0 => Some(0),
+        local => local.checked_mul(shards)?.checked_add(shard),
+    }
+}
+
+/// A command for a database, where to send the reply, and who it's from
+pub(crate) type DatabaseMessage = (Command, Sender<RespData>, Option<Arc<dyn Caller>>);
+
+enum ShardMessage {
+    Command(Command, Sender<RespData>, Option<Arc<dyn Caller>>),
+    // Keys the coordinator changed, blocked reads waiting on them should try again
+    Changed(Vec<Key>),
+    // Answered once everything queued before it has run
+    Barrier(Sender<()>),
+}
+
+struct Shard {
+    sender: Sender<ShardMessage>,
+    state: Arc<Mutex<CoreState>>,
+}
+
+pub(crate) fn lock(state: &Mutex<CoreState>) -> MutexGuard<'_, CoreState> {
+    // A panicking command shouldn't take the whole shard down with it
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Retries the blocked reads at `indices`, replying to and removing the ones that can be served
+fn serve_woken(
+    state: &mut CoreState,
+    blocked: &mut Vec<(BlockedRead, Sender<RespData>)>,
+    indices: Vec<usize>,
+) {
+    // Oldest first, so the first client to block gets served first
+    let mut served = vec![];
+    for i in indices {
+        if let Some(response) = streams::retry(state, &blocked[i].0) {
+            // The client may have given up waiting, that's fine
+            let _ = blocked[i].1.send(response);
+            served.push(i);
+        }
+    }
+    for i in served.into_iter().rev() {
+        blocked.remove(i);
+    }
+}
+
+fn run_shard(
+    name: String,
+    state: Arc<Mutex<CoreState>>,
+    slowlog: Arc<SlowLog>,
+    receiver: Receiver<ShardMessage>,
+) {
+    let latency = lock(&state).latency.clone();
+    // Reads that are waiting for something to be written, and who to tell about it
+    let mut blocked: Vec<(BlockedRead, Sender<RespData>)> = Vec::new();
+    let mut next_expire_cycle = Instant::now() + ACTIVE_EXPIRE_INTERVAL;
+    loop {
+        let expire_deadline = Some(next_expire_cycle).filter(|_| !lock(&state).expires.is_empty());
+        let msg = match blocked
+            .iter()
+            .filter_map(|(read, _)| read.deadline())
+            .chain(expire_deadline)
+            .min()
+        {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match msg {
+            Ok(ShardMessage::Command(cmd, responder, caller)) => {
+                let woken: Vec<usize> = (0..blocked.len())
+                    .filter(|i| blocked[*i].0.woken_by(&cmd))
+                    .collect();
+
+                let mut state = lock(&state);
+                match latency.time("command", || {
+                    slowlog.time(cmd, caller.as_deref(), |cmd| {
+                        base_logic::execute(&mut state, cmd)
+                    })
+                }) {
+                    Ok(response) => responder
+                        .send(response)
+                        .unwrap_or_else(|_| panic!("[core::{}] can't reply to messages", name)),
+                    Err(read) => blocked.push((read, responder)),
+                }
+                serve_woken(&mut state, &mut blocked, woken);
+            }
+            Ok(ShardMessage::Changed(keys)) => {
+                let woken: Vec<usize> = (0..blocked.len())
+                    .filter(|i| keys.iter().any(|key| blocked[*i].0.waits_on(key)))
+                    .collect();
+                serve_woken(&mut lock(&state), &mut blocked, woken);
+            }
+            Ok(ShardMessage::Barrier(done)) => {
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                log::debug!("Shard {} stopped, its database has gone", name);
+                break;
+            }
+        }
+
+        let now = Instant::now();
+        if now >= next_expire_cycle {
+            latency.time("expire-cycle", || expiry::active_expire(&mut lock(&state)));
+            next_expire_cycle = now + ACTIVE_EXPIRE_INTERVAL;
+        }
+        blocked.retain(|(read, responder)| match read.deadline() {
+            Some(deadline) if deadline <= now => {
+                let _ = responder.send(RespData::nil());
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+struct Database {
+    id: String,
+    shards: Vec<Shard>,
+    memory: Arc<Memory>,
+    replication: Arc<Replication>,
+    stats: Arc<Stats>,
+    slowlog: Arc<SlowLog>,
+    latency: Arc<LatencyMonitor>,
+}
+
+impl Database {
+    fn shard_of(&self, key: &str) -> usize {
+        key_hash_slot(key) as usize % self.shards.len()
+    }
+
+    fn dispatch(&self, cmd: Command, responder: Sender<RespData>, caller: Option<Arc<dyn Caller>>) {
+        if let Command::Scan(args) = cmd {
+            let _ = responder.send(self.scan(args));
+            return;
+        }
+
+        let involved: BTreeSet<usize> = if cmd.uses_whole_keyspace() {
+            (0..self.shards.len()).collect()
+        } else {
+            cmd.key_args()
+                .into_iter()
+                .map(|key| self.shard_of(key))
+                .collect()
+        };
+
+        // Memory is shared, so room for a command on one shard may have to be made on another
+        if cmd.denied_when_out_of_memory() && !self.make_room() {
+            let _ = responder.send(out_of_memory());
+            return;
+        }
+
+        // Only commands for a single shard are queued, the rest run here once the commands sent
+        // before them have finished, so they can't overtake them
+        if involved.len() > 1 {
+            self.wait_for_shards(&involved);
+        }
+        let response = match (involved.len(), &cmd) {
+            // Commands without keys can run anywhere
+            (0, _) => return self.send_to_shard(0, cmd, responder, caller),
+            (1, _) => {
+                let shard = *involved.iter().next().expect("one shard");
+                return self.send_to_shard(shard, cmd, responder, caller);
+            }
+            (_, Command::Keys(_)) | (_, Command::FlushAll) => self.latency.time("command", || {
+                self.slowlog
+                    .time(cmd, caller.as_deref(), |cmd| self.broadcast(cmd))
+            }),
+            _ => self.latency.time("command", || {
+                self.slowlog
+                    .time(cmd, caller.as_deref(), |cmd| self.coordinate(cmd, involved))
+            }),
+        };
+        // The client may have gone away, that's fine
+        let _ = responder.send(response);
+    }
+
+    /// Waits until the shards have run everything queued on them
+    fn wait_for_shards(&self, shards: &BTreeSet<usize>) {
+        let (done, finished) = channel();
+        for shard in shards {
+            self.shards[*shard]
+                .sender
+                .send(ShardMessage::Barrier(done.clone()))
+                .expect("[core::database] Can't send to shard");
+        }
+        drop(done);
+        // Stops early only if a shard has gone
+        finished.iter().take(shards.len()).for_each(drop);
+    }
+
+    /// Evicts the best candidates from across every shard until memory use is under the limit,
+    /// returning false if that isn't possible
+    fn make_room(&self) -> bool {
+        if !self.memory.over_limit() {
+            return true;
+        }
+        self.latency.time("eviction-cycle", || {
+            while self.memory.over_limit() {
+                // One shard locked at a time, so this can't deadlock with a coordinator
+                let best = self
+                    .shards
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, shard)| {
+                        let (rank, key) = lock(&shard.state).pick_eviction()?;
+                        Some((rank, index, key))
+                    })
+                    .max_by_key(|(rank, _, _)| *rank);
+                match best {
+                    Some((_, shard, key)) => lock(&self.shards[shard].state).evict(&key),
+                    None => return false,
+                }
+            }
+            true
+        })
+    }
+
+    fn send_to_shard(
+        &self,
+        shard: usize,
+        cmd: Command,
+        responder: Sender<RespData>,
+        caller: Option<Arc<dyn Caller>>,
+    ) {
+        self.shards[shard]
+            .sender
+            .send(ShardMessage::Command(cmd, responder, caller))
+            .expect("[core::database] Can't send to shard");
+    }
+
+    /// Runs KEYS or FLUSHALL on every shard, with all of them locked so the result is consistent
+    fn broadcast(&self, cmd: Command) -> RespData {
+        let mut states: Vec<MutexGuard<CoreState>> =
+            self.shards.iter().map(|shard| lock(&shard.state)).collect();
+        match cmd {
+            Command::Keys(pattern) => RespData::List(
+                states
+                    .iter_mut()
+                    .flat_map(|state| {
+                        match base_logic::execute(state, Command::Keys(pattern.clone())) {
+                            Ok(RespData::List(keys)) => keys,
+                            _ => Default::default(),
+                        }
+                    })
+                    .collect(),
+            ),
+            _ => {
+                for state in states.iter_mut() {
+                    let _ = base_logic::execute(state, Command::FlushAll);
+                }
+                drop(states);
+                for shard in &self.shards {
+                    let _ = shard.sender.send(ShardMessage::Changed(vec![]));
+                }
+                RespData::ok()
+            }
+        }
+    }
+
+    /// Runs SCAN over one shard at a time. The cursor given to the client is the shard's own
+    /// cursor times the number of shards, plus the shard it belongs to.
+    fn scan(&self, mut args: Vec<String>) -> RespData {
+        let shards = self.shards.len() as u64;
+        let cursor: u64 = match args.first().and_then(|cursor| cursor.parse().ok()) {
+            Some(cursor) => cursor,
+            None => return RespData::Error("ERR invalid cursor".into()),
+        };
+        let shard = cursor % shards;
+        args[0] = (cursor / shards).to_string();
+
+        self.wait_for_shards(&BTreeSet::from([shard as usize]));
+        let state = &mut lock(&self.shards[shard as usize].state);
+        match base_logic::execute(state, Command::Scan(args)) {
+            Ok(RespData::List(mut reply)) if reply.len() == 2 => {
+                let local = match &reply[0] {
+                    RespData::BulkStr(cursor) => cursor.parse().unwrap_or(0),
+                    _ => 0,
+                };
+                match next_cursor(local, shard, shards) {
+                    Some(next) => {
+                        reply[0] = RespData::BulkStr(next.to_string());
+                        RespData::List(reply)
+                    }
+                    None => RespData::Error("ERR cursor out of range for this many shards".into()),
+                }
+            }
+            response => response.unwrap_or_else(|_| RespData::nil()),
+        }
+    }
+
+    /// Runs a command whose keys live on several shards
+    fn coordinate(&self, cmd: Command, involved: BTreeSet<usize>) -> RespData {
+        // Always locked in the same order, so two coordinators can never deadlock
+        let mut states: Vec<(usize, MutexGuard<CoreState>)> = involved
+            .iter()
+            .map(|shard| (*shard, lock(&self.shards[*shard].state)))
+            .collect();
+
+        // Room was made before the shards were locked. Evicting from the scratch state could only
+        // throw away the keys the command is using.
+        if cmd.denied_when_out_of_memory() && self.memory.over_limit() {
+            return out_of_memory();
+        }
+
+        let keys: Vec<Key> = if cmd.uses_whole_keyspace() {
+            states
+                .iter()
+                .flat_map(|(_, state)| state.key_names())
+                .collect()
+        } else {
+            cmd.key_args().into_iter().cloned().collect()
+        };
+
+        let mut scratch = CoreState::for_database(
+            self.id.clone(),
+            self.memory.clone(),
+            self.replication.clone(),
+            self.stats.clone(),
+            self.latency.clone(),
+        );
+        for key in &keys {
+            let shard = self.shard_of(key);
+            let (_, state) = states
+                .iter_mut()
+                .find(|(index, _)| *index == shard)
+                .expect("every shard with a key is locked");
+            if let Some(entry) = state.take_key(key) {
+                scratch.put_key(key.clone(), entry);
+            }
+        }
+
+        let response = base_logic::execute(&mut scratch, cmd).unwrap_or_else(|_| {
+            RespData::Error(
+                "ERR blocking reads can't wait on keys in different shards, use a {hash tag} to \
+                 keep the keys together"
+                    .into(),
+            )
+        });
+
+        let mut changed: Vec<Vec<Key>> = vec![vec![]; self.shards.len()];
+        for key in scratch.key_names() {
+            let shard = self.shard_of(&key);
+            let (_, state) = states
+                .iter_mut()
+                .find(|(index, _)| *index == shard)
+                .expect("keys only move between locked shards");
+            let entry = scratch
+                .take_key(&key)
+                .expect("key listed by the scratch state");
+            state.put_key(key.clone(), entry);
+        }
+        for key in keys {
+            changed[self.shard_of(&key)].push(key);
+        }
+        drop(states);
+
+        for (shard, keys) in changed.into_iter().enumerate() {
+            if !keys.is_empty() {
+                let _ = self.shards[shard].sender.send(ShardMessage::Changed(keys));
+            }
+        }
+        response
+    }
+}
+
+/// Starts the threads for one database, returning where to send its commands
+pub(crate) fn start_database(
+    db_id: String,
+    memory: Arc<Memory>,
+    replication: Arc<Replication>,
+    stats: Arc<Stats>,
+    slowlog: Arc<SlowLog>,
+    latency: Arc<LatencyMonitor>,
+    shards: usize,
+) -> Sender<DatabaseMessage> {
+    let (db_sender, db_reciever) = channel::<DatabaseMessage>();
+
+    let shards: Vec<Shard> = (0..shards)
+        .map(|index| {
+            let (sender, receiver) = channel();
+            let state = Arc::new(Mutex::new(CoreState::for_database(
+                db_id.clone(),
+                memory.clone(),
+                replication.clone(),
+                stats.clone(),
+                latency.clone(),
+            )));
+            let shard_state = state.clone();
+            let shard_slowlog = slowlog.clone();
+            let name = format!("{}/{}", db_id, index);
+            thread::spawn(move || run_shard(name, shard_state, shard_slowlog, receiver));
+            Shard { sender, state }
+        })
+        .collect();
+    let states: Vec<_> = shards.iter().map(|shard| shard.state.clone()).collect();
+    replication.add_database(db_id.clone(), &states);
+    stats.add_database(db_id.clone(), &states);
+    let database = Database {
+        id: db_id.clone(),
+        shards,
+        memory,
+        replication,
+        stats,
+        slowlog,
+        latency,
+    };
+
+    thread::spawn(move || {
+        for (cmd, responder, caller) in db_reciever {
+            database.dispatch(cmd, responder, caller);
+        }
+        log::debug!("Database {} stopped", db_id);
+    });
+
+    db_sender
+}
+
+#[cfg(test)]
+mod key_hash_slot_should {
+    use super::*;
+
+    #[test]
+    fn match_redis_cluster() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot("foo"), 12182);
+        assert_eq!(key_hash_slot("bar"), 5061);
+    }
+
+    #[test]
+    fn only_hash_the_tag_when_there_is_one() {
+        assert_eq!(
+            key_hash_slot("{user1000}.following"),
+            key_hash_slot("{user1000}.followers")
+        );
+        assert_eq!(
+            key_hash_slot("{user1000}.following"),
+            key_hash_slot("user1000")
+        );
+        // Empty tags don't count
+        assert_eq!(
+            key_hash_slot("foo{}{bar}"),
+            crc16(b"foo{}{bar}") % HASH_SLOTS
+        );
+    }
+
+    #[test]
+    fn hash_the_bytes_the_key_came_in_as() {
+        let slot = crc16("€uro".as_bytes()) % HASH_SLOTS;
+        // How the deserialiser stores the UTF-8 sent by a client
+        assert_eq!(key_hash_slot("\u{e2}\u{82}\u{ac}uro"), slot);
+        assert_eq!(key_hash_slot("€uro"), slot);
+    }
+}
+
+#[cfg(test)]
+mod database_should {
+    use super::*;
+    use crate::test_helpers::bulk;
+    use crate::EvictionPolicy;
+
+    fn run(db: &Sender<DatabaseMessage>, cmd: Command) -> RespData {
+        let (sender, receiver) = channel();
+        db.send((cmd, sender, None)).unwrap();
+        receiver.recv().unwrap()
+    }
+
+    fn database() -> Sender<DatabaseMessage> {
+        database_logging_to(Arc::new(SlowLog::default()))
+    }
+
+    fn database_logging_to(slowlog: Arc<SlowLog>) -> Sender<DatabaseMessage> {
+        start_database(
+            "test".into(),
+            Arc::new(Memory::default()),
+            Arc::new(Replication::default()),
+            Arc::new(Stats::default()),
+            slowlog,
+            Arc::new(LatencyMonitor::default()),
+            4,
+        )
+    }
+
+    // Keys that are known to land on different shards out of 4
+    const A: &str = "foo"; // slot 12182, shard 2
+    const B: &str = "bar"; // slot 5061, shard 1
+
+    #[test]
+    fn evict_from_every_shard_to_make_room() {
+        let memory = Arc::new(Memory::new(0, EvictionPolicy::AllKeysLru));
+        let db = start_database(
+            "test".into(),
+            memory.clone(),
+            Arc::new(Replication::default()),
+            Arc::new(Stats::default()),
+            Arc::new(SlowLog::default()),
+            Arc::new(LatencyMonitor::default()),
+            4,
+        );
+        let keys: Vec<String> = (0..100).map(|i| format!("key:{}", i)).collect();
+        for key in &keys {
+            run(&db, Command::Set(key.clone(), bulk("value"), vec![]));
+        }
+        memory.set_maxmemory(memory.used() / 2);
+
+        assert_eq!(
+            run(&db, Command::Set("{b}1".into(), bulk("v"), vec![])),
+            RespData::ok()
+        );
+        let evicted: BTreeSet<usize> = keys
+            .iter()
+            .filter(|key| run(&db, Command::Get(key.to_string())) == RespData::NullString)
+            .map(|key| key_hash_slot(key) as usize % 4)
+            .collect();
+        assert!(evicted.len() > 1, "evicted from shards {:?}", evicted);
+        assert!(memory.evicted_keys() > 0);
+    }
+
+    #[test]
+    fn read_keys_from_several_shards() {
+        let db = database();
+        run(&db, Command::Set(A.into(), bulk("1"), vec![]));
+        run(&db, Command::Set(B.into(), bulk("2"), vec![]));
+
+        assert_eq!(
+            run(
+                &db,
+                Command::Mget(vec![A.into(), B.into(), "missing".into()])
+            ),
+            RespData::List(vec![bulk("1"), bulk("2"), RespData::nil()].into())
+        );
+    }
+
+    #[test]
+    fn rename_keys_across_shards() {
+        let db = database();
+        run(&db, Command::Rpush(A.into(), vec![bulk("x")]));
+
+        assert_eq!(
+            run(&db, Command::Rename(A.into(), B.into())),
+            RespData::ok()
+        );
+
+        assert_eq!(run(&db, Command::Llen(A.into())), RespData::nil());
+        assert_eq!(run(&db, Command::Llen(B.into())), RespData::Number(1));
+    }
+
+    #[test]
+    fn store_set_intersections_across_shards() {
+        let db = database();
+        run(&db, Command::Sadd(A.into(), vec!["1".into(), "2".into()]));
+        run(&db, Command::Sadd(B.into(), vec!["2".into(), "3".into()]));
+
+        assert_eq!(
+            run(
+                &db,
+                Command::Sinterstore("dest".into(), vec![A.into(), B.into()])
+            ),
+            RespData::Number(1)
+        );
+        assert_eq!(
+            run(&db, Command::Smembers("dest".into())),
+            RespData::List(vec![bulk("2")].into())
+        );
+    }
+
+    #[test]
+    fn run_transactions_across_shards() {
+        let db = database();
+
+        let response = run(
+            &db,
+            Command::Exec(vec![
+                Command::Incr(A.into(), None),
+                Command::Incr(B.into(), None),
+                Command::Mget(vec![A.into(), B.into()]),
+            ]),
+        );
+
+        assert_eq!(
+            response,
+            RespData::List(
+                vec![
+                    RespData::Number(1),
+                    RespData::Number(1),
+                    RespData::List(vec![RespData::Number(1), RespData::Number(1)].into())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn list_and_flush_keys_on_every_shard() {
+        let db = database();
+        for key in [A, B, "baz", "qux"] {
+            run(&db, Command::Set(key.into(), bulk("1"), vec![]));
+        }
+
+        match run(&db, Command::Keys("*".into())) {
+            RespData::List(keys) => assert_eq!(keys.len(), 4),
+            other => panic!("unexpected KEYS reply {:?}", other),
+        }
+
+        match run(&db, Command::Keys("ba*".into())) {
+            RespData::List(keys) => assert_eq!(keys.len(), 2),
+            other => panic!("unexpected KEYS reply {:?}", other),
+        }
+
+        assert_eq!(run(&db, Command::FlushAll), RespData::ok());
+        assert_eq!(
+            run(&db, Command::Keys("*".into())),
+            RespData::List(vec![].into())
+        );
+    }
+
+    #[test]
+    fn run_transactions_with_every_key_on_one_shard() {
+        let db = database();
+
+        assert_eq!(
+            run(
+                &db,
+                Command::Exec(vec![
+                    Command::Set(A.into(), bulk("1"), vec![]),
+                    Command::Get(A.into()),
+                    Command::Ping(None),
+                ])
+            ),
+            RespData::List(
+                vec![
+                    RespData::ok(),
+                    bulk("1"),
+                    RespData::SimpleStr("PONG".into())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn not_overflow_scan_cursors() {
+        assert_eq!(next_cursor(5, 1, 4), Some(21));
+        assert_eq!(next_cursor(0, 1, 4), Some(2));
+        assert_eq!(next_cursor(0, 3, 4), Some(0));
+        assert_eq!(next_cursor(u64::MAX / 2, 1, 4), None);
+    }
+
+    #[test]
+    fn scan_every_shard_in_turn() {
+        let db = database();
+        let keys: Vec<String> = (0..20).map(|i| format!("key:{}", i)).collect();
+        for key in &keys {
+            run(&db, Command::Set(key.clone(), bulk("1"), vec![]));
+        }
+
+        let mut cursor = "0".to_string();
+        let mut found = vec![];
+        loop {
+            let args = vec![cursor.clone(), "COUNT".into(), "3".into()];
+            match run(&db, Command::Scan(args)) {
+                RespData::List(reply) => match (&reply[0], &reply[1]) {
+                    (RespData::BulkStr(next), RespData::List(batch)) => {
+                        cursor = next.clone();
+                        found.extend(batch.iter().cloned());
+                    }
+                    other => panic!("unexpected SCAN reply {:?}", other),
+                },
+                other => panic!("unexpected SCAN reply {:?}", other),
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        let mut found: Vec<String> = found
+            .into_iter()
+            .map(|key| match key {
+                RespData::BulkStr(key) => key,
+                other => panic!("unexpected key {:?}", other),
+            })
+            .collect();
+        found.sort();
+        let mut expected = keys;
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn wake_blocked_reads_when_a_transaction_writes_to_the_stream() {
+        let db = database();
+        let (sender, receiver) = channel();
+        db.send((
+            Command::Xread(
+                ["BLOCK", "1000", "STREAMS", A, "$"]
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect(),
+            ),
+            sender,
+            None,
+        ))
+        .unwrap();
+        // Make sure the read is blocked before writing
+        run(&db, Command::Get(A.into()));
+
+        run(
+            &db,
+            Command::Exec(vec![
+                Command::Set(B.into(), bulk("1"), vec![]),
+                Command::Xadd(A.into(), vec!["*".into(), "f".into(), "v".into()]),
+            ]),
+        );
+
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(500)),
+            Ok(RespData::List(_))
+        ));
+    }
+
+    #[test]
+    fn log_each_slow_command_once_however_many_shards_it_uses() {
+        let slowlog = Arc::new(SlowLog::new(0, 10));
+        let db = database_logging_to(slowlog.clone());
+        run(&db, Command::Set(A.into(), bulk("1"), vec![]));
+        run(&db, Command::Keys("*".into()));
+        run(&db, Command::Mget(vec![A.into(), B.into()]));
+
+        let logged: Vec<Vec<String>> = slowlog
+            .get(None)
+            .into_iter()
+            .map(|entry| entry.argv)
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                vec!["MGET".to_string(), A.into(), B.into()],
+                vec!["KEYS".to_string(), "*".into()],
+                vec!["SET".to_string(), A.into(), "1".into()],
+            ]
+        );
+    }
+}
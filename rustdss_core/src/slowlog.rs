@@ -0,0 +1,229 @@
+// The slow log, SLOWLOG GET / LEN / RESET
+//
+// Commands are timed in the database threads, around running the command itself, so waiting in
+// a queue or on the network doesn't count. Only the commands over `slowlog-log-slower-than` are
+// kept, newest first, up to `slowlog-max-len` of them.
+
+use rustdss_data::Command;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Like Redis, long commands only keep their first arguments, and long arguments their start
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+/// Who sent a command, so that slow ones can say who they came from
+pub trait Caller: Send + Sync {
+    fn address(&self) -> String;
+    fn name(&self) -> String;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Seconds since the Unix epoch when the command ran
+    pub timestamp: u64,
+    /// How long it took, in microseconds
+    pub duration: u64,
+    pub argv: Vec<String>,
+    pub address: String,
+    pub name: String,
+}
+
+pub struct SlowLog {
+    // In microseconds, negative turns the log off and zero logs everything
+    slower_than: AtomicI64,
+    max_len: AtomicUsize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self::new(10_000, 128)
+    }
+}
+
+/// Cuts down the arguments the way Redis does, saying how much was left out
+fn truncate(mut argv: Vec<String>) -> Vec<String> {
+    if argv.len() > MAX_ARGS {
+        let more = argv.len() - (MAX_ARGS - 1);
+        argv.truncate(MAX_ARGS - 1);
+        argv.push(format!("... ({} more arguments)", more));
+    }
+    for arg in argv.iter_mut() {
+        if arg.len() > MAX_ARG_LEN {
+            let mut end = MAX_ARG_LEN;
+            while !arg.is_char_boundary(end) {
+                end -= 1;
+            }
+            let more = arg.len() - end;
+            arg.truncate(end);
+            arg.push_str(&format!("... ({} more bytes)", more));
+        }
+    }
+    argv
+}
+
+impl SlowLog {
+    pub fn new(slower_than: i64, max_len: usize) -> Self {
+        Self {
+            slower_than: AtomicI64::new(slower_than),
+            max_len: AtomicUsize::new(max_len),
+            next_id: AtomicU64::new(0),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, VecDeque<SlowLogEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set_slower_than(&self, micros: i64) {
+        self.slower_than.store(micros, Ordering::Relaxed);
+    }
+
+    /// Changes how many entries are kept, dropping the oldest ones that no longer fit
+    pub fn set_max_len(&self, max_len: usize) {
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.entries().truncate(max_len);
+    }
+
+    /// Whether commands need timing at all
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.slower_than.load(Ordering::Relaxed) >= 0
+    }
+
+    /// Runs a command, keeping it if it takes too long. The arguments have to be taken before it
+    /// runs, so that's only done while the log is on.
+    pub(crate) fn time<T>(
+        &self,
+        cmd: Command,
+        caller: Option<&dyn Caller>,
+        run: impl FnOnce(Command) -> T,
+    ) -> T {
+        let argv = self.is_enabled().then(|| cmd.to_args());
+        let started = Instant::now();
+        let result = run(cmd);
+        if let Some(argv) = argv {
+            self.record(started.elapsed(), argv, caller);
+        }
+        result
+    }
+
+    /// Keeps a command if it took too long
+    pub(crate) fn record(&self, took: Duration, argv: Vec<String>, caller: Option<&dyn Caller>) {
+        let slower_than = self.slower_than.load(Ordering::Relaxed);
+        let duration = took.as_micros() as u64;
+        if slower_than < 0 || duration < slower_than as u64 {
+            return;
+        }
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration,
+            argv: truncate(argv),
+            address: caller.map(Caller::address).unwrap_or_default(),
+            name: caller.map(Caller::name).unwrap_or_default(),
+        };
+        let max_len = self.max_len.load(Ordering::Relaxed);
+        let mut entries = self.entries();
+        entries.push_front(entry);
+        entries.truncate(max_len);
+    }
+
+    /// The newest entries first, all of them when `count` is None
+    pub fn get(&self, count: Option<usize>) -> Vec<SlowLogEntry> {
+        let entries = self.entries();
+        let count = count.unwrap_or(entries.len());
+        entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    pub fn reset(&self) {
+        self.entries().clear();
+    }
+}
+
+#[cfg(test)]
+mod slowlog_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    struct Client;
+
+    impl Caller for Client {
+        fn address(&self) -> String {
+            "127.0.0.1:5000".into()
+        }
+
+        fn name(&self) -> String {
+            "worker".into()
+        }
+    }
+
+    #[test]
+    fn keep_only_slow_commands_newest_first() {
+        let log = SlowLog::new(1000, 2);
+        log.record(Duration::from_micros(999), args(&["get", "a"]), None);
+        log.record(Duration::from_micros(1000), args(&["keys", "*"]), None);
+        log.record(
+            Duration::from_millis(5),
+            args(&["lrange", "l", "0", "-1"]),
+            Some(&Client),
+        );
+
+        let entries = log.get(None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, 1);
+        assert_eq!(entries[0].duration, 5000);
+        assert_eq!(entries[0].argv, args(&["lrange", "l", "0", "-1"]));
+        assert_eq!(
+            (entries[0].address.as_str(), entries[0].name.as_str()),
+            ("127.0.0.1:5000", "worker")
+        );
+        assert_eq!(entries[1].argv, args(&["keys", "*"]));
+        assert_eq!(log.get(Some(1)).len(), 1);
+
+        log.record(Duration::from_millis(1), args(&["del", "a"]), None);
+        assert_eq!(log.get(None)[1].argv, args(&["lrange", "l", "0", "-1"]));
+
+        log.set_max_len(1);
+        assert_eq!(log.len(), 1);
+        log.reset();
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn log_nothing_when_turned_off() {
+        let log = SlowLog::new(-1, 10);
+        log.record(Duration::from_secs(1), args(&["keys", "*"]), None);
+        assert!(log.is_empty());
+        assert!(!log.is_enabled());
+    }
+
+    #[test]
+    fn cut_down_long_commands() {
+        let many: Vec<String> = (0..40).map(|i| i.to_string()).collect();
+        let argv = truncate(many);
+        assert_eq!(argv.len(), 32);
+        assert_eq!(argv[31], "... (9 more arguments)");
+
+        let argv = truncate(vec!["x".repeat(130)]);
+        assert_eq!(argv[0], format!("{}... (2 more bytes)", "x".repeat(128)));
+    }
+}
@@ -0,0 +1,192 @@
+// Counters for INFO that the database threads keep
+//
+// Hits and misses are counted as commands run. Key counts aren't kept anywhere, so they're
+// gathered from each shard when they're asked for, one shard locked at a time.
+
+use crate::replication::DatabaseShards;
+use crate::{CoreState, DatabaseId};
+use rustdss_data::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+// How many keys with a TTL are looked at to estimate the average TTL
+const TTL_SAMPLES: usize = 16;
+
+/// How many keys a database has, for the keyspace section of INFO
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyspaceInfo {
+    pub keys: usize,
+    pub expires: usize,
+    /// Roughly how long keys with a TTL have left, in milliseconds
+    pub avg_ttl: u64,
+}
+
+#[derive(Default)]
+pub struct Stats {
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    // Every shard of every database, held weakly like in replication
+    databases: Mutex<Vec<DatabaseShards>>,
+}
+
+impl Stats {
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters, for CONFIG RESETSTAT
+    pub fn reset(&self) {
+        self.keyspace_hits.store(0, Ordering::Relaxed);
+        self.keyspace_misses.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_database(&self, id: DatabaseId, shards: &[Arc<Mutex<CoreState>>]) {
+        self.databases
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((id, shards.iter().map(Arc::downgrade).collect()));
+    }
+
+    /// The databases that have keys in them, by name
+    pub fn keyspace(&self) -> Vec<(DatabaseId, KeyspaceInfo)> {
+        let databases = self
+            .databases
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let mut keyspace: Vec<_> = databases
+            .into_iter()
+            .map(|(id, shards)| {
+                let shards: Vec<_> = shards.iter().filter_map(Weak::upgrade).collect();
+                let infos: Vec<_> = shards
+                    .iter()
+                    .map(|shard| crate::sharding::lock(shard).keyspace_info())
+                    .collect();
+                let keys = infos.iter().map(|info| info.keys).sum();
+                let expires: usize = infos.iter().map(|info| info.expires).sum();
+                // Each shard's average counts for as many keys with a TTL as it has
+                let avg_ttl = infos
+                    .iter()
+                    .map(|info| info.avg_ttl * info.expires as u64)
+                    .sum::<u64>()
+                    .checked_div(expires as u64)
+                    .unwrap_or(0);
+                let info = KeyspaceInfo {
+                    keys,
+                    expires,
+                    avg_ttl,
+                };
+                (id, info)
+            })
+            .filter(|(_, info)| info.keys > 0)
+            .collect();
+        keyspace.sort_by(|(a, _), (b, _)| a.cmp(b));
+        keyspace
+    }
+}
+
+impl CoreState {
+    /// Counts the keys a read command looked up as hits or misses
+    pub(crate) fn count_lookups(&self, cmd: &Command) {
+        // OBJECT looks at a key without it counting as a use, like it doesn't touch it
+        if cmd.is_write() || matches!(cmd, Command::Object(..)) {
+            return;
+        }
+        for key in cmd.key_args() {
+            let counter = if self.value_type(key).is_some() {
+                &self.stats.keyspace_hits
+            } else {
+                &self.stats.keyspace_misses
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn keyspace_info(&mut self) -> KeyspaceInfo {
+        let now = Instant::now();
+        let sampled: Vec<u64> = self
+            .expires
+            .sample_keys(TTL_SAMPLES, &mut self.rng)
+            .into_iter()
+            .filter_map(|key| self.expires.get(key))
+            .map(|expires_at| expires_at.saturating_duration_since(now).as_millis() as u64)
+            .collect();
+        KeyspaceInfo {
+            keys: self.keyval.len() + self.streams.len() + self.sorted_sets.len() + self.sets.len(),
+            expires: self.expires.len(),
+            avg_ttl: sampled
+                .iter()
+                .sum::<u64>()
+                .checked_div(sampled.len() as u64)
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod stats_should {
+    use super::*;
+    use crate::base_logic::execute;
+    use rustdss_data::RespData;
+    use std::time::Duration;
+
+    #[test]
+    fn count_hits_and_misses_for_reads() {
+        let stats = Arc::new(Stats::default());
+        let mut state = CoreState {
+            stats: stats.clone(),
+            ..CoreState::default()
+        };
+        execute(
+            &mut state,
+            Command::Set("a".into(), RespData::BulkStr("1".into()), vec![]),
+        )
+        .ok();
+        execute(&mut state, Command::Get("a".into())).ok();
+        execute(&mut state, Command::Get("b".into())).ok();
+        execute(&mut state, Command::Mget(vec!["a".into(), "b".into()])).ok();
+        execute(&mut state, Command::Object("encoding".into(), "a".into())).ok();
+
+        assert_eq!((stats.keyspace_hits(), stats.keyspace_misses()), (2, 2));
+        stats.reset();
+        assert_eq!((stats.keyspace_hits(), stats.keyspace_misses()), (0, 0));
+    }
+
+    #[test]
+    fn add_up_the_keys_in_every_shard() {
+        let stats = Stats::default();
+        let shards: Vec<_> = (0..2)
+            .map(|_| Arc::new(Mutex::new(CoreState::default())))
+            .collect();
+        stats.add_database(
+            "empty".into(),
+            &[Arc::new(Mutex::new(CoreState::default()))],
+        );
+        stats.add_database("0".into(), &shards);
+        {
+            let mut first = shards[0].lock().unwrap();
+            first.keyval.insert("a".into(), RespData::Number(1));
+            first.keyval.insert("b".into(), RespData::Number(2));
+            first
+                .expires
+                .insert("b".into(), Instant::now() + Duration::from_secs(100));
+            shards[1]
+                .lock()
+                .unwrap()
+                .keyval
+                .insert("c".into(), RespData::Number(3));
+        }
+
+        let keyspace = stats.keyspace();
+        assert_eq!(keyspace.len(), 1);
+        let (id, info) = &keyspace[0];
+        assert_eq!(id, "0");
+        assert_eq!((info.keys, info.expires), (3, 1));
+        assert!(info.avg_ttl > 99_000 && info.avg_ttl <= 100_000);
+    }
+}
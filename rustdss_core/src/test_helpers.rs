@@ -0,0 +1,10 @@
+// Shorthands for building commands and replies in tests
+use rustdss_data::RespData;
+
+pub fn args(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+pub fn bulk(value: &str) -> RespData {
+    RespData::BulkStr(value.into())
+}
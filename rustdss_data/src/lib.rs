@@ -29,6 +29,7 @@ mod should {
     // Showing that it can decode and re-encode data into the same thing.
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn run_tests() {
         assert!(true);
     }
@@ -36,23 +37,492 @@ mod should {
 
 pub type Key = String;
 pub type Number = i64;
+
+/// The bytes a string came off the wire as. The deserialiser stores every incoming byte as the
+/// char with the same value, chars that can't have come from the wire are taken as UTF-8.
+pub fn wire_bytes(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len());
+    for c in string.chars() {
+        if (c as u32) <= 0xff {
+            bytes.push(c as u32 as u8);
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    bytes
+}
 #[derive(Debug)]
 pub enum Command {
-    Ping,
+    // The message to answer with instead of PONG
+    Ping(Option<RespData>),
     Echo(RespData),
     Get(Key), // Do we want to use strings or do we want to use Resp values?
-    Set(Key, RespData),
+    Set(Key, RespData, Vec<String>),
     Incr(Key, Option<Number>),
     Decr(Key, Option<Number>),
     Select(String),
-    Lpop(Key),
-    Lpush(Key, RespData),
-    Rpop(Key),
-    Rpush(Key, RespData),
+    Lpop(Key, Option<Number>),
+    Lpush(Key, Vec<RespData>),
+    Rpop(Key, Option<Number>),
+    Rpush(Key, Vec<RespData>),
     Llen(Key),
     Lrange(Key, Number, Number),
-    Keys,
-    Info,
+    Keys(String),
+    Scan(Vec<String>),
+    // The section to show, if only one is wanted
+    Info(Vec<String>),
     FlushAll,
     Dump(Key),
+    Pfadd(Key, Vec<String>),
+    Pfcount(Vec<Key>),
+    Pfmerge(Key, Vec<Key>),
+    Xadd(Key, Vec<String>),
+    Xlen(Key),
+    Xrange(Key, Vec<String>),
+    Xrevrange(Key, Vec<String>),
+    Xdel(Key, Vec<String>),
+    Xtrim(Key, Vec<String>),
+    Xread(Vec<String>),
+    Xreadgroup(Vec<String>),
+    Xgroup(Vec<String>),
+    Xack(Key, String, Vec<String>),
+    Xpending(Key, String, Vec<String>),
+    Xclaim(Key, Vec<String>),
+    Xautoclaim(Key, Vec<String>),
+    Xinfo(Vec<String>),
+    Geoadd(Key, Vec<String>),
+    Geopos(Key, Vec<String>),
+    Geodist(Key, Vec<String>),
+    Geohash(Key, Vec<String>),
+    Geosearch(Key, Vec<String>),
+    Geosearchstore(Key, Key, Vec<String>),
+    Del(Vec<Key>),
+    Expire(Key, String, Vec<String>),
+    Pexpire(Key, String, Vec<String>),
+    Expireat(Key, String, Vec<String>),
+    Pexpireat(Key, String, Vec<String>),
+    Ttl(Key),
+    Pttl(Key),
+    Persist(Key),
+    Object(String, Key),
+    Mget(Vec<Key>),
+    Rename(Key, Key),
+    Sadd(Key, Vec<String>),
+    Srem(Key, Vec<String>),
+    Smembers(Key),
+    Sismember(Key, String),
+    Scard(Key),
+    Sinter(Vec<Key>),
+    Sinterstore(Key, Vec<Key>),
+    Multi,
+    Discard,
+    // The commands queued since MULTI, run together
+    Exec(Vec<Command>),
+    Replicaof(String, String),
+    Psync(String, String),
+    Replconf(Vec<String>),
+    Wait(String, String),
+    Role,
+    Exists(Vec<Key>),
+    // The key, then the TTL, the payload from DUMP and any options
+    Restore(Key, Vec<String>),
+    Cluster(Vec<String>),
+    Asking,
+    Migrate(Vec<String>),
+    Config(Vec<String>),
+    Auth(Vec<String>),
+    Acl(Vec<String>),
+    Client(Vec<String>),
+    Slowlog(Vec<String>),
+    Monitor,
+    Latency(Vec<String>),
+    Shutdown(Vec<String>),
+    Command(Vec<String>),
+}
+
+/// The keys MIGRATE moves: the key argument, or everything after `KEYS` when that's empty
+fn migrate_keys(args: &[String]) -> Vec<&Key> {
+    match args.get(2) {
+        Some(key) if !key.is_empty() => vec![key],
+        _ => args
+            .iter()
+            .skip(5)
+            .position(|arg| arg.eq_ignore_ascii_case("keys"))
+            .map(|index| args[index + 6..].iter().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// The keys that follow `STREAMS` in XREAD and XREADGROUP, the rest of the args are ids
+fn stream_keys(args: &[String]) -> Vec<&Key> {
+    match args
+        .iter()
+        .position(|arg| arg.eq_ignore_ascii_case("streams"))
+    {
+        Some(index) => {
+            let rest = &args[index + 1..];
+            rest[..rest.len() / 2].iter().collect()
+        }
+        None => vec![],
+    }
+}
+
+impl Command {
+    /// Every key the command reads or writes
+    pub fn key_args(&self) -> Vec<&Key> {
+        match self {
+            Command::Get(key)
+            | Command::Set(key, _, _)
+            | Command::Incr(key, _)
+            | Command::Decr(key, _)
+            | Command::Lpop(key, _)
+            | Command::Lpush(key, _)
+            | Command::Rpop(key, _)
+            | Command::Rpush(key, _)
+            | Command::Llen(key)
+            | Command::Lrange(key, _, _)
+            | Command::Dump(key)
+            | Command::Pfadd(key, _)
+            | Command::Xadd(key, _)
+            | Command::Xlen(key)
+            | Command::Xrange(key, _)
+            | Command::Xrevrange(key, _)
+            | Command::Xdel(key, _)
+            | Command::Xtrim(key, _)
+            | Command::Xack(key, _, _)
+            | Command::Xpending(key, _, _)
+            | Command::Xclaim(key, _)
+            | Command::Xautoclaim(key, _)
+            | Command::Geoadd(key, _)
+            | Command::Geopos(key, _)
+            | Command::Geodist(key, _)
+            | Command::Geohash(key, _)
+            | Command::Geosearch(key, _)
+            | Command::Expire(key, _, _)
+            | Command::Pexpire(key, _, _)
+            | Command::Expireat(key, _, _)
+            | Command::Pexpireat(key, _, _)
+            | Command::Ttl(key)
+            | Command::Pttl(key)
+            | Command::Persist(key)
+            | Command::Object(_, key)
+            | Command::Sadd(key, _)
+            | Command::Srem(key, _)
+            | Command::Smembers(key)
+            | Command::Sismember(key, _)
+            | Command::Scard(key)
+            | Command::Restore(key, _) => vec![key],
+            Command::Pfcount(keys)
+            | Command::Del(keys)
+            | Command::Mget(keys)
+            | Command::Sinter(keys)
+            | Command::Exists(keys) => keys.iter().collect(),
+            Command::Migrate(args) => migrate_keys(args),
+            Command::Rename(source, dest) => vec![source, dest],
+            Command::Sinterstore(dest, keys) => std::iter::once(dest).chain(keys).collect(),
+            Command::Exec(commands) => commands.iter().flat_map(Command::key_args).collect(),
+            Command::Pfmerge(dest, sources) => std::iter::once(dest).chain(sources).collect(),
+            Command::Geosearchstore(dest, source, _) => vec![dest, source],
+            Command::Xread(args) | Command::Xreadgroup(args) => stream_keys(args),
+            Command::Xgroup(args) | Command::Xinfo(args) => args.get(1).into_iter().collect(),
+            Command::Ping(_)
+            | Command::Echo(_)
+            | Command::Select(_)
+            | Command::Keys(_)
+            | Command::Scan(_)
+            | Command::Info(_)
+            | Command::FlushAll
+            | Command::Multi
+            | Command::Discard
+            | Command::Replicaof(..)
+            | Command::Psync(..)
+            | Command::Replconf(_)
+            | Command::Wait(..)
+            | Command::Role
+            | Command::Cluster(_)
+            | Command::Asking
+            | Command::Config(_)
+            | Command::Auth(_)
+            | Command::Acl(_)
+            | Command::Client(_)
+            | Command::Slowlog(_)
+            | Command::Monitor
+            | Command::Latency(_)
+            | Command::Shutdown(_)
+            | Command::Command(_) => vec![],
+        }
+    }
+
+    /// Commands that look at every key in the database rather than the ones they name
+    pub fn uses_whole_keyspace(&self) -> bool {
+        match self {
+            Command::Keys(_) | Command::Scan(_) | Command::FlushAll => true,
+            Command::Exec(commands) => commands.iter().any(Command::uses_whole_keyspace),
+            _ => false,
+        }
+    }
+
+    /// Commands that may use more memory, these are refused when the memory limit is reached and
+    /// nothing can be evicted
+    pub fn denied_when_out_of_memory(&self) -> bool {
+        if let Command::Exec(commands) = self {
+            return commands.iter().any(Command::denied_when_out_of_memory);
+        }
+        matches!(
+            self,
+            Command::Set(..)
+                | Command::Incr(..)
+                | Command::Decr(..)
+                | Command::Lpush(..)
+                | Command::Rpush(..)
+                | Command::Pfadd(..)
+                | Command::Pfmerge(..)
+                | Command::Xadd(..)
+                | Command::Xgroup(..)
+                | Command::Xclaim(..)
+                | Command::Xautoclaim(..)
+                | Command::Geoadd(..)
+                | Command::Geosearchstore(..)
+                | Command::Sadd(..)
+                | Command::Sinterstore(..)
+                | Command::Restore(..)
+        )
+    }
+
+    /// Commands that change the data, these are the ones sent on to replicas
+    pub fn is_write(&self) -> bool {
+        if let Command::Exec(commands) = self {
+            return commands.iter().any(Command::is_write);
+        }
+        self.denied_when_out_of_memory()
+            || matches!(
+                self,
+                Command::Lpop(..)
+                    | Command::Rpop(..)
+                    | Command::FlushAll
+                    | Command::Xdel(..)
+                    | Command::Xtrim(..)
+                    | Command::Xreadgroup(..)
+                    | Command::Xack(..)
+                    | Command::Del(..)
+                    | Command::Expire(..)
+                    | Command::Pexpire(..)
+                    | Command::Expireat(..)
+                    | Command::Pexpireat(..)
+                    | Command::Persist(..)
+                    | Command::Rename(..)
+                    | Command::Srem(..)
+                    | Command::Migrate(..)
+            )
+    }
+
+    /// The command's name as a client would send it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Ping(_) => "PING",
+            Command::Echo(_) => "ECHO",
+            Command::Get(_) => "GET",
+            Command::Set(..) => "SET",
+            Command::Incr(_, None) => "INCR",
+            Command::Incr(_, Some(_)) => "INCRBY",
+            Command::Decr(_, None) => "DECR",
+            Command::Decr(_, Some(_)) => "DECRBY",
+            Command::Select(_) => "SELECT",
+            Command::Lpop(..) => "LPOP",
+            Command::Lpush(..) => "LPUSH",
+            Command::Rpop(..) => "RPOP",
+            Command::Rpush(..) => "RPUSH",
+            Command::Llen(_) => "LLEN",
+            Command::Lrange(..) => "LRANGE",
+            Command::Keys(_) => "KEYS",
+            Command::Scan(_) => "SCAN",
+            Command::Info(_) => "INFO",
+            Command::FlushAll => "FLUSHALL",
+            Command::Dump(_) => "DUMP",
+            Command::Pfadd(..) => "PFADD",
+            Command::Pfcount(_) => "PFCOUNT",
+            Command::Pfmerge(..) => "PFMERGE",
+            Command::Xadd(..) => "XADD",
+            Command::Xlen(_) => "XLEN",
+            Command::Xrange(..) => "XRANGE",
+            Command::Xrevrange(..) => "XREVRANGE",
+            Command::Xdel(..) => "XDEL",
+            Command::Xtrim(..) => "XTRIM",
+            Command::Xread(_) => "XREAD",
+            Command::Xreadgroup(_) => "XREADGROUP",
+            Command::Xgroup(_) => "XGROUP",
+            Command::Xack(..) => "XACK",
+            Command::Xpending(..) => "XPENDING",
+            Command::Xclaim(..) => "XCLAIM",
+            Command::Xautoclaim(..) => "XAUTOCLAIM",
+            Command::Xinfo(_) => "XINFO",
+            Command::Geoadd(..) => "GEOADD",
+            Command::Geopos(..) => "GEOPOS",
+            Command::Geodist(..) => "GEODIST",
+            Command::Geohash(..) => "GEOHASH",
+            Command::Geosearch(..) => "GEOSEARCH",
+            Command::Geosearchstore(..) => "GEOSEARCHSTORE",
+            Command::Del(_) => "DEL",
+            Command::Expire(..) => "EXPIRE",
+            Command::Pexpire(..) => "PEXPIRE",
+            Command::Expireat(..) => "EXPIREAT",
+            Command::Pexpireat(..) => "PEXPIREAT",
+            Command::Ttl(_) => "TTL",
+            Command::Pttl(_) => "PTTL",
+            Command::Persist(_) => "PERSIST",
+            Command::Object(..) => "OBJECT",
+            Command::Mget(_) => "MGET",
+            Command::Rename(..) => "RENAME",
+            Command::Sadd(..) => "SADD",
+            Command::Srem(..) => "SREM",
+            Command::Smembers(_) => "SMEMBERS",
+            Command::Sismember(..) => "SISMEMBER",
+            Command::Scard(_) => "SCARD",
+            Command::Sinter(_) => "SINTER",
+            Command::Sinterstore(..) => "SINTERSTORE",
+            Command::Multi => "MULTI",
+            Command::Discard => "DISCARD",
+            Command::Exec(_) => "EXEC",
+            Command::Replicaof(..) => "REPLICAOF",
+            Command::Psync(..) => "PSYNC",
+            Command::Replconf(_) => "REPLCONF",
+            Command::Wait(..) => "WAIT",
+            Command::Role => "ROLE",
+            Command::Exists(_) => "EXISTS",
+            Command::Restore(..) => "RESTORE",
+            Command::Cluster(_) => "CLUSTER",
+            Command::Asking => "ASKING",
+            Command::Migrate(_) => "MIGRATE",
+            Command::Config(_) => "CONFIG",
+            Command::Auth(_) => "AUTH",
+            Command::Acl(_) => "ACL",
+            Command::Client(_) => "CLIENT",
+            Command::Slowlog(_) => "SLOWLOG",
+            Command::Monitor => "MONITOR",
+            Command::Latency(_) => "LATENCY",
+            Command::Shutdown(_) => "SHUTDOWN",
+            Command::Command(_) => "COMMAND",
+        }
+    }
+
+    /// The command as the list of strings a client would send, name first. The commands queued
+    /// in an EXEC aren't included.
+    pub fn to_args(&self) -> Vec<String> {
+        fn value(data: &RespData) -> String {
+            match data {
+                RespData::BulkStr(string) | RespData::SimpleStr(string) => string.clone(),
+                RespData::Number(n) => n.to_string(),
+                _ => String::new(),
+            }
+        }
+
+        let mut args = vec![self.name().to_string()];
+        match self {
+            Command::Echo(data) | Command::Ping(Some(data)) => args.push(value(data)),
+            Command::Get(key)
+            | Command::Incr(key, None)
+            | Command::Decr(key, None)
+            | Command::Lpop(key, None)
+            | Command::Rpop(key, None)
+            | Command::Llen(key)
+            | Command::Dump(key)
+            | Command::Xlen(key)
+            | Command::Ttl(key)
+            | Command::Pttl(key)
+            | Command::Persist(key)
+            | Command::Smembers(key)
+            | Command::Scard(key)
+            | Command::Select(key)
+            | Command::Keys(key) => args.push(key.clone()),
+            Command::Set(key, data, options) => {
+                args.extend([key.clone(), value(data)]);
+                args.extend(options.iter().cloned());
+            }
+            Command::Lpush(key, values) | Command::Rpush(key, values) => {
+                args.push(key.clone());
+                args.extend(values.iter().map(value));
+            }
+            Command::Incr(key, Some(by))
+            | Command::Decr(key, Some(by))
+            | Command::Lpop(key, Some(by))
+            | Command::Rpop(key, Some(by)) => args.extend([key.clone(), by.to_string()]),
+            Command::Lrange(key, start, end) => {
+                args.extend([key.clone(), start.to_string(), end.to_string()])
+            }
+            Command::Pfadd(key, rest)
+            | Command::Pfmerge(key, rest)
+            | Command::Xadd(key, rest)
+            | Command::Xrange(key, rest)
+            | Command::Xrevrange(key, rest)
+            | Command::Xdel(key, rest)
+            | Command::Xtrim(key, rest)
+            | Command::Xclaim(key, rest)
+            | Command::Xautoclaim(key, rest)
+            | Command::Geoadd(key, rest)
+            | Command::Geopos(key, rest)
+            | Command::Geodist(key, rest)
+            | Command::Geohash(key, rest)
+            | Command::Geosearch(key, rest)
+            | Command::Sadd(key, rest)
+            | Command::Srem(key, rest)
+            | Command::Sinterstore(key, rest)
+            | Command::Restore(key, rest) => {
+                args.push(key.clone());
+                args.extend(rest.iter().cloned());
+            }
+            Command::Xack(key, group, rest) | Command::Xpending(key, group, rest) => {
+                args.extend([key.clone(), group.clone()]);
+                args.extend(rest.iter().cloned());
+            }
+            Command::Geosearchstore(dest, source, rest) => {
+                args.extend([dest.clone(), source.clone()]);
+                args.extend(rest.iter().cloned());
+            }
+            Command::Expire(key, ttl, options)
+            | Command::Pexpire(key, ttl, options)
+            | Command::Expireat(key, ttl, options)
+            | Command::Pexpireat(key, ttl, options) => {
+                args.extend([key.clone(), ttl.clone()]);
+                args.extend(options.iter().cloned());
+            }
+            Command::Object(subcommand, key) => args.extend([subcommand.clone(), key.clone()]),
+            Command::Sismember(key, member) => args.extend([key.clone(), member.clone()]),
+            Command::Rename(source, dest)
+            | Command::Replicaof(source, dest)
+            | Command::Psync(source, dest)
+            | Command::Wait(source, dest) => args.extend([source.clone(), dest.clone()]),
+            Command::Scan(rest)
+            | Command::Pfcount(rest)
+            | Command::Xread(rest)
+            | Command::Xreadgroup(rest)
+            | Command::Xgroup(rest)
+            | Command::Xinfo(rest)
+            | Command::Del(rest)
+            | Command::Mget(rest)
+            | Command::Sinter(rest)
+            | Command::Replconf(rest)
+            | Command::Exists(rest)
+            | Command::Cluster(rest)
+            | Command::Migrate(rest)
+            | Command::Config(rest)
+            | Command::Auth(rest)
+            | Command::Acl(rest)
+            | Command::Client(rest)
+            | Command::Slowlog(rest)
+            | Command::Latency(rest)
+            | Command::Shutdown(rest)
+            | Command::Command(rest)
+            | Command::Info(rest) => args.extend(rest.iter().cloned()),
+            Command::Ping(None)
+            | Command::FlushAll
+            | Command::Multi
+            | Command::Discard
+            | Command::Role
+            | Command::Monitor
+            | Command::Asking
+            | Command::Exec(_) => {}
+        }
+        args
+    }
 }
@@ -0,0 +1,937 @@
+// Users, what each of them is allowed to do, and a log of what they were stopped from doing
+//
+// Like Redis, a user has passwords (kept as SHA-256 hashes), the commands it can run as categories
+// and names, the keys it can read and write as glob patterns, and the pub/sub channels it can use.
+use crate::command_table::{self, COMMANDS};
+use crate::config::split_args;
+use ring::digest;
+use rustdss_core::keyspace::glob_match;
+use rustdss_data::{wire_bytes, Command, RespData};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// How long a denial that keeps happening adds to the same log entry rather than a new one
+const LOG_MERGE_MILLIS: u128 = 60_000;
+
+fn unknown_name() -> String {
+    "Unknown command or category name in ACL".into()
+}
+
+/// The commands in a category, `all` being every one of them
+pub fn commands_in(category: &str) -> Option<Vec<&'static str>> {
+    if category != "all" && !CATEGORIES.contains(&category) {
+        return None;
+    }
+    Some(
+        COMMANDS
+            .iter()
+            .filter(|spec| category == "all" || spec.categories.contains(&category))
+            .map(|spec| spec.name)
+            .collect(),
+    )
+}
+
+pub fn categories() -> &'static [&'static str] {
+    CATEGORIES
+}
+
+/// Whether a command reads the keys it names, and whether it writes them. Writes that don't look
+/// at what was there before, like SET or LPUSH, only need to be allowed to write.
+fn key_access(cmd: &Command) -> (bool, bool) {
+    if !cmd.is_write() {
+        return (true, false);
+    }
+    // SET with GET gives back what was there
+    if let Command::Set(_, _, options) = cmd {
+        let get = options
+            .iter()
+            .any(|option| option.eq_ignore_ascii_case("get"));
+        return (get, true);
+    }
+    let blind = matches!(
+        cmd.name(),
+        "LPUSH"
+            | "RPUSH"
+            | "DEL"
+            | "EXPIRE"
+            | "PEXPIRE"
+            | "EXPIREAT"
+            | "PEXPIREAT"
+            | "PERSIST"
+            | "SADD"
+            | "SREM"
+            | "PFADD"
+            | "XADD"
+            | "XDEL"
+            | "XTRIM"
+            | "XACK"
+            | "XGROUP"
+            | "GEOADD"
+            | "RESTORE"
+    );
+    (!blind, true)
+}
+
+/// The subcommand a command was run with, for the commands that have them
+pub fn subcommand(cmd: &Command) -> Option<String> {
+    let spec = command_table::lookup(cmd.name())?;
+    if spec.subcommands.is_empty() {
+        return None;
+    }
+    cmd.to_args().get(1).map(|sub| sub.to_lowercase())
+}
+
+/// The SHA-256 of a password as 64 lowercase hex characters, which is how passwords are kept. The
+/// bytes the client sent are hashed, so the hashes match the ones Redis makes.
+fn password_hash(password: &str) -> String {
+    digest::digest(&digest::SHA256, &wire_bytes(password))
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, true) => format!("~{}", self.pattern),
+            (true, false) => format!("%R~{}", self.pattern),
+            _ => format!("%W~{}", self.pattern),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    enabled: bool,
+    nopass: bool,
+    passwords: BTreeSet<String>,
+    // The command rules as they were given since the last +@all or -@all, to describe the user
+    command_rules: Vec<String>,
+    commands: BTreeSet<&'static str>,
+    // `command|subcommand` rules, which win over the command's own
+    subcommands: BTreeMap<String, bool>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+impl Default for User {
+    /// A new user can't log in or do anything until it's given permissions
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            command_rules: vec!["-@all".into()],
+            commands: BTreeSet::new(),
+            subcommands: BTreeMap::new(),
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+}
+
+impl User {
+    /// The default user everyone is when nothing's been set up
+    fn unrestricted() -> Self {
+        let mut user = Self::default();
+        for rule in &["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).expect("valid rule");
+        }
+        user
+    }
+
+    fn set_command(&mut self, name: &'static str, allowed: bool) {
+        if allowed {
+            self.commands.insert(name);
+        } else {
+            self.commands.remove(name);
+        }
+        let prefix = format!("{}|", name);
+        self.subcommands.retain(|sub, _| !sub.starts_with(&prefix));
+    }
+
+    fn apply_command_rule(&mut self, allowed: bool, name: &str) -> Result<(), String> {
+        let name = name.to_lowercase();
+        if let Some(category) = name.strip_prefix('@') {
+            for command in commands_in(category).ok_or_else(unknown_name)? {
+                self.set_command(command, allowed);
+            }
+            if category == "all" {
+                self.command_rules.clear();
+            }
+        } else if let Some((command, sub)) = name.split_once('|') {
+            let has_subcommands =
+                command_table::lookup(command).is_some_and(|spec| !spec.subcommands.is_empty());
+            if sub.is_empty() || !has_subcommands {
+                return Err(unknown_name());
+            }
+            self.subcommands.insert(name.clone(), allowed);
+        } else {
+            let command = COMMANDS
+                .iter()
+                .find(|spec| spec.name == name)
+                .ok_or_else(unknown_name)?;
+            self.set_command(command.name, allowed);
+        }
+        self.command_rules
+            .push(format!("{}{}", if allowed { '+' } else { '-' }, name));
+        Ok(())
+    }
+
+    fn add_key(&mut self, pattern: KeyPattern) -> Result<(), String> {
+        if self
+            .keys
+            .iter()
+            .any(|key| key.pattern == "*" && key.read && key.write)
+        {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".into());
+        }
+        if pattern.pattern == "*" && pattern.read && pattern.write {
+            self.keys.clear();
+        }
+        self.keys.push(pattern);
+        Ok(())
+    }
+
+    fn add_channel(&mut self, pattern: &str) -> Result<(), String> {
+        if self.channels.iter().any(|channel| channel == "*") {
+            return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels".into());
+        }
+        if pattern == "*" {
+            self.channels.clear();
+        }
+        self.channels.push(pattern.into());
+        Ok(())
+    }
+
+    /// Applies one ACL SETUSER rule
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.passwords.clear();
+                self.nopass = true;
+            }
+            "resetpass" => {
+                self.passwords.clear();
+                self.nopass = false;
+            }
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => *self = Self::default(),
+            _ => {
+                let mut chars = rule.chars();
+                let first = chars.next();
+                self.apply_prefixed(first, chars.as_str())?
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a rule that's a character and then a password, pattern or command
+    fn apply_prefixed(&mut self, first: Option<char>, rest: &str) -> Result<(), String> {
+        let syntax_error = || String::from("Syntax error");
+        match first {
+            Some('>') => {
+                self.passwords.insert(password_hash(rest));
+                self.nopass = false;
+            }
+            Some('#') => {
+                let valid = rest.len() == 64
+                    && rest
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+                if !valid {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".into());
+                }
+                self.passwords.insert(rest.into());
+                self.nopass = false;
+            }
+            Some('<') | Some('!') => {
+                let hash = if first == Some('<') {
+                    password_hash(rest)
+                } else {
+                    rest.to_string()
+                };
+                if !self.passwords.remove(&hash) {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist".into(),
+                    );
+                }
+            }
+            Some('~') => self.add_key(KeyPattern {
+                pattern: rest.into(),
+                read: true,
+                write: true,
+            })?,
+            Some('%') => {
+                let (flags, pattern) = rest.split_once('~').ok_or_else(syntax_error)?;
+                let flags = flags.to_uppercase();
+                if flags.is_empty() || flags.chars().any(|flag| flag != 'R' && flag != 'W') {
+                    return Err(syntax_error());
+                }
+                self.add_key(KeyPattern {
+                    pattern: pattern.into(),
+                    read: flags.contains('R'),
+                    write: flags.contains('W'),
+                })?
+            }
+            Some('&') => self.add_channel(rest)?,
+            Some('+') => self.apply_command_rule(true, rest)?,
+            Some('-') => self.apply_command_rule(false, rest)?,
+            _ => return Err(syntax_error()),
+        }
+        Ok(())
+    }
+
+    fn check_password(&self, password: &str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&password_hash(password)))
+    }
+
+    fn can_run(&self, name: &str, sub: Option<&str>) -> bool {
+        if let Some(sub) = sub {
+            if let Some(allowed) = self.subcommands.get(&format!("{}|{}", name, sub)) {
+                return *allowed;
+            }
+        }
+        self.commands.contains(name)
+    }
+
+    fn can_access(&self, key: &str, read: bool, write: bool) -> bool {
+        let key: Vec<char> = key.chars().collect();
+        self.keys.iter().any(|pattern| {
+            (pattern.read || !read)
+                && (pattern.write || !write)
+                && glob_match(&pattern.pattern.chars().collect::<Vec<_>>(), &key)
+        })
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    fn keys(&self) -> String {
+        let keys: Vec<_> = self.keys.iter().map(KeyPattern::describe).collect();
+        keys.join(" ")
+    }
+
+    fn channels(&self) -> String {
+        let channels: Vec<_> = self
+            .channels
+            .iter()
+            .map(|channel| format!("&{}", channel))
+            .collect();
+        channels.join(" ")
+    }
+
+    /// The rules that would make this user again, as ACL LIST and the ACL file show them
+    fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().iter().map(|flag| flag.to_string()).collect();
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        if !self.keys.is_empty() {
+            rules.push(self.keys());
+        }
+        if self.channels.is_empty() {
+            rules.push("resetchannels".into());
+        } else {
+            rules.push(self.channels());
+        }
+        rules.extend(self.command_rules.iter().cloned());
+        rules.join(" ")
+    }
+}
+
+/// Why a command wasn't allowed
+#[derive(Debug, PartialEq)]
+pub struct Denial {
+    /// What the log calls it: command, key or channel
+    pub reason: &'static str,
+    /// The command or key that was denied
+    pub object: String,
+    pub message: String,
+}
+
+struct LogEntry {
+    count: i64,
+    reason: &'static str,
+    context: &'static str,
+    object: String,
+    username: String,
+    client_info: String,
+    entry_id: i64,
+    created: u128,
+    updated: u128,
+}
+
+#[derive(Default)]
+struct State {
+    users: BTreeMap<String, User>,
+    // Newest first
+    log: VecDeque<LogEntry>,
+    next_entry_id: i64,
+}
+
+pub struct Acl {
+    state: Mutex<State>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let mut users = BTreeMap::new();
+        users.insert("default".into(), User::unrestricted());
+        Self {
+            state: Mutex::new(State {
+                users,
+                ..State::default()
+            }),
+        }
+    }
+}
+
+fn parse_users(text: &str, source: &str) -> Result<BTreeMap<String, User>, String> {
+    let mut users = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", source, index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args = split_args(line).map_err(error)?;
+        match args.as_slice() {
+            [user, name, rules @ ..] if user == "user" => {
+                if users.contains_key(name) {
+                    return Err(error(format!("Duplicate user '{}' found", name)));
+                }
+                let mut user = User::default();
+                for rule in rules {
+                    user.apply(rule).map_err(|message| {
+                        error(format!("Error in user declaration '{}': {}", rule, message))
+                    })?;
+                }
+                users.insert(name.clone(), user);
+            }
+            _ => return Err(error("should start with user keyword".into())),
+        }
+    }
+    // There's always a default user, which is the usual one when the file doesn't say
+    users
+        .entry("default".into())
+        .or_insert_with(User::unrestricted);
+    Ok(users)
+}
+
+impl Acl {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Who a new connection is before it uses AUTH, if anyone
+    pub fn initial_user(&self) -> Option<String> {
+        let state = self.lock();
+        let default = state.users.get("default")?;
+        Some("default".to_string()).filter(|_| default.enabled && default.nopass)
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        self.lock()
+            .users
+            .get(username)
+            .is_some_and(|user| user.check_password(password))
+    }
+
+    /// What `requirepass` does: the default user needs this password, or none when it's empty
+    pub fn set_default_password(&self, password: &str) {
+        let mut state = self.lock();
+        let default = state.users.entry("default".into()).or_default();
+        default.passwords.clear();
+        if password.is_empty() {
+            default.nopass = true;
+        } else {
+            default.nopass = false;
+            default.passwords.insert(password_hash(password));
+        }
+    }
+
+    /// Whether the user can run a command on the keys it names
+    pub fn check(&self, username: &str, cmd: &Command) -> Result<(), Denial> {
+        let state = self.lock();
+        let name = cmd.name().to_lowercase();
+        let sub = subcommand(cmd);
+        let user = state.users.get(username);
+        if !user.is_some_and(|user| user.can_run(&name, sub.as_deref())) {
+            let name = match sub {
+                Some(sub) => format!("{}|{}", name, sub),
+                None => name,
+            };
+            return Err(Denial {
+                reason: "command",
+                message: format!(
+                    "NOPERM User {} has no permissions to run the '{}' command",
+                    username, name
+                ),
+                object: name,
+            });
+        }
+        let user = user.expect("checked above");
+        let (read, write) = key_access(cmd);
+        match cmd
+            .key_args()
+            .into_iter()
+            .find(|key| !user.can_access(key, read, write))
+        {
+            Some(key) => Err(Denial {
+                reason: "key",
+                object: key.clone(),
+                message: "NOPERM No permissions to access a key".into(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies ACL SETUSER rules to a user, making it if it's new. Either every rule is used or
+    /// none of them are.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut state = self.lock();
+        let mut user = state.users.get(name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply(rule).map_err(|message| {
+                format!("Error in ACL SETUSER modifier '{}': {}", rule, message)
+            })?;
+        }
+        state.users.insert(name.into(), user);
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> Option<RespData> {
+        let state = self.lock();
+        let user = state.users.get(name)?;
+        let strings = |strings: Vec<String>| {
+            RespData::List(strings.into_iter().map(RespData::BulkStr).collect())
+        };
+        Some(RespData::List(
+            vec![
+                RespData::BulkStr("flags".into()),
+                strings(user.flags().iter().map(|flag| flag.to_string()).collect()),
+                RespData::BulkStr("passwords".into()),
+                strings(user.passwords.iter().cloned().collect()),
+                RespData::BulkStr("commands".into()),
+                RespData::BulkStr(user.command_rules.join(" ")),
+                RespData::BulkStr("keys".into()),
+                RespData::BulkStr(user.keys()),
+                RespData::BulkStr("channels".into()),
+                RespData::BulkStr(user.channels()),
+                RespData::BulkStr("selectors".into()),
+                RespData::List(Default::default()),
+            ]
+            .into(),
+        ))
+    }
+
+    /// Removes users, giving how many there were
+    pub fn delete_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == "default") {
+            return Err("The 'default' user cannot be removed".into());
+        }
+        let mut state = self.lock();
+        Ok(names
+            .iter()
+            .filter(|name| state.users.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    pub fn usernames(&self) -> Vec<String> {
+        self.lock().users.keys().cloned().collect()
+    }
+
+    /// Whether there's a user by this name that can log in
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.lock().users.get(name).is_some_and(|user| user.enabled)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.lock().users.contains_key(name)
+    }
+
+    /// Each user as the line that would make it again
+    pub fn list(&self) -> Vec<String> {
+        self.lock()
+            .users
+            .iter()
+            .map(|(name, user)| format!("user {} {}", name, user.describe()))
+            .collect()
+    }
+
+    /// Replaces every user with the ones in an ACL file, unless there's anything wrong with it
+    pub fn load(&self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Error loading ACLs, opening file '{}': {}", path, error))?;
+        let users = parse_users(&text, path)?;
+        self.lock().users = users;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut text = self.list().join("\n");
+        text.push('\n');
+        // Written next to it first, so the file is never left half written
+        let temporary = format!("{}.save", path);
+        fs::write(&temporary, text)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|error| format!("There was an error trying to save the ACLs: {}", error))
+    }
+
+    /// Notes a denial in the ACL LOG, adding to a recent entry for the same thing if there is one
+    pub fn log(
+        &self,
+        denial: &Denial,
+        username: &str,
+        context: &'static str,
+        client_info: &str,
+        max_len: usize,
+    ) {
+        let now = now_millis();
+        let mut state = self.lock();
+        let similar = state.log.iter().position(|entry| {
+            entry.reason == denial.reason
+                && entry.context == context
+                && entry.object == denial.object
+                && entry.username == username
+                && now.saturating_sub(entry.updated) < LOG_MERGE_MILLIS
+        });
+        let entry = match similar.and_then(|index| state.log.remove(index)) {
+            Some(mut entry) => {
+                entry.count += 1;
+                entry.updated = now;
+                entry.client_info = client_info.into();
+                entry
+            }
+            None => {
+                state.next_entry_id += 1;
+                LogEntry {
+                    count: 1,
+                    reason: denial.reason,
+                    context,
+                    object: denial.object.clone(),
+                    username: username.into(),
+                    client_info: client_info.into(),
+                    entry_id: state.next_entry_id - 1,
+                    created: now,
+                    updated: now,
+                }
+            }
+        };
+        state.log.push_front(entry);
+        state.log.truncate(max_len);
+    }
+
+    /// The newest log entries, as ACL LOG gives them
+    pub fn log_entries(&self, count: usize) -> RespData {
+        let now = now_millis();
+        let state = self.lock();
+        RespData::List(
+            state
+                .log
+                .iter()
+                .take(count)
+                .map(|entry| {
+                    let field = |name: &str| RespData::BulkStr(name.into());
+                    RespData::List(
+                        vec![
+                            field("count"),
+                            RespData::Number(entry.count),
+                            field("reason"),
+                            field(entry.reason),
+                            field("context"),
+                            field(entry.context),
+                            field("object"),
+                            field(&entry.object),
+                            field("username"),
+                            field(&entry.username),
+                            field("age-seconds"),
+                            RespData::BulkStr(format!(
+                                "{:.3}",
+                                now.saturating_sub(entry.created) as f64 / 1000.0
+                            )),
+                            field("client-info"),
+                            field(&entry.client_info),
+                            field("entry-id"),
+                            RespData::Number(entry.entry_id),
+                            field("timestamp-created"),
+                            RespData::Number(entry.created as i64),
+                            field("timestamp-last-updated"),
+                            RespData::Number(entry.updated as i64),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn reset_log(&self) {
+        self.lock().log.clear();
+    }
+}
+
+#[cfg(test)]
+mod acl_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    fn get(key: &str) -> Command {
+        Command::Get(key.into())
+    }
+
+    fn set(key: &str) -> Command {
+        Command::Set(key.into(), RespData::BulkStr("1".into()), vec![])
+    }
+
+    #[test]
+    fn let_the_default_user_do_anything() {
+        let acl = Acl::default();
+
+        assert_eq!(acl.initial_user(), Some("default".into()));
+        assert!(acl.authenticate("default", "anything"));
+        assert_eq!(acl.check("default", &Command::FlushAll), Ok(()));
+        assert_eq!(
+            acl.list(),
+            vec!["user default on nopass ~* &* +@all".to_string()]
+        );
+    }
+
+    #[test]
+    fn need_a_password_once_one_is_required() {
+        let acl = Acl::default();
+        acl.set_default_password("secret");
+
+        assert_eq!(acl.initial_user(), None);
+        assert!(!acl.authenticate("default", "guess"));
+        assert!(acl.authenticate("default", "secret"));
+        assert!(!acl.authenticate("nobody", "secret"));
+    }
+
+    #[test]
+    fn hash_the_password_bytes_the_client_sent() {
+        let acl = Acl::default();
+        // SHA-256 of "pässword" in UTF-8, as ACL GETUSER on Redis gives it
+        acl.set_user(
+            "bob",
+            &args(&[
+                "on",
+                "#3478267b5612791b40988906b3a7897eb6ab501e04b95ed32f99d0afdf669d9c",
+            ]),
+        )
+        .unwrap();
+        // How the deserialiser stores those bytes
+        let sent = "p\u{c3}\u{a4}ssword";
+
+        assert!(acl.authenticate("bob", sent));
+        acl.set_user("carol", &args(&["on", &format!(">{}", sent)]))
+            .unwrap();
+        assert!(acl.authenticate("carol", sent));
+        assert!(!acl.authenticate("carol", "pässword"));
+    }
+
+    #[test]
+    fn limit_users_to_their_commands() {
+        let acl = Acl::default();
+        acl.set_user(
+            "alice",
+            &args(&["on", ">pass", "~*", "+@read", "-mget", "+config|get"]),
+        )
+        .unwrap();
+
+        assert!(acl.authenticate("alice", "pass"));
+        assert_eq!(acl.check("alice", &get("a")), Ok(()));
+        assert_eq!(
+            acl.check("alice", &set("a")).unwrap_err().message,
+            "NOPERM User alice has no permissions to run the 'set' command"
+        );
+        assert!(acl
+            .check("alice", &Command::Mget(vec!["a".into()]))
+            .is_err());
+        assert_eq!(
+            acl.check("alice", &Command::Config(args(&["GET", "port"]))),
+            Ok(())
+        );
+        assert_eq!(
+            acl.check("alice", &Command::Config(args(&["SET", "port", "1"])))
+                .unwrap_err()
+                .object,
+            "config|set"
+        );
+    }
+
+    #[test]
+    fn limit_users_to_their_keys() {
+        let acl = Acl::default();
+        acl.set_user(
+            "bob",
+            &args(&[
+                "on",
+                "nopass",
+                "+@all",
+                "~public:*",
+                "%R~shared:*",
+                "%W~inbox:*",
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(acl.check("bob", &set("public:1")), Ok(()));
+        assert_eq!(acl.check("bob", &get("shared:1")), Ok(()));
+        assert_eq!(acl.check("bob", &set("inbox:1")), Ok(()));
+        assert_eq!(
+            acl.check("bob", &set("shared:1")).unwrap_err(),
+            Denial {
+                reason: "key",
+                object: "shared:1".into(),
+                message: "NOPERM No permissions to access a key".into(),
+            }
+        );
+        assert!(acl.check("bob", &get("inbox:1")).is_err());
+        // INCR reads the old value as well as writing the new one
+        assert!(acl
+            .check("bob", &Command::Incr("inbox:1".into(), None))
+            .is_err());
+        let set_and_get = Command::Set(
+            "inbox:1".into(),
+            RespData::BulkStr("1".into()),
+            vec!["GET".into()],
+        );
+        assert!(acl.check("bob", &set_and_get).is_err());
+        assert!(acl.check("bob", &get("secret")).is_err());
+    }
+
+    #[test]
+    fn describe_users_the_way_they_were_made() {
+        let acl = Acl::default();
+        acl.set_user(
+            "carol",
+            &args(&[
+                "on",
+                "#5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8",
+                "%R~cache:*",
+                "&news",
+                "+@all",
+                "-@dangerous",
+                "+info",
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            acl.list()[0],
+            "user carol on #5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8 %R~cache:* &news +@all -@dangerous +info"
+        );
+        assert!(acl.authenticate("carol", "password"));
+        assert_eq!(acl.check("carol", &Command::Info(vec![])), Ok(()));
+        assert!(acl.check("carol", &Command::FlushAll).is_err());
+
+        let reloaded = parse_users(&acl.list().join("\n"), "test").unwrap();
+        assert_eq!(reloaded["carol"].describe(), acl.list()[0][11..]);
+    }
+
+    #[test]
+    fn reject_bad_rules_without_changing_anything() {
+        let acl = Acl::default();
+        acl.set_user("dave", &args(&["on", "+get"])).unwrap();
+
+        for bad in &[
+            "+nonsense",
+            "+@nonsense",
+            "#tooshort",
+            "<notapassword",
+            "%X~a",
+            "bogus",
+        ] {
+            assert!(
+                acl.set_user("dave", &args(&["off", bad])).is_err(),
+                "{}",
+                bad
+            );
+        }
+        assert!(acl.set_user("dave", &args(&["~*", "~more"])).is_err());
+        assert_eq!(acl.list()[0], "user dave on resetchannels -@all +get");
+        assert!(acl.delete_users(&args(&["default"])).is_err());
+        assert_eq!(acl.delete_users(&args(&["dave", "nobody"])), Ok(1));
+    }
+
+    #[test]
+    fn log_repeated_denials_together() {
+        let acl = Acl::default();
+        acl.set_user("eve", &args(&["on", "nopass"])).unwrap();
+        let denial = acl.check("eve", &get("a")).unwrap_err();
+        acl.log(&denial, "eve", "toplevel", "addr=1", 10);
+        acl.log(&denial, "eve", "toplevel", "addr=2", 10);
+        acl.log(&denial, "eve", "multi", "addr=2", 10);
+
+        match acl.log_entries(10) {
+            RespData::List(entries) => {
+                assert_eq!(entries.len(), 2);
+                match &entries[1] {
+                    RespData::List(fields) => {
+                        assert_eq!(fields[1], RespData::Number(2));
+                        assert_eq!(fields[7], RespData::BulkStr("get".into()));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+        acl.reset_log();
+        assert_eq!(acl.log_entries(10), RespData::List(Default::default()));
+    }
+}
@@ -0,0 +1,72 @@
+// A plain connection to another server, for the commands servers send each other
+use rustdss_data::RespData;
+use rustdss_transport::{deserialise::DeserialiseRespData, serialise::SerialiseRespData};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub fn encode(args: &[&str]) -> Vec<u8> {
+    RespData::List(
+        args.iter()
+            .map(|arg| RespData::BulkStr((*arg).into()))
+            .collect(),
+    )
+    .as_bytes()
+}
+
+pub struct Client {
+    stream: TcpStream,
+    input: Box<dyn Iterator<Item = char> + Send>,
+}
+
+impl Client {
+    /// Connects to `host:port`, giving up on connecting, reading or writing after `timeout`
+    pub fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<Self> {
+        let address = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown host"))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let input = BufReader::new(stream.try_clone()?)
+            .bytes()
+            .map_while(Result::ok)
+            .map(|byte| byte as char);
+        Ok(Self {
+            stream,
+            input: Box::new(input),
+        })
+    }
+
+    /// Sends a command and waits for the reply
+    pub fn call(&mut self, args: &[&str]) -> io::Result<RespData> {
+        self.stream.write_all(&encode(args))?;
+        RespData::from_char_stream(&mut self.input)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+    }
+
+    /// Logs in with AUTH, when there's a password to log in with
+    pub fn auth(&mut self, user: &str, password: &str) -> io::Result<()> {
+        if password.is_empty() {
+            return Ok(());
+        }
+        let mut auth = vec!["AUTH"];
+        if !user.is_empty() {
+            auth.push(user);
+        }
+        auth.push(password);
+        match self.call(&auth)? {
+            RespData::Error(error) => Err(io::Error::new(io::ErrorKind::PermissionDenied, error)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The address this end of the connection has, which is how the other server sees this one
+    pub fn local_ip(&self) -> Option<String> {
+        self.stream
+            .local_addr()
+            .ok()
+            .map(|address| address.ip().to_string())
+    }
+}
@@ -0,0 +1,517 @@
+// Every connection to the server, for CLIENT LIST, CLIENT KILL and CLIENT PAUSE
+use crate::config::{OutputBufferLimit, OutputBufferLimits};
+use crate::connection::Stream;
+use rustdss_core::Caller;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientType {
+    Normal,
+    /// A replica being sent the replication stream
+    Replica,
+    /// This server's link to its master
+    Master,
+    PubSub,
+}
+
+impl ClientType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "normal" => Some(ClientType::Normal),
+            "replica" | "slave" => Some(ClientType::Replica),
+            "master" => Some(ClientType::Master),
+            "pubsub" => Some(ClientType::PubSub),
+            _ => None,
+        }
+    }
+
+    /// The output buffer limit clients of this kind are held to, the link to the master has none
+    pub fn output_buffer_limit(self, limits: &OutputBufferLimits) -> Option<OutputBufferLimit> {
+        match self {
+            ClientType::Normal => Some(limits.normal),
+            ClientType::Replica => Some(limits.replica),
+            ClientType::PubSub => Some(limits.pubsub),
+            ClientType::Master => None,
+        }
+    }
+}
+
+/// What a connection is doing, which it keeps up to date after every command
+#[derive(Clone, Debug)]
+pub struct ClientState {
+    pub name: String,
+    pub database: String,
+    pub user: String,
+    pub kind: ClientType,
+    /// The last command run, as `command` or `command|subcommand`
+    pub last_command: String,
+    pub last_interaction: Instant,
+    /// How many commands are queued since MULTI, if it's in a transaction
+    pub multi: Option<usize>,
+    /// The size of the last command's arguments
+    pub argv_memory: usize,
+    pub no_evict: bool,
+    /// Whether it's been turned into a MONITOR feed
+    pub monitor: bool,
+    /// Whether one of its commands is running, which keeps it from timing out
+    pub running: bool,
+}
+
+pub struct Client {
+    pub id: u64,
+    pub address: String,
+    local_address: String,
+    created: Instant,
+    stream: Stream,
+    killed: AtomicBool,
+    /// Bytes of replies still to be written to the client
+    output_buffer: AtomicUsize,
+    // When the output buffer went over the soft limit, if it still is
+    over_soft_limit: Mutex<Option<Instant>>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    state: Mutex<ClientState>,
+}
+
+impl Client {
+    pub fn state(&self) -> MutexGuard<'_, ClientState> {
+        lock(&self.state)
+    }
+
+    pub fn local_address(&self) -> &str {
+        &self.local_address
+    }
+
+    pub fn kind(&self) -> ClientType {
+        self.state().kind
+    }
+
+    /// Closes the connection, which its thread notices the next time it reads or writes
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+        self.stream.shutdown();
+    }
+
+    /// Like `kill`, but leaves the connection open until the reply to the current command is sent
+    pub fn kill_after_reply(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    pub fn read(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Call when a reply is ready to write, and `written` once it's gone
+    pub fn queued(&self, bytes: usize) {
+        self.output_buffer.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn written(&self, bytes: usize) {
+        self.output_buffer.fetch_sub(bytes, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Whether the replies waiting to be written are over the hard limit, or have been over the
+    /// soft limit for long enough
+    pub fn is_over_output_limit(&self, limit: OutputBufferLimit) -> bool {
+        let queued = self.output_buffer.load(Ordering::Relaxed);
+        if limit.hard > 0 && queued >= limit.hard {
+            return true;
+        }
+        let mut since = lock(&self.over_soft_limit);
+        if limit.soft == 0 || queued < limit.soft {
+            *since = None;
+            return false;
+        }
+        since.get_or_insert_with(Instant::now).elapsed().as_secs() >= limit.soft_seconds
+    }
+
+    /// Whether it's been waiting for a command for longer than `timeout`. Only normal clients
+    /// time out, and never while a command of theirs is running.
+    pub fn is_idle_for(&self, timeout: Duration) -> bool {
+        let state = self.state();
+        state.kind == ClientType::Normal
+            && !state.monitor
+            && !state.running
+            && state.last_interaction.elapsed() > timeout
+    }
+
+    fn net_bytes(&self) -> (u64, u64) {
+        (
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Seconds since the client connected
+    pub fn age(&self) -> u64 {
+        self.created.elapsed().as_secs()
+    }
+
+    fn flags(&self, state: &ClientState) -> String {
+        let mut flags = String::new();
+        match state.kind {
+            ClientType::Replica => flags.push('S'),
+            ClientType::Master => flags.push('M'),
+            ClientType::PubSub => flags.push('P'),
+            ClientType::Normal => {}
+        }
+        if state.monitor {
+            flags.push('O');
+        }
+        if state.multi.is_some() {
+            flags.push('x');
+        }
+        if state.no_evict {
+            flags.push('e');
+        }
+        if self.is_killed() {
+            flags.push('c');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        flags
+    }
+
+    /// The client's line in CLIENT LIST
+    pub fn describe(&self) -> String {
+        let state = self.state().clone();
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub=0 psub=0 multi={} argv-mem={} omem={} tot-net-in={} tot-net-out={} cmd={} user={} resp=2",
+            self.id,
+            self.address,
+            self.local_address,
+            state.name,
+            self.age(),
+            state.last_interaction.elapsed().as_secs(),
+            self.flags(&state),
+            state.database,
+            state.multi.map_or(-1, |queued| queued as i64),
+            state.argv_memory,
+            self.output_buffer.load(Ordering::Relaxed),
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+            state.last_command,
+            state.user,
+        )
+    }
+}
+
+impl Caller for Client {
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    fn name(&self) -> String {
+        self.state().name.clone()
+    }
+}
+
+// Commands from normal clients wait until this time, or just the writes if `writes_only`
+#[derive(Clone, Copy)]
+struct Pause {
+    until: Instant,
+    writes_only: bool,
+}
+
+/// Turned away from `Clients::register` because maxclients were already connected
+#[derive(Debug, PartialEq)]
+pub struct MaxClientsReached;
+
+#[derive(Default)]
+pub struct Clients {
+    last_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+    pause: Mutex<Option<Pause>>,
+    unpaused: Condvar,
+    // What clients that have gone sent and were sent, for the totals in INFO
+    closed_bytes_in: AtomicU64,
+    closed_bytes_out: AtomicU64,
+}
+
+impl Clients {
+    /// Adds a connection, giving it the next ID. With a `limit`, it's turned away if that many
+    /// connections are already registered.
+    pub fn register(
+        &self,
+        stream: &Stream,
+        kind: ClientType,
+        user: Option<String>,
+        database: String,
+        limit: Option<usize>,
+    ) -> Result<Arc<Client>, MaxClientsReached> {
+        // Held from the check to the insert, so connections arriving together can't both get in
+        let mut clients = lock(&self.clients);
+        if limit.is_some_and(|limit| clients.len() >= limit) {
+            return Err(MaxClientsReached);
+        }
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let now = Instant::now();
+        let client = Arc::new(Client {
+            id,
+            address: stream.peer_address(),
+            local_address: stream.local_address(),
+            created: now,
+            stream: stream.clone(),
+            killed: AtomicBool::new(false),
+            output_buffer: AtomicUsize::new(0),
+            over_soft_limit: Mutex::new(None),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            state: Mutex::new(ClientState {
+                name: String::new(),
+                database,
+                user: user.unwrap_or_default(),
+                kind,
+                last_command: "NULL".into(),
+                last_interaction: now,
+                multi: None,
+                argv_memory: 0,
+                no_evict: false,
+                monitor: false,
+                running: false,
+            }),
+        });
+        clients.insert(id, client.clone());
+        Ok(client)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        if let Some(client) = lock(&self.clients).remove(&id) {
+            let (bytes_in, bytes_out) = client.net_bytes();
+            self.closed_bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+            self.closed_bytes_out
+                .fetch_add(bytes_out, Ordering::Relaxed);
+        }
+    }
+
+    /// How many clients are connected, not counting replicas being fed the replication stream,
+    /// like Redis
+    pub fn connected(&self) -> usize {
+        self.list()
+            .iter()
+            .filter(|client| client.kind() != ClientType::Replica)
+            .count()
+    }
+
+    /// Bytes read from and written to every client there has been
+    pub fn net_bytes(&self) -> (u64, u64) {
+        self.list().iter().map(|client| client.net_bytes()).fold(
+            (
+                self.closed_bytes_in.load(Ordering::Relaxed),
+                self.closed_bytes_out.load(Ordering::Relaxed),
+            ),
+            |(total_in, total_out), (bytes_in, bytes_out)| {
+                (total_in + bytes_in, total_out + bytes_out)
+            },
+        )
+    }
+
+    /// Forgets what closed clients sent and were sent, for CONFIG RESETSTAT
+    pub fn reset_stats(&self) {
+        self.closed_bytes_in.store(0, Ordering::Relaxed);
+        self.closed_bytes_out.store(0, Ordering::Relaxed);
+    }
+
+    /// Every client, oldest first
+    pub fn list(&self) -> Vec<Arc<Client>> {
+        lock(&self.clients).values().cloned().collect()
+    }
+
+    /// Holds commands from normal clients for `timeout`, or just the writes. A pause that's
+    /// already running is only ever made longer or stricter.
+    pub fn pause(&self, timeout: Duration, writes_only: bool) {
+        let until = Instant::now() + timeout;
+        let mut pause = lock(&self.pause);
+        *pause = Some(match *pause {
+            Some(current) if current.until > Instant::now() => Pause {
+                until: current.until.max(until),
+                writes_only: current.writes_only && writes_only,
+            },
+            _ => Pause { until, writes_only },
+        });
+    }
+
+    pub fn unpause(&self) {
+        *lock(&self.pause) = None;
+        self.unpaused.notify_all();
+    }
+
+    /// Waits for a pause to end if it holds this kind of command
+    pub fn wait_while_paused(&self, write: bool) {
+        let mut pause = lock(&self.pause);
+        while let Some(current) = *pause {
+            let now = Instant::now();
+            if now >= current.until {
+                *pause = None;
+            } else if write || !current.writes_only {
+                pause = self
+                    .unpaused
+                    .wait_timeout(pause, current.until - now)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .0;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod clients_should {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    fn stream() -> Stream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        Stream::tcp(listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn give_each_client_its_own_id() {
+        let clients = Clients::default();
+        let first = clients
+            .register(&stream(), ClientType::Normal, None, "0".into(), None)
+            .unwrap();
+        let second = clients
+            .register(
+                &stream(),
+                ClientType::Normal,
+                Some("default".into()),
+                "0".into(),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!((first.id, second.id), (1, 2));
+        assert!(second.describe().starts_with("id=2 addr=127.0.0.1:"));
+        assert!(second.describe().contains(" flags=N db=0 "));
+        assert!(second.describe().ends_with(" cmd=NULL user=default resp=2"));
+
+        first.read(10);
+        second.queued(5);
+        second.written(5);
+        assert_eq!(clients.net_bytes(), (10, 5));
+        clients.unregister(first.id);
+        assert_eq!(clients.net_bytes(), (10, 5));
+        let ids: Vec<u64> = clients.list().iter().map(|client| client.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn turn_clients_away_at_the_limit() {
+        let clients = Clients::default();
+        let first = clients
+            .register(&stream(), ClientType::Normal, None, "0".into(), Some(1))
+            .unwrap();
+
+        assert_eq!(
+            clients
+                .register(&stream(), ClientType::Normal, None, "0".into(), Some(1))
+                .err(),
+            Some(MaxClientsReached)
+        );
+        clients.unregister(first.id);
+        let second = clients
+            .register(&stream(), ClientType::Normal, None, "0".into(), Some(1))
+            .unwrap();
+        // Nobody turned away used up an ID
+        assert_eq!(second.id, 2);
+    }
+
+    #[test]
+    fn close_clients_that_fall_behind() {
+        let clients = Clients::default();
+        let client = clients
+            .register(&stream(), ClientType::Normal, None, "0".into(), None)
+            .unwrap();
+        let hard = OutputBufferLimit {
+            hard: 100,
+            soft: 0,
+            soft_seconds: 0,
+        };
+        let soft = OutputBufferLimit {
+            hard: 0,
+            soft: 50,
+            soft_seconds: 60,
+        };
+
+        client.queued(60);
+        assert!(!client.is_over_output_limit(hard));
+        assert!(!client.is_over_output_limit(soft));
+        // Over the soft limit long enough
+        assert!(client.is_over_output_limit(OutputBufferLimit {
+            soft_seconds: 0,
+            ..soft
+        }));
+
+        client.queued(40);
+        assert!(client.is_over_output_limit(hard));
+        client.written(100);
+        assert!(!client.is_over_output_limit(hard));
+    }
+
+    #[test]
+    fn only_time_out_normal_clients_waiting_for_a_command() {
+        let clients = Clients::default();
+        let client = clients
+            .register(&stream(), ClientType::Normal, None, "0".into(), None)
+            .unwrap();
+        let replica = clients
+            .register(&stream(), ClientType::Replica, None, "0".into(), None)
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(client.is_idle_for(Duration::from_millis(10)));
+        assert!(!client.is_idle_for(Duration::from_secs(10)));
+        assert!(!replica.is_idle_for(Duration::from_millis(10)));
+        client.state().running = true;
+        assert!(!client.is_idle_for(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn hold_writes_until_the_pause_ends() {
+        let clients = Arc::new(Clients::default());
+        clients.pause(Duration::from_secs(60), true);
+
+        // Reads carry on
+        clients.wait_while_paused(false);
+
+        let waiting = {
+            let clients = clients.clone();
+            thread::spawn(move || clients.wait_while_paused(true))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        clients.unpause();
+        waiting.join().unwrap();
+    }
+
+    #[test]
+    fn only_make_a_pause_stricter() {
+        let clients = Clients::default();
+        clients.pause(Duration::from_secs(60), false);
+        clients.pause(Duration::from_millis(1), true);
+
+        let pause = lock(&clients.pause).unwrap();
+        assert!(!pause.writes_only);
+        assert!(pause.until > Instant::now() + Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,848 @@
+// Cluster mode
+//
+// Each node owns some of the 16384 hash slots and sends clients to the owner of any other slot
+// with a MOVED redirect. There's no cluster bus: nodes are introduced with CLUSTER MEET and then
+// poll each other's CLUSTER NODES every second, taking on what each node says it owns. When two
+// nodes claim the same slot the one with the higher config epoch wins, and a node bumps its epoch
+// whenever it takes a slot over with CLUSTER SETSLOT <slot> NODE <id>, so a resharded slot ends
+// up with its new owner everywhere.
+//
+// Resharding works like Redis: the target marks the slot IMPORTING, the source marks it
+// MIGRATING, MIGRATE moves the keys across, and SETSLOT NODE hands the slot over. In between the
+// source serves the keys it still has and sends clients to the target with ASK for the rest.
+
+use crate::client::Client;
+use crate::server::Server;
+use rustdss_core::replication::random_id;
+use rustdss_core::sharding::{key_hash_slot, HASH_SLOTS};
+use rustdss_data::{Command, Key, RespData};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PEER_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Node {
+    id: String,
+    host: String,
+    port: u16,
+    epoch: u64,
+}
+
+impl Node {
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+struct ClusterState {
+    myself: Node,
+    others: Vec<Node>,
+    // The id of the node each slot belongs to
+    owners: Vec<Option<String>>,
+    // Slots this node is handing over, and who to
+    migrating: BTreeMap<u16, String>,
+    // Slots this node is taking over, and who from
+    importing: BTreeMap<u16, String>,
+    current_epoch: u64,
+}
+
+/// Where a command for some keys should run
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Local,
+    /// The slot is being handed over: keys still here are served here, the rest are asked for
+    /// at `address`
+    Migrating {
+        slot: u16,
+        address: String,
+    },
+}
+
+fn error(message: &str) -> RespData {
+    RespData::Error(message.into())
+}
+
+fn bulk(string: &str) -> RespData {
+    RespData::BulkStr(string.into())
+}
+
+/// Runs of consecutive slots, as (first, last)
+fn ranges(slots: impl Iterator<Item = u16>) -> Vec<(u16, u16)> {
+    let mut ranges: Vec<(u16, u16)> = vec![];
+    for slot in slots {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == slot => *last = slot,
+            _ => ranges.push((slot, slot)),
+        }
+    }
+    ranges
+}
+
+fn parse_slot(slot: &str) -> Result<u16, RespData> {
+    slot.parse()
+        .ok()
+        .filter(|slot| *slot < HASH_SLOTS)
+        .ok_or_else(|| error("ERR Invalid or out of range slot"))
+}
+
+impl ClusterState {
+    fn node(&self, id: &str) -> Option<&Node> {
+        std::iter::once(&self.myself)
+            .chain(&self.others)
+            .find(|node| node.id == id)
+    }
+
+    fn address_of(&self, id: &str) -> String {
+        self.node(id).map(Node::address).unwrap_or_default()
+    }
+
+    fn owns(&self, slot: u16) -> bool {
+        self.owners[slot as usize].as_deref() == Some(self.myself.id.as_str())
+    }
+
+    fn slots_of<'a>(&'a self, id: &'a str) -> impl Iterator<Item = u16> + 'a {
+        (0..HASH_SLOTS).filter(move |slot| self.owners[*slot as usize].as_deref() == Some(id))
+    }
+
+    fn set_owner(&mut self, slot: u16, id: Option<String>) {
+        if self.owns(slot) && id.as_deref() != Some(self.myself.id.as_str()) {
+            self.migrating.remove(&slot);
+        }
+        if id.as_deref() == Some(self.myself.id.as_str()) {
+            self.importing.remove(&slot);
+        }
+        self.owners[slot as usize] = id;
+    }
+
+    /// A node says it owns a slot, which it gets unless the current owner has a higher epoch
+    fn claim(&mut self, slot: u16, id: &str, epoch: u64) {
+        let wins = match &self.owners[slot as usize] {
+            None => true,
+            Some(owner) if owner == id => false,
+            Some(owner) => self.node(owner).is_none_or(|owner| owner.epoch < epoch),
+        };
+        if wins {
+            self.set_owner(slot, Some(id.into()));
+        }
+    }
+
+    /// Takes in another node's CLUSTER NODES: what it owns, and any nodes it knows about
+    fn merge(&mut self, host: &str, port: u16, nodes: &str) {
+        for line in nodes.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() < 8 || fields[0] == self.myself.id {
+                continue;
+            }
+            let id = fields[0];
+            let epoch = fields[6].parse().unwrap_or(0);
+            if fields[2].split(',').any(|flag| flag == "myself") {
+                self.current_epoch = self.current_epoch.max(epoch);
+                let node = match self.others.iter_mut().find(|node| node.id == id) {
+                    Some(node) => node,
+                    None => {
+                        self.others.push(Node {
+                            id: id.into(),
+                            host: host.into(),
+                            port,
+                            epoch,
+                        });
+                        self.others.last_mut().expect("just pushed")
+                    }
+                };
+                node.epoch = epoch;
+                let claimed: BTreeSet<u16> = fields[8..]
+                    .iter()
+                    .filter(|range| !range.starts_with('['))
+                    .filter_map(|range| {
+                        let (first, last) = range.split_once('-').unwrap_or((range, range));
+                        Some(first.parse::<u16>().ok()?..=last.parse::<u16>().ok()?)
+                    })
+                    .flatten()
+                    .filter(|slot| *slot < HASH_SLOTS)
+                    .collect();
+                // Slots it's let go of
+                let dropped: Vec<u16> = self
+                    .slots_of(id)
+                    .filter(|slot| !claimed.contains(slot))
+                    .collect();
+                for slot in dropped {
+                    self.set_owner(slot, None);
+                }
+                for slot in claimed {
+                    self.claim(slot, id, epoch);
+                }
+            } else if self.node(id).is_none() {
+                let address = fields[1].split('@').next().unwrap_or_default();
+                if let Some((host, port)) = address.rsplit_once(':') {
+                    if let Ok(port) = port.parse() {
+                        self.others.push(Node {
+                            id: id.into(),
+                            host: host.into(),
+                            port,
+                            epoch,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn nodes(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis())
+            .unwrap_or_default();
+        let mut lines = String::new();
+        for node in std::iter::once(&self.myself).chain(&self.others) {
+            let myself = node.id == self.myself.id;
+            let mut fields = vec![
+                node.id.clone(),
+                format!("{}@{}", node.address(), node.port as u32 + 10000),
+                if myself { "myself,master" } else { "master" }.to_string(),
+                "-".into(),
+                "0".into(),
+                now.to_string(),
+                node.epoch.to_string(),
+                "connected".into(),
+            ];
+            for (first, last) in ranges(self.slots_of(&node.id)) {
+                fields.push(if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                });
+            }
+            if myself {
+                for (slot, target) in &self.migrating {
+                    fields.push(format!("[{}->-{}]", slot, target));
+                }
+                for (slot, source) in &self.importing {
+                    fields.push(format!("[{}-<-{}]", slot, source));
+                }
+            }
+            lines.push_str(&fields.join(" "));
+            lines.push('\n');
+        }
+        lines
+    }
+
+    fn info(&self) -> String {
+        let assigned = self.owners.iter().filter(|owner| owner.is_some()).count();
+        let size = std::iter::once(&self.myself)
+            .chain(&self.others)
+            .filter(|node| self.slots_of(&node.id).next().is_some())
+            .count();
+        let state = if assigned == HASH_SLOTS as usize {
+            "ok"
+        } else {
+            "fail"
+        };
+        [
+            format!("cluster_state:{}", state),
+            format!("cluster_slots_assigned:{}", assigned),
+            format!("cluster_slots_ok:{}", assigned),
+            "cluster_slots_pfail:0".into(),
+            "cluster_slots_fail:0".into(),
+            format!("cluster_known_nodes:{}", self.others.len() + 1),
+            format!("cluster_size:{}", size),
+            format!("cluster_current_epoch:{}", self.current_epoch),
+            format!("cluster_my_epoch:{}", self.myself.epoch),
+        ]
+        .join("\r\n")
+            + "\r\n"
+    }
+
+    /// Every run of slots with the node that owns it
+    fn slot_ranges(&self) -> Vec<(u16, u16, &Node)> {
+        let mut slot_ranges: Vec<(u16, u16, &Node)> = vec![];
+        for slot in 0..HASH_SLOTS {
+            let owner = match self.owners[slot as usize]
+                .as_deref()
+                .and_then(|id| self.node(id))
+            {
+                Some(owner) => owner,
+                None => continue,
+            };
+            match slot_ranges.last_mut() {
+                Some((_, last, node)) if *last + 1 == slot && node.id == owner.id => *last = slot,
+                _ => slot_ranges.push((slot, slot, owner)),
+            }
+        }
+        slot_ranges
+    }
+
+    fn slots(&self) -> RespData {
+        RespData::List(
+            self.slot_ranges()
+                .into_iter()
+                .map(|(first, last, node)| {
+                    RespData::List(
+                        vec![
+                            RespData::Number(first as i64),
+                            RespData::Number(last as i64),
+                            RespData::List(
+                                vec![
+                                    bulk(&node.host),
+                                    RespData::Number(node.port as i64),
+                                    bulk(&node.id),
+                                ]
+                                .into(),
+                            ),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn shards(&self) -> RespData {
+        RespData::List(
+            std::iter::once(&self.myself)
+                .chain(&self.others)
+                .map(|node| {
+                    let slots = ranges(self.slots_of(&node.id))
+                        .into_iter()
+                        .flat_map(|(first, last)| {
+                            [
+                                RespData::Number(first as i64),
+                                RespData::Number(last as i64),
+                            ]
+                        })
+                        .collect();
+                    let details = vec![
+                        bulk("id"),
+                        bulk(&node.id),
+                        bulk("port"),
+                        RespData::Number(node.port as i64),
+                        bulk("ip"),
+                        bulk(&node.host),
+                        bulk("endpoint"),
+                        bulk(&node.host),
+                        bulk("role"),
+                        bulk("master"),
+                        bulk("replication-offset"),
+                        RespData::Number(0),
+                        bulk("health"),
+                        bulk("online"),
+                    ];
+                    RespData::List(
+                        vec![
+                            bulk("slots"),
+                            RespData::List(slots),
+                            bulk("nodes"),
+                            RespData::List(vec![RespData::List(details.into())].into()),
+                        ]
+                        .into(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn add_slots(&mut self, slots: &[u16]) -> RespData {
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| self.owners[**slot as usize].is_some())
+        {
+            return RespData::Error(format!("ERR Slot {} is already busy", slot));
+        }
+        let myself = self.myself.id.clone();
+        for slot in slots {
+            self.set_owner(*slot, Some(myself.clone()));
+        }
+        RespData::ok()
+    }
+
+    fn del_slots(&mut self, slots: &[u16]) -> RespData {
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| self.owners[**slot as usize].is_none())
+        {
+            return RespData::Error(format!("ERR Slot {} is already unassigned", slot));
+        }
+        for slot in slots {
+            self.set_owner(*slot, None);
+        }
+        RespData::ok()
+    }
+
+    fn set_slot(&mut self, slot: u16, action: &str, id: Option<&String>) -> RespData {
+        let known = |state: &Self, id: Option<&String>| match id {
+            Some(id) if state.node(id).is_some() => Ok(id.clone()),
+            Some(id) => Err(RespData::Error(format!(
+                "ERR I don't know about node {}",
+                id
+            ))),
+            None => Err(error("ERR syntax error")),
+        };
+        let id = match action.to_lowercase().as_str() {
+            "stable" => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+                return RespData::ok();
+            }
+            _ => match known(self, id) {
+                Ok(id) => id,
+                Err(error) => return error,
+            },
+        };
+        match action.to_lowercase().as_str() {
+            "migrating" if !self.owns(slot) => {
+                RespData::Error(format!("ERR I'm not the owner of hash slot {}", slot))
+            }
+            "migrating" => {
+                self.migrating.insert(slot, id);
+                RespData::ok()
+            }
+            "importing" if self.owns(slot) => {
+                RespData::Error(format!("ERR I'm already the owner of hash slot {}", slot))
+            }
+            "importing" => {
+                self.importing.insert(slot, id);
+                RespData::ok()
+            }
+            "node" => {
+                if id == self.myself.id && !self.owns(slot) {
+                    // Taking a slot over, so this node's claim has to beat the old owner's
+                    self.current_epoch += 1;
+                    self.myself.epoch = self.current_epoch;
+                }
+                self.importing.remove(&slot);
+                self.set_owner(slot, Some(id));
+                RespData::ok()
+            }
+            _ => error("ERR Invalid CLUSTER SETSLOT action or number of arguments"),
+        }
+    }
+}
+
+pub struct Cluster {
+    state: Mutex<ClusterState>,
+    // The user and password to log in to other nodes with, masteruser and masterauth
+    auth: Mutex<(String, String)>,
+}
+
+impl Cluster {
+    pub fn new(port: u16) -> Self {
+        Self {
+            state: Mutex::new(ClusterState {
+                myself: Node {
+                    id: random_id(),
+                    host: "127.0.0.1".into(),
+                    port,
+                    epoch: 0,
+                },
+                others: vec![],
+                owners: vec![None; HASH_SLOTS as usize],
+                migrating: BTreeMap::new(),
+                importing: BTreeMap::new(),
+                current_epoch: 0,
+            }),
+            auth: Mutex::new((String::new(), String::new())),
+        }
+    }
+
+    /// Starts a cluster node that keeps up with the others in the background
+    pub fn start(port: u16) -> Arc<Self> {
+        let cluster = Arc::new(Self::new(port));
+        let weak = Arc::downgrade(&cluster);
+        thread::spawn(move || poll_peers(weak));
+        cluster
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClusterState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sets who to log in to the other nodes as, an empty password for not logging in
+    pub fn set_auth(&self, user: &str, password: &str) {
+        *self
+            .auth
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = (user.into(), password.into());
+    }
+
+    /// A connection to another node, logged in if there's a password for it
+    fn connect(&self, host: &str, port: u16) -> io::Result<Client> {
+        let (user, password) = self
+            .auth
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let mut client = Client::connect(host, port, PEER_TIMEOUT)?;
+        client.auth(&user, &password)?;
+        Ok(client)
+    }
+
+    /// Where a command for `keys` should run, or the redirect to give the client. `asking` is
+    /// set when the client sent ASKING just before.
+    pub fn route(&self, keys: &[&Key], asking: bool) -> Result<Route, RespData> {
+        let mut slots = keys.iter().map(|key| key_hash_slot(key));
+        let slot = match slots.next() {
+            Some(slot) => slot,
+            None => return Ok(Route::Local),
+        };
+        if slots.any(|other| other != slot) {
+            return Err(error(
+                "CROSSSLOT Keys in request don't hash to the same slot",
+            ));
+        }
+
+        let state = self.lock();
+        if asking && state.importing.contains_key(&slot) {
+            return Ok(Route::Local);
+        }
+        match &state.owners[slot as usize] {
+            None => Err(error("CLUSTERDOWN Hash slot not served")),
+            Some(owner) if *owner == state.myself.id => match state.migrating.get(&slot) {
+                Some(target) => Ok(Route::Migrating {
+                    slot,
+                    address: state.address_of(target),
+                }),
+                None => Ok(Route::Local),
+            },
+            Some(owner) => Err(RespData::Error(format!(
+                "MOVED {} {}",
+                slot,
+                state.address_of(owner)
+            ))),
+        }
+    }
+
+    /// Takes in the CLUSTER NODES of the node at `host:port`
+    fn merge(&self, host: &str, port: u16, nodes: &RespData, local_ip: Option<String>) {
+        if let RespData::BulkStr(nodes) = nodes {
+            let mut state = self.lock();
+            if let Some(local_ip) = local_ip {
+                state.myself.host = local_ip;
+            }
+            state.merge(host, port, nodes);
+        }
+    }
+
+    fn meet(&self, host: &str, port: &str) -> RespData {
+        let port: u16 = match port.parse() {
+            Ok(port) => port,
+            Err(_) => {
+                return RespData::Error(format!(
+                    "ERR Invalid node address specified: {}:{}",
+                    host, port
+                ))
+            }
+        };
+        let mut client = match self.connect(host, port) {
+            Ok(client) => client,
+            Err(_) => {
+                return RespData::Error(format!("ERR Can't connect to node {}:{}", host, port))
+            }
+        };
+        let id = match client.call(&["CLUSTER", "MYID"]) {
+            Ok(RespData::BulkStr(id)) => id,
+            _ => {
+                return RespData::Error(format!("ERR Node {}:{} isn't in cluster mode", host, port))
+            }
+        };
+
+        let (new, my_host, my_port) = {
+            let mut state = self.lock();
+            if let Some(local_ip) = client.local_ip() {
+                state.myself.host = local_ip;
+            }
+            let new = id != state.myself.id && state.node(&id).is_none();
+            if new {
+                state.others.push(Node {
+                    id,
+                    host: host.into(),
+                    port,
+                    epoch: 0,
+                });
+            }
+            (
+                new,
+                state.myself.host.clone(),
+                state.myself.port.to_string(),
+            )
+        };
+        if new {
+            // So that it knows about this node without waiting to be polled
+            let _ = client.call(&["CLUSTER", "MEET", &my_host, &my_port]);
+            if let Ok(nodes) = client.call(&["CLUSTER", "NODES"]) {
+                self.merge(host, port, &nodes, None);
+            }
+        }
+        RespData::ok()
+    }
+
+    /// The keys in a slot of the default database
+    fn keys_in_slot(server: &Server, slot: u16) -> Vec<String> {
+        let database_id = server.default_database();
+        match server.send_to_core(&database_id, Command::Keys("*".into())) {
+            RespData::List(keys) => keys
+                .into_iter()
+                .filter_map(|key| match key {
+                    RespData::BulkStr(key) | RespData::SimpleStr(key)
+                        if key_hash_slot(&key) == slot =>
+                    {
+                        Some(key)
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Runs CLUSTER <subcommand>
+    pub fn command(&self, server: &Server, args: &[String]) -> RespData {
+        let subcommand = args[0].to_lowercase();
+        let args = &args[1..];
+        let wrong_arity = || {
+            RespData::Error(format!(
+                "ERR wrong number of arguments for 'cluster|{}' command",
+                subcommand
+            ))
+        };
+        let slots = |args: &[String]| -> Result<Vec<u16>, RespData> {
+            args.iter().map(|slot| parse_slot(slot)).collect()
+        };
+        match (subcommand.as_str(), args) {
+            ("info", []) => RespData::BulkStr(self.lock().info()),
+            ("myid", []) => RespData::BulkStr(self.lock().myself.id.clone()),
+            ("nodes", []) => RespData::BulkStr(self.lock().nodes()),
+            ("slots", []) => self.lock().slots(),
+            ("shards", []) => self.lock().shards(),
+            ("meet", [host, port]) => self.meet(host, port),
+            ("keyslot", [key]) => RespData::Number(key_hash_slot(key) as i64),
+            ("countkeysinslot", [slot]) => match parse_slot(slot) {
+                Ok(slot) => RespData::Number(Self::keys_in_slot(server, slot).len() as i64),
+                Err(error) => error,
+            },
+            ("getkeysinslot", [slot, count]) => match (parse_slot(slot), count.parse::<usize>()) {
+                (Err(error), _) => error,
+                (_, Err(_)) => error("ERR Invalid number of keys"),
+                (Ok(slot), Ok(count)) => RespData::List(
+                    Self::keys_in_slot(server, slot)
+                        .into_iter()
+                        .take(count)
+                        .map(RespData::BulkStr)
+                        .collect(),
+                ),
+            },
+            ("addslots", slot_args) | ("delslots", slot_args) if !slot_args.is_empty() => {
+                match slots(slot_args) {
+                    Ok(slots) if subcommand == "addslots" => self.lock().add_slots(&slots),
+                    Ok(slots) => self.lock().del_slots(&slots),
+                    Err(error) => error,
+                }
+            }
+            ("addslotsrange", range_args)
+                if !range_args.is_empty() && range_args.len() % 2 == 0 =>
+            {
+                match slots(range_args) {
+                    Ok(bounds) => {
+                        let mut slots = vec![];
+                        for range in bounds.chunks(2) {
+                            if range[0] > range[1] {
+                                return RespData::Error(format!(
+                                    "ERR start slot number {} is greater than end slot number {}",
+                                    range[0], range[1]
+                                ));
+                            }
+                            slots.extend(range[0]..=range[1]);
+                        }
+                        self.lock().add_slots(&slots)
+                    }
+                    Err(error) => error,
+                }
+            }
+            ("setslot", [slot, action, rest @ ..]) if rest.len() <= 1 => match parse_slot(slot) {
+                Ok(slot) => self.lock().set_slot(slot, action, rest.first()),
+                Err(error) => error,
+            },
+            ("info", _)
+            | ("myid", _)
+            | ("nodes", _)
+            | ("slots", _)
+            | ("shards", _)
+            | ("meet", _)
+            | ("keyslot", _)
+            | ("countkeysinslot", _)
+            | ("getkeysinslot", _)
+            | ("addslots", _)
+            | ("delslots", _)
+            | ("addslotsrange", _)
+            | ("setslot", _) => wrong_arity(),
+            _ => RespData::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLUSTER HELP.",
+                subcommand
+            )),
+        }
+    }
+}
+
+/// Keeps up with what the other nodes own, until the cluster is dropped
+fn poll_peers(cluster: Weak<Cluster>) {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let cluster = match cluster.upgrade() {
+            Some(cluster) => cluster,
+            None => return,
+        };
+        let peers: Vec<(String, u16)> = cluster
+            .lock()
+            .others
+            .iter()
+            .map(|node| (node.host.clone(), node.port))
+            .collect();
+        for (host, port) in peers {
+            match cluster.connect(&host, port) {
+                Ok(mut client) => {
+                    if let Ok(nodes) = client.call(&["CLUSTER", "NODES"]) {
+                        cluster.merge(&host, port, &nodes, client.local_ip());
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                    log::warn!("Can't log in to cluster node {}:{}: {}", host, port, error)
+                }
+                // Nodes that are down are just tried again next time
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod cluster_should {
+    use super::*;
+
+    fn node_line(id: &str, port: u16, epoch: u64, slots: &str) -> String {
+        format!(
+            "{} 127.0.0.1:{}@{} myself,master - 0 0 {} connected {}\n",
+            id,
+            port,
+            port as u32 + 10000,
+            epoch,
+            slots
+        )
+    }
+
+    fn cluster_with_peer() -> (Cluster, String) {
+        let cluster = Cluster::new(7000);
+        let peer = "b".repeat(40);
+        {
+            let mut state = cluster.lock();
+            state.add_slots(&(0..100).collect::<Vec<u16>>());
+            state.merge("127.0.0.1", 7001, &node_line(&peer, 7001, 0, "100-16383"));
+        }
+        (cluster, peer)
+    }
+
+    fn key_in_slot(slot: u16) -> String {
+        (0..)
+            .map(|n| format!("key{}", n))
+            .find(|key| key_hash_slot(key) == slot)
+            .expect("some key hashes to every slot")
+    }
+
+    #[test]
+    fn redirect_keys_owned_by_other_nodes() {
+        let (cluster, _) = cluster_with_peer();
+        let mine = key_in_slot(5);
+        let theirs = key_in_slot(200);
+
+        assert_eq!(cluster.route(&[&mine], false), Ok(Route::Local));
+        assert_eq!(
+            cluster.route(&[&theirs], false),
+            Err(error("MOVED 200 127.0.0.1:7001"))
+        );
+        assert_eq!(
+            cluster.route(&[&mine, &theirs], false),
+            Err(error(
+                "CROSSSLOT Keys in request don't hash to the same slot"
+            ))
+        );
+        assert_eq!(
+            cluster.route(&[&"{tag}a".into(), &"{tag}b".into()], false),
+            cluster.route(&[&"tag".into()], false)
+        );
+    }
+
+    #[test]
+    fn report_unserved_slots() {
+        let cluster = Cluster::new(7000);
+
+        assert_eq!(
+            cluster.route(&[&key_in_slot(1)], false),
+            Err(error("CLUSTERDOWN Hash slot not served"))
+        );
+    }
+
+    #[test]
+    fn serve_slots_being_moved_in_and_out() {
+        let (cluster, peer) = cluster_with_peer();
+        let outgoing = key_in_slot(5);
+        let incoming = key_in_slot(200);
+        {
+            let mut state = cluster.lock();
+            assert_eq!(state.set_slot(5, "MIGRATING", Some(&peer)), RespData::ok());
+            assert_eq!(
+                state.set_slot(200, "IMPORTING", Some(&peer)),
+                RespData::ok()
+            );
+        }
+
+        assert_eq!(
+            cluster.route(&[&outgoing], false),
+            Ok(Route::Migrating {
+                slot: 5,
+                address: "127.0.0.1:7001".into()
+            })
+        );
+        assert_eq!(
+            cluster.route(&[&incoming], false),
+            Err(error("MOVED 200 127.0.0.1:7001"))
+        );
+        assert_eq!(cluster.route(&[&incoming], true), Ok(Route::Local));
+    }
+
+    #[test]
+    fn hand_slots_to_whoever_has_the_higher_epoch() {
+        let (cluster, peer) = cluster_with_peer();
+        let myself = cluster.lock().myself.id.clone();
+        let mut state = cluster.lock();
+
+        // Taking a slot over bumps this node's epoch past the peer's
+        assert_eq!(state.set_slot(200, "NODE", Some(&myself)), RespData::ok());
+        state.merge("127.0.0.1", 7001, &node_line(&peer, 7001, 0, "100-16383"));
+        assert!(state.owns(200));
+
+        // Until the peer takes it back with an even higher one
+        state.merge("127.0.0.1", 7001, &node_line(&peer, 7001, 5, "100-16383"));
+        assert!(!state.owns(200));
+        assert_eq!(state.current_epoch, 5);
+    }
+
+    #[test]
+    fn learn_about_nodes_from_peers() {
+        let (cluster, peer) = cluster_with_peer();
+        let third = "c".repeat(40);
+        let mut state = cluster.lock();
+        let nodes = node_line(&peer, 7001, 0, "100-16383")
+            + &format!("{} 127.0.0.1:7002@17002 master - 0 0 0 connected\n", third);
+
+        state.merge("127.0.0.1", 7001, &nodes);
+
+        assert_eq!(state.address_of(&third), "127.0.0.1:7002");
+        assert_eq!(
+            state.set_slot(5, "MIGRATING", Some(&"d".repeat(40))),
+            RespData::Error(format!("ERR I don't know about node {}", "d".repeat(40)))
+        );
+    }
+}
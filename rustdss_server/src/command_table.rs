@@ -0,0 +1,1102 @@
+// Every command the server knows: how many arguments it takes, its flags, ACL categories, where
+// its keys are and what it's for
+//
+// This is what COMMAND reports, what the parser checks arity against and what ACL categories are
+// made of. Arity is counted like Redis counts it, with the command's name: a positive arity is
+// exactly that many arguments and a negative one is at least that many. A command's key specs
+// say where its keys start and how many follow, so a client can find them without running it.
+
+/// Where the search for a command's keys starts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BeginSearch {
+    /// At this argument, the command's name being 0
+    Index(i64),
+    /// After the first argument from `startfrom` on that's this keyword
+    Keyword {
+        keyword: &'static str,
+        startfrom: i64,
+    },
+}
+
+/// How many keys there are once the first is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FindKeys {
+    /// Up to `lastkey` arguments on, -1 being the last argument, every `keystep`. With a
+    /// `limit` of 2 only half of what's left are keys, like the streams in XREAD.
+    Range {
+        lastkey: i64,
+        keystep: i64,
+        limit: i64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeySpec {
+    /// How the keys are used, like RW and access or OW and update
+    pub flags: &'static [&'static str],
+    pub begin_search: BeginSearch,
+    pub find_keys: FindKeys,
+}
+
+/// A single key at `index`
+const fn key(index: i64, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        flags,
+        begin_search: BeginSearch::Index(index),
+        find_keys: FindKeys::Range {
+            lastkey: 0,
+            keystep: 1,
+            limit: 0,
+        },
+    }
+}
+
+/// Every argument from `index` on is a key
+const fn keys_from(index: i64, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        flags,
+        begin_search: BeginSearch::Index(index),
+        find_keys: FindKeys::Range {
+            lastkey: -1,
+            keystep: 1,
+            limit: 0,
+        },
+    }
+}
+
+/// Keys following a keyword, all of them or the first half of what follows with a `limit` of 2
+const fn keys_after(
+    keyword: &'static str,
+    startfrom: i64,
+    limit: i64,
+    flags: &'static [&'static str],
+) -> KeySpec {
+    KeySpec {
+        flags,
+        begin_search: BeginSearch::Keyword { keyword, startfrom },
+        find_keys: FindKeys::Range {
+            lastkey: -1,
+            keystep: 1,
+            limit,
+        },
+    }
+}
+
+const RO: &[&str] = &["RO", "access"];
+const RW: &[&str] = &["RW", "access", "update"];
+const RW_INSERT: &[&str] = &["RW", "insert"];
+const RW_DELETE: &[&str] = &["RW", "access", "delete"];
+const OW: &[&str] = &["OW", "update"];
+const RM: &[&str] = &["RM", "delete"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct CommandSpec {
+    /// In lower case, and without the parent's name for a subcommand
+    pub name: &'static str,
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// ACL categories, without the @
+    pub categories: &'static [&'static str],
+    pub key_specs: &'static [KeySpec],
+    pub subcommands: &'static [CommandSpec],
+    pub group: &'static str,
+    /// The Redis version that added it
+    pub since: &'static str,
+    pub complexity: &'static str,
+    pub summary: &'static str,
+}
+
+impl CommandSpec {
+    const fn new(
+        name: &'static str,
+        arity: i64,
+        flags: &'static [&'static str],
+        categories: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            flags,
+            categories,
+            key_specs: &[],
+            subcommands: &[],
+            group: "",
+            since: "",
+            complexity: "",
+            summary: "",
+        }
+    }
+
+    const fn keys(self, key_specs: &'static [KeySpec]) -> Self {
+        Self { key_specs, ..self }
+    }
+
+    const fn subcommands(self, subcommands: &'static [CommandSpec]) -> Self {
+        Self {
+            subcommands,
+            ..self
+        }
+    }
+
+    const fn docs(
+        self,
+        group: &'static str,
+        since: &'static str,
+        complexity: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        Self {
+            group,
+            since,
+            complexity,
+            summary,
+            ..self
+        }
+    }
+
+    /// Whether it can be run with `count` arguments, counting its name
+    pub fn accepts(&self, count: usize) -> bool {
+        let count = count as i64;
+        if self.arity < 0 {
+            count >= -self.arity
+        } else {
+            count == self.arity
+        }
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&'static CommandSpec> {
+        let name = name.to_lowercase();
+        self.subcommands
+            .iter()
+            .find(|subcommand| subcommand.name == name)
+    }
+
+    /// Its keys the way COMMAND gave them before key specs: the first key, the last (negative
+    /// counting back from the end) and the step between them. Keys that can only be found by
+    /// looking at the arguments give zeroes.
+    pub fn legacy_key_range(&self) -> (i64, i64, i64) {
+        let ranges: Vec<(i64, i64, i64)> = self
+            .key_specs
+            .iter()
+            .filter_map(|spec| match (spec.begin_search, spec.find_keys) {
+                (
+                    BeginSearch::Index(first),
+                    FindKeys::Range {
+                        lastkey,
+                        keystep,
+                        limit: 0,
+                    },
+                ) => Some((
+                    first,
+                    if lastkey < 0 {
+                        lastkey
+                    } else {
+                        first + lastkey
+                    },
+                    keystep,
+                )),
+                _ => None,
+            })
+            .collect();
+        let first = match ranges.iter().map(|(first, _, _)| *first).min() {
+            Some(first) => first,
+            None => return (0, 0, 0),
+        };
+        let last = ranges
+            .iter()
+            .map(|(_, last, _)| *last)
+            .find(|last| *last < 0)
+            .or_else(|| ranges.iter().map(|(_, last, _)| *last).max())
+            .unwrap_or(first);
+        let step = if ranges.len() == 1 { ranges[0].2 } else { 1 };
+        (first, last, step)
+    }
+
+    /// Its flags, with movablekeys added when its keys have to be searched for
+    pub fn all_flags(&self) -> Vec<&'static str> {
+        let mut flags = self.flags.to_vec();
+        let movable = self
+            .key_specs
+            .iter()
+            .any(|spec| !matches!(spec.begin_search, BeginSearch::Index(_)));
+        if movable {
+            flags.push("movablekeys");
+        }
+        flags
+    }
+}
+
+const WRITE: &[&str] = &["write", "denyoom", "fast"];
+const WRITE_SLOW: &[&str] = &["write", "denyoom"];
+const DELETE: &[&str] = &["write", "fast"];
+const DELETE_SLOW: &[&str] = &["write"];
+const READ: &[&str] = &["readonly", "fast"];
+const READ_SLOW: &[&str] = &["readonly"];
+const ADMIN: &[&str] = &["admin", "noscript", "loading", "stale"];
+const CONNECTION: &[&str] = &["noscript", "loading", "stale", "fast"];
+
+const ACL_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("cat", -2, &["noscript", "loading", "stale"], &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1) since the categories and commands are a fixed set.",
+        "Lists the ACL categories, or the commands inside a category.",
+    ),
+    CommandSpec::new("deluser", -3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(1) amortized time considering the typical user.",
+        "Deletes ACL users, and terminates their connections.",
+    ),
+    CommandSpec::new("dryrun", -4, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "7.0.0",
+        "O(1).",
+        "Simulates the execution of a command by a user, without executing the command.",
+    ),
+    CommandSpec::new("genpass", -2, &["noscript", "loading", "stale"], &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1)",
+        "Generates a pseudorandom, secure password that can be used to identify ACL users.",
+    ),
+    CommandSpec::new("getuser", 3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of password, command and pattern rules that the user has.",
+        "Lists the ACL rules of a user.",
+    ),
+    CommandSpec::new("list", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Dumps the effective rules in ACL file format.",
+    ),
+    CommandSpec::new("load", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Reloads the rules from the configured ACL file.",
+    ),
+    CommandSpec::new("log", -2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N) with N being the number of entries shown.",
+        "Lists recent security events generated due to ACL rules.",
+    ),
+    CommandSpec::new("save", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Saves the effective ACL rules in the configured ACL file.",
+    ),
+    CommandSpec::new("setuser", -3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of rules provided.",
+        "Creates and modifies an ACL user and its rules.",
+    ),
+    CommandSpec::new("users", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Lists all ACL users.",
+    ),
+    CommandSpec::new("whoami", 2, &["noscript", "loading", "stale"], &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1)",
+        "Returns the authenticated username of the current connection.",
+    ),
+];
+
+const CLIENT_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("getname", 2, CONNECTION, &["slow", "connection"]).docs(
+        "connection",
+        "2.6.9",
+        "O(1)",
+        "Returns the name of the connection.",
+    ),
+    CommandSpec::new("id", 2, CONNECTION, &["slow", "connection"]).docs(
+        "connection",
+        "5.0.0",
+        "O(1)",
+        "Returns the unique client ID of the connection.",
+    ),
+    CommandSpec::new("info", 2, CONNECTION, &["slow", "connection"]).docs(
+        "connection",
+        "6.2.0",
+        "O(1)",
+        "Returns information about the connection.",
+    ),
+    CommandSpec::new(
+        "kill",
+        -3,
+        ADMIN,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "2.4.0",
+        "O(N) where N is the number of client connections",
+        "Terminates open connections.",
+    ),
+    CommandSpec::new(
+        "list",
+        -2,
+        ADMIN,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "2.4.0",
+        "O(N) where N is the number of client connections",
+        "Lists open connections.",
+    ),
+    CommandSpec::new(
+        "no-evict",
+        3,
+        ADMIN,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "7.0.0",
+        "O(1)",
+        "Sets the client eviction mode of the connection.",
+    ),
+    CommandSpec::new(
+        "pause",
+        -3,
+        ADMIN,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "3.0.0",
+        "O(1)",
+        "Suspends commands processing.",
+    ),
+    CommandSpec::new("reply", 3, CONNECTION, &["slow", "connection"]).docs(
+        "connection",
+        "3.2.0",
+        "O(1)",
+        "Instructs the server whether to reply to commands.",
+    ),
+    CommandSpec::new("setname", 3, CONNECTION, &["slow", "connection"]).docs(
+        "connection",
+        "2.6.9",
+        "O(1)",
+        "Sets the connection name.",
+    ),
+    CommandSpec::new(
+        "unpause",
+        2,
+        ADMIN,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "6.2.0",
+        "O(N) Where N is the number of paused clients",
+        "Resumes processing commands from paused clients.",
+    ),
+];
+
+const CLUSTER_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("addslots", -3, &["admin", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the total number of hash slot arguments",
+        "Assigns new hash slots to a node.",
+    ),
+    CommandSpec::new("addslotsrange", -4, &["admin", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "cluster",
+        "7.0.0",
+        "O(N) where N is the total number of the slots between the start slot and end slot arguments.",
+        "Assigns new hash slot ranges to a node.",
+    ),
+    CommandSpec::new("countkeysinslot", 3, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Returns the number of keys in a hash slot.",
+    ),
+    CommandSpec::new("delslots", -3, &["admin", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the total number of hash slot arguments",
+        "Sets hash slots as unbound for a node.",
+    ),
+    CommandSpec::new("getkeysinslot", 4, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the number of requested keys",
+        "Returns the key names in a hash slot.",
+    ),
+    CommandSpec::new("info", 2, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Returns information about the state of a node.",
+    ),
+    CommandSpec::new("keyslot", 3, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the number of bytes in the key",
+        "Returns the hash slot for a key.",
+    ),
+    CommandSpec::new("meet", 4, &["admin", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Forces a node to handshake with another node.",
+    ),
+    CommandSpec::new("myid", 2, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Returns the ID of a node.",
+    ),
+    CommandSpec::new("nodes", 2, &["stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the total number of Cluster nodes",
+        "Returns the cluster configuration for a node.",
+    ),
+    CommandSpec::new("setslot", -4, &["admin", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Binds a hash slot to a node.",
+    ),
+    CommandSpec::new("shards", 2, &["loading", "stale"], &["slow"]).docs(
+        "cluster",
+        "7.0.0",
+        "O(N) where N is the total number of cluster nodes",
+        "Returns the mapping of cluster slots to shards.",
+    ),
+    CommandSpec::new("slots", 2, &["loading", "stale"], &["slow"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(N) where N is the total number of Cluster nodes",
+        "Returns the mapping of cluster slots to nodes.",
+    ),
+];
+
+const COMMAND_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("count", 2, &["loading", "stale"], &["slow", "connection"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns a count of commands.",
+    ),
+    CommandSpec::new("docs", -2, &["loading", "stale"], &["slow", "connection"]).docs(
+        "server",
+        "7.0.0",
+        "O(N) where N is the number of commands to look up",
+        "Returns documentary information about one, multiple or all commands.",
+    ),
+    CommandSpec::new(
+        "getkeys",
+        -3,
+        &["loading", "stale"],
+        &["slow", "connection"],
+    )
+    .docs(
+        "server",
+        "2.8.13",
+        "O(N) where N is the number of arguments to the command",
+        "Extracts the key names from an arbitrary command.",
+    ),
+    CommandSpec::new("info", -2, &["loading", "stale"], &["slow", "connection"]).docs(
+        "server",
+        "2.8.13",
+        "O(N) where N is the number of commands to look up",
+        "Returns information about one, multiple or all commands.",
+    ),
+    CommandSpec::new("list", -2, &["loading", "stale"], &["slow", "connection"]).docs(
+        "server",
+        "7.0.0",
+        "O(N) where N is the total number of commands",
+        "Returns a list of command names.",
+    ),
+];
+
+const CONFIG_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("get", -3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.0.0",
+        "O(N) when N is the number of configuration parameters provided",
+        "Returns the effective values of configuration parameters.",
+    ),
+    CommandSpec::new("resetstat", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.0.0",
+        "O(1)",
+        "Resets the server's statistics.",
+    ),
+    CommandSpec::new("rewrite", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.0",
+        "O(1)",
+        "Persists the effective configuration to file.",
+    ),
+    CommandSpec::new("set", -4, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.0.0",
+        "O(N) when N is the number of configuration parameters provided",
+        "Sets configuration parameters in-flight.",
+    ),
+];
+
+const LATENCY_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("doctor", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns a human-readable latency analysis report.",
+    ),
+    CommandSpec::new("graph", 3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns a latency graph for an event.",
+    ),
+    CommandSpec::new("histogram", -2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "7.0.0",
+        "O(N) where N is the number of commands with latency information being retrieved.",
+        "Returns the cumulative distribution of latencies of a subset or all commands.",
+    ),
+    CommandSpec::new("history", 3, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns timestamp-latency samples for an event.",
+    ),
+    CommandSpec::new("latest", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns the latest latency samples for all events.",
+    ),
+    CommandSpec::new("reset", -2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Resets the latency data for one or more events.",
+    ),
+];
+
+const OBJECT_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("freq", 3, READ_SLOW, &["keyspace", "read", "slow"])
+        .keys(&[key(2, RO)])
+        .docs(
+            "generic",
+            "4.0.0",
+            "O(1)",
+            "Returns the logarithmic access frequency counter of a Redis object.",
+        ),
+    CommandSpec::new("idletime", 3, READ_SLOW, &["keyspace", "read", "slow"])
+        .keys(&[key(2, RO)])
+        .docs(
+            "generic",
+            "2.2.3",
+            "O(1)",
+            "Returns the time since the last access to a Redis object.",
+        ),
+];
+
+const SLOWLOG_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("get", -2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.2.12",
+        "O(N) where N is the number of entries returned",
+        "Returns the slow log's entries.",
+    ),
+    CommandSpec::new("len", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.2.12",
+        "O(1)",
+        "Returns the number of entries in the slow log.",
+    ),
+    CommandSpec::new("reset", 2, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.2.12",
+        "O(N) where N is the number of entries in the slowlog",
+        "Clears all entries from the slow log.",
+    ),
+];
+
+const XGROUP_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("create", -5, WRITE_SLOW, &["write", "stream", "slow"])
+        .keys(&[key(2, RW_INSERT)])
+        .docs("stream", "5.0.0", "O(1)", "Creates a consumer group."),
+    CommandSpec::new(
+        "createconsumer",
+        5,
+        WRITE_SLOW,
+        &["write", "stream", "slow"],
+    )
+    .keys(&[key(2, RW_INSERT)])
+    .docs(
+        "stream",
+        "6.2.0",
+        "O(1)",
+        "Creates a consumer in a consumer group.",
+    ),
+    CommandSpec::new("delconsumer", 5, DELETE_SLOW, &["write", "stream", "slow"])
+        .keys(&[key(2, RW_DELETE)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(1)",
+            "Deletes a consumer from a consumer group.",
+        ),
+    CommandSpec::new("destroy", 4, DELETE_SLOW, &["write", "stream", "slow"])
+        .keys(&[key(2, RW_DELETE)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(N) where N is the number of entries in the group's pending entries list (PEL).",
+            "Destroys a consumer group.",
+        ),
+    CommandSpec::new("setid", -5, DELETE_SLOW, &["write", "stream", "slow"])
+        .keys(&[key(2, RW)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(1)",
+            "Sets the last-delivered ID of a consumer group.",
+        ),
+];
+
+const XINFO_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("consumers", 4, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(2, RO)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(1)",
+            "Returns a list of the consumers in a consumer group.",
+        ),
+    CommandSpec::new("groups", 3, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(2, RO)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(1)",
+            "Returns a list of the consumer groups of a stream.",
+        ),
+    CommandSpec::new("stream", -3, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(2, RO)])
+        .docs(
+            "stream",
+            "5.0.0",
+            "O(1)",
+            "Returns information about a stream.",
+        ),
+];
+
+/// Every command, in alphabetical order
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("acl", -2, &[], &["slow"])
+        .subcommands(ACL_SUBCOMMANDS)
+        .docs("server", "6.0.0", "Depends on subcommand.", "A container for Access List Control commands."),
+    CommandSpec::new("asking", 1, &["fast"], &["fast", "connection"]).docs(
+        "cluster",
+        "3.0.0",
+        "O(1)",
+        "Signals that a cluster client is following an -ASK redirect.",
+    ),
+    CommandSpec::new("auth", -2, &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"], &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(N) where N is the number of passwords defined for the user",
+        "Authenticates the connection.",
+    ),
+    CommandSpec::new("client", -2, &[], &["slow", "connection"])
+        .subcommands(CLIENT_SUBCOMMANDS)
+        .docs("connection", "2.4.0", "Depends on subcommand.", "A container for client connection commands."),
+    CommandSpec::new("cluster", -2, &[], &["slow"])
+        .subcommands(CLUSTER_SUBCOMMANDS)
+        .docs("cluster", "3.0.0", "Depends on subcommand.", "A container for Redis Cluster commands."),
+    CommandSpec::new("command", -1, &["loading", "stale"], &["slow", "connection"])
+        .subcommands(COMMAND_SUBCOMMANDS)
+        .docs("server", "2.8.13", "O(N) where N is the total number of Redis commands", "Returns detailed information about all commands."),
+    CommandSpec::new("config", -2, &[], &["admin", "slow", "dangerous"])
+        .subcommands(CONFIG_SUBCOMMANDS)
+        .docs("server", "2.0.0", "Depends on subcommand.", "A container for server configuration commands."),
+    CommandSpec::new("decr", 2, WRITE, &["write", "string", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("string", "1.0.0", "O(1)", "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+    CommandSpec::new("decrby", 3, WRITE, &["write", "string", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("string", "1.0.0", "O(1)", "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist."),
+    CommandSpec::new("del", -2, DELETE_SLOW, &["keyspace", "write", "slow"])
+        .keys(&[keys_from(1, RM)])
+        .docs("generic", "1.0.0", "O(N) where N is the number of keys that will be removed.", "Deletes one or more keys."),
+    CommandSpec::new("discard", 1, &["noscript", "loading", "stale", "fast", "allow_busy"], &["fast", "transaction"]).docs(
+        "transactions",
+        "2.0.0",
+        "O(N), when N is the number of queued commands",
+        "Discards a transaction.",
+    ),
+    CommandSpec::new("dump", 2, READ_SLOW, &["keyspace", "read", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("generic", "2.6.0", "O(1) to access the key and additional O(N*M) to serialize it.", "Returns a serialized representation of the value stored at a key."),
+    CommandSpec::new("echo", 2, &["fast"], &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Returns the given string.",
+    ),
+    CommandSpec::new("exec", 1, &["noscript", "loading", "stale", "skip_slowlog"], &["slow", "transaction"]).docs(
+        "transactions",
+        "1.2.0",
+        "Depends on commands in the transaction",
+        "Executes all commands in a transaction.",
+    ),
+    CommandSpec::new("exists", -2, READ, &["keyspace", "read", "fast"])
+        .keys(&[keys_from(1, RO)])
+        .docs("generic", "1.0.0", "O(N) where N is the number of keys to check.", "Determines whether one or more keys exist."),
+    CommandSpec::new("expire", -3, DELETE, &["keyspace", "write", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("generic", "1.0.0", "O(1)", "Sets the expiration time of a key in seconds."),
+    CommandSpec::new("expireat", -3, DELETE, &["keyspace", "write", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("generic", "1.2.0", "O(1)", "Sets the expiration time of a key to a Unix timestamp."),
+    CommandSpec::new("flushall", -1, DELETE_SLOW, &["keyspace", "write", "slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "O(N) where N is the total number of keys in all databases",
+        "Removes all keys from all databases.",
+    ),
+    CommandSpec::new("geoadd", -5, WRITE_SLOW, &["write", "geo", "slow"])
+        .keys(&[key(1, RW)])
+        .docs("geo", "3.2.0", "O(log(N)) for each item added, where N is the number of elements in the sorted set.", "Adds one or more members to a geospatial index. The key is created if it doesn't exist."),
+    CommandSpec::new("geodist", -4, READ_SLOW, &["read", "geo", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("geo", "3.2.0", "O(1)", "Returns the distance between two members of a geospatial index."),
+    CommandSpec::new("geohash", -2, READ_SLOW, &["read", "geo", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("geo", "3.2.0", "O(1) for each member requested.", "Returns members from a geospatial index as geohash strings."),
+    CommandSpec::new("geopos", -2, READ_SLOW, &["read", "geo", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("geo", "3.2.0", "O(1) for each member requested.", "Returns the longitude and latitude of members from a geospatial index."),
+    CommandSpec::new("geosearch", -7, READ_SLOW, &["read", "geo", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("geo", "6.2.0", "O(N+log(M)) where N is the number of elements in the grid-aligned bounding box area around the shape provided as the filter and M is the number of items inside the shape", "Queries a geospatial index for members inside an area of a box or a circle."),
+    CommandSpec::new("geosearchstore", -8, WRITE_SLOW, &["write", "geo", "slow"])
+        .keys(&[key(1, OW), key(2, RO)])
+        .docs("geo", "6.2.0", "O(N+log(M)) where N is the number of elements in the grid-aligned bounding box area around the shape provided as the filter and M is the number of items inside the shape", "Queries a geospatial index for members inside an area of a box or a circle, optionally stores the result."),
+    CommandSpec::new("get", 2, READ, &["read", "string", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("string", "1.0.0", "O(1)", "Returns the string value of a key."),
+    CommandSpec::new("incr", 2, WRITE, &["write", "string", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("string", "1.0.0", "O(1)", "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+    CommandSpec::new("incrby", 3, WRITE, &["write", "string", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("string", "1.0.0", "O(1)", "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist."),
+    CommandSpec::new("info", -1, &["loading", "stale"], &["slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "O(1)",
+        "Returns information and statistics about the server.",
+    ),
+    CommandSpec::new("keys", 2, READ_SLOW, &["keyspace", "read", "slow", "dangerous"]).docs(
+        "generic",
+        "1.0.0",
+        "O(N) with N being the number of keys in the database",
+        "Returns all key names that match a pattern.",
+    ),
+    CommandSpec::new("latency", -2, &[], &["admin", "slow", "dangerous"])
+        .subcommands(LATENCY_SUBCOMMANDS)
+        .docs("server", "2.8.13", "Depends on subcommand.", "A container for latency diagnostics commands."),
+    CommandSpec::new("llen", 2, READ, &["read", "list", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("list", "1.0.0", "O(1)", "Returns the length of a list."),
+    CommandSpec::new("lpop", -2, DELETE, &["write", "list", "fast"])
+        .keys(&[key(1, RW_DELETE)])
+        .docs("list", "1.0.0", "O(1)", "Returns the first element of a list after removing it. Deletes the list if the last element was popped."),
+    CommandSpec::new("lpush", -3, WRITE, &["write", "list", "fast"])
+        .keys(&[key(1, RW_INSERT)])
+        .docs("list", "1.0.0", "O(1)", "Prepends an element to a list. Creates the key if it doesn't exist."),
+    CommandSpec::new("lrange", 4, READ_SLOW, &["read", "list", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("list", "1.0.0", "O(S+N) where S is the distance of start offset from HEAD for small lists, from nearest end (HEAD or TAIL) for large lists; and N is the number of elements in the specified range.", "Returns a range of elements from a list."),
+    CommandSpec::new("mget", -2, READ, &["read", "string", "fast"])
+        .keys(&[keys_from(1, RO)])
+        .docs("string", "1.0.0", "O(N) where N is the number of keys to retrieve.", "Atomically returns the string values of one or more keys."),
+    CommandSpec::new("migrate", -6, &["write"], &["keyspace", "write", "slow", "dangerous"])
+        .keys(&[key(3, RW_DELETE), keys_after("KEYS", -2, 0, RW_DELETE)])
+        .docs("generic", "2.6.0", "This command actually executes a DUMP+DEL in the source instance, and a RESTORE in the target instance.", "Atomically transfers a key from one Redis instance to another."),
+    CommandSpec::new("monitor", 1, ADMIN, &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "",
+        "Listens for all requests received by the server in real-time.",
+    ),
+    CommandSpec::new("multi", 1, &["noscript", "loading", "stale", "fast", "allow_busy"], &["fast", "transaction"]).docs(
+        "transactions",
+        "1.2.0",
+        "O(1)",
+        "Starts a transaction.",
+    ),
+    CommandSpec::new("object", -2, &[], &["keyspace", "read", "slow"])
+        .subcommands(OBJECT_SUBCOMMANDS)
+        .docs("generic", "2.2.3", "Depends on subcommand.", "A container for object introspection commands."),
+    CommandSpec::new("persist", 2, DELETE, &["keyspace", "write", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("generic", "2.2.0", "O(1)", "Removes the expiration time of a key."),
+    CommandSpec::new("pexpire", -3, DELETE, &["keyspace", "write", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("generic", "2.6.0", "O(1)", "Sets the expiration time of a key in milliseconds."),
+    CommandSpec::new("pexpireat", -3, DELETE, &["keyspace", "write", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("generic", "2.6.0", "O(1)", "Sets the expiration time of a key to a Unix milliseconds timestamp."),
+    CommandSpec::new("pfadd", -2, WRITE, &["write", "hyperloglog", "fast"])
+        .keys(&[key(1, RW_INSERT)])
+        .docs("hyperloglog", "2.8.9", "O(1) to add every element.", "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist."),
+    CommandSpec::new("pfcount", -2, READ_SLOW, &["read", "hyperloglog", "slow"])
+        .keys(&[keys_from(1, RO)])
+        .docs("hyperloglog", "2.8.9", "O(1) with a very small average constant time when called with a single key. O(N) with N being the number of keys, and much bigger constant times, when called with multiple keys.", "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s)."),
+    CommandSpec::new("pfmerge", -2, WRITE_SLOW, &["write", "hyperloglog", "slow"])
+        .keys(&[key(1, RW_INSERT), keys_from(2, RO)])
+        .docs("hyperloglog", "2.8.9", "O(N) to merge N HyperLogLogs, but with high constant times.", "Merges one or more HyperLogLog values into a single key."),
+    CommandSpec::new("ping", -1, &["fast"], &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Returns the server's liveliness response.",
+    ),
+    CommandSpec::new("psync", -3, &["admin", "noscript", "no_async_loading", "no_multi"], &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "2.8.0",
+        "",
+        "An internal command used in replication.",
+    ),
+    CommandSpec::new("pttl", 2, READ, &["keyspace", "read", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("generic", "2.6.0", "O(1)", "Returns the expiration time in milliseconds of a key."),
+    CommandSpec::new("rename", 3, DELETE_SLOW, &["keyspace", "write", "slow"])
+        .keys(&[key(1, RW_DELETE), key(2, OW)])
+        .docs("generic", "1.0.0", "O(1)", "Renames a key and overwrites the destination."),
+    CommandSpec::new("replconf", -1, &["admin", "noscript", "loading", "stale", "allow_busy"], &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "3.0.0",
+        "O(1)",
+        "An internal command for configuring the replication stream.",
+    ),
+    CommandSpec::new("replicaof", 3, &["admin", "noscript", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "5.0.0",
+        "O(1)",
+        "Configures a server as replica of another, or promotes it to a master.",
+    ),
+    CommandSpec::new("restore", -4, WRITE_SLOW, &["keyspace", "write", "slow", "dangerous"])
+        .keys(&[key(1, OW)])
+        .docs("generic", "2.6.0", "O(1) to create the new key and additional O(N*M) to reconstruct the serialized value.", "Creates a key from the serialized representation of a value."),
+    CommandSpec::new("role", 1, &["noscript", "loading", "stale", "fast"], &["admin", "fast", "dangerous"]).docs(
+        "server",
+        "2.8.12",
+        "O(1)",
+        "Returns the replication role.",
+    ),
+    CommandSpec::new("rpop", -2, DELETE, &["write", "list", "fast"])
+        .keys(&[key(1, RW_DELETE)])
+        .docs("list", "1.0.0", "O(1)", "Returns and removes the last element of a list. Deletes the list if the last element was popped."),
+    CommandSpec::new("rpush", -3, WRITE, &["write", "list", "fast"])
+        .keys(&[key(1, RW_INSERT)])
+        .docs("list", "1.0.0", "O(1)", "Appends an element to a list. Creates the key if it doesn't exist."),
+    CommandSpec::new("sadd", -3, WRITE, &["write", "set", "fast"])
+        .keys(&[key(1, RW_INSERT)])
+        .docs("set", "1.0.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Adds one or more members to a set. Creates the key if it doesn't exist."),
+    CommandSpec::new("scan", -2, READ_SLOW, &["keyspace", "read", "slow"]).docs(
+        "generic",
+        "2.8.0",
+        "O(1) for every call. O(N) for a complete iteration, including enough command calls for the cursor to return back to 0. N is the number of elements inside the collection.",
+        "Iterates over the key names in the database.",
+    ),
+    CommandSpec::new("scard", 2, READ, &["read", "set", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("set", "1.0.0", "O(1)", "Returns the number of members in a set."),
+    CommandSpec::new("select", 2, CONNECTION, &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Changes the selected database.",
+    ),
+    CommandSpec::new("set", -3, WRITE_SLOW, &["write", "string", "slow"])
+        .keys(&[key(1, OW)])
+        .docs("string", "1.0.0", "O(1)", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    CommandSpec::new("shutdown", -1, &["admin", "noscript", "loading", "stale", "no_multi", "allow_busy"], &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "O(N) when saving, where N is the total number of keys in all databases when saving data, otherwise O(1)",
+        "Synchronously saves the database(s) to disk and shuts down the Redis server.",
+    ),
+    CommandSpec::new("sinter", -2, READ_SLOW, &["read", "set", "slow"])
+        .keys(&[keys_from(1, RO)])
+        .docs("set", "1.0.0", "O(N*M) worst case where N is the cardinality of the smallest set and M is the number of sets.", "Returns the intersect of multiple sets."),
+    CommandSpec::new("sinterstore", -3, WRITE_SLOW, &["write", "set", "slow"])
+        .keys(&[key(1, OW), keys_from(2, RO)])
+        .docs("set", "1.0.0", "O(N*M) worst case where N is the cardinality of the smallest set and M is the number of sets.", "Stores the intersect of multiple sets in a key."),
+    CommandSpec::new("sismember", 3, READ, &["read", "set", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("set", "1.0.0", "O(1)", "Determines whether a member belongs to a set."),
+    CommandSpec::new("slaveof", 3, &["admin", "noscript", "stale", "no_async_loading"], &["admin", "slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "O(1)",
+        "Sets a Redis server as a replica of another, or promotes it to being a master.",
+    ),
+    CommandSpec::new("slowlog", -2, &[], &["admin", "slow", "dangerous"])
+        .subcommands(SLOWLOG_SUBCOMMANDS)
+        .docs("server", "2.2.12", "Depends on subcommand.", "A container for slow log commands."),
+    CommandSpec::new("smembers", 2, READ_SLOW, &["read", "set", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("set", "1.0.0", "O(N) where N is the set cardinality.", "Returns all members of a set."),
+    CommandSpec::new("srem", -3, DELETE, &["write", "set", "fast"])
+        .keys(&[key(1, RW_DELETE)])
+        .docs("set", "1.0.0", "O(N) where N is the number of members to be removed.", "Removes one or more members from a set. Deletes the set if the last member was removed."),
+    CommandSpec::new("ttl", 2, READ, &["keyspace", "read", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("generic", "1.0.0", "O(1)", "Returns the expiration time in seconds of a key."),
+    CommandSpec::new("wait", 3, &[], &["slow", "connection"]).docs(
+        "generic",
+        "3.0.0",
+        "O(1)",
+        "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed.",
+    ),
+    CommandSpec::new("xack", -4, DELETE, &["write", "stream", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("stream", "5.0.0", "O(1) for each message ID processed.", "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream."),
+    CommandSpec::new("xadd", -5, WRITE, &["write", "stream", "fast"])
+        .keys(&[key(1, RW_INSERT)])
+        .docs("stream", "5.0.0", "O(1) when adding a new entry, O(N) when trimming where N being the number of entries evicted.", "Appends a new message to a stream. Creates the key if it doesn't exist."),
+    CommandSpec::new("xautoclaim", -6, DELETE, &["write", "stream", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("stream", "6.2.0", "O(1) if COUNT is small.", "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member."),
+    CommandSpec::new("xclaim", -6, DELETE, &["write", "stream", "fast"])
+        .keys(&[key(1, RW)])
+        .docs("stream", "5.0.0", "O(log N) with N being the number of messages in the PEL of the consumer group.", "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member."),
+    CommandSpec::new("xdel", -3, DELETE, &["write", "stream", "fast"])
+        .keys(&[key(1, RW_DELETE)])
+        .docs("stream", "5.0.0", "O(1) for each single item to delete in the stream, regardless of the stream size.", "Returns the number of messages after removing them from a stream."),
+    CommandSpec::new("xgroup", -2, &[], &["write", "stream", "slow"])
+        .subcommands(XGROUP_SUBCOMMANDS)
+        .docs("stream", "5.0.0", "Depends on subcommand.", "A container for consumer groups commands."),
+    CommandSpec::new("xinfo", -2, &[], &["read", "stream", "slow"])
+        .subcommands(XINFO_SUBCOMMANDS)
+        .docs("stream", "5.0.0", "Depends on subcommand.", "A container for stream introspection commands."),
+    CommandSpec::new("xlen", 2, READ, &["read", "stream", "fast"])
+        .keys(&[key(1, RO)])
+        .docs("stream", "5.0.0", "O(1)", "Return the number of messages in a stream."),
+    CommandSpec::new("xpending", -3, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("stream", "5.0.0", "O(N) with N being the number of elements returned, so asking for a small fixed number of entries per call is O(1). O(M), where M is the total number of entries scanned when used with the IDLE filter. When the command returns just the summary and the list of consumers is small, it runs in O(1) time; otherwise, an additional O(N) time for iterating every consumer.", "Returns the information and entries from a stream consumer group's pending entries list."),
+    CommandSpec::new("xrange", -4, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("stream", "5.0.0", "O(N) with N being the number of elements being returned. If N is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1).", "Returns the messages from a stream within a range of IDs."),
+    CommandSpec::new("xread", -4, &["readonly", "blocking"], &["read", "stream", "slow", "blocking"])
+        .keys(&[keys_after("STREAMS", 1, 2, RO)])
+        .docs("stream", "5.0.0", "", "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise."),
+    CommandSpec::new("xreadgroup", -7, &["write", "blocking"], &["write", "stream", "slow", "blocking"])
+        .keys(&[keys_after("STREAMS", 4, 2, RW)])
+        .docs("stream", "5.0.0", "For each stream mentioned: O(M) with M being the number of elements returned. If M is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1). On the other side when XREADGROUP blocks, XADD will pay the O(N) time in order to serve the N clients blocked on the stream getting new data.", "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise."),
+    CommandSpec::new("xrevrange", -4, READ_SLOW, &["read", "stream", "slow"])
+        .keys(&[key(1, RO)])
+        .docs("stream", "5.0.0", "O(N) with N being the number of elements returned. If N is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1).", "Returns the messages from a stream within a range of IDs in reverse order."),
+    CommandSpec::new("xtrim", -4, DELETE_SLOW, &["write", "stream", "slow"])
+        .keys(&[key(1, RW_DELETE)])
+        .docs("stream", "5.0.0", "O(N), with N being the number of evicted entries. Constant times are very small however, since entries are organized in macro nodes containing multiple entries that can be released with a single deallocation.", "Deletes messages from the beginning of a stream."),
+];
+
+/// A command by name, or a subcommand as `command|subcommand`
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    let name = name.to_lowercase();
+    let (command, subcommand) = match name.split_once('|') {
+        Some((command, subcommand)) => (command, Some(subcommand)),
+        None => (name.as_str(), None),
+    };
+    let spec = COMMANDS.iter().find(|spec| spec.name == command)?;
+    match subcommand {
+        Some(subcommand) => spec.subcommand(subcommand),
+        None => Some(spec),
+    }
+}
+
+/// Checks a command is being given the right number of arguments, counting its name. Its
+/// subcommand is checked too, if it has one the table knows. Commands that aren't in the table
+/// are left for the parser to turn down.
+pub fn check_arity(name: &str, subcommand: Option<&str>, count: usize) -> Result<(), String> {
+    let wrong_arity =
+        |name: String| format!("ERR wrong number of arguments for '{}' command", name);
+    let spec = match lookup(name) {
+        Some(spec) => spec,
+        None => return Ok(()),
+    };
+    if !spec.accepts(count) {
+        return Err(wrong_arity(spec.name.into()));
+    }
+    match subcommand.and_then(|subcommand| spec.subcommand(subcommand)) {
+        Some(sub) if !sub.accepts(count) => Err(wrong_arity(format!("{}|{}", spec.name, sub.name))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod command_table_should {
+    use super::*;
+
+    #[test]
+    fn be_in_order_without_duplicates() {
+        let names: Vec<&str> = COMMANDS.iter().map(|spec| spec.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(names, sorted);
+        for spec in COMMANDS {
+            let subcommands: Vec<&str> = spec.subcommands.iter().map(|sub| sub.name).collect();
+            let mut sorted = subcommands.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(subcommands, sorted, "{}'s subcommands", spec.name);
+        }
+    }
+
+    #[test]
+    fn check_arity_like_redis() {
+        assert!(check_arity("GET", None, 2).is_ok());
+        assert_eq!(
+            check_arity("get", None, 3),
+            Err("ERR wrong number of arguments for 'get' command".into())
+        );
+        assert!(check_arity("del", None, 5).is_ok());
+        assert!(check_arity("del", None, 1).is_err());
+        assert_eq!(
+            check_arity("config", Some("GET"), 2),
+            Err("ERR wrong number of arguments for 'config|get' command".into())
+        );
+        // Subcommands the table doesn't know are for the command to turn down
+        assert!(check_arity("config", Some("nonsense"), 2).is_ok());
+        assert!(check_arity("nonsense", None, 1).is_ok());
+    }
+
+    #[test]
+    fn give_the_old_key_ranges() {
+        assert_eq!(lookup("get").unwrap().legacy_key_range(), (1, 1, 1));
+        assert_eq!(lookup("del").unwrap().legacy_key_range(), (1, -1, 1));
+        assert_eq!(lookup("rename").unwrap().legacy_key_range(), (1, 2, 1));
+        assert_eq!(lookup("ping").unwrap().legacy_key_range(), (0, 0, 0));
+        assert_eq!(lookup("xread").unwrap().legacy_key_range(), (0, 0, 0));
+        assert!(lookup("xread")
+            .unwrap()
+            .all_flags()
+            .contains(&"movablekeys"));
+        assert_eq!(lookup("object|freq").unwrap().legacy_key_range(), (2, 2, 1));
+    }
+}
@@ -0,0 +1,1107 @@
+// Server settings, from a redis.conf style file and the command line
+//
+// `rustdss [config-file] [--directive value ...]` reads the file first, then treats each `--name`
+// and the values after it as one more line of the file, so the command line wins. A bad setting
+// stops the server at startup with the line it was on, the same way Redis reports it.
+
+use rustdss_core::keyspace::glob_match;
+use rustdss_core::memory::parse_memory;
+use rustdss_core::EvictionPolicy;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+    Nothing,
+}
+
+impl LogLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+            LogLevel::Nothing => "nothing",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [
+            LogLevel::Debug,
+            LogLevel::Verbose,
+            LogLevel::Notice,
+            LogLevel::Warning,
+            LogLevel::Nothing,
+        ]
+        .iter()
+        .copied()
+        .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// How far a client's replies can fall behind before it's disconnected. Zero turns a limit off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimit {
+    /// Bytes that disconnect the client straight away
+    pub hard: usize,
+    /// Bytes that disconnect the client if it stays over them for `soft_seconds`
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    const fn new(hard: usize, soft: usize, soft_seconds: u64) -> Self {
+        Self {
+            hard,
+            soft,
+            soft_seconds,
+        }
+    }
+}
+
+/// The output buffer limit for each class of client
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::new(0, 0, 0),
+            replica: OutputBufferLimit::new(256 << 20, 64 << 20, 60),
+            pubsub: OutputBufferLimit::new(32 << 20, 8 << 20, 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The file the settings were read from, if there was one
+    pub file: Option<PathBuf>,
+    pub port: u16,
+    pub bind: Vec<IpAddr>,
+    /// How many databases can be used, numbered ones have to be below this
+    pub databases: usize,
+    /// The database connections start on
+    pub default_database: String,
+    /// Zero means no limit
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    /// How many worker threads each database is split across
+    pub shards: usize,
+    /// Snapshot after this many seconds if there were at least this many changes. Stored for when
+    /// there's persistence, nothing is saved yet.
+    pub save: Vec<(u64, u64)>,
+    pub appendonly: bool,
+    pub appendfsync: String,
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    pub appendfilename: String,
+    pub loglevel: LogLevel,
+    /// Empty means standard output
+    pub logfile: String,
+    /// Logs to syslog instead of logfile
+    pub syslog_enabled: bool,
+    pub syslog_ident: String,
+    /// user or local0 to local7
+    pub syslog_facility: String,
+    /// How log lines are written: text, like Redis, or json
+    pub log_format: String,
+    /// Seconds a client can sit idle before it's disconnected, zero for never
+    pub timeout: u64,
+    /// Seconds between TCP keepalive probes on idle connections, zero for none
+    pub tcp_keepalive: u64,
+    /// How many clients can be connected at once
+    pub maxclients: usize,
+    pub client_output_buffer_limit: OutputBufferLimits,
+    pub replicaof: Option<(String, u16)>,
+    pub cluster_enabled: bool,
+    /// Commands taking at least this many microseconds go in the slow log, negative turns it off
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: u64,
+    /// Milliseconds, events taking at least this long are kept for LATENCY, zero for none
+    pub latency_monitor_threshold: u64,
+    /// The default user's password, empty for none
+    pub requirepass: String,
+    /// Where users are loaded from and saved to, if anywhere
+    pub aclfile: String,
+    pub acllog_max_len: usize,
+    /// How a replica logs in to its master
+    pub masteruser: String,
+    pub masterauth: String,
+    /// The port TLS clients connect to, zero for none
+    pub tls_port: u16,
+    pub tls_cert_file: String,
+    pub tls_key_file: String,
+    pub tls_ca_cert_file: String,
+    /// Whether TLS clients need a certificate: yes, no or optional
+    pub tls_auth_clients: String,
+    /// CN to log clients in as the user their certificate names, off otherwise
+    pub tls_auth_clients_user: String,
+    /// Whether a replica uses TLS to talk to its master
+    pub tls_replication: bool,
+    /// A Unix socket to take connections on too, empty for none
+    pub unixsocket: String,
+    /// The socket's permissions, zero to leave them as they're created
+    pub unixsocketperm: u32,
+    /// The port Prometheus scrapes /metrics from, zero for none
+    pub metrics_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            port: 6380,
+            bind: vec![IpAddr::from([0, 0, 0, 0])],
+            databases: 16,
+            default_database: crate::constants::default_database_name(),
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::default(),
+            shards: std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfsync: "everysec".into(),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".into(),
+            appendfilename: "appendonly.aof".into(),
+            loglevel: LogLevel::Notice,
+            logfile: String::new(),
+            syslog_enabled: false,
+            syslog_ident: "rustdss".into(),
+            syslog_facility: "local0".into(),
+            log_format: "text".into(),
+            timeout: 0,
+            tcp_keepalive: 300,
+            maxclients: 10000,
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            replicaof: None,
+            cluster_enabled: false,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            requirepass: String::new(),
+            aclfile: String::new(),
+            acllog_max_len: 128,
+            masteruser: String::new(),
+            masterauth: String::new(),
+            tls_port: 0,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            tls_ca_cert_file: String::new(),
+            tls_auth_clients: "yes".into(),
+            tls_auth_clients_user: "off".into(),
+            tls_replication: false,
+            unixsocket: String::new(),
+            unixsocketperm: 0,
+            metrics_port: 0,
+        }
+    }
+}
+
+/// A setting that couldn't be used, and where it came from
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    source: String,
+    line_number: usize,
+    line: String,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "\n*** FATAL CONFIG FILE ERROR ***")?;
+        writeln!(
+            f,
+            "Reading the configuration file ({}), at line {}",
+            self.source, self.line_number
+        )?;
+        writeln!(f, ">>> '{}'", self.line)?;
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Splits a line into arguments like Redis does, with "double quotes" taking escapes and
+/// 'single quotes' taking everything as it is
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let unbalanced = || String::from("Unbalanced quotes in configuration line");
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote = match chars.peek() {
+            None => return Ok(args),
+            Some('"') | Some('\'') => chars.next(),
+            Some(_) => None,
+        };
+        let mut arg = String::new();
+        loop {
+            match (quote, chars.next()) {
+                (None, None) => break,
+                (None, Some(c)) if c.is_whitespace() => break,
+                (Some(_), None) => return Err(unbalanced()),
+                (Some(quote), Some(c)) if c == quote => {
+                    // A closing quote has to end the argument
+                    if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                        return Err(unbalanced());
+                    }
+                    break;
+                }
+                (Some('"'), Some('\\')) => match chars.next() {
+                    Some('n') => arg.push('\n'),
+                    Some('r') => arg.push('\r'),
+                    Some('t') => arg.push('\t'),
+                    Some(c) => arg.push(c),
+                    None => return Err(unbalanced()),
+                },
+                (_, Some(c)) => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Every setting CONFIG knows about, and whether CONFIG SET can change it while running
+const PARAMETERS: &[(&str, bool)] = &[
+    ("port", false),
+    ("bind", false),
+    ("databases", false),
+    ("default-database", false),
+    ("maxmemory", true),
+    ("maxmemory-policy", true),
+    ("shards", false),
+    ("save", true),
+    ("appendonly", true),
+    ("appendfsync", true),
+    ("dir", true),
+    ("dbfilename", true),
+    ("appendfilename", false),
+    ("loglevel", true),
+    ("logfile", false),
+    ("syslog-enabled", false),
+    ("syslog-ident", false),
+    ("syslog-facility", false),
+    ("log-format", true),
+    ("timeout", true),
+    ("tcp-keepalive", true),
+    ("maxclients", true),
+    ("client-output-buffer-limit", true),
+    ("replicaof", false),
+    ("cluster-enabled", false),
+    ("slowlog-log-slower-than", true),
+    ("slowlog-max-len", true),
+    ("latency-monitor-threshold", true),
+    ("requirepass", true),
+    ("aclfile", false),
+    ("acllog-max-len", true),
+    ("masteruser", true),
+    ("masterauth", true),
+    ("tls-port", false),
+    ("tls-cert-file", false),
+    ("tls-key-file", false),
+    ("tls-ca-cert-file", false),
+    ("tls-auth-clients", false),
+    ("tls-auth-clients-user", false),
+    ("tls-replication", false),
+    ("unixsocket", false),
+    ("unixsocketperm", false),
+    ("metrics-port", false),
+];
+
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+fn canonical_name(name: &str) -> String {
+    match name.to_lowercase().as_str() {
+        "slaveof" => "replicaof".into(),
+        name => name.into(),
+    }
+}
+
+/// Puts an argument in double quotes when it wouldn't read back as the same argument otherwise
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    if plain {
+        return arg.into();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn yes_or_no(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".into()),
+    }
+}
+
+fn number(value: &str, min: u64, max: u64, message: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .ok_or_else(|| message.into())
+}
+
+fn file_name(value: &str, directive: &str) -> Result<String, String> {
+    if value.contains('/') || value.contains('\\') {
+        Err(format!("{} can't be a path, just a filename", directive))
+    } else {
+        Ok(value.into())
+    }
+}
+
+fn bind_address(address: &str) -> Result<IpAddr, String> {
+    // A leading - means the address is optional in Redis, here they all have to work
+    match address.trim_start_matches('-') {
+        "*" => Ok(IpAddr::from([0, 0, 0, 0])),
+        "::*" => Ok(IpAddr::from([0u16; 8])),
+        address => address
+            .parse()
+            .map_err(|_| format!("Invalid bind address '{}'", address)),
+    }
+}
+
+impl Config {
+    /// The settings from the command line: an optional config file, then `--directive value`s
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut args = args.into_iter().peekable();
+        if let Some(file) = args.next_if(|arg| !arg.starts_with("--")) {
+            config.file = Some(PathBuf::from(&file));
+            config.load_file(Path::new(&file))?;
+        }
+
+        let mut lines = vec![];
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(directive) => lines.push(directive.to_string()),
+                None => match lines.last_mut() {
+                    // Values go in as they are, so `--replicaof "host port"` works, apart from
+                    // empty ones like `--save ""` which would otherwise disappear
+                    Some(line) if arg.is_empty() => line.push_str(" \"\""),
+                    Some(line) => {
+                        line.push(' ');
+                        line.push_str(&arg);
+                    }
+                    None => {
+                        return Err(ConfigError {
+                            source: "command line".into(),
+                            line_number: 0,
+                            line: arg,
+                            message: "Expected a --directive".into(),
+                        })
+                    }
+                },
+            }
+        }
+        config.load_str(&lines.join("\n"), "command line")?;
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|error| ConfigError {
+            source: path.display().to_string(),
+            line_number: 0,
+            line: String::new(),
+            message: format!("Can't open the config file: {}", error),
+        })?;
+        self.load_str(&text, &path.display().to_string())
+    }
+
+    /// Applies every setting in some config file text, `source` is where it came from
+    pub fn load_str(&mut self, text: &str, source: &str) -> Result<(), ConfigError> {
+        // The first save line replaces the default rules rather than adding to them
+        let mut saves_replaced = false;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ConfigError {
+                source: source.into(),
+                line_number: index + 1,
+                line: line.trim().into(),
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let args = split_args(line).map_err(error)?;
+            if args.is_empty() {
+                continue;
+            }
+            let name = args[0].to_lowercase();
+            if name == "include" && args.len() == 2 {
+                self.load_file(Path::new(&args[1]))?;
+                continue;
+            }
+            if name == "save" && !saves_replaced {
+                self.save.clear();
+                saves_replaced = true;
+            }
+            self.set(&name, &args[1..]).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    /// Changes one setting, giving why it can't be used otherwise
+    pub fn set(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        let values: Vec<&str> = args.iter().map(String::as_str).collect();
+        match (name, values.as_slice()) {
+            ("port", [port]) => self.port = number(port, 0, 65535, "Invalid port")? as u16,
+            ("bind", addresses) if !addresses.is_empty() => {
+                self.bind = addresses
+                    .iter()
+                    .map(|address| bind_address(address))
+                    .collect::<Result<_, _>>()?
+            }
+            ("databases", [count]) => {
+                self.databases =
+                    number(count, 1, i32::MAX as u64, "Invalid number of databases")? as usize
+            }
+            ("default-database", [name]) if !name.is_empty() => {
+                self.default_database = name.to_string()
+            }
+            ("maxmemory", [size]) => {
+                self.maxmemory =
+                    parse_memory(size).ok_or("argument must be a memory value")?
+            }
+            ("maxmemory-policy", [policy]) => self.maxmemory_policy = policy.parse()?,
+            ("shards", [count]) => {
+                self.shards = number(count, 1, 1024, "Invalid number of shards")? as usize
+            }
+            ("save", [""]) => self.save.clear(),
+            ("save", rules) if !rules.is_empty() && rules.len() % 2 == 0 => {
+                for rule in rules.chunks(2) {
+                    let seconds = number(rule[0], 1, u64::MAX, "Invalid save parameters")?;
+                    let changes = number(rule[1], 0, u64::MAX, "Invalid save parameters")?;
+                    self.save.push((seconds, changes));
+                }
+            }
+            ("appendonly", [value]) => self.appendonly = yes_or_no(value)?,
+            ("appendfsync", [value]) => {
+                let value = value.to_lowercase();
+                if !["always", "everysec", "no"].contains(&value.as_str()) {
+                    return Err(
+                        "argument(s) must be one of the following: always, everysec, no".into(),
+                    );
+                }
+                self.appendfsync = value;
+            }
+            ("dir", [dir]) => {
+                if !Path::new(dir).is_dir() {
+                    return Err(format!("No such directory: {}", dir));
+                }
+                self.dir = PathBuf::from(dir)
+            }
+            ("dbfilename", [file]) => self.dbfilename = file_name(file, "dbfilename")?,
+            ("appendfilename", [file]) => {
+                self.appendfilename = file_name(file, "appendfilename")?
+            }
+            ("loglevel", [level]) => {
+                self.loglevel = LogLevel::parse(level).ok_or(
+                    "argument(s) must be one of the following: debug, verbose, notice, warning, nothing",
+                )?
+            }
+            ("logfile", [file]) => self.logfile = file.to_string(),
+            ("syslog-enabled", [value]) => self.syslog_enabled = yes_or_no(value)?,
+            ("syslog-ident", [ident]) => self.syslog_ident = ident.to_string(),
+            ("syslog-facility", [facility]) => {
+                if crate::logging::facility(facility).is_none() {
+                    return Err("Invalid log facility. Must be one of USER or between LOCAL0-LOCAL7".into());
+                }
+                self.syslog_facility = facility.to_lowercase();
+            }
+            ("log-format", [format]) => {
+                let format = format.to_lowercase();
+                if format != "text" && format != "json" {
+                    return Err("argument(s) must be one of the following: text, json".into());
+                }
+                self.log_format = format;
+            }
+            ("timeout", [seconds]) => {
+                self.timeout = number(seconds, 0, i32::MAX as u64, "Invalid timeout value")?
+            }
+            ("tcp-keepalive", [seconds]) => {
+                self.tcp_keepalive =
+                    number(seconds, 0, i32::MAX as u64, "Invalid tcp-keepalive value")?
+            }
+            ("maxclients", [count]) => {
+                self.maxclients =
+                    number(count, 1, i32::MAX as u64, "Invalid max clients limit")? as usize
+            }
+            ("client-output-buffer-limit", limits) if !limits.is_empty() => {
+                if limits.len() % 4 != 0 {
+                    return Err("Wrong number of arguments in buffer limit configuration.".into());
+                }
+                // Each class is checked before any of them change
+                let mut changed = self.client_output_buffer_limit;
+                for limit in limits.chunks(4) {
+                    let class = match limit[0].to_lowercase().as_str() {
+                        "normal" => &mut changed.normal,
+                        "replica" | "slave" => &mut changed.replica,
+                        "pubsub" => &mut changed.pubsub,
+                        _ => {
+                            return Err(
+                                "Invalid client class specified in buffer limit configuration."
+                                    .into(),
+                            )
+                        }
+                    };
+                    let invalid = "Error in hard, soft or soft_seconds setting in buffer limit configuration.";
+                    *class = OutputBufferLimit {
+                        hard: parse_memory(limit[1]).ok_or(invalid)?,
+                        soft: parse_memory(limit[2]).ok_or(invalid)?,
+                        soft_seconds: number(limit[3], 0, i64::MAX as u64, invalid)?,
+                    };
+                }
+                self.client_output_buffer_limit = changed;
+            }
+            ("replicaof", [host, port]) | ("slaveof", [host, port]) => {
+                self.replicaof = Some((
+                    host.to_string(),
+                    number(port, 1, 65535, "Invalid master port")? as u16,
+                ))
+            }
+            ("cluster-enabled", [value]) => self.cluster_enabled = yes_or_no(value)?,
+            ("slowlog-log-slower-than", [micros]) => {
+                self.slowlog_log_slower_than = micros
+                    .parse()
+                    .map_err(|_| "argument couldn't be parsed into an integer")?
+            }
+            ("requirepass", [password]) => self.requirepass = password.to_string(),
+            ("aclfile", [file]) => self.aclfile = file.to_string(),
+            ("acllog-max-len", [length]) => {
+                self.acllog_max_len =
+                    number(length, 0, i32::MAX as u64, "Invalid acllog-max-len value")? as usize
+            }
+            ("masteruser", [user]) => self.masteruser = user.to_string(),
+            ("masterauth", [password]) => self.masterauth = password.to_string(),
+            ("tls-port", [port]) => {
+                self.tls_port = number(port, 0, 65535, "Invalid tls-port")? as u16
+            }
+            ("tls-cert-file", [file]) => self.tls_cert_file = file.to_string(),
+            ("tls-key-file", [file]) => self.tls_key_file = file.to_string(),
+            ("tls-ca-cert-file", [file]) => self.tls_ca_cert_file = file.to_string(),
+            ("tls-auth-clients", [value]) => {
+                let value = value.to_lowercase();
+                if !["yes", "no", "optional"].contains(&value.as_str()) {
+                    return Err(
+                        "argument(s) must be one of the following: no, yes, optional".into(),
+                    );
+                }
+                self.tls_auth_clients = value;
+            }
+            ("tls-auth-clients-user", [value]) => {
+                self.tls_auth_clients_user = match value.to_lowercase().as_str() {
+                    "off" => "off".into(),
+                    "cn" => "CN".into(),
+                    _ => return Err("argument(s) must be one of the following: off, CN".into()),
+                }
+            }
+            ("tls-replication", [value]) => self.tls_replication = yes_or_no(value)?,
+            ("unixsocket", [path]) => self.unixsocket = path.to_string(),
+            ("unixsocketperm", [mode]) => {
+                self.unixsocketperm = u32::from_str_radix(mode, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or("Invalid socket file permissions")?
+            }
+            ("metrics-port", [port]) => {
+                self.metrics_port = number(port, 0, 65535, "Invalid metrics-port")? as u16
+            }
+            ("slowlog-max-len", [length]) => {
+                self.slowlog_max_len =
+                    number(length, 0, i64::MAX as u64, "Invalid slowlog-max-len value")?
+            }
+            ("latency-monitor-threshold", [millis]) => {
+                self.latency_monitor_threshold = number(
+                    millis,
+                    0,
+                    i64::MAX as u64,
+                    "Invalid latency-monitor-threshold value",
+                )?
+            }
+            _ => return Err("Bad directive or wrong number of arguments".into()),
+        }
+        Ok(())
+    }
+
+    /// Each line a setting takes in a config file, as the arguments after its name
+    fn lines(&self, name: &str) -> Vec<Vec<String>> {
+        let one = |value: String| vec![vec![value]];
+        let yes_no = |value: bool| one(if value { "yes" } else { "no" }.into());
+        match name {
+            "port" => one(self.port.to_string()),
+            "bind" => vec![self.bind.iter().map(IpAddr::to_string).collect()],
+            "databases" => one(self.databases.to_string()),
+            "default-database" => one(self.default_database.clone()),
+            "maxmemory" => one(self.maxmemory.to_string()),
+            "maxmemory-policy" => one(self.maxmemory_policy.name().into()),
+            "shards" => one(self.shards.to_string()),
+            "save" if self.save.is_empty() => one(String::new()),
+            "save" => self
+                .save
+                .iter()
+                .map(|(seconds, changes)| vec![seconds.to_string(), changes.to_string()])
+                .collect(),
+            "appendonly" => yes_no(self.appendonly),
+            "appendfsync" => one(self.appendfsync.clone()),
+            "dir" => one(self.dir.display().to_string()),
+            "dbfilename" => one(self.dbfilename.clone()),
+            "appendfilename" => one(self.appendfilename.clone()),
+            "loglevel" => one(self.loglevel.name().into()),
+            "logfile" => one(self.logfile.clone()),
+            "syslog-enabled" => yes_no(self.syslog_enabled),
+            "syslog-ident" => one(self.syslog_ident.clone()),
+            "syslog-facility" => one(self.syslog_facility.clone()),
+            "log-format" => one(self.log_format.clone()),
+            "timeout" => one(self.timeout.to_string()),
+            "tcp-keepalive" => one(self.tcp_keepalive.to_string()),
+            "maxclients" => one(self.maxclients.to_string()),
+            "client-output-buffer-limit" => {
+                let limits = self.client_output_buffer_limit;
+                [
+                    ("normal", limits.normal),
+                    ("replica", limits.replica),
+                    ("pubsub", limits.pubsub),
+                ]
+                .iter()
+                .map(|(class, limit)| {
+                    vec![
+                        class.to_string(),
+                        limit.hard.to_string(),
+                        limit.soft.to_string(),
+                        limit.soft_seconds.to_string(),
+                    ]
+                })
+                .collect()
+            }
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => vec![vec![host.clone(), port.to_string()]],
+                None => vec![],
+            },
+            "cluster-enabled" => yes_no(self.cluster_enabled),
+            "slowlog-log-slower-than" => one(self.slowlog_log_slower_than.to_string()),
+            "slowlog-max-len" => one(self.slowlog_max_len.to_string()),
+            "latency-monitor-threshold" => one(self.latency_monitor_threshold.to_string()),
+            "requirepass" => one(self.requirepass.clone()),
+            "aclfile" => one(self.aclfile.clone()),
+            "acllog-max-len" => one(self.acllog_max_len.to_string()),
+            "masteruser" => one(self.masteruser.clone()),
+            "masterauth" => one(self.masterauth.clone()),
+            "tls-port" => one(self.tls_port.to_string()),
+            "tls-cert-file" => one(self.tls_cert_file.clone()),
+            "tls-key-file" => one(self.tls_key_file.clone()),
+            "tls-ca-cert-file" => one(self.tls_ca_cert_file.clone()),
+            "tls-auth-clients" => one(self.tls_auth_clients.clone()),
+            "tls-auth-clients-user" => one(self.tls_auth_clients_user.clone()),
+            "tls-replication" => yes_no(self.tls_replication),
+            "unixsocket" => one(self.unixsocket.clone()),
+            "unixsocketperm" => one(format!("{:o}", self.unixsocketperm)),
+            "metrics-port" => one(self.metrics_port.to_string()),
+            _ => vec![],
+        }
+    }
+
+    /// The settings whose names match a glob pattern, with their values the way CONFIG GET shows
+    /// them
+    pub fn get(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+        PARAMETERS
+            .iter()
+            .filter(|(name, _)| glob_match(&pattern, &name.chars().collect::<Vec<_>>()))
+            .map(|(name, _)| {
+                let value = self
+                    .lines(name)
+                    .iter()
+                    .map(|args| args.join(" "))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (*name, value)
+            })
+            .collect()
+    }
+
+    /// Changes settings while the server's running, all of them or none of them. Each value is
+    /// one argument, apart from save's and client-output-buffer-limit's which hold all the rules.
+    pub fn set_at_runtime(&mut self, changes: &[(String, String)]) -> Result<(), String> {
+        let mut changed = self.clone();
+        for (name, value) in changes {
+            let name = canonical_name(name);
+            match PARAMETERS.iter().find(|(known, _)| *known == name) {
+                Some((_, true)) => {}
+                Some((_, false)) => {
+                    return Err(format!(
+                        "CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                        name
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ))
+                }
+            }
+            let args: Vec<String> = if name == "save" {
+                changed.save.clear();
+                value.split_whitespace().map(String::from).collect()
+            } else if name == "client-output-buffer-limit" {
+                value.split_whitespace().map(String::from).collect()
+            } else {
+                vec![value.clone()]
+            };
+            if args.is_empty() {
+                continue;
+            }
+            changed.set(&name, &args).map_err(|message| {
+                format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, message
+                )
+            })?;
+        }
+        *self = changed;
+        Ok(())
+    }
+
+    /// The lines to write for a setting
+    fn config_lines(&self, name: &str) -> Vec<String> {
+        self.lines(name)
+            .iter()
+            .map(|args| {
+                std::iter::once(name.to_string())
+                    .chain(args.iter().map(|arg| quote(arg)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    /// The config file with the current settings in it. Comments and anything else stay where
+    /// they are, each setting replaces its first line and loses the rest, and settings that
+    /// aren't in the file but have been changed from the defaults go on the end.
+    pub fn rewritten(&self, old: &str) -> String {
+        let mut written = BTreeSet::new();
+        let mut lines = vec![];
+        for line in old.lines() {
+            let name = Some(line.trim())
+                .filter(|line| !line.starts_with('#'))
+                .and_then(|line| split_args(line).ok())
+                .and_then(|args| args.into_iter().next())
+                .map(|name| canonical_name(&name))
+                .filter(|name| PARAMETERS.iter().any(|(known, _)| known == name));
+            match name {
+                Some(name) => {
+                    if written.insert(name.clone()) {
+                        lines.extend(self.config_lines(&name));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let defaults = Self::default();
+        let changed: Vec<_> = PARAMETERS
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| !written.contains(*name) && self.lines(name) != defaults.lines(name))
+            .collect();
+        if !changed.is_empty() && !lines.iter().any(|line| line == REWRITE_SIGNATURE) {
+            lines.push(REWRITE_SIGNATURE.into());
+        }
+        for name in changed {
+            lines.extend(self.config_lines(name));
+        }
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// Saves the current settings into the file they were read from
+    pub fn rewrite(&self) -> Result<(), String> {
+        let path = self
+            .file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        let old = match fs::read_to_string(path) {
+            Ok(old) => old,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(format!("Rewriting config file: {}", error)),
+        };
+        // Written next to it first, so the file is never left half written
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".rewrite");
+        fs::write(&temporary, self.rewritten(&old))
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|error| format!("Rewriting config file: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod config_should {
+    use super::*;
+
+    fn from_args(args: &[&str]) -> Result<Config, ConfigError> {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn load(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        config.load_str(text, "test").map(|_| config)
+    }
+
+    #[test]
+    fn split_lines_like_redis() {
+        assert_eq!(
+            split_args(r#"  save "" "a \"b\"\n" 'c d'  "#),
+            Ok(vec![
+                "save".into(),
+                "".into(),
+                "a \"b\"\n".into(),
+                "c d".into()
+            ])
+        );
+        assert!(split_args(r#"logfile "unfinished"#).is_err());
+        assert!(split_args(r#"logfile "a"b"#).is_err());
+    }
+
+    #[test]
+    fn read_a_config_file() {
+        let config = load(
+            "# a comment\n\
+             port 7000\n\
+             bind 127.0.0.1 ::1\n\
+             MAXMEMORY 100mb\n\
+             maxmemory-policy allkeys-lru\n\
+             save 900 1\n\
+             save 60 1000\n\
+             loglevel warning\n\
+             replicaof 10.0.0.1 6379\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 7000);
+        assert_eq!(
+            config.bind,
+            vec![IpAddr::from([127, 0, 0, 1]), "::1".parse().unwrap()]
+        );
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.maxmemory_policy, EvictionPolicy::AllKeysLru);
+        assert_eq!(config.save, vec![(900, 1), (60, 1000)]);
+        assert_eq!(config.loglevel, LogLevel::Warning);
+        assert_eq!(config.replicaof, Some(("10.0.0.1".into(), 6379)));
+    }
+
+    #[test]
+    fn let_the_command_line_win() {
+        let dir = std::env::temp_dir().join(format!("rustdss-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("rustdss.conf");
+        fs::write(&file, "port 7000\ntimeout 10\n").unwrap();
+
+        let config = from_args(&[
+            file.to_str().unwrap(),
+            "--port",
+            "7001",
+            "--replicaof",
+            "127.0.0.1 6380",
+            "--save",
+            "",
+        ])
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.file, Some(file));
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.timeout, 10);
+        assert_eq!(config.replicaof, Some(("127.0.0.1".into(), 6380)));
+        assert!(config.save.is_empty());
+    }
+
+    #[test]
+    fn say_which_line_is_wrong() {
+        let error = load("port 7000\n\nport seventy\n").unwrap_err();
+
+        assert_eq!(
+            error,
+            ConfigError {
+                source: "test".into(),
+                line_number: 3,
+                line: "port seventy".into(),
+                message: "Invalid port".into(),
+            }
+        );
+        assert!(error.to_string().contains(">>> 'port seventy'"));
+    }
+
+    #[test]
+    fn reject_bad_settings() {
+        for line in &[
+            "port 65536",
+            "bind nowhere",
+            "databases 0",
+            "maxmemory lots",
+            "maxmemory-policy sometimes",
+            "save 60",
+            "appendonly maybe",
+            "appendfsync sometimes",
+            "dir /does/not/exist",
+            "dbfilename /tmp/dump.rdb",
+            "loglevel loud",
+            "syslog-facility kern",
+            "log-format xml",
+            "timeout -1",
+            "maxclients 0",
+            "client-output-buffer-limit normal 0 0",
+            "client-output-buffer-limit master 0 0 0",
+            "client-output-buffer-limit pubsub lots 0 0",
+            "replicaof somewhere",
+            "unixsocketperm 888",
+            "metrics-port -1",
+            "nonsense yes",
+        ] {
+            assert!(load(line).is_err(), "{} should be rejected", line);
+        }
+        assert!(from_args(&["--port"]).is_err());
+        assert!(from_args(&["/does/not/exist.conf"]).is_err());
+    }
+
+    fn changes(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn get_settings_by_pattern() {
+        let config = load("maxmemory 1kb\nsave 900 1\nsave 60 100\n").unwrap();
+
+        assert_eq!(
+            config.get("MAXMEMORY*"),
+            vec![
+                ("maxmemory", "1024".to_string()),
+                ("maxmemory-policy", "noeviction".to_string())
+            ]
+        );
+        assert_eq!(
+            config.get("save"),
+            vec![("save", "900 1 60 100".to_string())]
+        );
+        assert!(config.get("nothing-*").is_empty());
+    }
+
+    #[test]
+    fn read_socket_permissions_as_octal() {
+        let config = load("unixsocket /tmp/rustdss.sock\nunixsocketperm 770\n").unwrap();
+
+        assert_eq!(config.unixsocket, "/tmp/rustdss.sock");
+        assert_eq!(config.unixsocketperm, 0o770);
+        assert_eq!(
+            config.get("unixsocketperm"),
+            vec![("unixsocketperm", "770".to_string())]
+        );
+    }
+
+    #[test]
+    fn set_output_buffer_limits_by_class() {
+        let mut config = load("client-output-buffer-limit pubsub 64mb 16mb 90\n").unwrap();
+        config
+            .set_at_runtime(&changes(&[(
+                "client-output-buffer-limit",
+                "normal 1mb 0 0 slave 1gb 512mb 30",
+            )]))
+            .unwrap();
+
+        let limits = config.client_output_buffer_limit;
+        assert_eq!(limits.normal, OutputBufferLimit::new(1 << 20, 0, 0));
+        assert_eq!(
+            limits.replica,
+            OutputBufferLimit::new(1 << 30, 512 << 20, 30)
+        );
+        assert_eq!(
+            limits.pubsub,
+            OutputBufferLimit::new(64 << 20, 16 << 20, 90)
+        );
+        assert_eq!(
+            config.get("client-output-buffer-limit")[0].1,
+            "normal 1048576 0 0 replica 1073741824 536870912 30 pubsub 67108864 16777216 90"
+        );
+        assert_eq!(load(&config.rewritten("")).unwrap(), config);
+    }
+
+    #[test]
+    fn set_all_the_settings_or_none() {
+        let mut config = Config::default();
+
+        config
+            .set_at_runtime(&changes(&[("maxmemory", "2mb"), ("save", "30 5 10 50")]))
+            .unwrap();
+        assert_eq!(config.maxmemory, 2 * 1024 * 1024);
+        assert_eq!(config.save, vec![(30, 5), (10, 50)]);
+
+        let error = config
+            .set_at_runtime(&changes(&[("timeout", "5"), ("maxmemory", "lots")]))
+            .unwrap_err();
+        assert!(error.contains("'maxmemory'"));
+        assert_eq!(config.timeout, 0);
+
+        assert!(config
+            .set_at_runtime(&changes(&[("port", "7000")]))
+            .unwrap_err()
+            .contains("immutable"));
+        assert!(config
+            .set_at_runtime(&changes(&[("nonsense", "1")]))
+            .unwrap_err()
+            .starts_with("Unknown option"));
+
+        config.set_at_runtime(&changes(&[("save", "")])).unwrap();
+        assert!(config.save.is_empty());
+    }
+
+    #[test]
+    fn rewrite_the_file_keeping_comments() {
+        let old = "# The port\nport 7000\n\nsave 900 1\nsave 60 100\n# The end\n";
+        let mut config = load(old).unwrap();
+        config
+            .set_at_runtime(&changes(&[("save", "30 5"), ("dbfilename", "my dump.rdb")]))
+            .unwrap();
+
+        let rewritten = config.rewritten(old);
+
+        assert_eq!(
+            rewritten,
+            "# The port\nport 7000\n\nsave 30 5\n# The end\n\
+             # Generated by CONFIG REWRITE\ndbfilename \"my dump.rdb\"\n"
+        );
+        assert_eq!(load(&rewritten).unwrap(), config);
+    }
+}
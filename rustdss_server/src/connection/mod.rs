@@ -1,60 +1,395 @@
-use crate::request::Request;
-use rustdss_core::Message;
-use rustdss_data::RespData;
+use crate::clients::{Client, ClientType, MaxClientsReached};
+use crate::request::command::ParseCommand;
+use crate::request::{Request, Session};
+use crate::server::Server;
+use rustdss_core::replication::ReplicaStream;
+use rustdss_data::{Command, RespData};
 use rustdss_transport::{deserialise::DeserialiseRespData, serialise::SerialiseRespData};
-use std::io::{BufReader, Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc::Sender;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Weak};
 use std::thread;
+use std::time::Duration;
+
+mod stream;
+
+pub use stream::Stream;
+
+// How often clients are checked for sitting idle or falling behind, like Redis' clientsCron
+const CLIENTS_CRON_INTERVAL: Duration = Duration::from_millis(100);
+// How long to wait after failing to accept a connection before trying again
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Counts what's read from a client, for CLIENT LIST
+struct Counted {
+    stream: Stream,
+    client: Arc<Client>,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        self.client.read(read);
+        Ok(read)
+    }
+}
 
 pub struct Connection {}
 
 impl Connection {
-    fn handle_incoming_stream(core_sender: Sender<Message>, stream: &mut TcpStream) -> () {
-        // This function will create and use instances of Request
-        println!("[connection], handling tcp stream from client {:?}", stream);
+    /// `user` is who the connection starts out logged in as, if anyone
+    fn handle_incoming_stream(server: Arc<Server>, mut stream: Stream, user: Option<String>) {
+        let (maxclients, keepalive) = {
+            let config = server.config();
+            (config.maxclients, config.tcp_keepalive)
+        };
+        let client = match server.clients.register(
+            &stream,
+            ClientType::Normal,
+            user.clone(),
+            server.default_database(),
+            Some(maxclients),
+        ) {
+            Ok(client) => client,
+            Err(MaxClientsReached) => {
+                server.stats.connection_rejected();
+                let _ = stream.write_all(b"-ERR max number of clients reached\r\n");
+                stream.shutdown();
+                return;
+            }
+        };
+        if keepalive > 0 {
+            if let Err(error) = stream.set_keepalive(keepalive) {
+                log::debug!("Can't turn on TCP keepalive: {}", error);
+            }
+        }
+        server.stats.connection_received();
+        log::debug!("Accepted {}", client.address);
+        Self::serve(&server, stream, &client, user);
+        log::debug!("Client closed connection {}", client.address);
+        server.clients.unregister(client.id);
+    }
 
-        let bufreader: BufReader<TcpStream> = BufReader::new(stream.try_clone().unwrap());
+    fn serve(server: &Arc<Server>, mut stream: Stream, client: &Arc<Client>, user: Option<String>) {
+        // This function will create and use instances of Request
+        let bufreader = BufReader::new(Counted {
+            stream: stream.clone(),
+            client: client.clone(),
+        });
 
         let mut byte_stream = &mut bufreader
             .bytes()
-            .map(|item| item.expect("should be achar") as char);
+            .map_while(Result::ok)
+            .map(|byte| byte as char);
+
+        // Which database this connection is talking to, and any transaction it has open
+        let mut session = Session::new(client.clone(), user);
+        while let Some(input_data) = RespData::from_char_stream(&mut byte_stream) {
+            // Parse each request and give the parsed request to the Request module
+            // Turn the bytes into a stream of chars!
+            let response = Request::handle(&mut session, server, input_data);
+
+            if session.should_reply() {
+                let bytes = response.as_bytes();
+                client.queued(bytes.len());
+                if Self::over_output_limit(server, client) || stream.write_all(&bytes).is_err() {
+                    return;
+                }
+                client.written(bytes.len());
+            }
+            // CLIENT KILL on itself waits until the reply has gone
+            if client.is_killed() {
+                stream.shutdown();
+                return;
+            }
+
+            if let Some(replica) = session.take_replica_stream() {
+                client.state().kind = ClientType::Replica;
+                Self::serve_replica(server, &stream, client, byte_stream, replica);
+                return;
+            }
+            if let Some(feed) = session.take_monitor_feed() {
+                Self::serve_monitor(server, &stream, client, byte_stream, feed);
+                return;
+            }
+        }
+    }
+
+    /// Closes a client whose replies are piling up past its output buffer limit
+    fn over_output_limit(server: &Server, client: &Client) -> bool {
+        let limits = server.config().client_output_buffer_limit;
+        let over = client
+            .kind()
+            .output_buffer_limit(&limits)
+            .is_some_and(|limit| client.is_over_output_limit(limit));
+        if over {
+            log::warn!(
+                "Client id={} addr={} closed for overcoming of output buffer limits.",
+                client.id,
+                client.address
+            );
+            server.stats.output_limit_disconnection();
+            client.kill();
+        }
+        over
+    }
+
+    /// Writes `initial` and then everything from `feed` to the client from threads of their own.
+    /// What's waiting to be written counts as the client's output buffer, so one that can't keep
+    /// up is closed rather than queueing replies for ever.
+    fn relay<T: Send + 'static>(
+        server: &Arc<Server>,
+        stream: &Stream,
+        client: &Arc<Client>,
+        initial: Vec<u8>,
+        feed: Receiver<T>,
+        encode: fn(T) -> Vec<u8>,
+    ) {
+        let (queue, queued) = channel::<Vec<u8>>();
+        {
+            let (server, client) = (server.clone(), client.clone());
+            thread::spawn(move || {
+                // Ends once the feed does, or the writer has given up
+                for item in feed {
+                    let bytes = encode(item);
+                    client.queued(bytes.len());
+                    if Self::over_output_limit(&server, &client) || queue.send(bytes).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        let mut writer = stream.clone();
+        let client = client.clone();
+        thread::spawn(move || {
+            client.queued(initial.len());
+            if writer.write_all(&initial).is_ok() {
+                client.written(initial.len());
+                for bytes in queued {
+                    if writer.write_all(&bytes).is_err() {
+                        break;
+                    }
+                    client.written(bytes.len());
+                }
+            }
+            writer.shutdown();
+        });
+    }
+
+    /// After PSYNC the connection carries the replication stream, and all the replica sends back
+    /// are acks
+    fn serve_replica<I: Iterator<Item = char>>(
+        server: &Arc<Server>,
+        stream: &Stream,
+        client: &Arc<Client>,
+        input: &mut I,
+        replica: ReplicaStream,
+    ) {
+        let ReplicaStream { id, initial, feed } = replica;
+        // Ends once the replica is removed
+        Self::relay(server, stream, client, initial, feed, |bytes| bytes);
 
-        let mut database_id = None;
+        while let Some(input_data) = RespData::from_char_stream(input) {
+            if let Ok(Command::Replconf(args)) = Command::from_resp(input_data) {
+                if let [option, offset] = args.as_slice() {
+                    if let (true, Ok(offset)) = (option.eq_ignore_ascii_case("ack"), offset.parse())
+                    {
+                        server.replication.ack(id, offset);
+                    }
+                }
+            }
+        }
+        server.replication.remove_replica(id);
+    }
+
+    /// After MONITOR the connection only carries the feed, anything the client sends is ignored
+    /// until it hangs up
+    fn serve_monitor<I: Iterator<Item = char>>(
+        server: &Arc<Server>,
+        stream: &Stream,
+        client: &Arc<Client>,
+        input: &mut I,
+        feed: Receiver<String>,
+    ) {
+        // Ends once the monitor is detached
+        Self::relay(server, stream, client, vec![], feed, |line| {
+            format!("{}\r\n", line).into_bytes()
+        });
+
+        while RespData::from_char_stream(input).is_some() {}
+        server.monitors.detach(client.id);
+    }
+
+    /// Closes clients that have been idle for longer than `timeout` or are too far behind with
+    /// their replies, until the server's gone
+    fn watch_clients(server: Weak<Server>) {
         loop {
-            if let Some(input_data) = RespData::from_char_stream(&mut byte_stream) {
-                // Parse each request and give the parsed request to the Request module
-                // Turn the bytes into a stream of chars!
-                //
-                // We need some state here to manage which database this connection/session is
-                // talking to
-                let (new_database_id, response) =
-                    Request::handle(database_id.clone(), core_sender.clone(), input_data);
-
-                database_id = new_database_id;
-
-                stream
-                    .write(response.as_string().as_bytes())
-                    .expect("Can't write to socket");
-            } else {
+            thread::sleep(CLIENTS_CRON_INTERVAL);
+            let Some(server) = server.upgrade() else {
                 break;
+            };
+            let timeout = Duration::from_secs(server.config().timeout);
+            for client in server.clients.list() {
+                if client.is_killed() || Self::over_output_limit(&server, &client) {
+                    continue;
+                }
+                if !timeout.is_zero() && client.is_idle_for(timeout) {
+                    log::debug!("Closing idle client {}", client.address);
+                    client.kill();
+                }
             }
         }
     }
 
-    pub fn start(outer_core_sender: Sender<Message>) -> std::io::Result<Self> {
-        println!("[connection] Starting to listen to connections");
+    fn bind(addresses: &[IpAddr], port: u16) -> io::Result<Vec<TcpListener>> {
+        addresses
+            .iter()
+            .map(|address| {
+                TcpListener::bind((*address, port)).map_err(|error| {
+                    io::Error::new(
+                        error.kind(),
+                        format!("Can't listen on {}:{}: {}", address, port, error),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Listens on a Unix socket, replacing whatever was left at the path by a previous run
+    fn bind_unix(path: &str, permissions: u32) -> io::Result<UnixListener> {
+        let error = |error: io::Error| {
+            io::Error::new(error.kind(), format!("Can't listen on {}: {}", path, error))
+        };
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(error)?;
+        if permissions != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(permissions)).map_err(error)?;
+        }
+        Ok(listener)
+    }
+
+    pub fn start(server: Arc<Server>) -> io::Result<Self> {
+        // Every address has to be free before any connections are taken. A port of zero or an
+        // empty socket path means there's no listener of that kind.
+        let (bind, tls_port, unixsocket, unixsocketperm, metrics_port) = {
+            let config = server.config();
+            (
+                config.bind.clone(),
+                config.tls_port,
+                config.unixsocket.clone(),
+                config.unixsocketperm,
+                config.metrics_port,
+            )
+        };
+        let mut listeners = vec![];
+        if server.port != 0 {
+            for listener in Self::bind(&bind, server.port)? {
+                listeners.push((listener, false));
+            }
+        }
+        if tls_port != 0 {
+            for listener in Self::bind(&bind, tls_port)? {
+                listeners.push((listener, true));
+            }
+        }
+        let unix_listener = if unixsocket.is_empty() {
+            None
+        } else {
+            Some(Self::bind_unix(&unixsocket, unixsocketperm)?)
+        };
+        let metrics_listeners = if metrics_port == 0 {
+            vec![]
+        } else {
+            Self::bind(&bind, metrics_port)?
+        };
+        for listener in metrics_listeners {
+            crate::metrics::serve(server.clone(), listener);
+        }
 
-        let listener = TcpListener::bind("0.0.0.0:6380")?;
+        let watched = Arc::downgrade(&server);
+        thread::spawn(move || Self::watch_clients(watched));
 
+        log::info!("Ready to accept connections");
+        let mut accepting: Vec<_> = listeners
+            .into_iter()
+            .map(|(listener, tls)| {
+                let server = server.clone();
+                thread::spawn(move || Self::accept(server, listener, tls))
+            })
+            .collect();
+        if let Some(listener) = unix_listener {
+            let server = server.clone();
+            accepting.push(thread::spawn(move || Self::accept_unix(server, listener)));
+        }
+        for thread in accepting {
+            let _ = thread.join();
+        }
+
+        Ok(Self {})
+    }
+
+    fn accept_unix(server: Arc<Server>, listener: UnixListener) {
         for stream in listener.incoming() {
-            let core_sender = outer_core_sender.clone();
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    Self::accept_failed(error);
+                    continue;
+                }
+            };
+            // Dropping it closes it
+            if server.is_shutting_down() {
+                continue;
+            }
+            let server = server.clone();
             thread::spawn(move || {
-                Connection::handle_incoming_stream(core_sender.clone(), &mut stream.unwrap());
-                println!("[connection] connection terminated");
+                let user = server.acl.initial_user();
+                Connection::handle_incoming_stream(server, Stream::unix(stream), user);
             });
         }
+    }
 
-        Ok(Self {})
+    /// Errors accepting a connection, like running out of file descriptors, usually pass, so
+    /// wait a moment and keep going rather than stop listening
+    pub(crate) fn accept_failed(error: io::Error) {
+        log::warn!("Accepting client connection: {}", error);
+        thread::sleep(ACCEPT_RETRY);
+    }
+
+    fn accept(server: Arc<Server>, listener: TcpListener, tls: bool) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    Self::accept_failed(error);
+                    continue;
+                }
+            };
+            if server.is_shutting_down() {
+                continue;
+            }
+            let server = server.clone();
+            thread::spawn(move || {
+                let (stream, user) = match (tls, &server.tls) {
+                    (true, Some(tls)) => match tls.accept(stream) {
+                        // A certificate for a user that can log in stands in for AUTH
+                        Ok((stream, Some(user))) if server.acl.is_enabled(&user) => {
+                            (Stream::Tls(stream), Some(user))
+                        }
+                        Ok((stream, _)) => (Stream::Tls(stream), server.acl.initial_user()),
+                        Err(error) => {
+                            log::debug!("TLS handshake failed: {}", error);
+                            return;
+                        }
+                    },
+                    _ => (Stream::tcp(stream), server.acl.initial_user()),
+                };
+                Connection::handle_incoming_stream(server, stream, user);
+            });
+        }
     }
 }
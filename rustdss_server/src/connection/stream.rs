@@ -0,0 +1,144 @@
+use crate::tls::TlsStream;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: the value is a c_int that lives for the whole call, and its size is passed along
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// A connection to a client or another server, plain, over TLS or through a Unix socket
+#[derive(Clone)]
+pub enum Stream {
+    Tcp(Arc<TcpStream>),
+    Tls(TlsStream),
+    Unix(Arc<UnixStream>),
+}
+
+impl Stream {
+    pub fn tcp(stream: TcpStream) -> Self {
+        Stream::Tcp(Arc::new(stream))
+    }
+
+    pub fn unix(stream: UnixStream) -> Self {
+        Stream::Unix(Arc::new(stream))
+    }
+
+    fn unix_path(stream: &UnixStream) -> String {
+        stream
+            .local_addr()
+            .ok()
+            .and_then(|address| address.as_pathname().map(|path| path.display().to_string()))
+            .map(|path| format!("{}:0", path))
+            .unwrap_or_default()
+    }
+
+    /// Where the other end is, as `ip:port`, or `path:0` for a Unix socket like Redis shows it
+    pub fn peer_address(&self) -> String {
+        let address = match self {
+            Stream::Tcp(stream) => stream.peer_addr(),
+            Stream::Tls(stream) => stream.tcp().peer_addr(),
+            Stream::Unix(stream) => return Self::unix_path(stream),
+        };
+        address
+            .map(|address| address.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Which of this server's addresses the connection came in on
+    pub fn local_address(&self) -> String {
+        let address = match self {
+            Stream::Tcp(stream) => stream.local_addr(),
+            Stream::Tls(stream) => stream.tcp().local_addr(),
+            Stream::Unix(stream) => return Self::unix_path(stream),
+        };
+        address
+            .map(|address| address.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Has the kernel check an idle TCP connection is still there every `seconds`, like Redis'
+    /// tcp-keepalive. Unix sockets don't need it.
+    pub fn set_keepalive(&self, seconds: u64) -> io::Result<()> {
+        let fd = match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Tls(stream) => stream.tcp().as_raw_fd(),
+            Stream::Unix(_) => return Ok(()),
+        };
+        set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+        // Elsewhere the probes keep the system's timings
+        #[cfg(target_os = "linux")]
+        {
+            let seconds = seconds.min(i32::MAX as u64) as libc::c_int;
+            set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, seconds)?;
+            set_option(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_KEEPINTVL,
+                (seconds / 3).max(1),
+            )?;
+            set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = seconds;
+        Ok(())
+    }
+
+    /// Closes the connection, waking up anything waiting on it
+    pub fn shutdown(&self) {
+        let _ = match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Stream::Tls(stream) => stream.tcp().shutdown(Shutdown::Both),
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&**stream).read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+            Stream::Unix(stream) => (&**stream).read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&**stream).write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+            Stream::Unix(stream) => (&**stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&**stream).flush(),
+            Stream::Tls(stream) => stream.flush(),
+            Stream::Unix(stream) => (&**stream).flush(),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+// Database 0, like Redis, so SELECT 0 and INFO keyspace's db0 mean the same database
 pub fn default_database_name() -> String {
-    "default".into()
+    "0".into()
 }
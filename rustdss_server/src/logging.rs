@@ -0,0 +1,342 @@
+// The server's log, which the `log` macros in every crate write to
+//
+// Redis' levels map onto the log crate's: debug is trace, verbose is debug, notice is info, and
+// warning is warn and error. Lines go to standard output, `logfile` or syslog, as text like Redis
+// writes them or as JSON. Anything below the level is dropped by the macros before it's even
+// formatted, so debug logging on busy paths costs nothing at the default level.
+use crate::config::{Config, LogLevel};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Where syslog listens, on Linux and then on macOS
+const SYSLOG_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+enum Output {
+    Stdout,
+    File(File),
+    Syslog {
+        socket: UnixDatagram,
+        ident: String,
+        facility: u8,
+    },
+}
+
+struct Logger {
+    json: AtomicBool,
+    output: Mutex<Output>,
+}
+
+/// The syslog facility code for a name, user or local0 to local7
+pub fn facility(name: &str) -> Option<u8> {
+    match name.to_lowercase().as_str() {
+        "user" => Some(1),
+        local => {
+            let n: u8 = local.strip_prefix("local")?.parse().ok()?;
+            (n <= 7).then_some(16 + n)
+        }
+    }
+}
+
+fn filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Debug => LevelFilter::Trace,
+        LogLevel::Verbose => LevelFilter::Debug,
+        LogLevel::Notice => LevelFilter::Info,
+        LogLevel::Warning => LevelFilter::Warn,
+        LogLevel::Nothing => LevelFilter::Off,
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "debug",
+        Level::Debug => "verbose",
+        Level::Info => "notice",
+        Level::Warn => "warning",
+        Level::Error => "error",
+    }
+}
+
+/// The character Redis marks each level's lines with
+fn level_mark(level: Level) -> char {
+    match level {
+        Level::Trace => '.',
+        Level::Debug => '-',
+        Level::Info => '*',
+        Level::Warn | Level::Error => '#',
+    }
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Trace => 7,
+        Level::Debug => 6,
+        Level::Info => 5,
+        Level::Warn => 4,
+        Level::Error => 3,
+    }
+}
+
+/// The UTC date and time: year, month, day, hours, minutes, seconds and milliseconds
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, of_day) = ((secs / 86400) as i64, secs % 86400);
+    // Days to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// A line like Redis writes: `pid dd Mon yyyy hh:mm:ss.mmm * message`
+fn text_line(time: SystemTime, pid: u32, level: Level, message: &str) -> String {
+    let (year, month, day, hours, minutes, seconds, millis) = utc(time);
+    format!(
+        "{} {:02} {} {} {:02}:{:02}:{:02}.{:03} {} {}",
+        pid,
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hours,
+        minutes,
+        seconds,
+        millis,
+        level_mark(level),
+        message
+    )
+}
+
+fn json_line(time: SystemTime, pid: u32, level: Level, target: &str, message: &str) -> String {
+    let (year, month, day, hours, minutes, seconds, millis) = utc(time);
+    format!(
+        "{{\"time\":\"{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z\",\"pid\":{},\"level\":\"{}\",\"target\":{},\"message\":{}}}",
+        year,
+        month,
+        day,
+        hours,
+        minutes,
+        seconds,
+        millis,
+        pid,
+        level_name(level),
+        json_string(target),
+        json_string(message)
+    )
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let now = SystemTime::now();
+        let pid = std::process::id();
+        let json = self.json.load(Ordering::Relaxed);
+        let mut output = self
+            .output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // There's nowhere to say that the log can't be written to
+        let _ = match &mut *output {
+            Output::Syslog {
+                socket,
+                ident,
+                facility,
+            } => {
+                // Syslog adds its own time
+                let body = if json {
+                    json_line(now, pid, record.level(), record.target(), &message)
+                } else {
+                    message
+                };
+                let priority = *facility * 8 + syslog_severity(record.level());
+                socket
+                    .send(format!("<{}>{}[{}]: {}", priority, ident, pid, body).as_bytes())
+                    .map(|_| ())
+            }
+            output => {
+                let line = if json {
+                    json_line(now, pid, record.level(), record.target(), &message)
+                } else {
+                    text_line(now, pid, record.level(), &message)
+                };
+                match output {
+                    Output::File(file) => writeln!(file, "{}", line),
+                    _ => writeln!(io::stdout().lock(), "{}", line),
+                }
+            }
+        };
+    }
+
+    fn flush(&self) {
+        let mut output = self
+            .output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = match &mut *output {
+            Output::File(file) => file.flush(),
+            Output::Stdout => io::stdout().flush(),
+            Output::Syslog { .. } => Ok(()),
+        };
+    }
+}
+
+fn open_output(config: &Config) -> io::Result<Output> {
+    if config.syslog_enabled {
+        let socket = UnixDatagram::unbound()?;
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "no syslog socket");
+        for path in SYSLOG_SOCKETS {
+            match socket.connect(path) {
+                Ok(()) => {
+                    return Ok(Output::Syslog {
+                        socket,
+                        ident: config.syslog_ident.clone(),
+                        facility: facility(&config.syslog_facility).unwrap_or(16),
+                    })
+                }
+                Err(failed) => error = failed,
+            }
+        }
+        return Err(io::Error::new(
+            error.kind(),
+            format!("Can't connect to syslog: {}", error),
+        ));
+    }
+    if config.logfile.is_empty() {
+        return Ok(Output::Stdout);
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.logfile)
+        .map(Output::File)
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("Can't open the log file {}: {}", config.logfile, error),
+            )
+        })
+}
+
+/// Starts logging where the config says, once at startup
+pub fn init(config: &Config) -> io::Result<()> {
+    let logger = Logger {
+        json: AtomicBool::new(config.log_format == "json"),
+        output: Mutex::new(open_output(config)?),
+    };
+    if LOGGER.set(logger).is_ok() {
+        let _ = log::set_logger(LOGGER.get().expect("just set"));
+    }
+    log::set_max_level(filter(config.loglevel));
+    Ok(())
+}
+
+/// Applies a CONFIG SET of loglevel or log-format
+pub fn reconfigure(config: &Config) {
+    log::set_max_level(filter(config.loglevel));
+    if let Some(logger) = LOGGER.get() {
+        logger
+            .json
+            .store(config.log_format == "json", Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod logging_should {
+    use super::*;
+    use std::time::Duration;
+
+    // 2012-06-12 16:21:23.107 UTC
+    fn when() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_339_518_083_107)
+    }
+
+    #[test]
+    fn write_lines_like_redis() {
+        assert_eq!(
+            text_line(when(), 42, Level::Info, "Ready to accept connections"),
+            "42 12 Jun 2012 16:21:23.107 * Ready to accept connections"
+        );
+        assert!(text_line(when(), 42, Level::Warn, "").contains(" # "));
+    }
+
+    #[test]
+    fn write_json_lines() {
+        assert_eq!(
+            json_line(
+                when(),
+                42,
+                Level::Debug,
+                "rustdss::connection",
+                "say \"hi\"\n"
+            ),
+            "{\"time\":\"2012-06-12T16:21:23.107Z\",\"pid\":42,\"level\":\"verbose\",\
+             \"target\":\"rustdss::connection\",\"message\":\"say \\\"hi\\\"\\n\"}"
+        );
+    }
+
+    #[test]
+    fn work_out_dates_either_side_of_leap_days() {
+        let day = |days: u64| UNIX_EPOCH + Duration::from_secs(days * 86400);
+        assert_eq!(utc(day(0)), (1970, 1, 1, 0, 0, 0, 0));
+        assert_eq!(utc(day(11016)), (2000, 2, 29, 0, 0, 0, 0));
+        assert_eq!(utc(day(11017)), (2000, 3, 1, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn know_the_syslog_facilities() {
+        assert_eq!(facility("USER"), Some(1));
+        assert_eq!(facility("local7"), Some(23));
+        assert_eq!(facility("local8"), None);
+        assert_eq!(facility("kern"), None);
+    }
+}
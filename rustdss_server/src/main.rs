@@ -1,10 +1,78 @@
+mod acl;
+mod client;
+mod clients;
+mod cluster;
+mod command_table;
+mod config;
 mod connection;
 mod constants;
+mod logging;
+mod metrics;
+mod monitor;
+mod replica;
 mod request;
+mod server;
+mod shutdown;
+mod stats;
+#[cfg(test)]
+mod test_helpers;
+mod tls;
 
-fn main() -> Result<(), std::io::Error> {
-    let core = rustdss_core::Core::start();
-    connection::Connection::start(core.get_sender())?;
+use config::Config;
+use rustdss_core::{CoreOptions, LatencyMonitor, Memory, SlowLog};
+use server::Server;
+use std::sync::Arc;
 
-    Ok(())
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(error) = logging::init(&config) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+
+    let memory = Memory::default();
+    memory.set_maxmemory(config.maxmemory);
+    memory.set_policy(config.maxmemory_policy);
+    let core = rustdss_core::Core::start_with(CoreOptions {
+        memory,
+        slowlog: SlowLog::new(
+            config.slowlog_log_slower_than,
+            config.slowlog_max_len as usize,
+        ),
+        latency: LatencyMonitor::new(config.latency_monitor_threshold),
+        shards: config.shards,
+    });
+    let replicaof = config.replicaof.clone();
+    let aclfile = config.aclfile.clone();
+    let server = match Server::new(&core, config) {
+        Ok(server) => Arc::new(server),
+        Err(error) => {
+            log::error!("Failed to configure TLS: {}", error);
+            std::process::exit(1);
+        }
+    };
+    if !aclfile.is_empty() {
+        if let Err(error) = server.acl.load(&aclfile) {
+            log::error!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    if let Err(error) = shutdown::handle_signals(server.clone()) {
+        log::error!("Can't handle signals: {}", error);
+        std::process::exit(1);
+    }
+    if let Some((host, port)) = replicaof {
+        server.replicate_from(&host, port);
+    }
+    if let Err(error) = connection::Connection::start(server) {
+        log::error!("{}", error);
+        std::process::exit(1);
+    }
 }
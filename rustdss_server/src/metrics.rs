@@ -0,0 +1,378 @@
+// Prometheus metrics over HTTP on metrics-port, from the same counters as INFO
+//
+// Only `GET /metrics` is answered, one request per connection, so this is just enough HTTP for a
+// scraper and nothing else.
+use crate::connection::Connection;
+use crate::request::info::rss_bytes;
+use crate::server::Server;
+use crate::stats::LATENCY_BUCKETS;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A scraper has this long to send its whole request, so one that stops part way through or
+// trickles it in doesn't hold a thread for long
+const REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+// How much of a request, headers included, is read. Anything after it is ignored.
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+
+/// Label values are quoted, so quotes, backslashes and newlines in them need escaping
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics in the Prometheus text format
+#[derive(Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    /// Starts a metric, every sample of it has to follow before the next one starts
+    fn metric(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.text, "# HELP rustdss_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE rustdss_{} {}", name, kind);
+        self
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl ToString) -> &mut Self {
+        let _ = write!(self.text, "rustdss_{}", name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value.to_string());
+        self
+    }
+
+    /// A metric with only the one sample and no labels
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl ToString) {
+        self.metric(name, kind, help).sample(name, &[], value);
+    }
+}
+
+fn render(server: &Server) -> String {
+    let mut metrics = Metrics::default();
+    let stats = &server.stats;
+
+    metrics.single(
+        "uptime_seconds",
+        "gauge",
+        "Seconds since the server started",
+        stats.uptime().as_secs(),
+    );
+    metrics.single(
+        "connected_clients",
+        "gauge",
+        "Client connections, not counting replicas",
+        server.clients.connected(),
+    );
+    metrics.single(
+        "connections_received_total",
+        "counter",
+        "Connections accepted",
+        stats.total_connections(),
+    );
+    metrics.single(
+        "commands_processed_total",
+        "counter",
+        "Commands run",
+        stats.total_commands(),
+    );
+
+    let commands = stats.commands();
+    metrics.metric(
+        "commands_total",
+        "counter",
+        "Commands by name and whether they succeeded, failed or were rejected",
+    );
+    for (name, command) in &commands {
+        let ok = command.calls - command.failed_calls;
+        metrics
+            .sample("commands_total", &[("cmd", name), ("result", "ok")], ok)
+            .sample(
+                "commands_total",
+                &[("cmd", name), ("result", "failed")],
+                command.failed_calls,
+            )
+            .sample(
+                "commands_total",
+                &[("cmd", name), ("result", "rejected")],
+                command.rejected_calls,
+            );
+    }
+    metrics.metric(
+        "command_duration_seconds",
+        "histogram",
+        "How long commands took to run",
+    );
+    for (name, command) in &commands {
+        let mut count = 0;
+        for (bound, calls) in LATENCY_BUCKETS.iter().zip(command.latency) {
+            count += calls;
+            let le = (*bound as f64 / 1e6).to_string();
+            metrics.sample(
+                "command_duration_seconds_bucket",
+                &[("cmd", name), ("le", &le)],
+                count,
+            );
+        }
+        metrics
+            .sample(
+                "command_duration_seconds_bucket",
+                &[("cmd", name), ("le", "+Inf")],
+                command.calls,
+            )
+            .sample(
+                "command_duration_seconds_sum",
+                &[("cmd", name)],
+                command.usec as f64 / 1e6,
+            )
+            .sample(
+                "command_duration_seconds_count",
+                &[("cmd", name)],
+                command.calls,
+            );
+    }
+    metrics.metric(
+        "errors_total",
+        "counter",
+        "Error replies by their first word",
+    );
+    for (kind, count) in stats.errors() {
+        metrics.sample("errors_total", &[("kind", &kind)], count);
+    }
+
+    let keyspace = server.keyspace.keyspace();
+    metrics.metric("db_keys", "gauge", "Keys in each database");
+    for (database, info) in &keyspace {
+        metrics.sample("db_keys", &[("db", database)], info.keys);
+    }
+    metrics.metric(
+        "db_keys_expiring",
+        "gauge",
+        "Keys with a TTL in each database",
+    );
+    for (database, info) in &keyspace {
+        metrics.sample("db_keys_expiring", &[("db", database)], info.expires);
+    }
+    metrics.single(
+        "keyspace_hits_total",
+        "counter",
+        "Key lookups that found the key",
+        server.keyspace.keyspace_hits(),
+    );
+    metrics.single(
+        "keyspace_misses_total",
+        "counter",
+        "Key lookups that didn't find the key",
+        server.keyspace.keyspace_misses(),
+    );
+    metrics.single(
+        "expired_keys_total",
+        "counter",
+        "Keys removed because their TTL ran out",
+        server.memory.expired_keys(),
+    );
+    metrics.single(
+        "evicted_keys_total",
+        "counter",
+        "Keys removed to stay under maxmemory",
+        server.memory.evicted_keys(),
+    );
+
+    metrics.single(
+        "memory_used_bytes",
+        "gauge",
+        "Memory used by keys and values",
+        server.memory.used(),
+    );
+    metrics.single(
+        "memory_rss_bytes",
+        "gauge",
+        "Resident set size of the process",
+        rss_bytes(),
+    );
+    metrics.single(
+        "memory_max_bytes",
+        "gauge",
+        "The maxmemory limit, zero for none",
+        server.memory.maxmemory(),
+    );
+    let (net_input, net_output) = server.clients.net_bytes();
+    metrics.single(
+        "net_input_bytes_total",
+        "counter",
+        "Bytes read from clients",
+        net_input,
+    );
+    metrics.single(
+        "net_output_bytes_total",
+        "counter",
+        "Bytes written to clients",
+        net_output,
+    );
+
+    // Nothing is saved to disk yet, like the persistence section of INFO says
+    metrics.single("loading", "gauge", "Whether a dataset is being loaded", 0);
+    metrics.single(
+        "rdb_bgsave_in_progress",
+        "gauge",
+        "Whether a snapshot is being saved",
+        0,
+    );
+    metrics.single(
+        "aof_enabled",
+        "gauge",
+        "Whether the append only file is on",
+        0,
+    );
+    metrics.text
+}
+
+/// The path asked for, if the request is a GET
+fn requested_path(request_line: &str) -> Option<&str> {
+    match request_line
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["GET", path, version] if version.starts_with("HTTP/") => Some(path),
+        _ => None,
+    }
+}
+
+/// Reads from a stream until a deadline, each read only waits for whatever time is left
+struct UntilDeadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for UntilDeadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// The request line, once the rest of the request has been read or the size limit reached
+fn read_request(stream: &TcpStream) -> io::Result<String> {
+    let deadline = Instant::now() + REQUEST_DEADLINE;
+    let mut reader = BufReader::new(UntilDeadline { stream, deadline }).take(MAX_REQUEST_BYTES);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers don't matter, but they have to be read before replying
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    Ok(request_line)
+}
+
+fn respond(server: &Server, stream: TcpStream) -> io::Result<()> {
+    let request_line = read_request(&stream)?;
+
+    let (status, content_type, body) = match requested_path(&request_line) {
+        Some("/metrics") => ("200 OK", "text/plain; version=0.0.4", render(server)),
+        Some(_) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        None => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method Not Allowed\n".to_string(),
+        ),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Answers each scrape in a thread of its own, so a slow one doesn't hold up the rest
+pub fn serve(server: Arc<Server>, listener: TcpListener) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    Connection::accept_failed(error);
+                    continue;
+                }
+            };
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(error) = respond(&server, stream) {
+                    log::debug!("Couldn't answer a metrics scrape: {}", error);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod metrics_should {
+    use super::*;
+
+    #[test]
+    fn write_the_prometheus_text_format() {
+        let mut metrics = Metrics::default();
+        metrics
+            .metric("db_keys", "gauge", "Keys in each database")
+            .sample("db_keys", &[("db", "say \"hi\"\\\n")], 3);
+        metrics.single("loading", "gauge", "Loading", 0);
+
+        assert_eq!(
+            metrics.text,
+            "# HELP rustdss_db_keys Keys in each database\n\
+             # TYPE rustdss_db_keys gauge\n\
+             rustdss_db_keys{db=\"say \\\"hi\\\"\\\\\\n\"} 3\n\
+             # HELP rustdss_loading Loading\n\
+             # TYPE rustdss_loading gauge\n\
+             rustdss_loading 0\n"
+        );
+    }
+
+    #[test]
+    fn only_answer_gets() {
+        assert_eq!(
+            requested_path("GET /metrics HTTP/1.1\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(requested_path("POST /metrics HTTP/1.1\r\n"), None);
+        assert_eq!(requested_path("nonsense\r\n"), None);
+    }
+
+    #[test]
+    fn stop_reading_a_request_at_the_size_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nX-Padding: ")
+            .unwrap();
+        client.write_all(&[b'a'; 16 * 1024]).unwrap();
+
+        // The client is still connected, but there's no waiting for the rest
+        let started = Instant::now();
+        assert_eq!(read_request(&stream).unwrap(), "GET /metrics HTTP/1.1\r\n");
+        assert!(started.elapsed() < REQUEST_DEADLINE);
+    }
+}
@@ -0,0 +1,198 @@
+// MONITOR, a live feed of every command the server runs
+//
+// Each monitoring connection gets a channel of lines, written out by its own thread. Commands are
+// only turned into lines while something is watching, so the rest of the time all this costs is
+// checking a counter.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const REDACTED: &str = "(redacted)";
+
+#[derive(Default)]
+pub struct Monitors {
+    watching: AtomicUsize,
+    // Each monitoring client's ID and where to send its lines
+    feeds: Mutex<Vec<(u64, Sender<String>)>>,
+}
+
+impl Monitors {
+    fn feeds(&self) -> MutexGuard<'_, Vec<(u64, Sender<String>)>> {
+        self.feeds
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether there's anything to feed
+    pub fn is_watched(&self) -> bool {
+        self.watching.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn attach(&self, id: u64) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        let mut feeds = self.feeds();
+        feeds.push((id, sender));
+        self.watching.store(feeds.len(), Ordering::Relaxed);
+        receiver
+    }
+
+    /// Stops feeding a client, which ends its channel
+    pub fn detach(&self, id: u64) {
+        let mut feeds = self.feeds();
+        feeds.retain(|(watcher, _)| *watcher != id);
+        self.watching.store(feeds.len(), Ordering::Relaxed);
+    }
+
+    /// Sends a command to every monitor, `args` being the command name and its arguments as the
+    /// client sent them
+    pub fn feed(&self, database: &str, address: &str, args: &[String]) {
+        let line = line(SystemTime::now(), database, address, args);
+        let mut feeds = self.feeds();
+        feeds.retain(|(_, feed)| feed.send(line.clone()).is_ok());
+        self.watching.store(feeds.len(), Ordering::Relaxed);
+    }
+}
+
+/// Quotes an argument the way Redis does, escaping anything that isn't printable
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            c if c == ' ' || c.is_ascii_graphic() => quoted.push(c),
+            // Connections read each byte as a char, so these are all bytes
+            c => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Hides passwords: everything after AUTH, MIGRATE's AUTH and AUTH2 options, and passwords set
+/// with CONFIG SET
+fn redact(args: &[String]) -> Vec<String> {
+    let mut hidden = vec![false; args.len()];
+    let mut hide = |from: usize, count: usize| {
+        hidden
+            .iter_mut()
+            .skip(from)
+            .take(count)
+            .for_each(|hide| *hide = true)
+    };
+    let lowercase: Vec<String> = args.iter().map(|arg| arg.to_lowercase()).collect();
+    match lowercase.first().map(String::as_str) {
+        Some("auth") => hide(1, args.len()),
+        Some("migrate") => {
+            for (i, arg) in lowercase.iter().enumerate() {
+                match arg.as_str() {
+                    "auth" => hide(i + 1, 1),
+                    "auth2" => hide(i + 1, 2),
+                    // The keys come after this, so there are no more options
+                    "keys" => break,
+                    _ => {}
+                }
+            }
+        }
+        Some("config") if lowercase.get(1).is_some_and(|sub| sub == "set") => {
+            for (i, parameter) in lowercase.iter().enumerate().skip(2).step_by(2) {
+                if parameter == "requirepass" || parameter == "masterauth" {
+                    hide(i + 1, 1);
+                }
+            }
+        }
+        _ => {}
+    }
+    args.iter()
+        .zip(hidden)
+        .map(|(arg, hidden)| if hidden { REDACTED.into() } else { arg.clone() })
+        .collect()
+}
+
+/// A line of the feed: `+<unix time> [<database> <address>] "command" "arg" ...`
+fn line(now: SystemTime, database: &str, address: &str, args: &[String]) -> String {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let args: Vec<String> = redact(args).iter().map(|arg| quote(arg)).collect();
+    format!(
+        "+{}.{:06} [{} {}] {}",
+        now.as_secs(),
+        now.subsec_micros(),
+        database,
+        address,
+        args.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod monitor_should {
+    use super::*;
+    use crate::test_helpers::args;
+    use std::time::Duration;
+
+    #[test]
+    fn format_lines_like_redis() {
+        let when = UNIX_EPOCH + Duration::from_micros(1_339_518_083_107_412);
+        assert_eq!(
+            line(
+                when,
+                "0",
+                "127.0.0.1:60866",
+                &args(&["set", "say", "\"hi\"\\\n\x01 there"])
+            ),
+            "+1339518083.107412 [0 127.0.0.1:60866] \"set\" \"say\" \"\\\"hi\\\"\\\\\\n\\x01 there\""
+        );
+    }
+
+    #[test]
+    fn hide_passwords() {
+        assert_eq!(
+            redact(&args(&["AUTH", "alice", "secret"])),
+            args(&["AUTH", REDACTED, REDACTED])
+        );
+        assert_eq!(
+            redact(&args(&[
+                "migrate", "h", "1", "", "0", "5000", "AUTH2", "u", "p", "KEYS", "auth"
+            ])),
+            args(&[
+                "migrate", "h", "1", "", "0", "5000", "AUTH2", REDACTED, REDACTED, "KEYS", "auth"
+            ])
+        );
+        assert_eq!(
+            redact(&args(&[
+                "config",
+                "set",
+                "maxmemory",
+                "1mb",
+                "requirepass",
+                "pw"
+            ])),
+            args(&["config", "set", "maxmemory", "1mb", "requirepass", REDACTED])
+        );
+    }
+
+    #[test]
+    fn only_feed_attached_monitors() {
+        let monitors = Monitors::default();
+        assert!(!monitors.is_watched());
+
+        let first = monitors.attach(1);
+        let second = monitors.attach(2);
+        monitors.feed("0", "127.0.0.1:1", &args(&["get", "a"]));
+        monitors.detach(1);
+        monitors.feed("0", "127.0.0.1:1", &args(&["get", "b"]));
+
+        assert_eq!(first.try_iter().count(), 1);
+        assert!(second.try_iter().last().unwrap().ends_with("\"get\" \"b\""));
+
+        // A monitor that's gone away stops being fed
+        drop(second);
+        monitors.feed("0", "127.0.0.1:1", &args(&["get", "c"]));
+        assert!(!monitors.is_watched());
+    }
+}
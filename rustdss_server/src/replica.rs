@@ -0,0 +1,286 @@
+// The replica's side of replication: a thread that follows the master's stream
+use crate::client::encode;
+use crate::clients::{Client, ClientType};
+use crate::connection::Stream;
+use crate::request::command::ParseCommand;
+use crate::server::Server;
+use rustdss_core::replication::LinkState;
+use rustdss_data::{Command, RespData};
+use rustdss_transport::deserialise::DeserialiseRespData;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A running connection to the master, which reconnects until it's stopped
+pub struct Link {
+    stopped: Arc<AtomicBool>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Link {
+    pub fn start(server: Arc<Server>, host: String, port: u16) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stream = Arc::new(Mutex::new(None));
+        let link = Self {
+            stopped: stopped.clone(),
+            stream: stream.clone(),
+        };
+        thread::spawn(move || follow(&server, &host, port, &stopped, &stream));
+        link
+    }
+
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the link up if it's waiting on the master
+        if let Some(stream) = lock(&self.stream).take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Reads the master's stream a byte at a time, keeping hold of the raw bytes so they can go in
+/// this server's own backlog
+struct Recorder<I> {
+    bytes: I,
+    recorded: Vec<u8>,
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Recorder<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let byte = self.bytes.next()?;
+        self.recorded.push(byte);
+        Some(byte as char)
+    }
+}
+
+fn follow(
+    server: &Server,
+    host: &str,
+    port: u16,
+    stopped: &AtomicBool,
+    shared_stream: &Mutex<Option<TcpStream>>,
+) {
+    // Which database the stream is on, kept across reconnects so a partial resync carries on
+    let mut database_id = server.default_database();
+    while !stopped.load(Ordering::SeqCst) {
+        server.replication.set_link_state(LinkState::Connect);
+        if let Ok(stream) = TcpStream::connect((host, port)) {
+            let registered = {
+                let mut shared = lock(shared_stream);
+                let clone = stream.try_clone();
+                match clone {
+                    Ok(clone) if !stopped.load(Ordering::SeqCst) => {
+                        *shared = Some(clone);
+                        true
+                    }
+                    _ => false,
+                }
+            };
+            if !registered {
+                break;
+            }
+            let result = sync_and_follow(server, host, stream, &mut database_id);
+            if let (Err(error), false) = (result, stopped.load(Ordering::SeqCst)) {
+                log::warn!("Lost the link to master {}:{}: {}", host, port, error);
+            }
+            lock(shared_stream).take();
+        }
+        if !stopped.load(Ordering::SeqCst) {
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+}
+
+fn broken(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn sync_and_follow(
+    server: &Server,
+    host: &str,
+    stream: TcpStream,
+    database_id: &mut String,
+) -> io::Result<()> {
+    let (tls_replication, listening_port) = {
+        let config = server.config();
+        let port = if config.tls_replication {
+            config.tls_port
+        } else {
+            server.port
+        };
+        (config.tls_replication, port)
+    };
+    let stream = match (&server.tls, tls_replication) {
+        (Some(tls), true) => Stream::Tls(tls.connect(stream, host)?),
+        _ => Stream::tcp(stream),
+    };
+    // The link shows up in CLIENT LIST, where killing it makes it reconnect. Without a limit it's
+    // never turned away by maxclients.
+    let client = server
+        .clients
+        .register(&stream, ClientType::Master, None, database_id.clone(), None)
+        .map_err(|_| io::Error::other("too many clients"))?;
+    let result = sync(server, stream, listening_port, &client, database_id);
+    server.clients.unregister(client.id);
+    result
+}
+
+fn sync(
+    server: &Server,
+    stream: Stream,
+    listening_port: u16,
+    client: &Client,
+    database_id: &mut String,
+) -> io::Result<()> {
+    let writer = Arc::new(Mutex::new(stream.clone()));
+    let mut input = Recorder {
+        bytes: BufReader::new(stream).bytes().map_while(Result::ok),
+        recorded: vec![],
+    };
+    let mut request = |args: &[&str]| -> io::Result<RespData> {
+        lock(&writer).write_all(&encode(args))?;
+        RespData::from_char_stream(&mut input).ok_or_else(|| broken("master hung up"))
+    };
+
+    server.replication.set_link_state(LinkState::Connecting);
+    let (user, password) = {
+        let config = server.config();
+        (config.masteruser.clone(), config.masterauth.clone())
+    };
+    if !password.is_empty() {
+        let mut auth = vec!["AUTH"];
+        if !user.is_empty() {
+            auth.push(&user);
+        }
+        auth.push(&password);
+        if let RespData::Error(error) = request(&auth)? {
+            return Err(broken(&error));
+        }
+    }
+    if let RespData::Error(error) = request(&["PING"])? {
+        return Err(broken(&error));
+    }
+    request(&["REPLCONF", "listening-port", &listening_port.to_string()])?;
+    request(&["REPLCONF", "capa", "psync2"])?;
+    let (replid, offset) = server.replication.resume_point();
+    let reply = match request(&["PSYNC", &replid, &offset.to_string()])? {
+        RespData::SimpleStr(reply) => reply,
+        RespData::Error(error) => return Err(broken(&error)),
+        _ => return Err(broken("unexpected reply to PSYNC")),
+    };
+    let reply: Vec<&str> = reply.split(' ').collect();
+    match reply.as_slice() {
+        ["FULLRESYNC", replid, offset] => {
+            let offset = offset.parse().map_err(|_| broken("bad offset"))?;
+            log::info!("Full resync from master: {}:{}", replid, offset);
+            server.replication.set_link_state(LinkState::Sync);
+            let snapshot = read_snapshot(&mut input)?;
+            server.replication.flush_everything();
+            let mut commands = snapshot.iter().map(|byte| *byte as char);
+            while let Some(data) = RespData::from_char_stream(&mut commands) {
+                apply(server, database_id, data);
+            }
+            server.replication.reset(replid, offset);
+        }
+        ["CONTINUE", replid] => {
+            log::info!(
+                "Partial resync from master, carrying on from offset {}",
+                offset
+            );
+            server.replication.switch_id(replid)
+        }
+        ["CONTINUE"] => log::info!(
+            "Partial resync from master, carrying on from offset {}",
+            offset
+        ),
+        _ => return Err(broken("unexpected reply to PSYNC")),
+    }
+    server.replication.set_link_state(LinkState::Connected);
+
+    // Lets the master know how far through the stream this is, for WAIT
+    let following = Arc::new(AtomicBool::new(true));
+    {
+        let following = following.clone();
+        let writer = writer.clone();
+        let replication = server.replication.clone();
+        thread::spawn(move || {
+            while following.load(Ordering::SeqCst) {
+                let offset = replication.offset().to_string();
+                if lock(&writer)
+                    .write_all(&encode(&["REPLCONF", "ACK", &offset]))
+                    .is_err()
+                {
+                    break;
+                }
+                thread::sleep(ACK_INTERVAL);
+            }
+        });
+    }
+
+    input.recorded.clear();
+    while let Some(data) = RespData::from_char_stream(&mut input) {
+        let get_ack = apply(server, database_id, data);
+        {
+            let mut state = client.state();
+            state.last_interaction = Instant::now();
+            state.database = database_id.clone();
+        }
+        server.replication.append_raw(&input.recorded);
+        input.recorded.clear();
+        if get_ack {
+            let offset = server.replication.offset().to_string();
+            lock(&writer).write_all(&encode(&["REPLCONF", "ACK", &offset]))?;
+        }
+    }
+    following.store(false, Ordering::SeqCst);
+    Err(broken("master hung up"))
+}
+
+/// The snapshot after FULLRESYNC: `$<length>\r\n` and then that many bytes of commands
+fn read_snapshot<I: Iterator<Item = char>>(input: &mut I) -> io::Result<Vec<u8>> {
+    let header: String = input.by_ref().take_while(|c| *c != '\n').collect();
+    let length: usize = header
+        .trim_end()
+        .strip_prefix('$')
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| broken("bad snapshot header"))?;
+    let snapshot: Vec<u8> = input.take(length).map(|c| c as u8).collect();
+    if snapshot.len() < length {
+        return Err(broken("snapshot cut short"));
+    }
+    Ok(snapshot)
+}
+
+/// Runs a command from the master. True when the master asked for an ack.
+fn apply(server: &Server, database_id: &mut String, data: RespData) -> bool {
+    match Command::from_resp(data) {
+        Ok(Command::Select(new_db)) => *database_id = new_db,
+        Ok(Command::Ping(_)) => {}
+        Ok(Command::Replconf(args)) => {
+            return args
+                .first()
+                .is_some_and(|option| option.eq_ignore_ascii_case("getack"))
+        }
+        Ok(cmd) => {
+            if let RespData::Error(error) = server.send_to_core(database_id, cmd) {
+                log::warn!("A command from the master failed: {}", error);
+            }
+        }
+        Err(reason) => log::warn!("Can't parse a command from the master: {}", reason),
+    }
+    false
+}
@@ -0,0 +1,143 @@
+// AUTH [username] password
+// ACL SETUSER | GETUSER | DELUSER | LIST | USERS | WHOAMI | CAT | LOG | LOAD | SAVE | GENPASS | DRYRUN
+use super::command::ParseCommand;
+use super::Session;
+use crate::acl::{self, Denial};
+use crate::server::Server;
+use rustdss_core::replication::random_id;
+use rustdss_data::{Command, RespData};
+
+fn error(message: String) -> RespData {
+    RespData::Error(format!("ERR {}", message))
+}
+
+fn strings<S: ToString>(strings: impl IntoIterator<Item = S>) -> RespData {
+    RespData::List(
+        strings
+            .into_iter()
+            .map(|string| RespData::BulkStr(string.to_string()))
+            .collect(),
+    )
+}
+
+pub fn auth(session: &mut Session, server: &Server, args: &[String]) -> RespData {
+    let (username, password) = match args {
+        [password] => ("default", password),
+        [username, password] => (username.as_str(), password),
+        _ => return error("syntax error".into()),
+    };
+    if args.len() == 1 && server.acl.initial_user().is_some() {
+        return error("AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".into());
+    }
+    if server.acl.authenticate(username, password) {
+        session.user = Some(username.into());
+        return RespData::ok();
+    }
+    let denial = Denial {
+        reason: "auth",
+        object: "AUTH".into(),
+        message: String::new(),
+    };
+    server.acl.log(
+        &denial,
+        username,
+        "toplevel",
+        &session.client_info(),
+        server.config().acllog_max_len,
+    );
+    RespData::Error("WRONGPASS invalid username-password pair or user is disabled.".into())
+}
+
+/// A random password with this many bits, as hex
+fn generate_password(bits: usize) -> String {
+    let length = bits.div_ceil(4);
+    let mut password = String::new();
+    while password.len() < length {
+        password.push_str(&random_id());
+    }
+    password.truncate(length);
+    password
+}
+
+fn dry_run(server: &Server, username: &str, args: &[String]) -> RespData {
+    if !server.acl.exists(username) {
+        return error(format!("User '{}' not found", username));
+    }
+    let input = RespData::List(args.iter().cloned().map(RespData::BulkStr).collect());
+    match Command::from_resp(input) {
+        Ok(cmd) => match server.acl.check(username, &cmd) {
+            Ok(()) => RespData::ok(),
+            Err(denial) => {
+                RespData::BulkStr(denial.message.trim_start_matches("NOPERM ").to_string())
+            }
+        },
+        Err(reason) => RespData::Error(reason),
+    }
+}
+
+fn acl_file(server: &Server) -> Result<String, RespData> {
+    let file = server.config().aclfile.clone();
+    if file.is_empty() {
+        Err(error("This instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a configuration file set) in order to store users in the configuration.".into()))
+    } else {
+        Ok(file)
+    }
+}
+
+pub fn acl(session: &Session, server: &Server, args: &[String]) -> RespData {
+    let subcommand = args[0].to_lowercase();
+    let outcome = |result: Result<(), String>| match result {
+        Ok(()) => RespData::ok(),
+        Err(message) => error(message),
+    };
+    match (subcommand.as_str(), &args[1..]) {
+        ("setuser", [name, rules @ ..]) => outcome(server.acl.set_user(name, rules)),
+        ("getuser", [name]) => server.acl.get_user(name).unwrap_or(RespData::NullString),
+        ("deluser", names) if !names.is_empty() => match server.acl.delete_users(names) {
+            Ok(deleted) => RespData::Number(deleted as i64),
+            Err(message) => error(message),
+        },
+        ("list", []) => strings(server.acl.list()),
+        ("users", []) => strings(server.acl.usernames()),
+        ("whoami", []) => RespData::BulkStr(session.user.clone().unwrap_or_default()),
+        ("cat", []) => strings(acl::categories()),
+        ("cat", [category]) => match acl::commands_in(&category.to_lowercase()) {
+            Some(commands) => strings(commands),
+            None => error(format!("Unknown category '{}'", category)),
+        },
+        ("log", []) => server.acl.log_entries(10),
+        ("log", [reset]) if reset.eq_ignore_ascii_case("reset") => {
+            server.acl.reset_log();
+            RespData::ok()
+        }
+        ("log", [count]) => match count.parse() {
+            Ok(count) => server.acl.log_entries(count),
+            Err(_) => error("value is out of range, must be positive".into()),
+        },
+        ("load", []) => match acl_file(server) {
+            Ok(file) => outcome(server.acl.load(&file)),
+            Err(error) => error,
+        },
+        ("save", []) => match acl_file(server) {
+            Ok(file) => outcome(server.acl.save(&file)),
+            Err(error) => error,
+        },
+        ("genpass", []) => RespData::BulkStr(generate_password(256)),
+        ("genpass", [bits]) => match bits.parse() {
+            Ok(bits) if (1..=4096).contains(&bits) => RespData::BulkStr(generate_password(bits)),
+            _ => error("ACL GENPASS argument must be the number of bits for the output password, a positive number up to 4096".into()),
+        },
+        ("dryrun", [username, command @ ..]) if !command.is_empty() => {
+            dry_run(server, username, command)
+        }
+        (
+            "setuser" | "getuser" | "deluser" | "list" | "users" | "whoami" | "cat" | "log"
+            | "load" | "save" | "genpass" | "dryrun",
+            _,
+        ) => error(format!(
+            "wrong number of arguments for 'acl|{}' command",
+            subcommand
+        )),
+        _ => error(format!("unknown subcommand '{}'. Try ACL HELP.", args[0])),
+    }
+}
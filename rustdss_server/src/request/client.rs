@@ -0,0 +1,246 @@
+// CLIENT ID | INFO | LIST | SETNAME | GETNAME | KILL | PAUSE | UNPAUSE | NO-EVICT | REPLY
+use super::Session;
+use crate::clients::{Client, ClientType};
+use crate::server::Server;
+use rustdss_data::RespData;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn error(message: &str) -> RespData {
+    RespData::Error(format!("ERR {}", message))
+}
+
+fn wrong_arity(subcommand: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for 'client|{}' command",
+        subcommand
+    ))
+}
+
+fn lines(clients: impl Iterator<Item = Arc<Client>>) -> RespData {
+    RespData::BulkStr(
+        clients
+            .map(|client| format!("{}\n", client.describe()))
+            .collect(),
+    )
+}
+
+fn list(server: &Server, args: &[String]) -> RespData {
+    let clients = server.clients.list();
+    match args {
+        [] => lines(clients.into_iter()),
+        [option, kind] if option.eq_ignore_ascii_case("type") => match ClientType::parse(kind) {
+            Some(kind) => lines(clients.into_iter().filter(|client| client.kind() == kind)),
+            None => error(&format!("Unknown client type '{}'", kind)),
+        },
+        [option, ids @ ..] if option.eq_ignore_ascii_case("id") && !ids.is_empty() => {
+            let ids: Result<Vec<u64>, _> = ids.iter().map(|id| id.parse()).collect();
+            match ids {
+                Ok(ids) => lines(
+                    clients
+                        .into_iter()
+                        .filter(|client| ids.contains(&client.id)),
+                ),
+                Err(_) => error("Invalid client ID"),
+            }
+        }
+        _ => error("syntax error"),
+    }
+}
+
+/// Client names are shown in CLIENT LIST, so they can't have spaces or anything unprintable
+fn set_name(client: &Client, name: &str) -> RespData {
+    if name.chars().any(|c| !c.is_ascii_graphic()) {
+        return error("Client names cannot contain spaces, newlines or special characters.");
+    }
+    client.state().name = name.into();
+    RespData::ok()
+}
+
+/// Which clients CLIENT KILL's filters pick out
+#[derive(Default)]
+struct KillFilter {
+    id: Option<u64>,
+    address: Option<String>,
+    local_address: Option<String>,
+    user: Option<String>,
+    kind: Option<ClientType>,
+    max_age: Option<u64>,
+    skip_me: bool,
+}
+
+impl KillFilter {
+    fn parse(args: &[String]) -> Result<Self, RespData> {
+        let mut filter = Self {
+            skip_me: true,
+            ..Self::default()
+        };
+        if !args.len().is_multiple_of(2) {
+            return Err(error("syntax error"));
+        }
+        for pair in args.chunks(2) {
+            let value = &pair[1];
+            match pair[0].to_lowercase().as_str() {
+                "id" => match value.parse() {
+                    Ok(id) if id > 0 => filter.id = Some(id),
+                    _ => return Err(error("client-id should be greater than 0")),
+                },
+                "addr" => filter.address = Some(value.clone()),
+                "laddr" => filter.local_address = Some(value.clone()),
+                "user" => filter.user = Some(value.clone()),
+                "type" => match ClientType::parse(value) {
+                    Some(kind) => filter.kind = Some(kind),
+                    None => return Err(error(&format!("Unknown client type '{}'", value))),
+                },
+                "maxage" => match value.parse() {
+                    Ok(age) => filter.max_age = Some(age),
+                    Err(_) => {
+                        return Err(error("value is not an integer or out of range"));
+                    }
+                },
+                "skipme" => match value.to_lowercase().as_str() {
+                    "yes" => filter.skip_me = true,
+                    "no" => filter.skip_me = false,
+                    _ => return Err(error("syntax error")),
+                },
+                _ => return Err(error("syntax error")),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, client: &Client, me: u64) -> bool {
+        let state = client.state();
+        self.id.is_none_or(|id| id == client.id)
+            && self
+                .address
+                .as_ref()
+                .is_none_or(|address| *address == client.address)
+            && self
+                .local_address
+                .as_ref()
+                .is_none_or(|address| address == client.local_address())
+            && self.user.as_ref().is_none_or(|user| *user == state.user)
+            && self.kind.is_none_or(|kind| kind == state.kind)
+            && self.max_age.is_none_or(|age| client.age() >= age)
+            && !(self.skip_me && client.id == me)
+    }
+}
+
+/// Closes the matching connections, the current one only once it has its reply
+fn kill(server: &Server, me: u64, filter: &KillFilter) -> usize {
+    let mut killed = 0;
+    for client in server.clients.list() {
+        if !filter.matches(&client, me) {
+            continue;
+        }
+        if client.id == me {
+            client.kill_after_reply();
+        } else {
+            client.kill();
+        }
+        killed += 1;
+    }
+    killed
+}
+
+fn pause(server: &Server, args: &[String]) -> RespData {
+    let writes_only = match args.get(1).map(|mode| mode.to_lowercase()).as_deref() {
+        None | Some("all") => false,
+        Some("write") => true,
+        Some(_) => return error("syntax error"),
+    };
+    match args[0].parse::<i64>() {
+        Ok(timeout) if timeout < 0 => error("timeout is negative"),
+        Ok(timeout) => {
+            server
+                .clients
+                .pause(Duration::from_millis(timeout as u64), writes_only);
+            RespData::ok()
+        }
+        Err(_) => error("timeout is not an integer or out of range"),
+    }
+}
+
+fn on_or_off(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn client(session: &mut Session, server: &Server, args: &[String]) -> RespData {
+    let client = match &session.client {
+        Some(client) => client.clone(),
+        None => return error("no client to run this on"),
+    };
+    let subcommand = args[0].to_lowercase();
+    let rest = &args[1..];
+    match (subcommand.as_str(), rest) {
+        ("id", []) => RespData::Number(client.id as i64),
+        ("info", []) => lines(std::iter::once(client)),
+        ("list", _) => list(server, rest),
+        ("setname", [name]) => set_name(&client, name),
+        ("getname", []) => match client.state().name.as_str() {
+            "" => RespData::nil(),
+            name => RespData::BulkStr(name.into()),
+        },
+        // The old form takes just an address, and has to find it
+        ("kill", [address]) => {
+            let filter = KillFilter {
+                address: Some(address.clone()),
+                ..KillFilter::default()
+            };
+            match kill(server, client.id, &filter) {
+                0 => error("No such client"),
+                _ => RespData::ok(),
+            }
+        }
+        ("kill", filters) if !filters.is_empty() => match KillFilter::parse(filters) {
+            Ok(filter) => RespData::Number(kill(server, client.id, &filter) as i64),
+            Err(error) => error,
+        },
+        ("pause", [_]) | ("pause", [_, _]) => pause(server, rest),
+        ("unpause", []) => {
+            server.clients.unpause();
+            RespData::ok()
+        }
+        ("no-evict", [value]) => match on_or_off(value) {
+            Some(no_evict) => {
+                client.state().no_evict = no_evict;
+                RespData::ok()
+            }
+            None => error("syntax error"),
+        },
+        ("reply", [mode]) => match mode.to_lowercase().as_str() {
+            "on" => {
+                session.reply_off = false;
+                RespData::ok()
+            }
+            // Neither of these are answered
+            "off" => {
+                session.reply_off = true;
+                RespData::ok()
+            }
+            "skip" => {
+                session.skip_replies = 2;
+                RespData::ok()
+            }
+            _ => error("syntax error"),
+        },
+        ("id", _)
+        | ("info", _)
+        | ("setname", _)
+        | ("getname", _)
+        | ("kill", _)
+        | ("pause", _)
+        | ("unpause", _)
+        | ("no-evict", _)
+        | ("reply", _) => wrong_arity(&subcommand),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+            args[0]
+        )),
+    }
+}
@@ -1,4 +1,5 @@
 // Parses commands -- Maybe move this into rustdss_data or transport?
+use crate::command_table;
 use rustdss_data::{Command, RespData};
 
 pub trait ParseCommand {
@@ -8,10 +9,29 @@ fn string_arg(data: Option<RespData>) -> Option<String> {
     data.and_then(|inner_data| match inner_data {
         RespData::BulkStr(string) => Some(string),
         RespData::SimpleStr(string) => Some(string),
+        RespData::Number(number) => Some(number.to_string()),
         _ => None,
     })
 }
 
+fn string_args<A: Iterator<Item = RespData>>(data: A) -> Vec<String> {
+    data.filter_map(|item| string_arg(Some(item))).collect()
+}
+
+/// A key followed by at least `min_args` more string arguments
+fn key_with_args<A: Iterator<Item = RespData>>(
+    mut data: A,
+    min_args: usize,
+) -> Option<(String, Vec<String>)> {
+    let key = string_arg(data.next())?;
+    let args = string_args(data);
+    if args.len() >= min_args {
+        Some((key, args))
+    } else {
+        None
+    }
+}
+
 fn numerical_arg(data: Option<RespData>) -> Option<i64> {
     data.clone() // ew gross
         .and_then(|val| match val {
@@ -20,105 +40,150 @@ fn numerical_arg(data: Option<RespData>) -> Option<i64> {
             _ => None,
         })
         .or_else(|| {
-            let number: Option<i64> = string_arg(data).map(|s| s.parse().ok()).flatten();
+            let number: Option<i64> = string_arg(data).and_then(|s| s.parse().ok());
             number
         })
 }
 
+fn not_an_integer() -> String {
+    "ERR value is not an integer or out of range".into()
+}
+
+fn syntax_error() -> String {
+    "ERR syntax error".into()
+}
+
+/// Like Redis, the arguments shown are cut off after 128 characters, and line breaks in them are
+/// turned into spaces so they can't break the reply
+fn unknown_command(name: &str, args: &[String]) -> String {
+    const SHOWN: usize = 128;
+    let mut shown = String::new();
+    for arg in args {
+        if shown.len() >= SHOWN {
+            break;
+        }
+        let arg: String = arg.chars().take(SHOWN - shown.len()).collect();
+        shown.push_str(&format!("'{}' ", arg));
+    }
+    let name: String = name.chars().take(SHOWN).collect();
+    format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        name, shown
+    )
+    .replace(['\r', '\n'], " ")
+}
+
 impl ParseCommand for Command {
     fn from_resp(input: RespData) -> Result<Self, String> {
         if let RespData::List(data) = input {
+            let count = data.len();
             let mut data = data.into_iter();
-            if let Some(RespData::BulkStr(cmd_string)) = data.next() {
+            if let Some(cmd_string) = string_arg(data.next()) {
+                let subcommand = string_arg(data.clone().next());
+                command_table::check_arity(&cmd_string, subcommand.as_deref(), count)?;
+                let wrong_arity = || {
+                    format!(
+                        "ERR wrong number of arguments for '{}' command",
+                        cmd_string.to_lowercase()
+                    )
+                };
                 match cmd_string.to_lowercase().as_str() {
-                    "ping" => Ok(Command::Ping),
+                    "ping" if count > 2 => Err(wrong_arity()),
+                    "ping" => Ok(Command::Ping(data.next())),
                     "echo" => {
                         if let Some(arg0) = data.next() {
                             Ok(Command::Echo(arg0.clone()))
                         } else {
-                            Err("too few args".into())
+                            Err(wrong_arity())
                         }
                     }
                     "get" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Get(arg0))
                         } else {
-                            Err("too few args".into())
+                            Err(wrong_arity())
                         }
                     }
                     "set" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             if let Some(arg1) = data.next() {
-                                Ok(Command::Set(arg0, arg1.clone()))
+                                Ok(Command::Set(arg0, arg1.clone(), string_args(data)))
                             } else {
-                                Err("Not enough args".into())
+                                Err(wrong_arity())
                             }
                         } else {
-                            Err("nope".into())
+                            Err(wrong_arity())
                         }
                     }
-                    "flushall" => Ok(Command::FlushAll),
+                    "flushall" => match string_args(data).as_slice() {
+                        [] => Ok(Command::FlushAll),
+                        [mode]
+                            if mode.eq_ignore_ascii_case("async")
+                                || mode.eq_ignore_ascii_case("sync") =>
+                        {
+                            Ok(Command::FlushAll)
+                        }
+                        _ => Err(syntax_error()),
+                    },
                     "decr" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Decr(arg0, None))
                         } else {
-                            Err("Not enough args".into())
-                        }
-                    }
-                    "decrby" => {
-                        if let Some(arg0) = string_arg(data.next()) {
-                            Ok(Command::Decr(arg0, numerical_arg(data.next())))
-                        } else {
-                            Err("Not enough args".into())
+                            Err(wrong_arity())
                         }
                     }
+                    "decrby" => match (string_arg(data.next()), numerical_arg(data.next())) {
+                        (Some(key), Some(by)) => Ok(Command::Decr(key, Some(by))),
+                        (Some(_), None) => Err(not_an_integer()),
+                        _ => Err(wrong_arity()),
+                    },
                     "incr" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Incr(arg0, None))
                         } else {
-                            Err("Not enough args".into())
-                        }
-                    }
-                    "incrby" => {
-                        if let Some(arg0) = string_arg(data.next()) {
-                            Ok(Command::Incr(arg0, numerical_arg(data.next())))
-                        } else {
-                            Err("Not enough args".into())
-                        }
-                    }
-                    "lpush" => {
-                        if let (Some(arg0), Some(arg1)) = (string_arg(data.next()), data.next()) {
-                            Ok(Command::Lpush(arg0, arg1))
-                        } else {
-                            Err("Not enough args".into())
+                            Err(wrong_arity())
                         }
                     }
-                    "rpush" => {
-                        if let (Some(arg0), Some(arg1)) = (string_arg(data.next()), data.next()) {
-                            Ok(Command::Rpush(arg0, arg1))
+                    "incrby" => match (string_arg(data.next()), numerical_arg(data.next())) {
+                        (Some(key), Some(by)) => Ok(Command::Incr(key, Some(by))),
+                        (Some(_), None) => Err(not_an_integer()),
+                        _ => Err(wrong_arity()),
+                    },
+                    "lpush" | "rpush" => {
+                        let key = string_arg(data.next()).ok_or_else(wrong_arity)?;
+                        let values: Vec<RespData> = data.collect();
+                        if values.is_empty() {
+                            Err(wrong_arity())
+                        } else if cmd_string.eq_ignore_ascii_case("lpush") {
+                            Ok(Command::Lpush(key, values))
                         } else {
-                            Err("Not enough args".into())
+                            Ok(Command::Rpush(key, values))
                         }
                     }
-                    "lpop" => {
-                        if let Some(arg0) = string_arg(data.next()) {
-                            Ok(Command::Lpop(arg0))
+                    "lpop" | "rpop" => {
+                        let key = string_arg(data.next()).ok_or_else(wrong_arity)?;
+                        let count = match data.next() {
+                            Some(count) => match numerical_arg(Some(count)) {
+                                Some(count) if count >= 0 => Some(count),
+                                _ => {
+                                    return Err("ERR value is out of range, must be positive".into())
+                                }
+                            },
+                            None => None,
+                        };
+                        if data.next().is_some() {
+                            Err(wrong_arity())
+                        } else if cmd_string.eq_ignore_ascii_case("lpop") {
+                            Ok(Command::Lpop(key, count))
                         } else {
-                            Err("Not enough args".into())
-                        }
-                    }
-                    "rpop" => {
-                        if let Some(arg0) = string_arg(data.next()) {
-                            Ok(Command::Rpop(arg0))
-                        } else {
-                            Err("Not enough args".into())
+                            Ok(Command::Rpop(key, count))
                         }
                     }
                     "llen" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Llen(arg0))
                         } else {
-                            Err("Not enough args".into())
+                            Err(wrong_arity())
                         }
                     }
                     "lrange" => {
@@ -128,36 +193,439 @@ impl ParseCommand for Command {
                             {
                                 Ok(Command::Lrange(arg0, arg1, arg2))
                             } else {
-                                Err("Not enough args".into())
+                                Err(not_an_integer())
                             }
                         } else {
-                            Err("Not enough args".into())
+                            Err(wrong_arity())
                         }
                     }
                     "dump" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Dump(arg0))
                         } else {
-                            Err("Not enough args".into())
+                            Err(wrong_arity())
                         }
                     }
+                    "pfadd" => {
+                        if let Some(arg0) = string_arg(data.next()) {
+                            Ok(Command::Pfadd(arg0, string_args(data)))
+                        } else {
+                            Err(wrong_arity())
+                        }
+                    }
+                    "pfcount" => {
+                        let keys = string_args(data);
+                        if keys.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Pfcount(keys))
+                        }
+                    }
+                    "pfmerge" => {
+                        if let Some(arg0) = string_arg(data.next()) {
+                            Ok(Command::Pfmerge(arg0, string_args(data)))
+                        } else {
+                            Err(wrong_arity())
+                        }
+                    }
+                    "xadd" => key_with_args(data, 3)
+                        .map(|(key, args)| Command::Xadd(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xlen" => {
+                        if let Some(arg0) = string_arg(data.next()) {
+                            Ok(Command::Xlen(arg0))
+                        } else {
+                            Err(wrong_arity())
+                        }
+                    }
+                    "xrange" => key_with_args(data, 2)
+                        .map(|(key, args)| Command::Xrange(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xrevrange" => key_with_args(data, 2)
+                        .map(|(key, args)| Command::Xrevrange(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xdel" => key_with_args(data, 1)
+                        .map(|(key, ids)| Command::Xdel(key, ids))
+                        .ok_or_else(wrong_arity),
+                    "xtrim" => key_with_args(data, 2)
+                        .map(|(key, args)| Command::Xtrim(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xread" => Ok(Command::Xread(string_args(data))),
+                    "xreadgroup" => Ok(Command::Xreadgroup(string_args(data))),
+                    "xgroup" => Ok(Command::Xgroup(string_args(data))),
+                    "xack" => key_with_args(data, 2)
+                        .map(|(key, mut args)| {
+                            let group = args.remove(0);
+                            Command::Xack(key, group, args)
+                        })
+                        .ok_or_else(wrong_arity),
+                    "xpending" => key_with_args(data, 1)
+                        .map(|(key, mut args)| {
+                            let group = args.remove(0);
+                            Command::Xpending(key, group, args)
+                        })
+                        .ok_or_else(wrong_arity),
+                    "xclaim" => key_with_args(data, 4)
+                        .map(|(key, args)| Command::Xclaim(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xautoclaim" => key_with_args(data, 4)
+                        .map(|(key, args)| Command::Xautoclaim(key, args))
+                        .ok_or_else(wrong_arity),
+                    "xinfo" => Ok(Command::Xinfo(string_args(data))),
+                    "geoadd" => key_with_args(data, 3)
+                        .map(|(key, args)| Command::Geoadd(key, args))
+                        .ok_or_else(wrong_arity),
+                    "geopos" => key_with_args(data, 0)
+                        .map(|(key, members)| Command::Geopos(key, members))
+                        .ok_or_else(wrong_arity),
+                    "geodist" => key_with_args(data, 2)
+                        .map(|(key, args)| Command::Geodist(key, args))
+                        .ok_or_else(wrong_arity),
+                    "geohash" => key_with_args(data, 0)
+                        .map(|(key, members)| Command::Geohash(key, members))
+                        .ok_or_else(wrong_arity),
+                    "geosearch" => key_with_args(data, 4)
+                        .map(|(key, args)| Command::Geosearch(key, args))
+                        .ok_or_else(wrong_arity),
+                    "geosearchstore" => key_with_args(data, 5)
+                        .map(|(dest, mut args)| {
+                            let source = args.remove(0);
+                            Command::Geosearchstore(dest, source, args)
+                        })
+                        .ok_or_else(wrong_arity),
+                    "exists" => {
+                        let keys = string_args(data);
+                        if keys.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Exists(keys))
+                        }
+                    }
+                    "restore" => key_with_args(data, 2)
+                        .map(|(key, args)| Command::Restore(key, args))
+                        .ok_or_else(wrong_arity),
+                    "cluster" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Cluster(args))
+                        }
+                    }
+                    "asking" => Ok(Command::Asking),
+                    "auth" => {
+                        let args = string_args(data);
+                        match args.len() {
+                            0 => Err(wrong_arity()),
+                            1 | 2 => Ok(Command::Auth(args)),
+                            _ => Err(syntax_error()),
+                        }
+                    }
+                    "acl" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Acl(args))
+                        }
+                    }
+                    "config" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Config(args))
+                        }
+                    }
+                    "client" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Client(args))
+                        }
+                    }
+                    "slowlog" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Slowlog(args))
+                        }
+                    }
+                    "shutdown" => Ok(Command::Shutdown(string_args(data))),
+                    "latency" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Latency(args))
+                        }
+                    }
+                    "migrate" => {
+                        let args = string_args(data);
+                        if args.len() < 5 {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Migrate(args))
+                        }
+                    }
+                    "del" => {
+                        let keys = string_args(data);
+                        if keys.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Del(keys))
+                        }
+                    }
+                    "expire" => key_with_args(data, 1)
+                        .map(|(key, mut args)| Command::Expire(key, args.remove(0), args))
+                        .ok_or_else(wrong_arity),
+                    "pexpire" => key_with_args(data, 1)
+                        .map(|(key, mut args)| Command::Pexpire(key, args.remove(0), args))
+                        .ok_or_else(wrong_arity),
+                    "expireat" => key_with_args(data, 1)
+                        .map(|(key, mut args)| Command::Expireat(key, args.remove(0), args))
+                        .ok_or_else(wrong_arity),
+                    "pexpireat" => key_with_args(data, 1)
+                        .map(|(key, mut args)| Command::Pexpireat(key, args.remove(0), args))
+                        .ok_or_else(wrong_arity),
+                    "ttl" => string_arg(data.next())
+                        .map(Command::Ttl)
+                        .ok_or_else(wrong_arity),
+                    "pttl" => string_arg(data.next())
+                        .map(Command::Pttl)
+                        .ok_or_else(wrong_arity),
+                    "persist" => string_arg(data.next())
+                        .map(Command::Persist)
+                        .ok_or_else(wrong_arity),
+                    "object" => match key_with_args(data, 1) {
+                        Some((subcommand, mut args)) if args.len() == 1 => {
+                            Ok(Command::Object(subcommand, args.remove(0)))
+                        }
+                        // Known subcommands have had their arity checked
+                        _ => Err(format!(
+                            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+                            subcommand.unwrap_or_default()
+                        )),
+                    },
+                    "mget" => {
+                        let keys = string_args(data);
+                        if keys.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Mget(keys))
+                        }
+                    }
+                    "rename" => key_with_args(data, 1)
+                        .map(|(source, mut args)| Command::Rename(source, args.remove(0)))
+                        .ok_or_else(wrong_arity),
+                    "sadd" => key_with_args(data, 1)
+                        .map(|(key, members)| Command::Sadd(key, members))
+                        .ok_or_else(wrong_arity),
+                    "srem" => key_with_args(data, 1)
+                        .map(|(key, members)| Command::Srem(key, members))
+                        .ok_or_else(wrong_arity),
+                    "smembers" => string_arg(data.next())
+                        .map(Command::Smembers)
+                        .ok_or_else(wrong_arity),
+                    "sismember" => key_with_args(data, 1)
+                        .map(|(key, mut args)| Command::Sismember(key, args.remove(0)))
+                        .ok_or_else(wrong_arity),
+                    "scard" => string_arg(data.next())
+                        .map(Command::Scard)
+                        .ok_or_else(wrong_arity),
+                    "sinter" => key_with_args(data, 0)
+                        .map(|(key, mut keys)| {
+                            keys.insert(0, key);
+                            Command::Sinter(keys)
+                        })
+                        .ok_or_else(wrong_arity),
+                    "sinterstore" => key_with_args(data, 1)
+                        .map(|(dest, keys)| Command::Sinterstore(dest, keys))
+                        .ok_or_else(wrong_arity),
+                    "monitor" => Ok(Command::Monitor),
+                    "multi" => Ok(Command::Multi),
+                    // The queued commands are filled in by the connection's session
+                    "exec" => Ok(Command::Exec(vec![])),
+                    "discard" => Ok(Command::Discard),
 
-                    "keys" => Ok(Command::Keys),
-                    "info" => Ok(Command::Info),
+                    "replicaof" | "slaveof" => key_with_args(data, 1)
+                        .map(|(host, mut args)| Command::Replicaof(host, args.remove(0)))
+                        .ok_or_else(wrong_arity),
+                    "psync" => key_with_args(data, 1)
+                        .map(|(replid, mut args)| Command::Psync(replid, args.remove(0)))
+                        .ok_or_else(wrong_arity),
+                    "replconf" => Ok(Command::Replconf(string_args(data))),
+                    "wait" => key_with_args(data, 1)
+                        .map(|(replicas, mut args)| Command::Wait(replicas, args.remove(0)))
+                        .ok_or_else(wrong_arity),
+                    "role" => Ok(Command::Role),
+                    "keys" => string_arg(data.next())
+                        .map(Command::Keys)
+                        .ok_or_else(wrong_arity),
+                    "scan" => {
+                        let args = string_args(data);
+                        if args.is_empty() {
+                            Err(wrong_arity())
+                        } else {
+                            Ok(Command::Scan(args))
+                        }
+                    }
+                    "info" => Ok(Command::Info(string_args(data))),
+                    "command" => Ok(Command::Command(string_args(data))),
                     "select" => {
                         if let Some(arg0) = string_arg(data.next()) {
                             Ok(Command::Select(arg0))
                         } else {
-                            Err("too few args".into())
+                            Err(wrong_arity())
                         }
                     }
-                    _ => Err("unknown command".into()),
+                    _ => Err(unknown_command(&cmd_string, &string_args(data))),
                 }
             } else {
-                Err("invalid command format".into())
+                Err("ERR Protocol error: expected a command name".into())
             }
         } else {
-            Err("invalid command format".into())
+            Err("ERR Protocol error: expected an array of arguments".into())
         }
     }
 }
+
+#[cfg(test)]
+mod command_should {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::from_resp(RespData::List(
+            args.iter()
+                .map(|arg| RespData::BulkStr(arg.to_string()))
+                .collect(),
+        ))
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn refuse_the_wrong_number_of_arguments() {
+        assert_eq!(
+            error(&["GET", "a", "b"]),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            error(&["set", "a"]),
+            "ERR wrong number of arguments for 'set' command"
+        );
+        assert_eq!(
+            error(&["ping", "a", "b"]),
+            "ERR wrong number of arguments for 'ping' command"
+        );
+        assert_eq!(
+            error(&["object", "freq"]),
+            "ERR wrong number of arguments for 'object|freq' command"
+        );
+    }
+
+    #[test]
+    fn say_which_command_it_does_not_know() {
+        assert_eq!(
+            error(&["nonsense", "a", "b"]),
+            "ERR unknown command 'nonsense', with args beginning with: 'a' 'b' "
+        );
+        assert_eq!(
+            error(&["nonsense"]),
+            "ERR unknown command 'nonsense', with args beginning with: "
+        );
+        let long = "x".repeat(200);
+        let unknown = error(&["nonsense", &long, "line\r\nbreak"]);
+        assert!(unknown.ends_with(&format!("'{}' ", "x".repeat(128))));
+        assert!(!unknown.contains('\n'));
+    }
+
+    #[test]
+    fn refuse_bad_values() {
+        assert_eq!(
+            error(&["incrby", "a", "one"]),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            error(&["lrange", "a", "0", "end"]),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(error(&["flushall", "later"]), "ERR syntax error");
+        assert!(matches!(
+            parse(&["flushall", "ASYNC"]),
+            Ok(Command::FlushAll)
+        ));
+        assert_eq!(
+            error(&["object", "help"]),
+            "ERR unknown subcommand 'help'. Try OBJECT HELP."
+        );
+    }
+
+    #[test]
+    fn keep_the_message_given_to_ping() {
+        assert!(matches!(parse(&["PING"]), Ok(Command::Ping(None))));
+        assert!(matches!(
+            parse(&["PING", "hello"]),
+            Ok(Command::Ping(Some(RespData::BulkStr(message)))) if message == "hello"
+        ));
+        assert!(matches!(
+            parse(&["SET", "k", "v", "EX", "10"]),
+            Ok(Command::Set(_, _, options)) if options == ["EX", "10"]
+        ));
+    }
+
+    #[test]
+    fn take_the_variadic_forms() {
+        assert!(matches!(
+            parse(&["LPUSH", "l", "a", "b", "c"]),
+            Ok(Command::Lpush(key, values)) if key == "l" && values.len() == 3
+        ));
+        assert!(matches!(
+            parse(&["RPUSH", "l", "a", "b"]),
+            Ok(Command::Rpush(_, values)) if values.len() == 2
+        ));
+        assert!(matches!(
+            parse(&["LPOP", "l", "2"]),
+            Ok(Command::Lpop(_, Some(2)))
+        ));
+        assert!(matches!(parse(&["RPOP", "l"]), Ok(Command::Rpop(_, None))));
+        assert_eq!(
+            error(&["lpop", "l", "-1"]),
+            "ERR value is out of range, must be positive"
+        );
+        assert_eq!(
+            error(&["lpop", "l", "1", "2"]),
+            "ERR wrong number of arguments for 'lpop' command"
+        );
+        assert!(matches!(
+            parse(&["EXPIRE", "k", "10", "NX"]),
+            Ok(Command::Expire(_, ttl, options)) if ttl == "10" && options == ["NX"]
+        ));
+    }
+
+    #[test]
+    fn take_names_and_arguments_that_are_not_bulk_strings() {
+        let input = RespData::List(
+            vec![
+                RespData::SimpleStr("INCRBY".into()),
+                RespData::BulkStr("a".into()),
+                RespData::Number(5),
+            ]
+            .into(),
+        );
+        assert!(matches!(
+            Command::from_resp(input),
+            Ok(Command::Incr(key, Some(5))) if key == "a"
+        ));
+        assert!(Command::from_resp(RespData::List(vec![].into()))
+            .unwrap_err()
+            .starts_with("ERR Protocol error"));
+        assert!(Command::from_resp(RespData::Number(1))
+            .unwrap_err()
+            .starts_with("ERR Protocol error"));
+    }
+}
@@ -0,0 +1,370 @@
+// COMMAND | COUNT | INFO [command ...] | DOCS [command ...] | GETKEYS command [arg ...] |
+// LIST [FILTERBY MODULE name | ACLCAT category | PATTERN pattern]
+use crate::command_table::{self, BeginSearch, CommandSpec, FindKeys, KeySpec, COMMANDS};
+use crate::request::command::ParseCommand;
+use rustdss_core::keyspace::glob_match;
+use rustdss_data::{Command, RespData};
+
+fn bulk(value: impl Into<String>) -> RespData {
+    RespData::BulkStr(value.into())
+}
+
+fn list(items: Vec<RespData>) -> RespData {
+    RespData::List(items.into())
+}
+
+fn wrong_arity(subcommand: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for 'command|{}' command",
+        subcommand
+    ))
+}
+
+/// A command's full name, `parent|name` for a subcommand
+fn full_name(parent: Option<&CommandSpec>, spec: &CommandSpec) -> String {
+    match parent {
+        Some(parent) => format!("{}|{}", parent.name, spec.name),
+        None => spec.name.to_string(),
+    }
+}
+
+fn key_spec(spec: &KeySpec) -> RespData {
+    let begin_search = match spec.begin_search {
+        BeginSearch::Index(index) => vec![
+            bulk("type"),
+            bulk("index"),
+            bulk("spec"),
+            list(vec![bulk("index"), RespData::Number(index)]),
+        ],
+        BeginSearch::Keyword { keyword, startfrom } => vec![
+            bulk("type"),
+            bulk("keyword"),
+            bulk("spec"),
+            list(vec![
+                bulk("keyword"),
+                bulk(keyword),
+                bulk("startfrom"),
+                RespData::Number(startfrom),
+            ]),
+        ],
+    };
+    let FindKeys::Range {
+        lastkey,
+        keystep,
+        limit,
+    } = spec.find_keys;
+    list(vec![
+        bulk("flags"),
+        list(spec.flags.iter().map(|flag| bulk(*flag)).collect()),
+        bulk("begin_search"),
+        list(begin_search),
+        bulk("find_keys"),
+        list(vec![
+            bulk("type"),
+            bulk("range"),
+            bulk("spec"),
+            list(vec![
+                bulk("lastkey"),
+                RespData::Number(lastkey),
+                bulk("keystep"),
+                RespData::Number(keystep),
+                bulk("limit"),
+                RespData::Number(limit),
+            ]),
+        ]),
+    ])
+}
+
+/// What COMMAND INFO gives for a command: name, arity, flags, first key, last key, step, ACL
+/// categories, tips, key specs and subcommands
+fn info(parent: Option<&CommandSpec>, spec: &CommandSpec) -> RespData {
+    let (first, last, step) = spec.legacy_key_range();
+    list(vec![
+        bulk(full_name(parent, spec)),
+        RespData::Number(spec.arity),
+        list(
+            spec.all_flags()
+                .into_iter()
+                .map(|flag| RespData::SimpleStr(flag.into()))
+                .collect(),
+        ),
+        RespData::Number(first),
+        RespData::Number(last),
+        RespData::Number(step),
+        list(
+            spec.categories
+                .iter()
+                .map(|category| RespData::SimpleStr(format!("@{}", category)))
+                .collect(),
+        ),
+        list(vec![]),
+        list(spec.key_specs.iter().map(key_spec).collect()),
+        list(
+            spec.subcommands
+                .iter()
+                .map(|sub| info(Some(spec), sub))
+                .collect(),
+        ),
+    ])
+}
+
+/// What COMMAND DOCS gives for a command, without its name
+fn docs(spec: &CommandSpec) -> RespData {
+    let mut doc = vec![
+        bulk("summary"),
+        bulk(spec.summary),
+        bulk("since"),
+        bulk(spec.since),
+        bulk("group"),
+        bulk(spec.group),
+        bulk("complexity"),
+        bulk(spec.complexity),
+    ];
+    if !spec.subcommands.is_empty() {
+        doc.push(bulk("subcommands"));
+        let mut subcommands = vec![];
+        for sub in spec.subcommands {
+            subcommands.push(bulk(full_name(Some(spec), sub)));
+            subcommands.push(docs(sub));
+        }
+        doc.push(list(subcommands));
+    }
+    list(doc)
+}
+
+fn all_info() -> RespData {
+    list(COMMANDS.iter().map(|spec| info(None, spec)).collect())
+}
+
+/// Each named command's info, nil for the ones that don't exist
+fn info_of(names: &[String]) -> RespData {
+    list(
+        names
+            .iter()
+            .map(|name| match lookup(name) {
+                Some((parent, spec)) => info(parent, spec),
+                None => RespData::nil(),
+            })
+            .collect(),
+    )
+}
+
+/// Names and docs, for every command or just the named ones that exist
+fn docs_of(names: &[String]) -> RespData {
+    let mut reply = vec![];
+    if names.is_empty() {
+        for spec in COMMANDS {
+            reply.push(bulk(spec.name));
+            reply.push(docs(spec));
+        }
+    }
+    for name in names {
+        if let Some((parent, spec)) = lookup(name) {
+            reply.push(bulk(full_name(parent, spec)));
+            reply.push(docs(spec));
+        }
+    }
+    list(reply)
+}
+
+/// A command or `command|subcommand`, with the command a subcommand belongs to
+fn lookup(name: &str) -> Option<(Option<&'static CommandSpec>, &'static CommandSpec)> {
+    let spec = command_table::lookup(name)?;
+    let parent = name
+        .split_once('|')
+        .and_then(|(parent, _)| command_table::lookup(parent));
+    Some((parent, spec))
+}
+
+/// The keys a command would use if it were run with these arguments
+fn getkeys(args: &[String]) -> RespData {
+    let spec = match command_table::lookup(&args[0]) {
+        Some(spec) => spec,
+        None => return RespData::Error("ERR Invalid command specified".into()),
+    };
+    let subcommand = args.get(1).and_then(|sub| spec.subcommand(sub));
+    if !spec.accepts(args.len()) || subcommand.is_some_and(|sub| !sub.accepts(args.len())) {
+        return RespData::Error("ERR Invalid number of arguments specified for command".into());
+    }
+    let input = RespData::List(args.iter().cloned().map(RespData::BulkStr).collect());
+    match Command::from_resp(input) {
+        Ok(cmd) => {
+            let keys = cmd.key_args();
+            if keys.is_empty() {
+                RespData::Error("ERR The command has no key arguments".into())
+            } else {
+                list(keys.into_iter().map(|key| bulk(key.clone())).collect())
+            }
+        }
+        Err(error) => RespData::Error(error),
+    }
+}
+
+/// Every command's name, and every subcommand's as `command|subcommand`
+fn names() -> Vec<(Option<&'static CommandSpec>, &'static CommandSpec)> {
+    let mut names = vec![];
+    for spec in COMMANDS {
+        names.push((None, spec));
+        names.extend(spec.subcommands.iter().map(|sub| (Some(spec), sub)));
+    }
+    names
+}
+
+fn list_names(filter: &[String]) -> RespData {
+    let all = names().into_iter();
+    let named = |commands: Vec<(Option<&CommandSpec>, &CommandSpec)>| {
+        list(
+            commands
+                .into_iter()
+                .map(|(parent, spec)| bulk(full_name(parent, spec)))
+                .collect(),
+        )
+    };
+    match filter {
+        [] => named(all.collect()),
+        [filterby, kind, value] if filterby.eq_ignore_ascii_case("filterby") => {
+            match kind.to_lowercase().as_str() {
+                // There are no modules
+                "module" => list(vec![]),
+                "aclcat" => {
+                    let category = value.to_lowercase();
+                    named(
+                        all.filter(|(_, spec)| spec.categories.contains(&category.as_str()))
+                            .collect(),
+                    )
+                }
+                "pattern" => {
+                    let pattern: Vec<char> = value.to_lowercase().chars().collect();
+                    named(
+                        all.filter(|(parent, spec)| {
+                            let name: Vec<char> = full_name(*parent, spec).chars().collect();
+                            glob_match(&pattern, &name)
+                        })
+                        .collect(),
+                    )
+                }
+                _ => RespData::Error("ERR syntax error".into()),
+            }
+        }
+        _ => RespData::Error("ERR syntax error".into()),
+    }
+}
+
+pub fn command(args: &[String]) -> RespData {
+    let subcommand = match args.first() {
+        Some(subcommand) => subcommand.to_lowercase(),
+        None => return all_info(),
+    };
+    let rest = &args[1..];
+    match (subcommand.as_str(), rest) {
+        ("count", []) => RespData::Number(COMMANDS.len() as i64),
+        ("info", []) => all_info(),
+        ("info", names) => info_of(names),
+        ("docs", names) => docs_of(names),
+        ("getkeys", []) => wrong_arity(&subcommand),
+        ("getkeys", command) => getkeys(command),
+        ("list", filter) => list_names(filter),
+        ("count", _) => wrong_arity(&subcommand),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            args[0]
+        )),
+    }
+}
+
+#[cfg(test)]
+mod command_info_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    /// Enough arguments for a command, and its subcommand if it has one, to get past the arity
+    /// check
+    fn enough_args(parent: Option<&CommandSpec>, spec: &CommandSpec) -> Vec<String> {
+        let mut args: Vec<String> = match parent {
+            Some(parent) => vec![parent.name.into(), spec.name.into()],
+            None => vec![spec.name.into()],
+        };
+        while !spec.accepts(args.len()) {
+            args.push("1".into());
+        }
+        args
+    }
+
+    #[test]
+    fn know_every_command_it_describes() {
+        for (parent, spec) in names() {
+            if parent.is_none() && !spec.subcommands.is_empty() {
+                continue;
+            }
+            let input = RespData::List(enough_args(parent, spec).into_iter().map(bulk).collect());
+            if let Err(error) = Command::from_resp(input) {
+                assert!(
+                    !error.contains("unknown command") && !error.contains("wrong number"),
+                    "{}: {}",
+                    full_name(parent, spec),
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn describe_commands() {
+        let get = match info_of(&args(&["GET", "nonsense"])) {
+            RespData::List(replies) => replies,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(get[1], RespData::nil());
+        match &get[0] {
+            RespData::List(info) => {
+                assert_eq!(info[0], bulk("get"));
+                assert_eq!(info[1], RespData::Number(2));
+                assert_eq!(
+                    &info.range(3..6).cloned().collect::<Vec<_>>(),
+                    &[
+                        RespData::Number(1),
+                        RespData::Number(1),
+                        RespData::Number(1)
+                    ]
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            command(&args(&["count"])),
+            RespData::Number(COMMANDS.len() as i64)
+        );
+    }
+
+    #[test]
+    fn find_the_keys_in_a_command() {
+        assert_eq!(
+            command(&args(&["getkeys", "sinterstore", "dest", "a", "b"])),
+            list(vec![bulk("dest"), bulk("a"), bulk("b")])
+        );
+        assert_eq!(
+            command(&args(&["getkeys", "ping"])),
+            RespData::Error("ERR The command has no key arguments".into())
+        );
+        assert_eq!(
+            command(&args(&["getkeys", "get"])),
+            RespData::Error("ERR Invalid number of arguments specified for command".into())
+        );
+        assert_eq!(
+            command(&args(&["getkeys", "nonsense", "a"])),
+            RespData::Error("ERR Invalid command specified".into())
+        );
+    }
+
+    #[test]
+    fn filter_the_list_of_names() {
+        let listed = |filter: &[&str]| match command(&args(filter)) {
+            RespData::List(names) => names.len(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(listed(&["list", "filterby", "pattern", "xgroup|*"]), 5);
+        assert_eq!(listed(&["list", "filterby", "module", "nonsense"]), 0);
+        assert!(listed(&["list", "filterby", "aclcat", "geo"]) >= 6);
+        assert!(listed(&["list"]) > COMMANDS.len());
+    }
+}
@@ -0,0 +1,85 @@
+// CONFIG GET pattern [pattern ...] | SET parameter value [parameter value ...] | REWRITE | RESETSTAT
+use crate::server::Server;
+use rustdss_data::RespData;
+
+fn wrong_arity(subcommand: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for 'config|{}' command",
+        subcommand
+    ))
+}
+
+fn get(server: &Server, patterns: &[String]) -> RespData {
+    let config = server.config();
+    let mut found: Vec<(&str, String)> = vec![];
+    for pattern in patterns {
+        for (name, value) in config.get(pattern) {
+            if !found.iter().any(|(seen, _)| *seen == name) {
+                found.push((name, value));
+            }
+        }
+    }
+    RespData::List(
+        found
+            .into_iter()
+            .flat_map(|(name, value)| {
+                vec![RespData::BulkStr(name.into()), RespData::BulkStr(value)]
+            })
+            .collect(),
+    )
+}
+
+fn set(server: &Server, args: &[String]) -> RespData {
+    let changes: Vec<(String, String)> = args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    let mut config = server.config();
+    let requirepass = config.requirepass.clone();
+    if let Err(message) = config.set_at_runtime(&changes) {
+        return RespData::Error(format!("ERR {}", message));
+    }
+    if config.requirepass != requirepass {
+        server.acl.set_default_password(&config.requirepass);
+    }
+    if let Some(cluster) = &server.cluster {
+        cluster.set_auth(&config.masteruser, &config.masterauth);
+    }
+    // The database threads read these on every write, and the slow log settings on every command
+    server.memory.set_maxmemory(config.maxmemory);
+    server.memory.set_policy(config.maxmemory_policy);
+    server
+        .slowlog
+        .set_slower_than(config.slowlog_log_slower_than);
+    server.slowlog.set_max_len(config.slowlog_max_len as usize);
+    server
+        .latency
+        .set_threshold(config.latency_monitor_threshold);
+    crate::logging::reconfigure(&config);
+    RespData::ok()
+}
+
+pub fn config(server: &Server, args: &[String]) -> RespData {
+    let subcommand = args[0].to_lowercase();
+    let rest = &args[1..];
+    match subcommand.as_str() {
+        "get" if !rest.is_empty() => get(server, rest),
+        "set" if !rest.is_empty() && rest.len().is_multiple_of(2) => set(server, rest),
+        "rewrite" if rest.is_empty() => match server.config().rewrite() {
+            Ok(()) => RespData::ok(),
+            Err(message) => RespData::Error(format!("ERR {}", message)),
+        },
+        "resetstat" if rest.is_empty() => {
+            server.memory.reset_stats();
+            server.keyspace.reset();
+            server.stats.reset();
+            server.clients.reset_stats();
+            RespData::ok()
+        }
+        "get" | "set" | "rewrite" | "resetstat" => wrong_arity(&subcommand),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+            args[0]
+        )),
+    }
+}
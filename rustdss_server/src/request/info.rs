@@ -0,0 +1,301 @@
+// INFO [section ...]
+use crate::server::Server;
+use rustdss_data::RespData;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The Redis version clients should treat this as, some check it before using newer commands
+const REDIS_VERSION: &str = "7.0.0";
+
+// Clock ticks per second in /proc/self/stat, which is 100 on every Linux this is likely to run on
+const CLOCK_TICKS: f64 = 100.0;
+
+// Every section in the order INFO gives them, and whether plain INFO includes it
+const SECTIONS: &[(&str, bool)] = &[
+    ("server", true),
+    ("clients", true),
+    ("memory", true),
+    ("persistence", true),
+    ("stats", true),
+    ("replication", true),
+    ("cpu", true),
+    ("commandstats", false),
+    ("errorstats", true),
+    ("cluster", true),
+    ("keyspace", true),
+];
+
+/// Sizes like Redis shows them next to the raw byte counts, e.g. 1.50M
+fn human(bytes: u64) -> String {
+    let units = ["K", "M", "G", "T", "P"];
+    let mut size = bytes as f64;
+    if size < 1024.0 {
+        return format!("{}B", bytes);
+    }
+    let mut unit = 0;
+    size /= 1024.0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", size, units[unit])
+}
+
+/// The resident set size from /proc, or 0 where there isn't one
+pub fn rss_bytes() -> u64 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+            let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+            Some(kilobytes * 1024)
+        })
+        .unwrap_or(0)
+}
+
+/// Seconds of CPU used in the kernel and in user space
+fn cpu_seconds() -> (f64, f64) {
+    std::fs::read_to_string("/proc/self/stat")
+        .ok()
+        .and_then(|stat| {
+            // The process name can have spaces in it, so count fields from after it
+            let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+            let user: u64 = fields.get(11)?.parse().ok()?;
+            let system: u64 = fields.get(12)?.parse().ok()?;
+            Some((system as f64 / CLOCK_TICKS, user as f64 / CLOCK_TICKS))
+        })
+        .unwrap_or((0.0, 0.0))
+}
+
+fn server_section(server: &Server) -> Vec<String> {
+    let config = server.config();
+    let uptime = server.stats.uptime().as_secs();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let executable = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let config_file = config
+        .file
+        .as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    vec![
+        format!("redis_version:{}", REDIS_VERSION),
+        format!("rustdss_version:{}", env!("CARGO_PKG_VERSION")),
+        format!(
+            "redis_mode:{}",
+            if server.cluster.is_some() {
+                "cluster"
+            } else {
+                "standalone"
+            }
+        ),
+        format!("os:{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        format!("arch_bits:{}", usize::BITS),
+        format!("process_id:{}", std::process::id()),
+        format!("run_id:{}", server.stats.run_id),
+        format!("tcp_port:{}", server.port),
+        format!("server_time_usec:{}", now.as_micros()),
+        format!("uptime_in_seconds:{}", uptime),
+        format!("uptime_in_days:{}", uptime / (24 * 60 * 60)),
+        format!("executable:{}", executable),
+        format!("config_file:{}", config_file),
+    ]
+}
+
+fn clients_section(server: &Server) -> Vec<String> {
+    vec![
+        format!("connected_clients:{}", server.clients.connected()),
+        format!("maxclients:{}", server.config().maxclients),
+    ]
+}
+
+fn memory_section(server: &Server) -> Vec<String> {
+    let used = server.memory.used() as u64;
+    let rss = rss_bytes();
+    let maxmemory = server.memory.maxmemory() as u64;
+    let fragmentation = if used > 0 {
+        rss as f64 / used as f64
+    } else {
+        0.0
+    };
+    vec![
+        format!("used_memory:{}", used),
+        format!("used_memory_human:{}", human(used)),
+        format!("used_memory_rss:{}", rss),
+        format!("used_memory_rss_human:{}", human(rss)),
+        format!("maxmemory:{}", maxmemory),
+        format!("maxmemory_human:{}", human(maxmemory)),
+        format!("maxmemory_policy:{}", server.memory.policy().name()),
+        format!("mem_fragmentation_ratio:{:.2}", fragmentation),
+    ]
+}
+
+// Nothing is saved to disk yet, so there's never anything in progress
+fn persistence_section() -> Vec<String> {
+    vec![
+        "loading:0".into(),
+        "async_loading:0".into(),
+        "rdb_bgsave_in_progress:0".into(),
+        "aof_enabled:0".into(),
+        "aof_rewrite_in_progress:0".into(),
+    ]
+}
+
+fn stats_section(server: &Server) -> Vec<String> {
+    let (net_input, net_output) = server.clients.net_bytes();
+    vec![
+        format!(
+            "total_connections_received:{}",
+            server.stats.total_connections()
+        ),
+        format!(
+            "rejected_connections:{}",
+            server.stats.rejected_connections()
+        ),
+        format!("total_commands_processed:{}", server.stats.total_commands()),
+        format!("instantaneous_ops_per_sec:{}", server.stats.ops_per_sec()),
+        format!("total_net_input_bytes:{}", net_input),
+        format!("total_net_output_bytes:{}", net_output),
+        format!("expired_keys:{}", server.memory.expired_keys()),
+        format!("evicted_keys:{}", server.memory.evicted_keys()),
+        format!("keyspace_hits:{}", server.keyspace.keyspace_hits()),
+        format!("keyspace_misses:{}", server.keyspace.keyspace_misses()),
+        format!("total_error_replies:{}", server.stats.total_error_replies()),
+        format!(
+            "client_output_buffer_limit_disconnections:{}",
+            server.stats.output_limit_disconnections()
+        ),
+    ]
+}
+
+fn cpu_section() -> Vec<String> {
+    let (system, user) = cpu_seconds();
+    vec![
+        format!("used_cpu_sys:{:.6}", system),
+        format!("used_cpu_user:{:.6}", user),
+    ]
+}
+
+fn commandstats_section(server: &Server) -> Vec<String> {
+    server
+        .stats
+        .commands()
+        .into_iter()
+        .map(|(name, stats)| {
+            let per_call = if stats.calls > 0 {
+                stats.usec as f64 / stats.calls as f64
+            } else {
+                0.0
+            };
+            format!(
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                name, stats.calls, stats.usec, per_call, stats.rejected_calls, stats.failed_calls
+            )
+        })
+        .collect()
+}
+
+fn errorstats_section(server: &Server) -> Vec<String> {
+    server
+        .stats
+        .errors()
+        .into_iter()
+        .map(|(kind, count)| format!("errorstat_{}:count={}", kind, count))
+        .collect()
+}
+
+fn keyspace_section(server: &Server) -> Vec<String> {
+    server
+        .keyspace
+        .keyspace()
+        .into_iter()
+        .map(|(database, info)| {
+            format!(
+                "db{}:keys={},expires={},avg_ttl={}",
+                database, info.keys, info.expires, info.avg_ttl
+            )
+        })
+        .collect()
+}
+
+fn section(server: &Server, name: &str) -> String {
+    // Replication writes its own section, heading and all
+    if name == "replication" {
+        return server.replication.info();
+    }
+    let lines = match name {
+        "server" => server_section(server),
+        "clients" => clients_section(server),
+        "memory" => memory_section(server),
+        "persistence" => persistence_section(),
+        "stats" => stats_section(server),
+        "cpu" => cpu_section(),
+        "commandstats" => commandstats_section(server),
+        "errorstats" => errorstats_section(server),
+        "cluster" => vec![format!(
+            "cluster_enabled:{}",
+            server.cluster.is_some() as u8
+        )],
+        "keyspace" => keyspace_section(server),
+        _ => vec![],
+    };
+    let mut title = name.to_string();
+    title[..1].make_ascii_uppercase();
+    let mut text = format!("# {}\r\n", title);
+    for line in lines {
+        text.push_str(&line);
+        text.push_str("\r\n");
+    }
+    text
+}
+
+/// Which sections to give: the default ones, `all` or `everything`, or the ones named
+fn wanted(args: &[String]) -> Vec<&'static str> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_lowercase()).collect();
+    SECTIONS
+        .iter()
+        .filter(|(name, default)| {
+            (args.is_empty() && *default)
+                || args.iter().any(|arg| match arg.as_str() {
+                    "default" => *default,
+                    "all" | "everything" => true,
+                    arg => arg == *name,
+                })
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+pub fn info(server: &Server, args: &[String]) -> RespData {
+    let sections: Vec<String> = wanted(args)
+        .into_iter()
+        .map(|name| section(server, name))
+        .collect();
+    RespData::BulkStr(sections.join("\r\n"))
+}
+
+#[cfg(test)]
+mod info_should {
+    use super::*;
+
+    #[test]
+    fn pick_sections_like_redis() {
+        assert!(!wanted(&[]).contains(&"commandstats"));
+        assert_eq!(wanted(&["default".into()]), wanted(&[]));
+        assert_eq!(wanted(&["all".into()]).len(), SECTIONS.len());
+        assert_eq!(
+            wanted(&["Keyspace".into(), "server".into()]),
+            vec!["server", "keyspace"]
+        );
+        assert!(wanted(&["nonsense".into()]).is_empty());
+    }
+
+    #[test]
+    fn show_sizes_for_people() {
+        assert_eq!(human(0), "0B");
+        assert_eq!(human(1536), "1.50K");
+        assert_eq!(human(3 * 1024 * 1024 * 1024), "3.00G");
+    }
+}
@@ -0,0 +1,331 @@
+// LATENCY LATEST | HISTORY event | RESET [event ...] | GRAPH event | HISTOGRAM [command ...] |
+// DOCTOR
+use crate::server::Server;
+use crate::stats::LATENCY_BUCKETS;
+use rustdss_core::latency::{LatencyEvent, LatencySample};
+use rustdss_data::RespData;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many rows of characters GRAPH draws its bars with
+const GRAPH_ROWS: u64 = 4;
+
+fn wrong_arity(subcommand: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for 'latency|{}' command",
+        subcommand
+    ))
+}
+
+fn bulk(value: impl Into<String>) -> RespData {
+    RespData::BulkStr(value.into())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Each event's latest sample and its worst since it was reset
+fn latest(server: &Server) -> RespData {
+    RespData::List(
+        server
+            .latency
+            .all()
+            .into_iter()
+            .filter_map(|(name, event)| {
+                let latest = event.latest()?;
+                Some(RespData::List(
+                    vec![
+                        bulk(name),
+                        RespData::Number(latest.timestamp as i64),
+                        RespData::Number(latest.latency as i64),
+                        RespData::Number(event.max as i64),
+                    ]
+                    .into(),
+                ))
+            })
+            .collect(),
+    )
+}
+
+fn history(server: &Server, event: &str) -> RespData {
+    RespData::List(
+        server
+            .latency
+            .history(event)
+            .into_iter()
+            .map(|sample| {
+                RespData::List(
+                    vec![
+                        RespData::Number(sample.timestamp as i64),
+                        RespData::Number(sample.latency as i64),
+                    ]
+                    .into(),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// How long ago something was, as short as possible: 15s, 2m, 3h or 1d
+fn ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// The character in a bar `level` half rows high at `row`, counting up from the bottom
+fn bar(level: u64, row: u64) -> char {
+    let top = (row + 1) * 2;
+    if level > top {
+        '|'
+    } else if level == top {
+        '#'
+    } else if level + 1 == top {
+        '_'
+    } else {
+        ' '
+    }
+}
+
+/// A bar for each sample, scaled between the lowest and highest, with how long ago it was written
+/// down underneath
+fn graph(name: &str, event: &LatencyEvent, now: u64) -> String {
+    let samples: Vec<LatencySample> = event.samples.iter().copied().collect();
+    let high = samples
+        .iter()
+        .map(|sample| sample.latency)
+        .max()
+        .unwrap_or(0);
+    let low = samples
+        .iter()
+        .map(|sample| sample.latency)
+        .min()
+        .unwrap_or(0);
+    let levels: Vec<u64> = samples
+        .iter()
+        .map(|sample| match high - low {
+            0 => GRAPH_ROWS * 2,
+            range => 1 + (sample.latency - low) * (GRAPH_ROWS * 2 - 1) / range,
+        })
+        .collect();
+    let labels: Vec<Vec<char>> = samples
+        .iter()
+        .map(|sample| ago(now.saturating_sub(sample.timestamp)).chars().collect())
+        .collect();
+
+    let mut lines = vec![
+        format!(
+            "{} - high {} ms, low {} ms (all time high {} ms)",
+            name, high, low, event.max
+        ),
+        "-".repeat(80),
+    ];
+    for row in (0..GRAPH_ROWS).rev() {
+        lines.push(levels.iter().map(|level| bar(*level, row)).collect());
+    }
+    lines.push(String::new());
+    let label_rows = labels.iter().map(Vec::len).max().unwrap_or(0);
+    for row in 0..label_rows {
+        lines.push(
+            labels
+                .iter()
+                .map(|label| label.get(row).copied().unwrap_or(' '))
+                .collect(),
+        );
+    }
+    let lines: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+    format!("{}\n", lines.join("\n"))
+}
+
+/// Each command's calls and how many took up to each bucket's microseconds, running totals like
+/// Redis gives them
+fn histogram(server: &Server, commands: &[String]) -> RespData {
+    let commands: Vec<String> = commands.iter().map(|name| name.to_lowercase()).collect();
+    let mut reply = vec![];
+    for (name, stats) in server.stats.commands() {
+        if stats.calls == 0 || !(commands.is_empty() || commands.contains(&name)) {
+            continue;
+        }
+        let mut total = 0;
+        let mut buckets = vec![];
+        for (bound, calls) in LATENCY_BUCKETS.iter().zip(stats.latency) {
+            if calls > 0 {
+                total += calls;
+                buckets.push(RespData::Number(*bound as i64));
+                buckets.push(RespData::Number(total as i64));
+            }
+        }
+        reply.push(bulk(name));
+        reply.push(RespData::List(
+            vec![
+                bulk("calls"),
+                RespData::Number(stats.calls as i64),
+                bulk("histogram_usec"),
+                RespData::List(buckets.into()),
+            ]
+            .into(),
+        ));
+    }
+    RespData::List(reply.into())
+}
+
+/// What can be done about each kind of event
+fn advice(name: &str, server: &Server) -> Vec<String> {
+    match name {
+        "command" => {
+            let config = server.config();
+            let threshold_micros = config.latency_monitor_threshold as i64 * 1000;
+            if config.slowlog_log_slower_than < 0 {
+                vec!["The slow log is off. Set slowlog-log-slower-than to see which commands are slow.".into()]
+            } else if config.slowlog_log_slower_than > threshold_micros {
+                vec!["slowlog-log-slower-than is above latency-monitor-threshold, so the slow log misses some of these commands. Lower it to see them all.".into()]
+            } else {
+                vec!["SLOWLOG GET shows which commands were slow. Commands like KEYS, or ranges over big values, are worth avoiding.".into()]
+            }
+        }
+        "expire-cycle" => vec!["Lots of keys are expiring at the same time. Spreading out their TTLs keeps each expire cycle short.".into()],
+        "eviction-cycle" | "eviction-del" => vec!["Keys are being evicted to stay under maxmemory. Raising maxmemory, or keeping less data, keeps eviction away from commands.".into()],
+        "key-free" => vec!["Big values are being deleted or overwritten, and freeing them takes a while. Splitting them over several keys spreads the work out.".into()],
+        "snapshot" => vec!["Replicas are doing full syncs, which hold every database still while the snapshot is made. Check why replicas are losing their connection.".into()],
+        _ => vec![],
+    }
+}
+
+/// Spikes for one event: how many, how big on average and how far from that, and how often
+fn summary(index: usize, name: &str, event: &LatencyEvent) -> String {
+    let count = event.samples.len() as u64;
+    let average = event.samples.iter().map(|s| s.latency).sum::<u64>() / count.max(1);
+    let deviation = event
+        .samples
+        .iter()
+        .map(|s| s.latency.abs_diff(average))
+        .sum::<u64>()
+        / count.max(1);
+    let period = match (event.samples.front(), event.samples.back()) {
+        (Some(first), Some(last)) if count > 1 => (last.timestamp - first.timestamp) / (count - 1),
+        _ => 0,
+    };
+    format!(
+        "{}. {}: {} latency spikes (average {}ms, mean deviation {}ms, period {} sec). Worst all time event {}ms.",
+        index, name, count, average, deviation, period, event.max
+    )
+}
+
+fn doctor(server: &Server, events: &BTreeMap<String, LatencyEvent>) -> String {
+    if !server.latency.is_enabled() {
+        return "Latency monitoring is off. Turn it on with CONFIG SET latency-monitor-threshold \
+                <milliseconds> to see what's been slow.\n"
+            .into();
+    }
+    if events.is_empty() {
+        return "No latency spikes have been seen since monitoring started or was last reset.\n"
+            .into();
+    }
+    let mut report = vec![
+        "Latency spikes have been seen for these events:".to_string(),
+        String::new(),
+    ];
+    let mut advice_given: Vec<String> = vec![];
+    for (index, (name, event)) in events.iter().enumerate() {
+        report.push(summary(index + 1, name, event));
+        for line in advice(name, server) {
+            if !advice_given.contains(&line) {
+                advice_given.push(line);
+            }
+        }
+    }
+    if !advice_given.is_empty() {
+        report.push(String::new());
+        report.push("Advice:".into());
+        report.push(String::new());
+        report.extend(advice_given.into_iter().map(|line| format!("- {}", line)));
+    }
+    format!("{}\n", report.join("\n"))
+}
+
+pub fn latency(server: &Server, args: &[String]) -> RespData {
+    let subcommand = args[0].to_lowercase();
+    let rest = &args[1..];
+    match (subcommand.as_str(), rest) {
+        ("latest", []) => latest(server),
+        ("history", [event]) => history(server, event),
+        ("reset", events) => RespData::Number(server.latency.reset(events) as i64),
+        ("graph", [event]) => match server.latency.all().get(event) {
+            Some(samples) => bulk(graph(event, samples, now())),
+            None => RespData::Error(format!("ERR No samples available for event '{}'", event)),
+        },
+        ("histogram", commands) => histogram(server, commands),
+        ("doctor", []) => bulk(doctor(server, &server.latency.all())),
+        ("latest", _) | ("history", _) | ("graph", _) | ("doctor", _) => wrong_arity(&subcommand),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try LATENCY HELP.",
+            args[0]
+        )),
+    }
+}
+
+#[cfg(test)]
+mod latency_should {
+    use super::*;
+
+    fn event(samples: &[(u64, u64)]) -> LatencyEvent {
+        LatencyEvent {
+            samples: samples
+                .iter()
+                .map(|(timestamp, latency)| LatencySample {
+                    timestamp: *timestamp,
+                    latency: *latency,
+                })
+                .collect(),
+            max: samples
+                .iter()
+                .map(|(_, latency)| *latency)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    #[test]
+    fn say_how_long_ago_briefly() {
+        assert_eq!(ago(15), "15s");
+        assert_eq!(ago(150), "2m");
+        assert_eq!(ago(3 * 3600 + 5), "3h");
+        assert_eq!(ago(200_000), "2d");
+    }
+
+    #[test]
+    fn draw_a_bar_per_sample() {
+        let drawn = graph(
+            "command",
+            &event(&[(1000, 10), (1010, 45), (1030, 80)]),
+            1040,
+        );
+        assert_eq!(
+            drawn,
+            format!(
+                "command - high 80 ms, low 10 ms (all time high 80 ms)\n{}\n  #\n  |\n #|\n_||\n\n431\n000\nsss\n",
+                "-".repeat(80)
+            )
+        );
+    }
+
+    #[test]
+    fn summarise_spikes() {
+        assert_eq!(
+            summary(
+                1,
+                "expire-cycle",
+                &event(&[(100, 10), (110, 30), (120, 20)])
+            ),
+            "1. expire-cycle: 3 latency spikes (average 20ms, mean deviation 6ms, period 10 sec). \
+             Worst all time event 30ms."
+        );
+    }
+}
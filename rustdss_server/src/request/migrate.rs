@@ -0,0 +1,142 @@
+// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password]
+//   [AUTH2 username password] [KEYS key ...]
+//
+// Each key is sent to the other server as a RESTORE of its DUMP, and deleted here once it's
+// there unless COPY is given. A destination-db of 0 means the other server's default database.
+use crate::client::Client;
+use crate::server::Server;
+use rustdss_data::{Command, RespData};
+use std::time::Duration;
+
+struct Options<'a> {
+    copy: bool,
+    replace: bool,
+    auth: Vec<&'a str>,
+    keys: Vec<String>,
+}
+
+fn parse_options<'a>(key: &str, args: &'a [String]) -> Result<Options<'a>, RespData> {
+    let syntax_error = || RespData::Error("ERR syntax error".into());
+    let mut options = Options {
+        copy: false,
+        replace: false,
+        auth: vec![],
+        keys: vec![],
+    };
+    if !key.is_empty() {
+        options.keys.push(key.into());
+    }
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        match option.to_lowercase().as_str() {
+            "copy" => options.copy = true,
+            "replace" => options.replace = true,
+            "auth" => {
+                options.auth = vec!["AUTH", args.next().ok_or_else(syntax_error)?.as_str()];
+            }
+            "auth2" => {
+                let username = args.next().ok_or_else(syntax_error)?;
+                let password = args.next().ok_or_else(syntax_error)?;
+                options.auth = vec!["AUTH", username.as_str(), password.as_str()];
+            }
+            "keys" if key.is_empty() => {
+                options.keys = args.by_ref().cloned().collect();
+            }
+            "keys" => {
+                return Err(RespData::Error(
+                    "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".into(),
+                ))
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+pub fn migrate(database_id: &str, server: &Server, args: &[String]) -> RespData {
+    let (host, port, key, destination_db, timeout, rest) = match args {
+        [host, port, key, destination_db, timeout, rest @ ..] => {
+            (host, port, key, destination_db, timeout, rest)
+        }
+        _ => return RespData::Error("ERR wrong number of arguments for 'migrate' command".into()),
+    };
+    let (port, timeout) = match (port.parse::<u16>(), timeout.parse::<i64>()) {
+        (Ok(port), Ok(timeout)) => (port, timeout),
+        _ => return RespData::Error("ERR value is not an integer or out of range".into()),
+    };
+    // Like Redis, a timeout that isn't positive means a second
+    let timeout = Duration::from_millis(if timeout <= 0 { 1000 } else { timeout as u64 });
+    let options = match parse_options(key, rest) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+
+    let mut dumped = vec![];
+    for key in options.keys {
+        if let RespData::BulkStr(payload) =
+            server.send_to_core(database_id, Command::Dump(key.clone()))
+        {
+            let ttl = match server.send_to_core(database_id, Command::Pttl(key.clone())) {
+                RespData::Number(ttl) if ttl > 0 => ttl,
+                _ => 0,
+            };
+            dumped.push((key, ttl.to_string(), payload));
+        }
+    }
+    if dumped.is_empty() {
+        return RespData::SimpleStr("NOKEY".into());
+    }
+
+    let mut client = match Client::connect(host, port, timeout) {
+        Ok(client) => client,
+        Err(_) => return RespData::Error("IOERR error or timeout connecting to the client".into()),
+    };
+    let io_error = || RespData::Error("IOERR error or timeout reading to target instance".into());
+    let target_error = |error: String| {
+        RespData::Error(format!("ERR Target instance replied with error: {}", error))
+    };
+
+    let mut setup = vec![];
+    if !options.auth.is_empty() {
+        setup.push(options.auth.clone());
+    }
+    if destination_db != "0" {
+        setup.push(vec!["SELECT", destination_db.as_str()]);
+    }
+    for command in setup {
+        match client.call(&command) {
+            Ok(RespData::Error(error)) => return target_error(error),
+            Ok(_) => {}
+            Err(_) => return io_error(),
+        }
+    }
+
+    let mut moved = vec![];
+    let mut result = RespData::ok();
+    for (key, ttl, payload) in dumped {
+        // The target is importing the slot, so it only takes the key when asked
+        if server.cluster.is_some() && client.call(&["ASKING"]).is_err() {
+            result = io_error();
+            break;
+        }
+        let mut restore = vec!["RESTORE", key.as_str(), ttl.as_str(), payload.as_str()];
+        if options.replace {
+            restore.push("REPLACE");
+        }
+        match client.call(&restore) {
+            Ok(RespData::Error(error)) => {
+                result = target_error(error);
+                break;
+            }
+            Ok(_) => moved.push(key),
+            Err(_) => {
+                result = io_error();
+                break;
+            }
+        }
+    }
+    if !options.copy && !moved.is_empty() {
+        server.send_to_core(database_id, Command::Del(moved));
+    }
+    result
+}
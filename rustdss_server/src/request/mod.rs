@@ -1,46 +1,558 @@
+mod acl;
+mod client;
 pub mod command;
+mod command_info;
+mod config;
+pub mod info;
+mod latency;
+mod migrate;
+mod slowlog;
 
+use crate::clients::Client;
+use crate::cluster::Route;
+use crate::server::Server;
 use command::ParseCommand;
-use rustdss_core::Message;
+use rustdss_core::replication::ReplicaStream;
+use rustdss_core::Caller;
 use rustdss_data::Command;
 use rustdss_data::RespData;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The state kept for each connection between requests
+#[derive(Default)]
+pub struct Session {
+    // Where the client connected from
+    address: String,
+    database_id: Option<String>,
+    // Commands queued since MULTI, and whether any of them failed to parse
+    transaction: Option<(Vec<Command>, bool)>,
+    // The port a replica said it listens on, before it asks for the stream
+    listening_port: Option<u16>,
+    // Set once a replica has asked for the stream, which takes over the connection
+    replica_stream: Option<ReplicaStream>,
+    // Whether the last command was ASKING, which lets the next one use a slot being imported
+    asking: bool,
+    // Who the connection is logged in as, nobody until AUTH when there's a password
+    user: Option<String>,
+    // The connection's entry in the client list
+    client: Option<Arc<Client>>,
+    // Whether CLIENT REPLY OFF is on, and how many replies CLIENT REPLY SKIP has left to drop
+    reply_off: bool,
+    skip_replies: u8,
+    // Set once the client has asked for MONITOR, which takes over the connection
+    monitor_feed: Option<Receiver<String>>,
+}
+
+impl Session {
+    pub fn new(client: Arc<Client>, user: Option<String>) -> Self {
+        Self {
+            address: client.address.clone(),
+            user,
+            client: Some(client),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the reply to the command just handled should be sent, CLIENT REPLY can turn them
+    /// off
+    pub fn should_reply(&mut self) -> bool {
+        if self.skip_replies > 0 {
+            self.skip_replies -= 1;
+            return false;
+        }
+        !self.reply_off
+    }
+
+    /// Shows the command in the client list as it starts
+    fn record_command(&self, command: Option<String>, argv_memory: usize) {
+        if let Some(client) = &self.client {
+            let mut state = client.state();
+            if let Some(command) = command {
+                state.last_command = command;
+            }
+            state.argv_memory = argv_memory;
+            state.last_interaction = Instant::now();
+            state.running = true;
+        }
+    }
+
+    /// Brings the client list up to date with what the command changed
+    fn update_client(&self, server: &Server) {
+        if let Some(client) = &self.client {
+            let database = Request::database_id(self, server);
+            let mut state = client.state();
+            state.database = database;
+            state.user = self.user.clone().unwrap_or_default();
+            state.multi = self.transaction.as_ref().map(|(queued, _)| queued.len());
+            state.running = false;
+        }
+    }
+
+    /// Who the client is, for the ACL log
+    fn client_info(&self) -> String {
+        format!(
+            "addr={} user={}",
+            self.address,
+            self.user.as_deref().unwrap_or("")
+        )
+    }
+
+    pub fn take_replica_stream(&mut self) -> Option<ReplicaStream> {
+        self.replica_stream.take()
+    }
+
+    pub fn take_monitor_feed(&mut self) -> Option<Receiver<String>> {
+        self.monitor_feed.take()
+    }
+}
 
 pub struct Request {}
 
+fn not_an_integer() -> RespData {
+    RespData::Error("ERR value is not an integer or out of range".into())
+}
+
+fn cluster_disabled() -> RespData {
+    RespData::Error("ERR This instance has cluster support disabled".into())
+}
+
+/// How much the arguments of a request take up
+fn argv_size(input: &RespData) -> usize {
+    match input {
+        RespData::List(items) => items
+            .iter()
+            .map(|item| match item {
+                RespData::BulkStr(arg) | RespData::SimpleStr(arg) => arg.len(),
+                _ => 0,
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// The command name and arguments as the client sent them, for MONITOR
+fn argv(input: &RespData) -> Vec<String> {
+    match input {
+        RespData::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                RespData::BulkStr(arg) | RespData::SimpleStr(arg) => Some(arg.clone()),
+                RespData::Number(number) => Some(number.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The name CLIENT LIST shows for a command, `command|subcommand` for the ones that have them
+fn command_name(cmd: &Command) -> String {
+    let name = cmd.name().to_lowercase();
+    match crate::acl::subcommand(cmd) {
+        Some(subcommand) => format!("{}|{}", name, subcommand),
+        None => name,
+    }
+}
+
 impl Request {
-    pub fn handle(
-        database_id: Option<String>,
-        core_sender: Sender<Message>,
-        input: RespData,
-    ) -> (Option<String>, RespData) {
-        match Command::from_resp(input) {
+    fn database_id(session: &Session, server: &Server) -> String {
+        session
+            .database_id
+            .clone()
+            .unwrap_or_else(|| server.default_database())
+    }
+
+    fn send_to_core(session: &Session, server: &Server, core_cmd: Command) -> RespData {
+        let caller = session
+            .client
+            .clone()
+            .map(|client| client as Arc<dyn Caller>);
+        server.send_to_core_from(&Self::database_id(session, server), core_cmd, caller)
+    }
+
+    /// In cluster mode, the redirect to give when the command's keys aren't served here
+    fn redirect(
+        session: &Session,
+        server: &Server,
+        cmd: &Command,
+        asking: bool,
+    ) -> Option<RespData> {
+        let cluster = server.cluster.as_ref()?;
+        let mut keys = cmd.key_args();
+        keys.sort();
+        keys.dedup();
+        match cluster.route(&keys, asking) {
+            Ok(Route::Local) => None,
+            Ok(Route::Migrating { slot, address }) => {
+                let wanted = keys.len() as i64;
+                let keys = keys.into_iter().cloned().collect();
+                match Self::send_to_core(session, server, Command::Exists(keys)) {
+                    RespData::Number(found) if found == wanted => None,
+                    RespData::Number(0) => {
+                        Some(RespData::Error(format!("ASK {} {}", slot, address)))
+                    }
+                    _ => Some(RespData::Error(
+                        "TRYAGAIN Multiple keys request during rehashing of slot".into(),
+                    )),
+                }
+            }
+            Err(redirect) => Some(redirect),
+        }
+    }
+
+    /// NOAUTH until the connection has logged in, then NOPERM for whatever its user isn't allowed
+    fn check_access(session: &Session, server: &Server, cmd: &Command) -> Option<RespData> {
+        if let Command::Auth(_) = cmd {
+            return None;
+        }
+        let user = match &session.user {
+            Some(user) => user,
+            None => return Some(RespData::Error("NOAUTH Authentication required.".into())),
+        };
+        let denial = server.acl.check(user, cmd).err()?;
+        let context = if session.transaction.is_some() {
+            "multi"
+        } else {
+            "toplevel"
+        };
+        server.acl.log(
+            &denial,
+            user,
+            context,
+            &session.client_info(),
+            server.config().acllog_max_len,
+        );
+        Some(RespData::Error(denial.message))
+    }
+
+    /// Holds a command while clients are paused. CLIENT itself carries on so that the pause can
+    /// be lifted.
+    fn wait_if_paused(server: &Server, cmd: &Command) {
+        if !matches!(cmd, Command::Client(_)) {
+            server.clients.wait_while_paused(cmd.is_write());
+        }
+    }
+
+    /// Replicas only take writes from their master
+    fn read_only(server: &Server, cmd: &Command) -> Option<RespData> {
+        if cmd.is_write() && server.replication.is_replica() {
+            Some(RespData::Error(
+                "READONLY You can't write against a read only replica.".into(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn replicaof(server: &Arc<Server>, host: String, port: String) -> RespData {
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            server.stop_replicating();
+            return RespData::ok();
+        }
+        let port = match port.parse() {
+            Ok(port) => port,
+            Err(_) => return RespData::Error("ERR Invalid master port".into()),
+        };
+        if let rustdss_core::replication::Role::Replica {
+            host: current_host,
+            port: current_port,
+            ..
+        } = server.replication.role()
+        {
+            if current_host == host && current_port == port {
+                return RespData::SimpleStr("OK Already connected to specified master".into());
+            }
+        }
+        server.replicate_from(&host, port);
+        RespData::ok()
+    }
+
+    fn psync(session: &mut Session, server: &Server, replid: String, offset: String) -> RespData {
+        let address = session
+            .address
+            .rsplit_once(':')
+            .map_or(session.address.as_str(), |(ip, _)| ip)
+            .to_string();
+        let port = session.listening_port.unwrap_or(0);
+        let partial = offset.parse().ok().and_then(|offset| {
+            server
+                .replication
+                .partial_sync(&replid, offset, &address, port)
+        });
+        let (reply, stream) = match partial {
+            Some((replid, stream)) => (format!("CONTINUE {}", replid), stream),
+            None => {
+                // Every database is held still while the snapshot is made
+                let (replid, offset, stream) = server
+                    .latency
+                    .time("snapshot", || server.replication.full_sync(&address, port));
+                (format!("FULLRESYNC {} {}", replid, offset), stream)
+            }
+        };
+        session.replica_stream = Some(stream);
+        RespData::SimpleStr(reply)
+    }
+
+    fn replconf(session: &mut Session, args: Vec<String>) -> RespData {
+        match args.first().map(|option| option.to_lowercase()).as_deref() {
+            Some("listening-port") => match args.get(1).and_then(|port| port.parse().ok()) {
+                Some(port) => {
+                    session.listening_port = Some(port);
+                    RespData::ok()
+                }
+                None => not_an_integer(),
+            },
+            // Acks only mean something on a replica's stream, see connection
+            Some("capa") | Some("ip-address") | Some("ack") | Some("getack") => RespData::ok(),
+            Some(option) => {
+                RespData::Error(format!("ERR Unrecognized REPLCONF option: {}", option))
+            }
+            None => RespData::Error("ERR syntax error".into()),
+        }
+    }
+
+    fn wait(server: &Server, replicas: String, timeout: String) -> RespData {
+        if server.replication.is_replica() {
+            return RespData::Error("ERR WAIT cannot be used with replica instances.".into());
+        }
+        match (replicas.parse::<usize>(), timeout.parse::<i64>()) {
+            (Ok(_), Ok(timeout)) if timeout < 0 => {
+                RespData::Error("ERR timeout is negative".into())
+            }
+            (Ok(replicas), Ok(timeout)) => {
+                // A timeout of 0 waits for ever
+                let timeout = Some(timeout as u64)
+                    .filter(|timeout| *timeout > 0)
+                    .map(Duration::from_millis);
+                RespData::Number(server.replication.wait(replicas, timeout) as i64)
+            }
+            _ => not_an_integer(),
+        }
+    }
+
+    /// Queues commands between MULTI and EXEC
+    fn handle_transaction(
+        session: &mut Session,
+        server: &Server,
+        parsed: Result<Command, String>,
+        asking: bool,
+    ) -> RespData {
+        let (queued, failed) = session.transaction.as_mut().expect("in a transaction");
+        match parsed {
+            Ok(Command::Multi) => RespData::Error("ERR MULTI calls can not be nested".into()),
+            Ok(Command::Discard) => {
+                session.transaction = None;
+                RespData::ok()
+            }
+            Ok(Command::Exec(_)) => {
+                let (queued, failed) = session.transaction.take().expect("in a transaction");
+                if failed {
+                    RespData::Error(
+                        "EXECABORT Transaction discarded because of previous errors.".into(),
+                    )
+                } else {
+                    let exec = Command::Exec(queued);
+                    Self::wait_if_paused(server, &exec);
+                    match Self::redirect(session, server, &exec, asking) {
+                        Some(redirect) => redirect,
+                        None => Self::send_to_core(session, server, exec),
+                    }
+                }
+            }
+            Ok(Command::Select(_))
+            | Ok(Command::Info(_))
+            | Ok(Command::Replicaof(..))
+            | Ok(Command::Psync(..))
+            | Ok(Command::Replconf(_))
+            | Ok(Command::Wait(..))
+            | Ok(Command::Role)
+            | Ok(Command::Cluster(_))
+            | Ok(Command::Asking)
+            | Ok(Command::Migrate(_))
+            | Ok(Command::Config(_))
+            | Ok(Command::Auth(_))
+            | Ok(Command::Acl(_))
+            | Ok(Command::Client(_))
+            | Ok(Command::Slowlog(_))
+            | Ok(Command::Monitor)
+            | Ok(Command::Latency(_))
+            | Ok(Command::Shutdown(_))
+            | Ok(Command::Command(_)) => {
+                *failed = true;
+                RespData::Error("ERR command not allowed inside a transaction".into())
+            }
+            Ok(cmd) => {
+                if let Some(error) = Self::read_only(server, &cmd) {
+                    *failed = true;
+                    return error;
+                }
+                queued.push(cmd);
+                RespData::SimpleStr("QUEUED".into())
+            }
+            Err(reason) => {
+                *failed = true;
+                RespData::Error(reason)
+            }
+        }
+    }
+
+    pub fn handle(session: &mut Session, server: &Arc<Server>, input: RespData) -> RespData {
+        let argv_memory = argv_size(&input);
+        // The arguments are only copied while something is watching
+        let argv = server.monitors.is_watched().then(|| argv(&input));
+        let parsed = Command::from_resp(input);
+        let name = parsed.as_ref().ok().map(command_name);
+        session.record_command(name.clone(), argv_memory);
+        let started = Instant::now();
+        let response = Self::run(session, server, parsed);
+        if let (Some(argv), Some(name)) = (argv, &name) {
+            Self::monitor(session, server, name, &argv, &response);
+        }
+        Self::count(server, name, started.elapsed(), &response);
+        session.update_client(server);
+        response
+    }
+
+    /// Shows a command to every MONITOR once it's run. Like Redis, ones refused for want of
+    /// logging in or permission aren't shown, and neither is MONITOR itself.
+    fn monitor(
+        session: &Session,
+        server: &Server,
+        name: &str,
+        argv: &[String],
+        response: &RespData,
+    ) {
+        let refused = matches!(response, RespData::Error(error)
+            if error.starts_with("NOAUTH") || error.starts_with("NOPERM"));
+        if argv.is_empty() || refused || name == "monitor" {
+            return;
+        }
+        let database = Self::database_id(session, server);
+        server.monitors.feed(&database, &session.address, argv);
+    }
+
+    /// Adds a command to the stats in INFO. Commands queued by MULTI are counted as part of EXEC.
+    fn count(server: &Server, name: Option<String>, took: Duration, response: &RespData) {
+        let error = match response {
+            RespData::Error(error) => Some(error.as_str()),
+            RespData::SimpleStr(reply) if reply == "QUEUED" => return,
+            _ => None,
+        };
+        match (name, error) {
+            (Some(name), error) => server.stats.command(&name, took, error),
+            // It didn't parse, so there's no command to put it against
+            (None, Some(error)) => server.stats.error(error),
+            (None, None) => {}
+        }
+    }
+
+    fn run(
+        session: &mut Session,
+        server: &Arc<Server>,
+        parsed: Result<Command, String>,
+    ) -> RespData {
+        // ASKING only lasts for the command after it
+        let asking = std::mem::take(&mut session.asking);
+        if let Ok(cmd) = &parsed {
+            if let Some(error) = Self::check_access(session, server, cmd) {
+                if let Some((_, failed)) = session.transaction.as_mut() {
+                    *failed = true;
+                }
+                return error;
+            }
+        }
+        if session.transaction.is_some() {
+            return Self::handle_transaction(session, server, parsed, asking);
+        }
+        if let Ok(cmd) = &parsed {
+            Self::wait_if_paused(server, cmd);
+        }
+
+        match parsed {
             // Some commands don't even need to touch the core.
-            Ok(Command::Ping) => (database_id, RespData::SimpleStr("PONG".into())),
-            Ok(Command::Echo(data)) => (database_id, data),
-            Ok(Command::Info) => (database_id, RespData::SimpleStr("info".into())),
-            Ok(Command::Select(new_db)) => (Some(new_db), RespData::ok()),
-            Ok(core_cmd) => {
-                // How do we stream data from the responder?
-                let (return_sender, recv) = channel::<RespData>();
-                match core_sender
-                    .send((
-                        database_id
-                            .clone()
-                            .unwrap_or(crate::constants::default_database_name()),
-                        core_cmd,
-                        return_sender,
-                    ))
-                    .map_err(|_| String::from("Can't send to core"))
-                    .and(
-                        recv.recv()
-                            .map_err(|_| String::from("Can't recv from core")),
-                    ) {
-                    Ok(response) => (database_id, response),
-                    Err(message) => (database_id, RespData::Error(message)),
-                }
-            }
-            Err(reason) => (database_id, RespData::Error(reason)),
+            Ok(Command::Ping(None)) => RespData::SimpleStr("PONG".into()),
+            Ok(Command::Ping(Some(data))) | Ok(Command::Echo(data)) => data,
+            Ok(Command::Info(args)) => info::info(server, &args),
+            // A cluster only has the default database, which SELECT 0 picks
+            Ok(Command::Select(new_db)) if server.cluster.is_some() => {
+                if new_db == "0" {
+                    RespData::ok()
+                } else {
+                    RespData::Error("ERR SELECT is not allowed in cluster mode".into())
+                }
+            }
+            Ok(Command::Select(new_db)) => {
+                // Databases can have names, but numbered ones are limited like in Redis
+                match new_db.parse::<i64>() {
+                    Ok(index) if index < 0 || index as usize >= server.config().databases => {
+                        RespData::Error("ERR DB index is out of range".into())
+                    }
+                    // Numbered the same however the index is written, so SELECT 00 is database 0
+                    Ok(index) => {
+                        session.database_id = Some(index.to_string());
+                        RespData::ok()
+                    }
+                    Err(_) => {
+                        session.database_id = Some(new_db);
+                        RespData::ok()
+                    }
+                }
+            }
+            Ok(Command::Multi) => {
+                session.transaction = Some((vec![], false));
+                RespData::ok()
+            }
+            Ok(Command::Exec(_)) => RespData::Error("ERR EXEC without MULTI".into()),
+            Ok(Command::Discard) => RespData::Error("ERR DISCARD without MULTI".into()),
+            Ok(Command::Replicaof(host, port)) => Self::replicaof(server, host, port),
+            Ok(Command::Psync(replid, offset)) => Self::psync(session, server, replid, offset),
+            Ok(Command::Replconf(args)) => Self::replconf(session, args),
+            Ok(Command::Wait(replicas, timeout)) => Self::wait(server, replicas, timeout),
+            Ok(Command::Role) => server.replication.role_reply(),
+            Ok(Command::Cluster(args)) => match &server.cluster {
+                Some(cluster) => cluster.command(server, &args),
+                None => cluster_disabled(),
+            },
+            Ok(Command::Config(args)) => config::config(server, &args),
+            Ok(Command::Auth(args)) => acl::auth(session, server, &args),
+            Ok(Command::Acl(args)) => acl::acl(session, server, &args),
+            Ok(Command::Client(args)) => client::client(session, server, &args),
+            Ok(Command::Slowlog(args)) => slowlog::slowlog(server, &args),
+            Ok(Command::Latency(args)) => latency::latency(server, &args),
+            Ok(Command::Command(args)) => command_info::command(&args),
+            Ok(Command::Shutdown(args)) => match crate::shutdown::parse(&args) {
+                Ok(save) => crate::shutdown::shutdown(server, save),
+                Err(error) => error,
+            },
+            Ok(Command::Monitor) => match &session.client {
+                Some(client) => {
+                    client.state().monitor = true;
+                    session.monitor_feed = Some(server.monitors.attach(client.id));
+                    RespData::ok()
+                }
+                None => RespData::Error("ERR MONITOR needs a connection".into()),
+            },
+            Ok(Command::Asking) => match &server.cluster {
+                Some(_) => {
+                    session.asking = true;
+                    RespData::ok()
+                }
+                None => cluster_disabled(),
+            },
+            Ok(core_cmd) => match Self::redirect(session, server, &core_cmd, asking)
+                .or_else(|| Self::read_only(server, &core_cmd))
+            {
+                Some(error) => error,
+                None => match core_cmd {
+                    Command::Migrate(args) => {
+                        migrate::migrate(&Self::database_id(session, server), server, &args)
+                    }
+                    core_cmd => Self::send_to_core(session, server, core_cmd),
+                },
+            },
+            Err(reason) => RespData::Error(reason),
         }
     }
 }
@@ -0,0 +1,64 @@
+// SLOWLOG GET [count] | LEN | RESET
+use crate::server::Server;
+use rustdss_core::slowlog::SlowLogEntry;
+use rustdss_data::RespData;
+
+// How many entries GET gives without a count, like Redis
+const DEFAULT_COUNT: usize = 10;
+
+fn wrong_arity(subcommand: &str) -> RespData {
+    RespData::Error(format!(
+        "ERR wrong number of arguments for 'slowlog|{}' command",
+        subcommand
+    ))
+}
+
+fn bulk(value: String) -> RespData {
+    RespData::BulkStr(value)
+}
+
+/// An entry the way Redis gives it: id, time, microseconds taken, arguments, address and name
+fn entry(entry: SlowLogEntry) -> RespData {
+    RespData::List(
+        vec![
+            RespData::Number(entry.id as i64),
+            RespData::Number(entry.timestamp as i64),
+            RespData::Number(entry.duration as i64),
+            RespData::List(entry.argv.into_iter().map(bulk).collect()),
+            bulk(entry.address),
+            bulk(entry.name),
+        ]
+        .into(),
+    )
+}
+
+fn get(server: &Server, args: &[String]) -> RespData {
+    // -1 asks for all of them
+    let count = match args.first().map(|count| count.parse::<i64>()) {
+        None => Some(DEFAULT_COUNT),
+        Some(Ok(-1)) => None,
+        Some(Ok(count)) if count >= 0 => Some(count as usize),
+        Some(_) => {
+            return RespData::Error("ERR count should be greater than or equal to -1".into())
+        }
+    };
+    RespData::List(server.slowlog.get(count).into_iter().map(entry).collect())
+}
+
+pub fn slowlog(server: &Server, args: &[String]) -> RespData {
+    let subcommand = args[0].to_lowercase();
+    let rest = &args[1..];
+    match (subcommand.as_str(), rest.len()) {
+        ("get", 0) | ("get", 1) => get(server, rest),
+        ("len", 0) => RespData::Number(server.slowlog.len() as i64),
+        ("reset", 0) => {
+            server.slowlog.reset();
+            RespData::ok()
+        }
+        ("get", _) | ("len", _) | ("reset", _) => wrong_arity(&subcommand),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+            args[0]
+        )),
+    }
+}
@@ -0,0 +1,178 @@
+use crate::acl::Acl;
+use crate::clients::Clients;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::monitor::Monitors;
+use crate::replica::Link;
+use crate::stats::Stats;
+use crate::tls::Tls;
+use rustdss_core::{Caller, Core, LatencyMonitor, Memory, Message, Replication, SlowLog};
+use rustdss_data::{Command, RespData};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What every connection shares
+pub struct Server {
+    core_sender: Sender<Message>,
+    pub replication: Arc<Replication>,
+    /// The memory limit every database thread checks
+    pub memory: Arc<Memory>,
+    /// The port clients connect to, which replicas tell their master about
+    pub port: u16,
+    /// Set in cluster mode
+    pub cluster: Option<Arc<Cluster>>,
+    pub acl: Acl,
+    /// Every connection, including replicas and the link to the master
+    pub clients: Clients,
+    /// Commands, errors and connections, for INFO
+    pub stats: Arc<Stats>,
+    /// Hits, misses and key counts from the database threads
+    pub keyspace: Arc<rustdss_core::Stats>,
+    /// Commands the database threads took too long over
+    pub slowlog: Arc<SlowLog>,
+    /// Operations that took longer than latency-monitor-threshold
+    pub latency: Arc<LatencyMonitor>,
+    /// Connections that have asked for every command with MONITOR
+    pub monitors: Monitors,
+    /// Set when TLS is configured, for the TLS port and replication links
+    pub tls: Option<Tls>,
+    config: Mutex<Config>,
+    // Commands sent to the database threads that haven't been answered yet
+    in_flight: AtomicUsize,
+    shutting_down: AtomicBool,
+    // The connection to the master, when this server is a replica
+    link: Mutex<Option<Link>>,
+}
+
+impl Server {
+    pub fn new(core: &Core, config: Config) -> Result<Self, String> {
+        let acl = Acl::default();
+        acl.set_default_password(&config.requirepass);
+        let tls = Tls::from_config(&config)?;
+        let cluster = Some(config.port)
+            .filter(|_| config.cluster_enabled)
+            .map(Cluster::start);
+        if let Some(cluster) = &cluster {
+            cluster.set_auth(&config.masteruser, &config.masterauth);
+        }
+        Ok(Self {
+            core_sender: core.get_sender(),
+            replication: core.replication(),
+            memory: core.memory(),
+            port: config.port,
+            cluster,
+            acl,
+            clients: Clients::default(),
+            stats: Stats::start(),
+            keyspace: core.stats(),
+            slowlog: core.slowlog(),
+            latency: core.latency(),
+            monitors: Monitors::default(),
+            tls,
+            config: Mutex::new(config),
+            in_flight: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+            link: Mutex::new(None),
+        })
+    }
+
+    /// The settings the server is running with
+    pub fn config(&self) -> MutexGuard<'_, Config> {
+        self.config
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The database connections start on
+    pub fn default_database(&self) -> String {
+        self.config().default_database.clone()
+    }
+
+    pub fn send_to_core(&self, database_id: &str, core_cmd: Command) -> RespData {
+        self.send_to_core_from(database_id, core_cmd, None)
+    }
+
+    /// Like `send_to_core`, saying which client the command is from
+    pub fn send_to_core_from(
+        &self,
+        database_id: &str,
+        core_cmd: Command,
+        caller: Option<Arc<dyn Caller>>,
+    ) -> RespData {
+        // How do we stream data from the responder?
+        let (return_sender, recv) = channel::<RespData>();
+        // A read parked until something is added could keep shutting down waiting for nothing
+        let counted = !can_park(&core_cmd);
+        if counted {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+        }
+        let response = match self
+            .core_sender
+            .send((database_id.into(), core_cmd, return_sender, caller))
+            .map_err(|_| String::from("Can't send to core"))
+            .and(
+                recv.recv()
+                    .map_err(|_| String::from("Can't recv from core")),
+            ) {
+            Ok(response) => response,
+            Err(message) => RespData::Error(message),
+        };
+        if counted {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        response
+    }
+
+    /// How many commands are being run by the database threads, not counting reads that can block
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Marks the server as shutting down, false if it already was
+    pub fn begin_shutdown(&self) -> bool {
+        !self.shutting_down.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    fn swap_link(&self, new_link: Option<Link>) {
+        let mut link = self
+            .link
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(old_link) = std::mem::replace(&mut *link, new_link) {
+            old_link.stop();
+        }
+    }
+
+    /// Drops whatever master this was following and starts following `host:port`
+    pub fn replicate_from(self: &Arc<Self>, host: &str, port: u16) {
+        self.swap_link(None);
+        self.replication.replicate_from(host, port);
+        self.config().replicaof = Some((host.into(), port));
+        self.swap_link(Some(Link::start(self.clone(), host.into(), port)));
+    }
+
+    /// Stops following the master and takes writes again
+    pub fn stop_replicating(&self) {
+        self.swap_link(None);
+        self.replication.promote();
+        self.config().replicaof = None;
+    }
+}
+
+// XREAD and XREADGROUP with BLOCK can sit in a database thread until something is added
+fn can_park(cmd: &Command) -> bool {
+    match cmd {
+        Command::Xread(args) | Command::Xreadgroup(args) => {
+            // Anything after STREAMS is a key or an ID
+            args.iter()
+                .take_while(|arg| !arg.eq_ignore_ascii_case("streams"))
+                .any(|arg| arg.eq_ignore_ascii_case("block"))
+        }
+        _ => false,
+    }
+}
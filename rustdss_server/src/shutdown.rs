@@ -0,0 +1,196 @@
+// SHUTDOWN [NOSAVE|SAVE], and the same on SIGTERM or SIGINT
+//
+// Shutting down closes the door first: new connections are turned away and every client is
+// paused, so nothing new starts. Commands already in the database threads are let finish and
+// replicas get a moment to catch up, then every client is disconnected and the process exits.
+use crate::clients::ClientType;
+use crate::server::Server;
+use rustdss_data::RespData;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The longest commands and replicas are waited for, like Redis' shutdown-timeout
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+// How often running commands are checked on while they're let finish
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+// Longer than shutting down could ever take, the process is gone before it ends
+const PAUSE: Duration = Duration::from_secs(3600);
+
+// Where the signal handler writes the signals it gets, -1 until handle_signals sets it up
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveMode {
+    /// Save if there are save points, SHUTDOWN without an option and the signals
+    Default,
+    Save,
+    NoSave,
+}
+
+fn errors_shutting_down() -> RespData {
+    RespData::Error("ERR Errors trying to SHUTDOWN. Check logs.".into())
+}
+
+pub fn parse(args: &[String]) -> Result<SaveMode, RespData> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_lowercase()).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => Ok(SaveMode::Default),
+        ["save"] => Ok(SaveMode::Save),
+        ["nosave"] => Ok(SaveMode::NoSave),
+        _ => Err(RespData::Error("ERR syntax error".into())),
+    }
+}
+
+/// Waits for the commands the database threads are running to finish, up to the deadline
+fn drain(server: &Server, deadline: Instant) {
+    while server.in_flight() > 0 && Instant::now() < deadline {
+        thread::sleep(DRAIN_INTERVAL);
+    }
+    if server.in_flight() > 0 {
+        log::warn!(
+            "{} commands were still running when the server stopped waiting for them",
+            server.in_flight()
+        );
+    }
+}
+
+/// Gives replicas until the deadline to get everything written so far
+fn wait_for_replicas(server: &Server, deadline: Instant) {
+    let replicas = server
+        .clients
+        .list()
+        .iter()
+        .filter(|client| client.kind() == ClientType::Replica)
+        .count();
+    if replicas == 0 {
+        return;
+    }
+    log::info!("Waiting for {} replicas to catch up", replicas);
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    let caught_up = server
+        .replication
+        .wait(replicas, Some(timeout.max(Duration::from_millis(1))));
+    if caught_up < replicas {
+        log::warn!(
+            "{} of {} replicas didn't catch up before shutting down",
+            replicas - caught_up,
+            replicas
+        );
+    }
+}
+
+/// Stops the server. It only comes back, with the error to reply with, if the server can't stop.
+pub fn shutdown(server: &Server, save: SaveMode) -> RespData {
+    // Nothing is saved to disk yet, so a save that's asked for can't happen
+    match save {
+        SaveMode::Save => {
+            log::warn!("Error trying to save the DB, there's no persistence yet. Can't exit.");
+            return errors_shutting_down();
+        }
+        SaveMode::Default if !server.config().save.is_empty() => {
+            log::info!("Not saving a snapshot on the way out, there's no persistence yet")
+        }
+        _ => {}
+    }
+    if !server.begin_shutdown() {
+        return RespData::Error("ERR The server is already shutting down".into());
+    }
+    log::warn!("User requested shutdown...");
+
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    server.clients.pause(PAUSE, false);
+    drain(server, deadline);
+    wait_for_replicas(server, deadline);
+
+    let unixsocket = server.config().unixsocket.clone();
+    if !unixsocket.is_empty() {
+        log::info!("Removing the unix socket file");
+        let _ = std::fs::remove_file(&unixsocket);
+    }
+    for client in server.clients.list() {
+        client.kill();
+    }
+    log::warn!("rustdss is now ready to exit, bye bye...");
+    log::logger().flush();
+    std::process::exit(0);
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    let byte = signal as u8;
+    // SAFETY: write is async-signal-safe, and the byte outlives the call
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+/// Shuts down on SIGTERM or SIGINT. Getting either again while shutting down exits straight away.
+pub fn handle_signals(server: Arc<Server>) -> io::Result<()> {
+    let mut fds = [0 as libc::c_int; 2];
+    // SAFETY: fds has room for the two descriptors pipe fills in
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: on_signal only does async-signal-safe things
+        unsafe {
+            libc::signal(
+                signal,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+    // SAFETY: the read end of the pipe belongs to nothing else
+    let mut signals = unsafe { File::from_raw_fd(fds[0]) };
+
+    thread::spawn(move || {
+        let mut signal = [0u8];
+        while signals.read_exact(&mut signal).is_ok() {
+            let name = if signal[0] as libc::c_int == libc::SIGINT {
+                "SIGINT"
+            } else {
+                "SIGTERM"
+            };
+            if server.is_shutting_down() {
+                log::warn!("You insist... exiting now.");
+                log::logger().flush();
+                std::process::exit(1);
+            }
+            log::warn!("Received {} scheduling shutdown...", name);
+            // Shutting down in its own thread leaves this one to hear the next signal
+            let server = server.clone();
+            thread::spawn(move || {
+                if let RespData::Error(error) = shutdown(&server, SaveMode::Default) {
+                    log::warn!("{}", error);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod shutdown_should {
+    use super::*;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn take_save_or_nosave() {
+        assert_eq!(parse(&[]), Ok(SaveMode::Default));
+        assert_eq!(parse(&args(&["NOSAVE"])), Ok(SaveMode::NoSave));
+        assert_eq!(parse(&args(&["save"])), Ok(SaveMode::Save));
+        assert!(parse(&args(&["save", "nosave"])).is_err());
+        assert!(parse(&args(&["later"])).is_err());
+    }
+}
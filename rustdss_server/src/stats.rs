@@ -0,0 +1,281 @@
+// Counters for INFO that the connection threads keep: commands run, errors replied with, and how
+// busy the server is
+use rustdss_core::replication::random_id;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often, and over how many samples, instantaneous_ops_per_sec is worked out, like Redis
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const SAMPLES: usize = 16;
+
+/// The upper bounds, in microseconds, of the buckets command latencies are counted in
+pub const LATENCY_BUCKETS: [u64; 13] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// One command's line in the commandstats section
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+    /// Refused before running, by ACLs, a redirect or a read only replica
+    pub rejected_calls: u64,
+    /// Ran but replied with an error
+    pub failed_calls: u64,
+    /// How many calls took each of LATENCY_BUCKETS or less, but more than the bucket before.
+    /// Slower ones are only in `calls`.
+    pub latency: [u64; LATENCY_BUCKETS.len()],
+}
+
+#[derive(Default)]
+struct OpsSamples {
+    last_total: u64,
+    samples: [u64; SAMPLES],
+    next: usize,
+}
+
+pub struct Stats {
+    started: Instant,
+    /// Changes every time the server starts
+    pub run_id: String,
+    commands: Mutex<BTreeMap<String, CommandStats>>,
+    errors: Mutex<BTreeMap<String, u64>>,
+    total_commands: AtomicU64,
+    total_error_replies: AtomicU64,
+    total_connections: AtomicU64,
+    /// Turned away because of maxclients
+    rejected_connections: AtomicU64,
+    /// Closed for going over client-output-buffer-limit
+    output_limit_disconnections: AtomicU64,
+    ops: Mutex<OpsSamples>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            run_id: random_id(),
+            commands: Mutex::default(),
+            errors: Mutex::default(),
+            total_commands: AtomicU64::new(0),
+            total_error_replies: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            output_limit_disconnections: AtomicU64::new(0),
+            ops: Mutex::default(),
+        }
+    }
+}
+
+/// Errors that stop a command before it runs, rather than ones it replies with
+fn is_rejection(error: &str) -> bool {
+    [
+        "NOAUTH",
+        "NOPERM",
+        "READONLY",
+        "MOVED",
+        "ASK",
+        "CLUSTERDOWN",
+        "TRYAGAIN",
+        "OOM",
+    ]
+    .iter()
+    .any(|prefix| error.split(' ').next() == Some(prefix))
+}
+
+impl Stats {
+    /// Starts sampling the ops per second, which stops once the stats are dropped
+    pub fn start() -> Arc<Self> {
+        let stats = Arc::new(Self::default());
+        let sampled = Arc::downgrade(&stats);
+        thread::spawn(move || sample_ops(sampled));
+        stats
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn connection_received(&self) {
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn output_limit_disconnection(&self) {
+        self.output_limit_disconnections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a command that was run, or parsed and refused, and the error it replied with
+    pub fn command(&self, name: &str, took: Duration, error: Option<&str>) {
+        let rejected = error.is_some_and(is_rejection);
+        {
+            let mut commands = lock(&self.commands);
+            let stats = commands.entry(name.to_string()).or_default();
+            if rejected {
+                stats.rejected_calls += 1;
+            } else {
+                stats.calls += 1;
+                let usec = took.as_micros() as u64;
+                stats.usec += usec;
+                if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| usec <= *bound) {
+                    stats.latency[bucket] += 1;
+                }
+                stats.failed_calls += error.is_some() as u64;
+            }
+        }
+        if !rejected {
+            self.total_commands.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(error) = error {
+            self.error(error);
+        }
+    }
+
+    /// Counts an error reply by its first word, like ERR or WRONGTYPE
+    pub fn error(&self, error: &str) {
+        let kind = error.split(' ').next().unwrap_or_default();
+        *lock(&self.errors).entry(kind.to_string()).or_default() += 1;
+        self.total_error_replies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn commands(&self) -> BTreeMap<String, CommandStats> {
+        lock(&self.commands).clone()
+    }
+
+    pub fn errors(&self) -> BTreeMap<String, u64> {
+        lock(&self.errors).clone()
+    }
+
+    pub fn total_commands(&self) -> u64 {
+        self.total_commands.load(Ordering::Relaxed)
+    }
+
+    pub fn total_error_replies(&self) -> u64 {
+        self.total_error_replies.load(Ordering::Relaxed)
+    }
+
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn output_limit_disconnections(&self) -> u64 {
+        self.output_limit_disconnections.load(Ordering::Relaxed)
+    }
+
+    /// Commands a second, averaged over the last couple of seconds
+    pub fn ops_per_sec(&self) -> u64 {
+        let ops = lock(&self.ops);
+        ops.samples.iter().sum::<u64>() / SAMPLES as u64
+    }
+
+    fn sample(&self) {
+        let total = self.total_commands();
+        let mut ops = lock(&self.ops);
+        let per_sec =
+            total.saturating_sub(ops.last_total) * 1000 / SAMPLE_INTERVAL.as_millis() as u64;
+        let next = ops.next;
+        ops.samples[next] = per_sec;
+        ops.next = (next + 1) % SAMPLES;
+        ops.last_total = total;
+    }
+
+    /// Zeroes the counters, for CONFIG RESETSTAT
+    pub fn reset(&self) {
+        lock(&self.commands).clear();
+        lock(&self.errors).clear();
+        self.total_commands.store(0, Ordering::Relaxed);
+        self.total_error_replies.store(0, Ordering::Relaxed);
+        self.total_connections.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+        self.output_limit_disconnections.store(0, Ordering::Relaxed);
+        *lock(&self.ops) = OpsSamples::default();
+    }
+}
+
+fn sample_ops(stats: Weak<Stats>) {
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        match stats.upgrade() {
+            Some(stats) => stats.sample(),
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod stats_should {
+    use super::*;
+
+    #[test]
+    fn count_calls_failures_and_rejections() {
+        let stats = Stats::default();
+        stats.command("get", Duration::from_micros(10), None);
+        stats.command(
+            "get",
+            Duration::from_micros(20),
+            Some("WRONGTYPE Operation against"),
+        );
+        stats.command(
+            "get",
+            Duration::from_micros(30),
+            Some("NOPERM No permissions"),
+        );
+        stats.error("ERR unknown command");
+
+        let get = CommandStats {
+            calls: 2,
+            usec: 30,
+            rejected_calls: 1,
+            failed_calls: 1,
+            latency: [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        };
+        assert_eq!(stats.commands().get("get"), Some(&get));
+        let errors: Vec<_> = stats.errors().into_iter().collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("ERR".to_string(), 1),
+                ("NOPERM".to_string(), 1),
+                ("WRONGTYPE".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            (stats.total_commands(), stats.total_error_replies()),
+            (2, 3)
+        );
+
+        stats.reset();
+        assert!(stats.commands().is_empty() && stats.errors().is_empty());
+    }
+
+    #[test]
+    fn work_out_ops_per_second_from_the_samples() {
+        let stats = Stats::default();
+        for _ in 0..32 {
+            stats.command("ping", Duration::ZERO, None);
+        }
+        stats.sample();
+        stats.sample();
+
+        // 32 commands in one 100ms sample, averaged over 16 samples
+        assert_eq!(stats.ops_per_sec(), 320 / SAMPLES as u64);
+    }
+}
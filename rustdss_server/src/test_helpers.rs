@@ -0,0 +1,4 @@
+// Shorthands for building commands in tests
+pub fn args(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
@@ -0,0 +1,305 @@
+// TLS for client connections and replication links, using rustls
+//
+// A TLS connection is shared between a reading and a writing thread the same way a socket is: the
+// socket is only read without the lock held, and the lock is only taken to hand what was read to
+// rustls or to encrypt and send something.
+use crate::config::Config;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// How long a client has to finish the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn tls_error(error: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reads one DER element, giving its tag, its contents and whatever comes after it
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let length = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count]
+            .iter()
+            .fold(0, |length, byte| (length << 8) | *byte as usize);
+        rest = &rest[count..];
+        length
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+/// The common name (CN) in a certificate's subject
+pub fn common_name(certificate: &[u8]) -> Option<String> {
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, mut fields, _) = der_element(certificate)?;
+    // The version is optional, then the serial number, signature, issuer, validity and subject
+    let mut tbs = vec![];
+    while let Some((tag, contents, rest)) = der_element(fields) {
+        tbs.push((tag, contents));
+        fields = rest;
+    }
+    let skip = if tbs.first()?.0 == 0xa0 { 1 } else { 0 };
+    let mut names = tbs.get(skip + 4)?.1;
+    while let Some((_, relative_name, rest)) = der_element(names) {
+        let mut attributes = relative_name;
+        while let Some((_, attribute, next)) = der_element(attributes) {
+            let (_, oid, value) = der_element(attribute)?;
+            if oid == [0x55, 0x04, 0x03] {
+                let (_, name, _) = der_element(value)?;
+                return String::from_utf8(name.to_vec()).ok();
+            }
+            attributes = next;
+        }
+        names = rest;
+    }
+    None
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| format!("Can't read certificates from {}: {}", path, error))?;
+    if certificates.is_empty() {
+        return Err(format!("No certificates in {}", path));
+    }
+    Ok(certificates)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|error| format!("Can't read a private key from {}: {}", path, error))
+}
+
+fn load_roots(path: &str) -> Result<Arc<RootCertStore>, String> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots
+            .add(certificate)
+            .map_err(|error| format!("Bad CA certificate in {}: {}", path, error))?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// The TLS settings, ready to make connections with
+pub struct Tls {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+    // Whether a client certificate's CN logs the connection in as the user with that name
+    user_from_certificate: bool,
+}
+
+impl Tls {
+    /// Loads the certificates and keys the config names, if it uses TLS at all
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        if config.tls_port == 0 && !config.tls_replication {
+            return Ok(None);
+        }
+        if config.tls_cert_file.is_empty() || config.tls_key_file.is_empty() {
+            return Err("tls-cert-file and tls-key-file are needed for TLS".into());
+        }
+        let certificates = load_certificates(&config.tls_cert_file)?;
+        let key = load_key(&config.tls_key_file)?;
+        let roots = if config.tls_ca_cert_file.is_empty() {
+            None
+        } else {
+            Some(load_roots(&config.tls_ca_cert_file)?)
+        };
+
+        let builder = ServerConfig::builder();
+        let builder = match (config.tls_auth_clients.as_str(), &roots) {
+            ("no", _) => builder.with_no_client_auth(),
+            (_, None) => return Err("tls-ca-cert-file is needed to check client certificates, or set tls-auth-clients to no".into()),
+            (auth, Some(roots)) => {
+                let verifier = WebPkiClientVerifier::builder(roots.clone());
+                let verifier = if auth == "optional" {
+                    verifier.allow_unauthenticated()
+                } else {
+                    verifier
+                };
+                builder.with_client_cert_verifier(
+                    verifier.build().map_err(|error| error.to_string())?,
+                )
+            }
+        };
+        let server = builder
+            .with_single_cert(certificates.clone(), key.clone_key())
+            .map_err(|error| format!("Bad certificate or key: {}", error))?;
+
+        // A replica checks its master against the same CA, and shows it the same certificate
+        let roots = roots.unwrap_or_else(|| Arc::new(RootCertStore::empty()));
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certificates, key)
+            .map_err(|error| format!("Bad certificate or key: {}", error))?;
+
+        Ok(Some(Self {
+            server: Arc::new(server),
+            client: Arc::new(client),
+            user_from_certificate: config.tls_auth_clients_user == "CN",
+        }))
+    }
+
+    /// Handshakes with a client, giving the connection and the user its certificate logs in as
+    pub fn accept(&self, tcp: TcpStream) -> io::Result<(TlsStream, Option<String>)> {
+        let connection = ServerConnection::new(self.server.clone()).map_err(tls_error)?;
+        let stream = TlsStream::handshake(Connection::Server(connection), tcp)?;
+        let user = if self.user_from_certificate {
+            stream
+                .lock()
+                .peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .and_then(|certificate| common_name(certificate))
+        } else {
+            None
+        };
+        Ok((stream, user))
+    }
+
+    /// Handshakes with another server, such as a replica's master
+    pub fn connect(&self, tcp: TcpStream, host: &str) -> io::Result<TlsStream> {
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let connection = ClientConnection::new(self.client.clone(), name).map_err(tls_error)?;
+        TlsStream::handshake(Connection::Client(connection), tcp)
+    }
+}
+
+/// A TLS connection that can be cloned for reading on one thread and writing on another
+#[derive(Clone)]
+pub struct TlsStream {
+    tcp: Arc<TcpStream>,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl TlsStream {
+    fn handshake(mut connection: Connection, mut tcp: TcpStream) -> io::Result<Self> {
+        tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp)?;
+        }
+        tcp.set_read_timeout(None)?;
+        Ok(Self {
+            tcp: Arc::new(tcp),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        &self.tcp
+    }
+
+    fn send_pending(&self, connection: &mut Connection) -> io::Result<()> {
+        while connection.wants_write() {
+            connection.write_tls(&mut &*self.tcp)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut encrypted = [0; 16 * 1024];
+        loop {
+            match self.lock().reader().read(buf) {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let read = (&*self.tcp).read(&mut encrypted)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            let mut connection = self.lock();
+            let mut received = &encrypted[..read];
+            while !received.is_empty() {
+                connection.read_tls(&mut received)?;
+                if let Err(error) = connection.process_new_packets() {
+                    // Lets the other end know why before giving up
+                    let _ = self.send_pending(&mut connection);
+                    return Err(tls_error(error));
+                }
+            }
+            self.send_pending(&mut connection)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // rustls only buffers so much plaintext, so big writes go out a piece at a time
+        let mut connection = self.lock();
+        let mut written = 0;
+        while written < buf.len() {
+            written += connection.writer().write(&buf[written..])?;
+            self.send_pending(&mut connection)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut connection = self.lock();
+        self.send_pending(&mut connection)
+    }
+}
+
+#[cfg(test)]
+mod tls_should {
+    use super::*;
+
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBmTCCAT+gAwIBAgIUEJXzJ24dg/+dPUdQK6ufg1bfceUwCgYIKoZIzj0EAwIw
+IjEQMA4GA1UECgwHcnVzdGRzczEOMAwGA1UEAwwFYWxpY2UwHhcNMjYxMDE5MDcy
+ODE3WhcNMzYxMDE2MDcyODE3WjAiMRAwDgYDVQQKDAdydXN0ZHNzMQ4wDAYDVQQD
+DAVhbGljZTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABLtI3XRrf8qsIr8lVoGX
+iRjDRu8ls0YbcYnsbQC3fh5nwB7jyXwlv8NnCvIC0sDTt6QWqY1k5nzgPQgcxKhE
+Nd2jUzBRMB0GA1UdDgQWBBSs8HDp15QCO1pbcA5+Z0htYl+S8DAfBgNVHSMEGDAW
+gBSs8HDp15QCO1pbcA5+Z0htYl+S8DAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49
+BAMCA0gAMEUCIQC96m9vKccowmZj/lUcAqfPayTUVyMc1up6KVMIQe8sewIgc8p2
+ZRLeMI3MtnHYwYyYCydZrAlsUJdCVwjvG4WfXI4=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn find_the_common_name_in_a_certificate() {
+        let certificate = CertificateDer::from_pem_slice(CERTIFICATE.as_bytes()).unwrap();
+
+        assert_eq!(common_name(&certificate), Some("alice".into()));
+        assert_eq!(common_name(&certificate[..40]), None);
+        assert_eq!(common_name(b""), None);
+    }
+
+    #[test]
+    fn need_a_certificate_and_key() {
+        let mut config = Config {
+            tls_port: 6390,
+            ..Config::default()
+        };
+        assert!(Tls::from_config(&config).is_err());
+
+        config.tls_port = 0;
+        assert!(Tls::from_config(&config).unwrap().is_none());
+    }
+}
@@ -69,7 +69,6 @@ impl DeserialiseRespData for RespData {
                     Ok(i) => {
                         // Read the following number of VALUES, not chunks!!
                         let vals: VecDeque<Self> = (0..i)
-                            .into_iter()
                             .map(|_| {
                                 // Read the next value from the stream
                                 // TODO: Handle this error properly - we don't want any panics in
@@ -98,12 +97,31 @@ where
     // A bulk string is made up of two chunks: the first is an int indicating how long the
     // string is, and the second is the string it's self
 
-    if first_chunk.parse::<i64>() == Ok(-1) {
-        RespData::NullString
-    } else if let Some(second_chunk) = parse_chunk(stream) {
-        RespData::BulkStr(second_chunk.into())
-    } else {
-        RespData::Error("Can't process bulk string".into())
+    match first_chunk.parse::<i64>() {
+        Ok(-1) => RespData::NullString,
+        Ok(len) if len >= 0 => {
+            // Read exactly `len` chars first so binary payloads (which may contain `\r\n` or
+            // leading/trailing whitespace) survive intact, then read up to the terminator in
+            // case the client lied about the length.
+            let mut payload: String = stream.take(len as usize).collect();
+            if payload.chars().count() < len as usize {
+                return RespData::Error("Can't process bulk string".into());
+            }
+            let mut prev = None;
+            for item in stream {
+                if item == '\n' && prev == Some('\r') {
+                    payload.pop();
+                    return RespData::BulkStr(payload);
+                }
+                payload.push(item);
+                prev = Some(item);
+            }
+            RespData::Error("Can't process bulk string".into())
+        }
+        _ => match parse_chunk(stream) {
+            Some(second_chunk) => RespData::BulkStr(second_chunk),
+            None => RespData::Error("Can't process bulk string".into()),
+        },
     }
 }
 
@@ -206,6 +224,25 @@ mod should {
         );
     }
 
+    #[test]
+    fn parse_binary_bulk_strings() {
+        let mut test1 = "$7\r\n a\r\nb\x00 \r\n".chars();
+        assert_eq!(
+            RespData::from_char_stream(&mut test1),
+            Some(RespData::BulkStr(" a\r\nb\x00 ".into()))
+        );
+
+        let mut test2 = "$0\r\n\r\n:1\r\n".chars();
+        assert_eq!(
+            RespData::from_char_stream(&mut test2),
+            Some(RespData::BulkStr("".into()))
+        );
+        assert_eq!(
+            RespData::from_char_stream(&mut test2),
+            Some(RespData::Number(1))
+        );
+    }
+
     #[test]
     fn parse_lists() {
         let mut test1 = "*2\r\n$4\r\nllen\r\n$6\r\nmylist\r\n".chars();
@@ -1,9 +1,36 @@
 // Provide an implementation of a serde serialiser for RESP data
-use rustdss_data::RespData;
+use rustdss_data::{wire_bytes, RespData};
 use std::collections::VecDeque;
 
 pub trait SerialiseRespData {
     fn as_string(&self) -> String;
+    fn as_bytes(&self) -> Vec<u8>;
+}
+
+/// The deserialiser maps every incoming byte onto the char with the same value, so map them
+/// back the same way when writing to the wire - this keeps binary values byte-for-byte
+/// identical.
+fn write_str(output: &mut Vec<u8>, string: &str) {
+    output.extend(wire_bytes(string));
+}
+
+fn write_bytes(output: &mut Vec<u8>, data: &RespData) {
+    match data {
+        RespData::BulkStr(string) => {
+            let mut payload = Vec::with_capacity(string.len());
+            write_str(&mut payload, string);
+            output.extend_from_slice(format!("${}\r\n", payload.len()).as_bytes());
+            output.extend_from_slice(&payload);
+            output.extend_from_slice(b"\r\n");
+        }
+        RespData::List(items) => {
+            output.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                write_bytes(output, item);
+            }
+        }
+        other => write_str(output, &other.as_string()),
+    }
 }
 
 fn serialise_list(items: &VecDeque<RespData>) -> String {
@@ -11,7 +38,7 @@ fn serialise_list(items: &VecDeque<RespData>) -> String {
     // optimised at some point
     let len = items.len();
 
-    let content: String = items.into_iter().map(|item| item.as_string()).collect();
+    let content: String = items.iter().map(|item| item.as_string()).collect();
 
     format!("*{}\r\n{}", len, content)
 }
@@ -27,6 +54,12 @@ impl SerialiseRespData for RespData {
             RespData::NullString => "$-1\r\n".into(),
         }
     }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_bytes(&mut output, self);
+        output
+    }
 }
 
 #[cfg(test)]
@@ -62,6 +95,13 @@ mod should {
         assert_eq!(input.as_string(), "-error\r\n");
     }
 
+    #[test]
+    fn serialise_binary_bulk_strings_as_raw_bytes() {
+        let input = RespData::BulkStr("\u{ff}\u{0}\r\n".into());
+
+        assert_eq!(input.as_bytes(), b"$4\r\n\xff\x00\r\n\r\n".to_vec());
+    }
+
     #[test]
     fn serialise_simple_lists_properly() {
         let input = RespData::List(